@@ -0,0 +1,855 @@
+//! Translates a [`Module`] into a single self-contained C11 file.
+//!
+//! Every value is a pointer to a heap object, with `Int`s and `Bool`s boxed, so the
+//! untyped IR registers all become `cool_value` locals. Each class gets a struct with
+//! its inherited attributes first, a class descriptor and a vtable. Vtables are indexed
+//! by a program-wide selector per method name, which lets a dispatch find its slot
+//! without knowing the receiver's static class.
+//!
+//! With debug information, every line of a method's function gets a `#line` directive
+//! naming the line of the COOL expression it came from, or of the method for the
+//! frame handling around them, so the C compiler's `-g` maps the machine code back to
+//! the `.cl` file. Each function's symbol is also renamed to `Class.method` with an
+//! assembler label.
+//!
+//! Either way, every function links a frame naming its method and line into the
+//! runtime's `cool_stack` on entry and unlinks it before returning, so a runtime error
+//! can print a backtrace of the COOL methods that were running. Before an instruction
+//! that can fail or call, the frame moves to a site for the instruction's line, so the
+//! error and each caller are reported at the expression being run.
+//!
+//! The frames are also the garbage collector's shadow stack. Each one points at the
+//! addresses of its function's registers, and the function calls `cool_safepoint`,
+//! where the runtime may collect, on entry and at the top of each loop.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+use lexer::source::SourceMap;
+use lexer::{Symbol, sym};
+use parser::ast::Span;
+
+use ir::{
+    BinOp, BlockId, ClassInfo, Const, Function, INIT_METHOD, Inst, Module, Reg, Target, Terminator,
+    UnOp,
+};
+
+/// Object layouts, helpers and the basic classes' methods shared by every program.
+/// The LLVM backend links against it rather than bundling it.
+pub const RUNTIME: &str = include_str!("runtime.c");
+
+/// Returns the C source for `module`, including the runtime and a `main` that runs
+/// `(new Main).main()`. Runtime errors name the files of `sources` that the module's
+/// spans refer to.
+pub fn generate(module: &Module, sources: &SourceMap) -> String {
+    Generator::new(module, sources, false).generate()
+}
+
+/// Like [`generate`], with `#line` directives pointing into the files of `sources` that
+/// the module's spans refer to, and methods' symbols named `Class.method`. Compile the
+/// result with `-g`.
+pub fn generate_with_debug_info(module: &Module, sources: &SourceMap) -> String {
+    Generator::new(module, sources, true).generate()
+}
+
+/// The C name of a method's function. Class names have their underscores doubled so
+/// the first single underscore always separates class from method.
+pub fn mangle(class: &str, method: &str) -> String {
+    format!("{}_{method}", class.replace('_', "__"))
+}
+
+struct Generator<'m> {
+    module: &'m Module,
+    /// Where the module's spans point.
+    sources: &'m SourceMap,
+    /// Whether to generate debug information.
+    debug: bool,
+    /// Vtable slot of every method name.
+    selectors: HashMap<Symbol, usize>,
+    /// Statically allocated constants, keyed by value, with their C names.
+    constants: HashMap<Const, String>,
+    constant_defs: String,
+}
+
+impl<'m> Generator<'m> {
+    fn new(module: &'m Module, sources: &'m SourceMap, debug: bool) -> Self {
+        let mut selectors = HashMap::new();
+        for method in module.classes.iter().flat_map(|c| &c.methods) {
+            if method.name != INIT_METHOD {
+                let next = selectors.len();
+                selectors.entry(method.name).or_insert(next);
+            }
+        }
+        Generator {
+            module,
+            sources,
+            debug,
+            selectors,
+            constants: HashMap::new(),
+            constant_defs: String::new(),
+        }
+    }
+
+    fn generate(mut self) -> String {
+        // Bodies go first so the constants they use are known.
+        let mut bodies = String::new();
+        for func in &self.module.functions {
+            self.function(&mut bodies, func);
+        }
+
+        let mut out = String::new();
+        out.push_str(RUNTIME);
+        out.push('\n');
+        for class in self.user_classes() {
+            self.class_struct(&mut out, class);
+        }
+        for class in self.user_classes() {
+            writeln!(out, "static cool_value new_{}(void);", class.name).unwrap();
+        }
+        for func in &self.module.functions {
+            if self.debug {
+                writeln!(
+                    out,
+                    "static {} __asm__(\"{}.{}\");",
+                    signature(func),
+                    func.class,
+                    func.method
+                )
+                .unwrap();
+            } else {
+                writeln!(out, "static {};", signature(func)).unwrap();
+            }
+        }
+        out.push('\n');
+        out.push_str(&self.constant_defs);
+        out.push('\n');
+        for class in &self.module.classes {
+            self.class_descriptor(&mut out, class);
+        }
+        for class in self.user_classes() {
+            self.constructor(&mut out, class);
+        }
+        // The bodies go last, since the C compiler would count whatever came after them
+        // as part of the COOL file their last `#line` named.
+        self.main(&mut out);
+        out.push('\n');
+        out.push_str(&bodies);
+        out
+    }
+
+    fn user_classes(&self) -> impl Iterator<Item = &'m ClassInfo> + use<'m> {
+        self.module.classes.iter().filter(|c| !c.builtin)
+    }
+
+    fn class_struct(&self, out: &mut String, class: &ClassInfo) {
+        writeln!(out, "struct {} {{", class.name).unwrap();
+        writeln!(out, "    const cool_class *class;").unwrap();
+        for (_, attr) in self.module.all_attributes(class.name) {
+            writeln!(out, "    cool_value a_{};", attr.name).unwrap();
+        }
+        writeln!(out, "}};\n").unwrap();
+    }
+
+    fn class_descriptor(&self, out: &mut String, class: &ClassInfo) {
+        let mut vtable = vec!["NULL".to_string(); self.selectors.len().max(1)];
+        for (&name, &slot) in &self.selectors {
+            if let Some(defining) = self.module.resolve_method(class.name, name) {
+                vtable[slot] = format!("(cool_fn){}", mangle(&defining, &name));
+            }
+        }
+        writeln!(
+            out,
+            "static const cool_fn vtable_{}[] = {{{}}};",
+            class.name,
+            vtable.join(", ")
+        )
+        .unwrap();
+
+        let size = match class.name {
+            sym::INT | sym::BOOL | sym::STRING => format!("sizeof(struct {})", class.name),
+            _ if class.builtin => "sizeof(struct cool_object)".to_string(),
+            _ => format!("sizeof(struct {})", class.name),
+        };
+        let parent = class
+            .parent
+            .as_ref()
+            .map_or("NULL".to_string(), |p| format!("&class_{p}"));
+        writeln!(
+            out,
+            "const cool_class class_{0} = {{\"{0}\", {parent}, {size}, new_{0}, vtable_{0}}};\n",
+            class.name
+        )
+        .unwrap();
+    }
+
+    /// `new_C` allocates a `C` with every attribute at its default value and runs its
+    /// initializer.
+    fn constructor(&self, out: &mut String, class: &ClassInfo) {
+        let name = class.name;
+        writeln!(out, "static cool_value new_{name}(void) {{").unwrap();
+        writeln!(
+            out,
+            "    struct {name} *self = (struct {name} *)cool_alloc(&class_{name});"
+        )
+        .unwrap();
+        for (_, attr) in self.module.all_attributes(name) {
+            if let sym::INT | sym::BOOL | sym::STRING = attr.typ {
+                writeln!(out, "    self->a_{} = new_{}();", attr.name, attr.typ).unwrap();
+            }
+        }
+        if self.module.function(name, INIT_METHOD).is_some() {
+            writeln!(
+                out,
+                "    return {}((cool_value)self);",
+                mangle(&name, &INIT_METHOD)
+            )
+            .unwrap();
+        } else {
+            writeln!(out, "    return (cool_value)self;").unwrap();
+        }
+        writeln!(out, "}}\n").unwrap();
+    }
+
+    fn main(&self, out: &mut String) {
+        writeln!(out, "int main(void) {{").unwrap();
+        match self
+            .module
+            .resolve_method(sym::MAIN_CLASS, sym::MAIN_METHOD)
+        {
+            Some(defining) if self.module.class(sym::MAIN_CLASS).is_some() => {
+                writeln!(out, "    {}(new_Main());", mangle(&defining, "main")).unwrap();
+            }
+            _ => writeln!(out, "    cool_error(\"undefined method Main.main\");").unwrap(),
+        }
+        writeln!(out, "    fflush(stdout);").unwrap();
+        writeln!(out, "    return 0;").unwrap();
+        writeln!(out, "}}").unwrap();
+    }
+
+    fn function(&mut self, out: &mut String, func: &Function) {
+        let start = self.location(func.span);
+        self.write_line(out, start, &format!("static {} {{", signature(func)));
+        let mut locals: Vec<Reg> = func
+            .blocks
+            .iter()
+            .flat_map(|b| b.insts().flat_map(|i| i.dst().into_iter().chain(i.uses())))
+            .filter(|r| !func.params.contains(r))
+            .collect();
+        locals.sort();
+        locals.dedup();
+        let declarations: Vec<String> = locals
+            .iter()
+            .map(|r| format!("{} = NULL", reg(*r)))
+            .collect();
+        for chunk in declarations.chunks(8) {
+            let text = format!("    cool_value {};", chunk.join(", "));
+            self.write_line(out, start, &text);
+        }
+        // Every register is a root, so the collector sees whatever the function holds.
+        let roots: Vec<String> = func
+            .params
+            .iter()
+            .chain(&locals)
+            .map(|r| format!("&{}", reg(*r)))
+            .collect();
+        for (i, chunk) in roots.chunks(8).enumerate() {
+            let text = match i {
+                0 => format!("    cool_value *const roots[] = {{{}", chunk.join(", ")),
+                _ => format!("        {}", chunk.join(", ")),
+            };
+            let end = if (i + 1) * 8 >= roots.len() {
+                "};"
+            } else {
+                ","
+            };
+            self.write_line(out, start, &format!("{text}{end}"));
+        }
+        // The frame points at the site of the line being run, so that a runtime error and
+        // the frames above it report that line. It starts out at the method's own.
+        let mut sites = vec![start];
+        let mut body = String::new();
+        let mut targets: HashSet<BlockId> = func
+            .blocks
+            .iter()
+            .flat_map(|b| b.term.successors())
+            .collect();
+        if func.entry != BlockId(0) {
+            self.write_line(&mut body, start, &format!("    goto {};", func.entry));
+            targets.insert(func.entry);
+        }
+        let loops = loop_headers(func);
+        for id in func.block_ids() {
+            if targets.contains(&id) {
+                self.write_line(&mut body, start, &format!("{id}:;"));
+            }
+            let block = func.block(id);
+            if loops.contains(&id) {
+                let span = block.stmts.first().map_or(block.term_span, |s| s.span);
+                self.write_line(&mut body, self.location(span), "    cool_safepoint();");
+            }
+            // Only what can fail or call needs the frame's line to be up to date.
+            let mut site = None;
+            for stmt in &block.stmts {
+                let location = self.location(stmt.span);
+                if stmt.inst.has_side_effects() {
+                    if let Some(text) = set_site(&mut sites, &mut site, location) {
+                        self.write_line(&mut body, location, &text);
+                    }
+                }
+                let text = format!("    {}", self.inst(func, &stmt.inst));
+                self.write_line(&mut body, location, &text);
+            }
+            let location = self.location(block.term_span);
+            if let Terminator::Case { .. } = block.term {
+                if let Some(text) = set_site(&mut sites, &mut site, location) {
+                    self.write_line(&mut body, location, &text);
+                }
+            }
+            let text = format!("    {}", self.terminator(&block.term));
+            self.write_line(&mut body, location, &text);
+        }
+
+        for (i, &(file, line)) in sites.iter().enumerate() {
+            let text = format!(
+                "    static const cool_site {} = {{\"{}.{}\", {}, {line}}};",
+                site_name(i),
+                func.class,
+                func.method,
+                c_string_literal(file),
+            );
+            self.write_line(out, start, &text);
+        }
+        let text = format!(
+            "    cool_frame frame = {{&site, cool_stack, roots, {}}};",
+            roots.len()
+        );
+        self.write_line(out, start, &text);
+        self.write_line(out, start, "    cool_stack = &frame;");
+        self.write_line(out, start, "    cool_safepoint();");
+        out.push_str(&body);
+        self.write_line(out, start, "}");
+        out.push('\n');
+    }
+
+    /// Writes `text` as a line of a function, preceded with debug information by a
+    /// `#line` directive for `location`. Every line gets its own, since the C compiler
+    /// would otherwise count up from the one before.
+    fn write_line(&self, out: &mut String, (file, line): (&str, usize), text: &str) {
+        if self.debug {
+            writeln!(out, "#line {line} {}", c_string_literal(file)).unwrap();
+        }
+        writeln!(out, "{text}").unwrap();
+    }
+
+    /// The file name and line `span` starts on.
+    fn location(&self, span: Span) -> (&'m str, usize) {
+        let file = self.sources.file(span.context);
+        (file.name(), file.location(span.start).line)
+    }
+
+    fn inst(&mut self, func: &Function, inst: &Inst) -> String {
+        match inst {
+            Inst::Const { dst, value } => format!("{} = {};", reg(*dst), self.constant(value)),
+            Inst::Copy { dst, src } => format!("{} = {};", reg(*dst), reg(*src)),
+            Inst::Binary { dst, op, lhs, rhs } => {
+                let (l, r) = (reg(*lhs), reg(*rhs));
+                let value = match op {
+                    BinOp::Add => format!("cool_add({l}, {r})"),
+                    BinOp::Sub => format!("cool_sub({l}, {r})"),
+                    BinOp::Mul => format!("cool_mul({l}, {r})"),
+                    BinOp::Div => format!("cool_div({l}, {r})"),
+                    BinOp::Lt => format!("cool_bool(COOL_INT({l}) < COOL_INT({r}))"),
+                    BinOp::Le => format!("cool_bool(COOL_INT({l}) <= COOL_INT({r}))"),
+                    BinOp::Eq => format!("cool_equal({l}, {r})"),
+                };
+                format!("{} = {value};", reg(*dst))
+            }
+            Inst::Unary { dst, op, src } => {
+                let value = match op {
+                    UnOp::Neg => format!("cool_neg({})", reg(*src)),
+                    UnOp::Not => format!("cool_bool(!COOL_BOOL({}))", reg(*src)),
+                };
+                format!("{} = {value};", reg(*dst))
+            }
+            Inst::IsVoid { dst, src } => {
+                format!("{} = cool_bool({} == NULL);", reg(*dst), reg(*src))
+            }
+            Inst::New {
+                dst,
+                class: Some(class),
+            } => {
+                let value = if self.module.class(*class).is_some() {
+                    format!("new_{class}()")
+                } else {
+                    undefined(&format!("class {class}"))
+                };
+                format!("{} = {value};", reg(*dst))
+            }
+            Inst::New { dst, class: None } => {
+                format!("{} = {}->class->new();", reg(*dst), reg(func.params[0]))
+            }
+            Inst::GetAttr {
+                dst,
+                obj,
+                class,
+                name,
+            } => {
+                let value = match self.module.attribute_type(*class, *name) {
+                    Some(_) => format!("((struct {class} *){})->a_{name}", reg(*obj)),
+                    None => undefined(&format!("attribute {class}.{name}")),
+                };
+                format!("{} = {value};", reg(*dst))
+            }
+            Inst::SetAttr {
+                obj,
+                class,
+                name,
+                src,
+            } => match self.module.attribute_type(*class, *name) {
+                Some(_) => format!(
+                    "((struct {class} *){})->a_{name} = {};",
+                    reg(*obj),
+                    reg(*src)
+                ),
+                None => format!("{};", undefined(&format!("attribute {class}.{name}"))),
+            },
+            Inst::Call {
+                dst,
+                recv,
+                target,
+                method,
+                args,
+            } => {
+                let params = vec!["cool_value"; args.len() + 1];
+                let args: String = args.iter().map(|a| ", ".to_string() + &reg(*a)).collect();
+                let value = match target {
+                    Target::Virtual => match self.selectors.get(method) {
+                        Some(selector) => {
+                            format!(
+                                "((cool_value (*)({}))cool_method({}, {selector}, \"{method}\"))({}{args})",
+                                params.join(", "),
+                                reg(*recv),
+                                reg(*recv)
+                            )
+                        }
+                        None => undefined(&format!("method {method}")),
+                    },
+                    Target::Static(class) => match self.module.resolve_method(*class, *method) {
+                        Some(defining) => format!(
+                            "{}(cool_check({}, \"{method}\"){args})",
+                            mangle(&defining, method),
+                            reg(*recv)
+                        ),
+                        None => undefined(&format!("method {class}.{method}")),
+                    },
+                };
+                format!("{} = {value};", reg(*dst))
+            }
+            Inst::Phi { .. } => unreachable!("phis are removed before code generation"),
+        }
+    }
+
+    fn terminator(&self, term: &Terminator) -> String {
+        match term {
+            Terminator::Jump(target) => format!("goto {target};"),
+            Terminator::Branch {
+                cond,
+                then_block,
+                else_block,
+            } => format!(
+                "if (COOL_BOOL({})) goto {then_block}; else goto {else_block};",
+                reg(*cond)
+            ),
+            Terminator::Case { scrutinee, arms } => {
+                // Arms naming classes that don't exist can never match.
+                let classes: Vec<String> = arms
+                    .iter()
+                    .map(|arm| match self.module.class(arm.typ) {
+                        Some(_) => format!("&class_{}", arm.typ),
+                        None => "NULL".to_string(),
+                    })
+                    .collect();
+                let mut gotos = String::new();
+                for (i, arm) in arms.iter().enumerate() {
+                    write!(gotos, " case {i}: goto {};", arm.block).unwrap();
+                }
+                format!(
+                    "{{ static const cool_class *const arms[] = {{{}}}; switch (cool_case({}, arms, {})) {{{gotos} }} }}",
+                    classes.join(", "),
+                    reg(*scrutinee),
+                    arms.len()
+                )
+            }
+            Terminator::Return(value) => {
+                format!("cool_stack = frame.caller; return {};", reg(*value))
+            }
+            Terminator::Unreachable => "abort();".to_string(),
+        }
+    }
+
+    /// A C expression for `value`. Literals are statically allocated once per program.
+    fn constant(&mut self, value: &Const) -> String {
+        if let Const::Void = value {
+            return "NULL".to_string();
+        }
+        if let Const::Bool(b) = value {
+            return format!("cool_bool({})", *b as i32);
+        }
+        if let Some(name) = self.constants.get(value) {
+            return format!("(cool_value)&{name}.object");
+        }
+        let name = format!("const_{}", self.constants.len());
+        match value {
+            Const::Int(i) => {
+                writeln!(
+                    self.constant_defs,
+                    "static struct cool_static_Int {name} = {{COOL_STATIC_HEADER, {{&class_Int, {i}}}}};"
+                )
+                .unwrap();
+            }
+            Const::Str(s) => {
+                writeln!(
+                    self.constant_defs,
+                    "static struct cool_static_String {name} = {{COOL_STATIC_HEADER, {{&class_String, {}, {}}}}};",
+                    s.len(),
+                    c_string_literal(s)
+                )
+                .unwrap();
+            }
+            Const::Bool(_) | Const::Void => unreachable!(),
+        }
+        self.constants.insert(value.clone(), name.clone());
+        format!("(cool_value)&{name}.object")
+    }
+}
+
+/// The statement pointing the frame at the site for `location`, adding one to `sites`
+/// if it's new, unless `current`, the index of the site it points at, says it already
+/// does.
+fn set_site<'a>(
+    sites: &mut Vec<(&'a str, usize)>,
+    current: &mut Option<usize>,
+    location: (&'a str, usize),
+) -> Option<String> {
+    let index = match sites.iter().position(|site| *site == location) {
+        Some(index) => index,
+        None => {
+            sites.push(location);
+            sites.len() - 1
+        }
+    };
+    if *current == Some(index) {
+        return None;
+    }
+    *current = Some(index);
+    Some(format!("    frame.site = &{};", site_name(index)))
+}
+
+/// The C name of a function's `index`th site. The first is the method's own.
+fn site_name(index: usize) -> String {
+    match index {
+        0 => "site".to_string(),
+        _ => format!("site{index}"),
+    }
+}
+
+fn signature(func: &Function) -> String {
+    let params: Vec<String> = func
+        .params
+        .iter()
+        .map(|p| format!("cool_value {}", reg(*p)))
+        .collect();
+    format!(
+        "cool_value {}({})",
+        mangle(&func.class, &func.method),
+        params.join(", ")
+    )
+}
+
+/// The blocks jumped to from themselves or a later block. Blocks are emitted in order,
+/// so every loop has one, where its generated code checks for a collection.
+pub(crate) fn loop_headers(func: &Function) -> HashSet<BlockId> {
+    func.block_ids()
+        .flat_map(|id| {
+            let term = &func.block(id).term;
+            term.successors().into_iter().filter(move |to| to.0 <= id.0)
+        })
+        .collect()
+}
+
+fn reg(r: Reg) -> String {
+    format!("r{}", r.0)
+}
+
+/// An expression that fails at runtime, for names the program uses but never defines.
+fn undefined(what: &str) -> String {
+    format!("(cool_error(\"undefined {what}\"), (cool_value)NULL)")
+}
+
+fn c_string_literal(s: &str) -> String {
+    let mut literal = String::from("\"");
+    for byte in s.bytes() {
+        match byte {
+            b'"' => literal.push_str("\\\""),
+            b'\\' => literal.push_str("\\\\"),
+            b' '..=b'~' => literal.push(byte as char),
+            // Octal escapes are at most three digits, so they can't swallow the next
+            // character the way hex escapes can.
+            _ => write!(literal, "\\{byte:03o}").unwrap(),
+        }
+    }
+    literal.push('"');
+    literal
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use std::process::{Command, Stdio};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static NEXT_TEST_PROGRAM: AtomicUsize = AtomicUsize::new(0);
+
+    fn generate_for(src: &str, level: ir::OptLevel) -> String {
+        let mut sources = SourceMap::new();
+        let file = sources.add("test.cl", src);
+        let mut module = ir::lower(&parser::parse_file(src, file).unwrap());
+        ir::optimize(&mut module, level);
+        generate(&module, &sources)
+    }
+
+    /// Compiles and runs the C generated for `src`, returning its exit status, stdout
+    /// and stderr, or returns `None` when there's no C compiler to do it with.
+    fn compile_and_run(
+        src: &str,
+        level: ir::OptLevel,
+        input: &str,
+    ) -> Option<(Option<i32>, String, String)> {
+        let dir = std::env::temp_dir().join(format!("coolc-c-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let name = format!(
+            "program{}",
+            NEXT_TEST_PROGRAM.fetch_add(1, Ordering::Relaxed)
+        );
+        let c_file: PathBuf = dir.join(format!("{name}.c"));
+        let exe = dir.join(name);
+        std::fs::write(&c_file, generate_for(src, level)).unwrap();
+        let status = Command::new("cc")
+            .args(["-std=c11", "-fno-strict-aliasing", "-o"])
+            .arg(&exe)
+            .arg(&c_file)
+            .status()
+            .ok()?;
+        assert!(status.success(), "generated C failed to compile");
+
+        let mut child = Command::new(&exe)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        std::io::Write::write_all(child.stdin.as_mut().unwrap(), input.as_bytes()).unwrap();
+        let output = child.wait_with_output().unwrap();
+        Some((
+            output.status.code(),
+            String::from_utf8(output.stdout).unwrap(),
+            String::from_utf8(output.stderr).unwrap(),
+        ))
+    }
+
+    const PROGRAM: &str = r#"
+        class Animal {
+            name : String <- "animal";
+            speak() : String { "..." };
+            name() : String { name };
+        };
+        class Dog inherits Animal { speak() : String { "woof" }; };
+        class Main inherits IO {
+            count : Int;
+            describe(a : Object) : String {
+                case a of d : Dog => "dog"; x : Animal => "animal"; o : Object => "other"; esac
+            };
+            main() : Object { let a : Animal <- new Dog, b : Animal <- new Animal in {
+                out_string(a.speak().concat(b.speak()).concat("\n"));
+                out_string(a@Animal.speak().concat(a.name()).concat("\n"));
+                out_string(describe(a).concat(describe(b)).concat(describe(1)).concat("\n"));
+                while count < 10 loop count <- count + 1 pool;
+                out_int(count * 3 - 10 / 4).out_string(" ").out_int(~count);
+                out_string(if "ab".concat("c") = "abc" then " eq\t" else " ne\t" fi);
+                out_string(a.type_name().substr(1, 2));
+                out_string("\n");
+            } };
+        };
+    "#;
+
+    #[test]
+    fn test_mangle() {
+        assert_eq!(mangle("Main", "main"), "Main_main");
+        assert_eq!(mangle("Main", "_init"), "Main__init");
+        assert_ne!(mangle("A_b", "c"), mangle("A", "b_c"));
+    }
+
+    #[test]
+    fn test_string_literals() {
+        assert_eq!(c_string_literal("a\"b\\c"), r#""a\"b\\c""#);
+        assert_eq!(c_string_literal("\n1"), r#""\0121""#);
+    }
+
+    #[test]
+    fn test_layout_and_vtables() {
+        let c = generate_for(PROGRAM, ir::OptLevel::O0);
+        // Inherited attributes come first.
+        assert!(
+            c.contains("struct Dog {\n    const cool_class *class;\n    cool_value a_name;\n};")
+        );
+        assert!(c.contains("const cool_class class_Dog = {\"Dog\", &class_Animal, sizeof(struct Dog), new_Dog, vtable_Dog};"));
+        let vtable = c
+            .lines()
+            .find(|l| l.starts_with("static const cool_fn vtable_Dog[]"))
+            .unwrap();
+        assert!(vtable.contains("(cool_fn)Dog_speak"));
+        assert!(vtable.contains("(cool_fn)Animal_name"));
+        assert!(vtable.contains("(cool_fn)Object_type_name"));
+        assert!(!vtable.contains("IO_out_string"));
+    }
+
+    #[test]
+    fn test_debug_info() {
+        let mut sources = SourceMap::new();
+        sources.add("lib.cl", "class A {};");
+        let file = sources.add(
+            "main.cl",
+            "class Main {\n  x : Int <- 1;\n\n  main() : Object {\n    {\n      x <- 2;\n      x;\n    }\n  };\n};",
+        );
+        let program = parser::parse_file(sources.file(file).src(), file).unwrap();
+        let c = generate_with_debug_info(&ir::lower(&program), &sources);
+        assert!(c.contains("static cool_value Main_main(cool_value r0) __asm__(\"Main.main\");"));
+        assert!(c.contains("#line 1 \"main.cl\"\nstatic cool_value Main__init("));
+        let main = c
+            .split("#line 4 \"main.cl\"\nstatic cool_value Main_main(")
+            .nth(1)
+            .unwrap();
+        let main: Vec<&str> = main.lines().take_while(|l| *l != "}").collect();
+        // Every line has a directive, and each statement's is its own line. The last
+        // one is the closing brace's.
+        let lines: Vec<(&str, &str)> = main[1..main.len() - 1]
+            .chunks(2)
+            .map(|pair| (pair[0], pair[1].trim()))
+            .collect();
+        assert!(lines.iter().all(|(line, _)| line.starts_with("#line ")));
+        let line_of = |text: &str| {
+            let (line, _) = lines.iter().find(|(_, t)| t.contains(text)).unwrap();
+            *line
+        };
+        assert_eq!(line_of("cool_stack = &frame;"), "#line 4 \"main.cl\"");
+        assert_eq!(line_of("->a_x = "), "#line 6 \"main.cl\"");
+        assert_eq!(
+            line_of("= ((struct Main *)r0)->a_x;"),
+            "#line 7 \"main.cl\""
+        );
+        // Nothing but bodies comes after the first directive.
+        let rest = &c[c.find("#line").unwrap()..];
+        assert!(!rest.contains("int main(void)"));
+        assert!(!generate(&ir::lower(&program), &sources).contains("#line"));
+    }
+
+    #[test]
+    fn test_compiled_program_output() {
+        let expected = "woof...\n...animal\ndoganimalother\n28 -10 eq\tog\n";
+        for level in [ir::OptLevel::O0, ir::OptLevel::O2] {
+            let Some((status, output, _)) = compile_and_run(PROGRAM, level, "") else {
+                return;
+            };
+            assert_eq!(status, Some(0));
+            assert_eq!(output, expected, "at {level}");
+        }
+    }
+
+    #[test]
+    fn test_compiled_runtime_error() {
+        let src = "class Main inherits IO {\n  a : Main;\n  f() : Object {\n    a.main()\n  };\n\
+                   main() : Object { {\n    out_string(\"x\");\n    f();\n  } };\n};";
+        // At -O2, `f` is inlined and the dispatch is reported in `main`.
+        for (level, backtrace) in [
+            (
+                ir::OptLevel::O0,
+                "#0  Main.f at test.cl:4\n#1  Main.main at test.cl:8\n",
+            ),
+            (ir::OptLevel::O2, "#0  Main.main at test.cl:4\n"),
+        ] {
+            let Some((status, output, errors)) = compile_and_run(src, level, "") else {
+                return;
+            };
+            assert_eq!(status, Some(4));
+            assert_eq!(output, "x");
+            assert_eq!(
+                errors,
+                format!("test.cl:4: runtime error: dispatch to void (main)\n{backtrace}"),
+                "at {level}"
+            );
+        }
+        if let Some((status, _, errors)) = compile_and_run("class Main { };", ir::OptLevel::O0, "")
+        {
+            assert_eq!(status, Some(4));
+            assert_eq!(errors, "runtime error: undefined method Main.main\n");
+        }
+    }
+
+    #[test]
+    fn test_compiled_input() {
+        let src = r#"
+            class Main inherits IO {
+                main() : Object { {
+                    out_int(in_int() + 1);
+                    out_string(in_string().concat("!"));
+                } };
+            };
+        "#;
+        if let Some((status, output, _)) = compile_and_run(src, ir::OptLevel::O1, "41\nhi\n") {
+            assert_eq!(status, Some(0));
+            assert_eq!(output, "42hi!");
+        }
+    }
+
+    /// Allocates more garbage than the heap limit lets stay live, while keeping a list
+    /// alive across every collection.
+    const GARBAGE: &str = r#"
+        class Node {
+            name : String;
+            next : Node;
+            init(n : String, rest : Node) : Node { { name <- n; next <- rest; self; } };
+            name() : String { name };
+            next() : Node { next };
+        };
+        class Main inherits IO {
+            main() : Object {
+                let i : Int <- 0, list : Node, s : String, names : String in {
+                    while i < 4000000 loop {
+                        s <- s.substr(0, 0).concat("0123456789012345678901234567890123456789");
+                        if i - i / 4000 * 4000 = 0 then
+                            list <- (new Node).init(i.type_name().concat("x").copy(), list)
+                        else 0 fi;
+                        i <- i + 1;
+                    } pool;
+                    while not isvoid list loop {
+                        names <- names.concat(list.name().substr(3, 1));
+                        list <- list.next();
+                    } pool;
+                    out_int(names.length()).out_string(names.substr(0, 3)).out_string("\n");
+                }
+            };
+        };
+    "#;
+
+    #[test]
+    fn test_compiled_garbage_collection() {
+        for level in [ir::OptLevel::O0, ir::OptLevel::O2] {
+            let Some((status, output, errors)) = compile_and_run(GARBAGE, level, "") else {
+                return;
+            };
+            assert_eq!((status, errors.as_str()), (Some(0), ""));
+            assert_eq!(output, "1000xxx\n");
+        }
+    }
+}
@@ -0,0 +1,5 @@
+//! Backends that turn an optimized [`ir::Module`] into code for other toolchains.
+
+pub mod c;
+pub mod llvm;
+pub mod wasm;
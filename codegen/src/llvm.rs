@@ -0,0 +1,1264 @@
+//! Translates a [`Module`] into a textual LLVM IR module.
+//!
+//! The object model is the C backend's: every value is a `ptr` to a boxed
+//! heap object, each class has a named struct type listing its inherited attributes
+//! first, and a `cool_class` descriptor whose vtable is indexed by a program-wide
+//! selector per method name. Allocation and the basic classes' methods come from the C
+//! runtime, which the generated module is linked against, so the output only needs a
+//! local `llc` (or `clang`) to become an executable.
+//!
+//! Every IR register gets an `alloca` slot that's loaded and stored around each
+//! instruction. The slots are the garbage collector's roots: the function's frame points
+//! at an array of them, and the function calls `cool_safepoint` on entry and at the top
+//! of each loop, where the collector may run.
+//!
+//! With debug information, each method's function gets a `DISubprogram` at the line of
+//! the COOL method, named `Class.method` like its symbol. Each instruction gets a `!dbg`
+//! location on the line of the expression it came from, and the frame handling around
+//! them the method's line. Expressions inlined from another file are scoped to a
+//! `DILexicalBlockFile` for it.
+//!
+//! Either way, every function keeps a `cool_frame` on its stack and links it into the
+//! C runtime's `cool_stack` for as long as it runs, which is what a runtime error's
+//! backtrace walks. The frame's site moves to the line of each instruction that can
+//! fail or call before it runs, as in the C backend.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use lexer::FileId;
+use lexer::source::SourceMap;
+use lexer::{Symbol, sym};
+use parser::ast::Span;
+
+use ir::{
+    BinOp, ClassInfo, Const, Function, INIT_METHOD, Inst, Module, Reg, Target, Terminator, UnOp,
+};
+
+use crate::c::{loop_headers, mangle};
+
+/// Struct types and declarations of the C runtime's functions.
+const RUNTIME: &str = include_str!("runtime.ll");
+
+/// The integer type of the runtime's `size_t` on the platform the compiler was built
+/// for. The module names no target triple or data layout, so the tools that read it
+/// compile for their default target, which for a local install is that platform too,
+/// and fill in its layout themselves.
+const SIZE_T: &str = if cfg!(target_pointer_width = "64") {
+    "i64"
+} else {
+    "i32"
+};
+
+/// Returns the IR for `module`, with a `main` that runs `(new Main).main()`. Runtime
+/// errors name the files of `sources` that the module's spans refer to.
+pub fn generate(module: &Module, sources: &SourceMap) -> String {
+    Generator::new(module, sources, false).generate()
+}
+
+/// Like [`generate`], with DWARF debug information pointing into the files of `sources`
+/// that the module's spans refer to, and methods' symbols named `Class.method`.
+pub fn generate_with_debug_info(module: &Module, sources: &SourceMap) -> String {
+    Generator::new(module, sources, true).generate()
+}
+
+/// The flags LLVM tool `tool`, such as `llc` or `opt`, needs to read the generated IR.
+/// LLVM 14 only reads opaque pointers with `-opaque-pointers`, which later versions
+/// turn on by default and eventually dropped. Empty when the tool can't be run.
+pub fn opaque_pointer_flags(tool: &str) -> &'static [&'static str] {
+    let version = std::process::Command::new(tool)
+        .arg("--version")
+        .output()
+        .map(|output| String::from_utf8_lossy(&output.stdout).into_owned())
+        .unwrap_or_default();
+    let major = version
+        .split("LLVM version ")
+        .nth(1)
+        .and_then(|v| v.split('.').next())
+        .and_then(|v| v.parse::<u32>().ok());
+    match major {
+        Some(major) if major < 15 => &["-opaque-pointers"],
+        _ => &[],
+    }
+}
+
+/// The metadata nodes every module with debug information starts with.
+const COMPILE_UNIT: usize = 0;
+const SUBROUTINE_TYPE: usize = 1;
+
+struct Generator<'m> {
+    module: &'m Module,
+    /// Where the module's spans point.
+    sources: &'m SourceMap,
+    /// Whether to generate debug information.
+    debug: bool,
+    /// Debug information, numbered by position. Starts with [`COMPILE_UNIT`] and
+    /// [`SUBROUTINE_TYPE`].
+    metadata: Vec<String>,
+    /// The `DIFile` node of each source file, once something refers to it.
+    files: HashMap<FileId, usize>,
+    /// Vtable slot of every method name.
+    selectors: HashMap<Symbol, usize>,
+    /// Literal objects, keyed by value, with their global names.
+    constants: HashMap<Const, String>,
+    /// Null-terminated byte arrays, keyed by contents, with their global names.
+    strings: HashMap<String, String>,
+    /// The `cool_site`s of the current function, keyed by file and line.
+    sites: HashMap<(FileId, usize), String>,
+    /// The current function's `DISubprogram` and the file it's in, when generating
+    /// debug information.
+    subprogram: Option<(usize, FileId)>,
+    /// The `DILocation`s of the current function, keyed by file and line.
+    locations: HashMap<(FileId, usize), usize>,
+    globals: String,
+    next_global: usize,
+    /// Number of the next `%t` temporary in the current function.
+    next_temp: usize,
+}
+
+impl<'m> Generator<'m> {
+    fn new(module: &'m Module, sources: &'m SourceMap, debug: bool) -> Self {
+        let mut selectors = HashMap::new();
+        for method in module.classes.iter().flat_map(|c| &c.methods) {
+            if method.name != INIT_METHOD {
+                let next = selectors.len();
+                selectors.entry(method.name).or_insert(next);
+            }
+        }
+        Generator {
+            module,
+            sources,
+            debug,
+            metadata: Vec::new(),
+            files: HashMap::new(),
+            selectors,
+            constants: HashMap::new(),
+            strings: HashMap::new(),
+            sites: HashMap::new(),
+            subprogram: None,
+            locations: HashMap::new(),
+            globals: String::new(),
+            next_global: 0,
+            next_temp: 0,
+        }
+    }
+
+    fn generate(mut self) -> String {
+        if self.debug {
+            self.metadata = vec![String::new(), "!DISubroutineType(types: !{})".to_string()];
+            let file = self.file_node(FileId::default());
+            self.metadata[COMPILE_UNIT] = format!(
+                "distinct !DICompileUnit(language: DW_LANG_C99, file: !{file}, \
+                 producer: \"coolc\", isOptimized: false, runtimeVersion: 0, \
+                 emissionKind: FullDebug)"
+            );
+        }
+
+        // Bodies go first so the globals they use are known.
+        let mut bodies = String::new();
+        for func in &self.module.functions {
+            self.function(&mut bodies, func);
+        }
+        for class in self.module.classes.iter().filter(|c| !c.builtin) {
+            self.constructor(&mut bodies, class);
+        }
+        self.main(&mut bodies);
+
+        let mut descriptors = String::new();
+        for class in &self.module.classes {
+            self.class_descriptor(&mut descriptors, class);
+        }
+
+        let mut out = RUNTIME.replace("$size_t", SIZE_T);
+        out.push('\n');
+        for class in self.module.classes.iter().filter(|c| c.builtin) {
+            writeln!(out, "declare ptr @new_{}()", class.name).unwrap();
+            for method in &class.methods {
+                let params = vec!["ptr"; method.formal_types.len() + 1];
+                writeln!(
+                    out,
+                    "declare ptr @{}({})",
+                    mangle(&class.name, &method.name),
+                    params.join(", ")
+                )
+                .unwrap();
+            }
+        }
+        out.push('\n');
+        for class in self.module.classes.iter().filter(|c| !c.builtin) {
+            let mut fields = vec!["ptr"];
+            fields.extend(self.module.all_attributes(class.name).iter().map(|_| "ptr"));
+            writeln!(out, "%{} = type {{ {} }}", class.name, fields.join(", ")).unwrap();
+        }
+        out.push('\n');
+        out.push_str(&self.globals);
+        out.push('\n');
+        out.push_str(&descriptors);
+        out.push_str(&bodies);
+        if !self.metadata.is_empty() {
+            writeln!(out, "\n!llvm.dbg.cu = !{{!{COMPILE_UNIT}}}").unwrap();
+            let flags = self.metadata.len();
+            writeln!(out, "!llvm.module.flags = !{{!{}, !{}}}", flags, flags + 1).unwrap();
+            for (i, node) in self.metadata.iter().enumerate() {
+                writeln!(out, "!{i} = {node}").unwrap();
+            }
+            writeln!(out, "!{flags} = !{{i32 2, !\"Dwarf Version\", i32 4}}").unwrap();
+            writeln!(
+                out,
+                "!{} = !{{i32 2, !\"Debug Info Version\", i32 3}}",
+                flags + 1
+            )
+            .unwrap();
+        }
+        out
+    }
+
+    /// The name of the function for `class`'s `method`: `Class.method` for the program's
+    /// own methods when generating debug information, and the C name otherwise, as the
+    /// runtime's functions always have.
+    fn symbol(&self, class: Symbol, method: Symbol) -> String {
+        match self.module.class(class) {
+            Some(info) if self.debug && !info.builtin => {
+                format!("\"{class}.{method}\"")
+            }
+            _ => mangle(&class, &method),
+        }
+    }
+
+    fn add_metadata(&mut self, node: String) -> usize {
+        self.metadata.push(node);
+        self.metadata.len() - 1
+    }
+
+    /// The `DIFile` node for `id`. Paths are relative to the directory the compiler ran
+    /// in, like a C compiler's.
+    fn file_node(&mut self, id: FileId) -> usize {
+        if let Some(&node) = self.files.get(&id) {
+            return node;
+        }
+        let directory = std::env::current_dir().unwrap_or_default();
+        let node = self.add_metadata(format!(
+            "!DIFile(filename: {}, directory: {})",
+            metadata_string(self.sources.file(id).name()),
+            metadata_string(&directory.to_string_lossy())
+        ));
+        self.files.insert(id, node);
+        node
+    }
+
+    /// The vtable and the `class_C` descriptor the runtime finds through each object.
+    fn class_descriptor(&mut self, out: &mut String, class: &ClassInfo) {
+        let mut vtable = vec!["ptr null".to_string(); self.selectors.len().max(1)];
+        for (&name, &slot) in &self.selectors {
+            if let Some(defining) = self.module.resolve_method(class.name, name) {
+                vtable[slot] = format!("ptr @{}", self.symbol(defining, name));
+            }
+        }
+        let vtable_type = format!("[{} x ptr]", vtable.len());
+        writeln!(
+            out,
+            "@vtable.{} = internal constant {vtable_type} [{}]",
+            class.name,
+            vtable.join(", ")
+        )
+        .unwrap();
+
+        let typ = match class.name {
+            sym::INT | sym::BOOL | sym::STRING => format!("%{}", class.name),
+            _ if class.builtin => "%obj".to_string(),
+            _ => format!("%{}", class.name),
+        };
+        let name = self.c_string(&class.name);
+        let parent = class
+            .parent
+            .as_ref()
+            .map_or("null".to_string(), |p| format!("@class_{p}"));
+        writeln!(
+            out,
+            "@class_{0} = constant %cool_class {{ ptr {name}, ptr {parent}, \
+             {SIZE_T} ptrtoint (ptr getelementptr ({typ}, ptr null, i32 1) to {SIZE_T}), \
+             ptr @new_{0}, ptr @vtable.{0} }}\n",
+            class.name
+        )
+        .unwrap();
+    }
+
+    /// `new_C` allocates a `C` with every attribute at its default value and runs its
+    /// initializer.
+    fn constructor(&mut self, out: &mut String, class: &ClassInfo) {
+        self.next_temp = 0;
+        let name = class.name;
+        writeln!(out, "define internal ptr @new_{name}() {{").unwrap();
+        writeln!(out, "  %self = call ptr @cool_alloc(ptr @class_{name})").unwrap();
+        for (index, (_, attr)) in self.module.all_attributes(name).iter().enumerate() {
+            if let sym::INT | sym::BOOL | sym::STRING = attr.typ {
+                let value = self.temp();
+                let field = self.temp();
+                writeln!(out, "  {value} = call ptr @new_{}()", attr.typ).unwrap();
+                writeln!(
+                    out,
+                    "  {field} = getelementptr inbounds %{name}, ptr %self, i32 0, i32 {}",
+                    index + 1
+                )
+                .unwrap();
+                writeln!(out, "  store ptr {value}, ptr {field}").unwrap();
+            }
+        }
+        if self.module.function(name, INIT_METHOD).is_some() {
+            writeln!(
+                out,
+                "  %result = call ptr @{}(ptr %self)",
+                self.symbol(name, INIT_METHOD)
+            )
+            .unwrap();
+            writeln!(out, "  ret ptr %result").unwrap();
+        } else {
+            writeln!(out, "  ret ptr %self").unwrap();
+        }
+        writeln!(out, "}}\n").unwrap();
+    }
+
+    fn main(&mut self, out: &mut String) {
+        writeln!(out, "define i32 @main() {{").unwrap();
+        match self
+            .module
+            .resolve_method(sym::MAIN_CLASS, sym::MAIN_METHOD)
+        {
+            Some(defining) if self.module.class(sym::MAIN_CLASS).is_some() => {
+                writeln!(out, "  %main = call ptr @new_Main()").unwrap();
+                writeln!(
+                    out,
+                    "  %result = call ptr @{}(ptr %main)",
+                    self.symbol(defining, sym::MAIN_METHOD)
+                )
+                .unwrap();
+            }
+            _ => {
+                self.undefined(out, "method Main.main");
+            }
+        }
+        writeln!(out, "  %flushed = call i32 @fflush(ptr null)").unwrap();
+        writeln!(out, "  ret i32 0").unwrap();
+        writeln!(out, "}}").unwrap();
+    }
+
+    fn function(&mut self, out: &mut String, func: &Function) {
+        self.subprogram = None;
+        self.locations.clear();
+        let mut attachments = String::new();
+        if self.debug {
+            let file = self.file_node(func.span.context);
+            let line = self
+                .sources
+                .location(func.span.context, func.span.start)
+                .line;
+            let name = format!("{}.{}", func.class, func.method);
+            let subprogram = self.add_metadata(format!(
+                "distinct !DISubprogram(name: {0}, linkageName: {0}, scope: !{file}, \
+                 file: !{file}, line: {line}, type: !{SUBROUTINE_TYPE}, scopeLine: {line}, \
+                 spFlags: DISPFlagDefinition, unit: !{COMPILE_UNIT})",
+                metadata_string(&name)
+            ));
+            self.subprogram = Some((subprogram, func.span.context));
+            attachments = format!(" !dbg !{subprogram}");
+        }
+
+        self.next_temp = 0;
+        let params: Vec<String> = func
+            .params
+            .iter()
+            .map(|p| format!("ptr {}.param", reg(*p)))
+            .collect();
+        writeln!(
+            out,
+            "define internal ptr @{}({}){attachments} {{",
+            self.symbol(func.class, func.method),
+            params.join(", ")
+        )
+        .unwrap();
+
+        // A block of its own, since LLVM's entry block can't be a branch target.
+        let mut prologue = String::new();
+        writeln!(prologue, "start:").unwrap();
+        let mut regs: Vec<Reg> = func
+            .blocks
+            .iter()
+            .flat_map(|b| {
+                b.insts()
+                    .flat_map(|i| i.dst().into_iter().chain(i.uses()))
+                    .chain(b.term.uses())
+            })
+            .chain(func.params.iter().copied())
+            .collect();
+        regs.sort();
+        regs.dedup();
+        for r in &regs {
+            writeln!(prologue, "  {} = alloca ptr", reg(*r)).unwrap();
+        }
+        for r in &regs {
+            let value = if func.params.contains(r) {
+                format!("{}.param", reg(*r))
+            } else {
+                "null".to_string()
+            };
+            writeln!(prologue, "  store ptr {value}, ptr {}", reg(*r)).unwrap();
+        }
+        // Every register is a root, so the collector sees whatever the function holds.
+        let roots = format!("[{} x ptr]", regs.len());
+        writeln!(prologue, "  %roots = alloca {roots}").unwrap();
+        for (i, r) in regs.iter().enumerate() {
+            writeln!(
+                prologue,
+                "  %root.{i} = getelementptr inbounds {roots}, ptr %roots, i32 0, i32 {i}"
+            )
+            .unwrap();
+            writeln!(prologue, "  store ptr {}, ptr %root.{i}", reg(*r)).unwrap();
+        }
+        self.sites.clear();
+        let site = self.site(func, func.span);
+        writeln!(prologue, "  %frame = alloca %cool_frame").unwrap();
+        writeln!(
+            prologue,
+            "  %frame.site = getelementptr inbounds %cool_frame, ptr %frame, i32 0, i32 0"
+        )
+        .unwrap();
+        writeln!(prologue, "  store ptr {site}, ptr %frame.site").unwrap();
+        writeln!(
+            prologue,
+            "  %frame.caller = getelementptr inbounds %cool_frame, ptr %frame, i32 0, i32 1"
+        )
+        .unwrap();
+        writeln!(prologue, "  %caller = load ptr, ptr @cool_stack").unwrap();
+        writeln!(prologue, "  store ptr %caller, ptr %frame.caller").unwrap();
+        writeln!(
+            prologue,
+            "  %frame.roots = getelementptr inbounds %cool_frame, ptr %frame, i32 0, i32 2"
+        )
+        .unwrap();
+        writeln!(prologue, "  store ptr %roots, ptr %frame.roots").unwrap();
+        writeln!(
+            prologue,
+            "  %frame.count = getelementptr inbounds %cool_frame, ptr %frame, i32 0, i32 3"
+        )
+        .unwrap();
+        writeln!(
+            prologue,
+            "  store {SIZE_T} {}, ptr %frame.count",
+            regs.len()
+        )
+        .unwrap();
+        writeln!(prologue, "  store ptr %frame, ptr @cool_stack").unwrap();
+        writeln!(prologue, "  call void @cool_safepoint()").unwrap();
+        writeln!(prologue, "  br label %{}", func.entry).unwrap();
+        self.located(out, func.span, &prologue);
+
+        let loops = loop_headers(func);
+        for id in func.block_ids() {
+            writeln!(out, "{id}:").unwrap();
+            let block = func.block(id);
+            if loops.contains(&id) {
+                let span = block.stmts.first().map_or(block.term_span, |s| s.span);
+                self.located(out, span, "  call void @cool_safepoint()\n");
+            }
+            // Only what can fail or call needs the frame's line to be up to date.
+            let mut site = None;
+            for stmt in &block.stmts {
+                let mut text = String::new();
+                if stmt.inst.has_side_effects() {
+                    self.set_site(&mut text, func, stmt.span, &mut site);
+                }
+                self.inst(&mut text, func, &stmt.inst);
+                self.located(out, stmt.span, &text);
+            }
+            let mut text = String::new();
+            if let Terminator::Case { .. } = block.term {
+                self.set_site(&mut text, func, block.term_span, &mut site);
+            }
+            self.terminator(&mut text, &block.term);
+            self.located(out, block.term_span, &text);
+        }
+        writeln!(out, "}}\n").unwrap();
+    }
+
+    /// Appends `text` to `out`, attaching the location of `span` to each of its
+    /// instructions when generating debug information. Instructions are indented;
+    /// labels aren't.
+    fn located(&mut self, out: &mut String, span: Span, text: &str) {
+        let Some((subprogram, file)) = self.subprogram else {
+            out.push_str(text);
+            return;
+        };
+        let line = self.sources.location(span.context, span.start).line;
+        let location = match self.locations.get(&(span.context, line)) {
+            Some(&location) => location,
+            None => {
+                let mut scope = subprogram;
+                if span.context != file {
+                    let file = self.file_node(span.context);
+                    scope = self.add_metadata(format!(
+                        "!DILexicalBlockFile(scope: !{subprogram}, file: !{file}, discriminator: 0)"
+                    ));
+                }
+                let location =
+                    self.add_metadata(format!("!DILocation(line: {line}, scope: !{scope})"));
+                self.locations.insert((span.context, line), location);
+                location
+            }
+        };
+        for line in text.lines() {
+            if line.starts_with("  ") {
+                writeln!(out, "{line}, !dbg !{location}").unwrap();
+            } else {
+                writeln!(out, "{line}").unwrap();
+            }
+        }
+    }
+
+    fn inst(&mut self, out: &mut String, func: &Function, inst: &Inst) {
+        match inst {
+            Inst::Const { dst, value } => {
+                let value = self.constant(out, value);
+                store(out, &value, *dst);
+            }
+            Inst::Copy { dst, src } => {
+                let value = self.load(out, *src);
+                store(out, &value, *dst);
+            }
+            Inst::Binary { dst, op, lhs, rhs } => {
+                let (l, r) = (self.load(out, *lhs), self.load(out, *rhs));
+                let value = match op {
+                    BinOp::Add | BinOp::Sub | BinOp::Mul => {
+                        let (a, b) = (self.unbox(out, &l, "%Int"), self.unbox(out, &r, "%Int"));
+                        let opcode = match op {
+                            BinOp::Add => "add",
+                            BinOp::Sub => "sub",
+                            _ => "mul",
+                        };
+                        let result = self.temp();
+                        writeln!(out, "  {result} = {opcode} i32 {a}, {b}").unwrap();
+                        self.call(out, "@cool_int", &[format!("i32 {result}")])
+                    }
+                    BinOp::Lt | BinOp::Le => {
+                        let (a, b) = (self.unbox(out, &l, "%Int"), self.unbox(out, &r, "%Int"));
+                        let predicate = if let BinOp::Lt = op { "slt" } else { "sle" };
+                        let result = self.temp();
+                        writeln!(out, "  {result} = icmp {predicate} i32 {a}, {b}").unwrap();
+                        self.bool(out, &result)
+                    }
+                    BinOp::Div => {
+                        self.call(out, "@cool_div", &[format!("ptr {l}"), format!("ptr {r}")])
+                    }
+                    BinOp::Eq => self.call(
+                        out,
+                        "@cool_equal",
+                        &[format!("ptr {l}"), format!("ptr {r}")],
+                    ),
+                };
+                store(out, &value, *dst);
+            }
+            Inst::Unary { dst, op, src } => {
+                let operand = self.load(out, *src);
+                let result = self.temp();
+                let value = match op {
+                    UnOp::Neg => {
+                        let a = self.unbox(out, &operand, "%Int");
+                        writeln!(out, "  {result} = sub i32 0, {a}").unwrap();
+                        self.call(out, "@cool_int", &[format!("i32 {result}")])
+                    }
+                    UnOp::Not => {
+                        let a = self.unbox(out, &operand, "%Bool");
+                        writeln!(out, "  {result} = icmp eq i32 {a}, 0").unwrap();
+                        self.bool(out, &result)
+                    }
+                };
+                store(out, &value, *dst);
+            }
+            Inst::IsVoid { dst, src } => {
+                let operand = self.load(out, *src);
+                let result = self.temp();
+                writeln!(out, "  {result} = icmp eq ptr {operand}, null").unwrap();
+                let value = self.bool(out, &result);
+                store(out, &value, *dst);
+            }
+            Inst::New {
+                dst,
+                class: Some(class),
+            } => {
+                let value = if self.module.class(*class).is_some() {
+                    self.call(out, &format!("@new_{class}"), &[])
+                } else {
+                    self.undefined(out, &format!("class {class}"))
+                };
+                store(out, &value, *dst);
+            }
+            Inst::New { dst, class: None } => {
+                let object = self.load(out, func.params[0]);
+                let class_field = self.temp();
+                let class = self.temp();
+                let new_field = self.temp();
+                let new = self.temp();
+                writeln!(
+                    out,
+                    "  {class_field} = getelementptr inbounds %obj, ptr {object}, i32 0, i32 0"
+                )
+                .unwrap();
+                writeln!(out, "  {class} = load ptr, ptr {class_field}").unwrap();
+                writeln!(
+                    out,
+                    "  {new_field} = getelementptr inbounds %cool_class, ptr {class}, i32 0, i32 3"
+                )
+                .unwrap();
+                writeln!(out, "  {new} = load ptr, ptr {new_field}").unwrap();
+                let value = self.call(out, &new, &[]);
+                store(out, &value, *dst);
+            }
+            Inst::GetAttr {
+                dst,
+                obj,
+                class,
+                name,
+            } => {
+                let value = match self.attribute(out, *obj, *class, *name) {
+                    Some(field) => {
+                        let value = self.temp();
+                        writeln!(out, "  {value} = load ptr, ptr {field}").unwrap();
+                        value
+                    }
+                    None => self.undefined(out, &format!("attribute {class}.{name}")),
+                };
+                store(out, &value, *dst);
+            }
+            Inst::SetAttr {
+                obj,
+                class,
+                name,
+                src,
+            } => match self.attribute(out, *obj, *class, *name) {
+                Some(field) => {
+                    let value = self.load(out, *src);
+                    writeln!(out, "  store ptr {value}, ptr {field}").unwrap();
+                }
+                None => {
+                    self.undefined(out, &format!("attribute {class}.{name}"));
+                }
+            },
+            Inst::Call {
+                dst,
+                recv,
+                target,
+                method,
+                args,
+            } => {
+                let receiver = self.load(out, *recv);
+                let mut operands = Vec::new();
+                for arg in args {
+                    operands.push(format!("ptr {}", self.load(out, *arg)));
+                }
+                let name = self.c_string(method);
+                let value = match target {
+                    Target::Virtual => match self.selectors.get(method) {
+                        Some(&selector) => {
+                            let callee = self.temp();
+                            writeln!(
+                                out,
+                                "  {callee} = call ptr @cool_method(ptr {receiver}, {SIZE_T} {selector}, ptr {name})"
+                            )
+                            .unwrap();
+                            operands.insert(0, format!("ptr {receiver}"));
+                            self.call(out, &callee, &operands)
+                        }
+                        None => self.undefined(out, &format!("method {method}")),
+                    },
+                    Target::Static(class) => match self.module.resolve_method(*class, *method) {
+                        Some(defining) => {
+                            let checked = self.call(
+                                out,
+                                "@cool_check",
+                                &[format!("ptr {receiver}"), format!("ptr {name}")],
+                            );
+                            operands.insert(0, format!("ptr {checked}"));
+                            let callee = format!("@{}", self.symbol(defining, *method));
+                            self.call(out, &callee, &operands)
+                        }
+                        None => self.undefined(out, &format!("method {class}.{method}")),
+                    },
+                };
+                store(out, &value, *dst);
+            }
+            Inst::Phi { .. } => unreachable!("phis are removed before code generation"),
+        }
+    }
+
+    fn terminator(&mut self, out: &mut String, term: &Terminator) {
+        match term {
+            Terminator::Jump(target) => writeln!(out, "  br label %{target}").unwrap(),
+            Terminator::Branch {
+                cond,
+                then_block,
+                else_block,
+            } => {
+                let value = self.load(out, *cond);
+                let flag = self.unbox(out, &value, "%Bool");
+                let taken = self.temp();
+                writeln!(out, "  {taken} = icmp ne i32 {flag}, 0").unwrap();
+                writeln!(
+                    out,
+                    "  br i1 {taken}, label %{then_block}, label %{else_block}"
+                )
+                .unwrap();
+            }
+            Terminator::Case { scrutinee, arms } => {
+                // Arms naming classes that don't exist can never match.
+                let classes: Vec<String> = arms
+                    .iter()
+                    .map(|arm| match self.module.class(arm.typ) {
+                        Some(_) => format!("ptr @class_{}", arm.typ),
+                        None => "ptr null".to_string(),
+                    })
+                    .collect();
+                let table = self.global();
+                let table_type = format!("[{} x ptr]", arms.len());
+                writeln!(
+                    self.globals,
+                    "{table} = private constant {table_type} [{}]",
+                    classes.join(", ")
+                )
+                .unwrap();
+                let value = self.load(out, *scrutinee);
+                let index = self.temp();
+                writeln!(
+                    out,
+                    "  {index} = call {SIZE_T} @cool_case(ptr {value}, ptr {table}, {SIZE_T} {})",
+                    arms.len()
+                )
+                .unwrap();
+                // `cool_case` only returns the index of an arm, so the first arm can
+                // double as the default.
+                match arms.split_first() {
+                    Some((first, rest)) => {
+                        let mut cases = String::new();
+                        for (i, arm) in rest.iter().enumerate() {
+                            write!(cases, " {SIZE_T} {}, label %{}", i + 1, arm.block).unwrap();
+                        }
+                        writeln!(
+                            out,
+                            "  switch {SIZE_T} {index}, label %{} [{cases} ]",
+                            first.block
+                        )
+                        .unwrap();
+                    }
+                    None => writeln!(out, "  unreachable").unwrap(),
+                }
+            }
+            Terminator::Return(value) => {
+                let value = self.load(out, *value);
+                writeln!(out, "  store ptr %caller, ptr @cool_stack").unwrap();
+                writeln!(out, "  ret ptr {value}").unwrap();
+            }
+            Terminator::Unreachable => writeln!(out, "  unreachable").unwrap(),
+        }
+    }
+
+    fn temp(&mut self) -> String {
+        self.next_temp += 1;
+        format!("%t{}", self.next_temp - 1)
+    }
+
+    fn global(&mut self) -> String {
+        self.next_global += 1;
+        format!("@.const.{}", self.next_global - 1)
+    }
+
+    fn load(&mut self, out: &mut String, r: Reg) -> String {
+        let value = self.temp();
+        writeln!(out, "  {value} = load ptr, ptr {}", reg(r)).unwrap();
+        value
+    }
+
+    /// Calls `callee`, which returns an object, with typed `args`.
+    fn call(&mut self, out: &mut String, callee: &str, args: &[String]) -> String {
+        let value = self.temp();
+        writeln!(out, "  {value} = call ptr {callee}({})", args.join(", ")).unwrap();
+        value
+    }
+
+    /// The `i32` in an `Int` or `Bool` (`typ`) object.
+    fn unbox(&mut self, out: &mut String, object: &str, typ: &str) -> String {
+        let field = self.temp();
+        let value = self.temp();
+        writeln!(
+            out,
+            "  {field} = getelementptr inbounds {typ}, ptr {object}, i32 0, i32 1"
+        )
+        .unwrap();
+        writeln!(out, "  {value} = load i32, ptr {field}").unwrap();
+        value
+    }
+
+    /// Boxes an `i1`.
+    fn bool(&mut self, out: &mut String, flag: &str) -> String {
+        let wide = self.temp();
+        writeln!(out, "  {wide} = zext i1 {flag} to i32").unwrap();
+        self.call(out, "@cool_bool", &[format!("i32 {wide}")])
+    }
+
+    /// A pointer to attribute `name` of `obj`, which is a `class` or a subclass of it.
+    fn attribute(
+        &mut self,
+        out: &mut String,
+        obj: Reg,
+        class: Symbol,
+        name: Symbol,
+    ) -> Option<String> {
+        let index = self
+            .module
+            .all_attributes(class)
+            .iter()
+            .position(|(_, attr)| attr.name == name)?;
+        let object = self.load(out, obj);
+        let field = self.temp();
+        writeln!(
+            out,
+            "  {field} = getelementptr inbounds %{class}, ptr {object}, i32 0, i32 {}",
+            index + 1
+        )
+        .unwrap();
+        Some(field)
+    }
+
+    /// Reports a name the program uses but never defines, returning void.
+    fn undefined(&mut self, out: &mut String, what: &str) -> String {
+        let message = self.c_string(&format!("undefined {what}"));
+        writeln!(out, "  call void (ptr, ...) @cool_error(ptr {message})").unwrap();
+        "null".to_string()
+    }
+
+    /// Points the frame at the site for the line `span` starts on, unless `current`
+    /// says it already does.
+    fn set_site(
+        &mut self,
+        out: &mut String,
+        func: &Function,
+        span: Span,
+        current: &mut Option<String>,
+    ) {
+        let site = self.site(func, span);
+        if current.as_ref() != Some(&site) {
+            writeln!(out, "  store ptr {site}, ptr %frame.site").unwrap();
+            *current = Some(site);
+        }
+    }
+
+    /// A `cool_site` naming `func` and the line `span` starts on, for backtraces.
+    fn site(&mut self, func: &Function, span: Span) -> String {
+        let file = self.sources.file(span.context);
+        let line = file.location(span.start).line;
+        if let Some(site) = self.sites.get(&(span.context, line)) {
+            return site.clone();
+        }
+        let function = self.c_string(&format!("{}.{}", func.class, func.method));
+        let file = self.c_string(file.name());
+        let name = self.global();
+        writeln!(
+            self.globals,
+            "{name} = private constant %cool_site {{ ptr {function}, ptr {file}, i32 {line} }}"
+        )
+        .unwrap();
+        self.sites.insert((span.context, line), name.clone());
+        name
+    }
+
+    /// An operand for `value`. Literals are statically allocated once per program.
+    fn constant(&mut self, out: &mut String, value: &Const) -> String {
+        match value {
+            Const::Void => return "null".to_string(),
+            Const::Bool(b) => {
+                return self.call(out, "@cool_bool", &[format!("i32 {}", *b as i32)]);
+            }
+            Const::Int(_) | Const::Str(_) => {}
+        }
+        if let Some(pointer) = self.constants.get(value) {
+            return pointer.clone();
+        }
+        let typ = if let Const::Int(_) = value {
+            "%Int"
+        } else {
+            "%String"
+        };
+        let fields = match value {
+            Const::Int(i) => format!("i32 {i}"),
+            Const::Str(s) => format!("i32 {}, ptr {}", s.len(), self.c_string(s)),
+            Const::Bool(_) | Const::Void => unreachable!(),
+        };
+        // Marked, like the C backend's constants, so the collector leaves it alone.
+        let name = self.global();
+        let cell = format!("{{ %cool_header, {typ} }}");
+        writeln!(
+            self.globals,
+            "{name} = private constant {cell} {{ %cool_header {{ ptr null, {SIZE_T} 1 }}, \
+             {typ} {{ ptr @class_{}, {fields} }} }}",
+            &typ[1..]
+        )
+        .unwrap();
+        let pointer = format!("getelementptr inbounds ({cell}, ptr {name}, i32 0, i32 1)");
+        self.constants.insert(value.clone(), pointer.clone());
+        pointer
+    }
+
+    /// A constant pointing at a null-terminated copy of `s`.
+    fn c_string(&mut self, s: &str) -> String {
+        match self.strings.get(s) {
+            Some(name) => name.clone(),
+            None => {
+                let name = self.global();
+                writeln!(
+                    self.globals,
+                    "{name} = private unnamed_addr constant [{} x i8] {}",
+                    s.len() + 1,
+                    llvm_string_literal(s)
+                )
+                .unwrap();
+                self.strings.insert(s.to_string(), name.clone());
+                name
+            }
+        }
+    }
+}
+
+fn store(out: &mut String, value: &str, r: Reg) {
+    writeln!(out, "  store ptr {value}, ptr {}", reg(r)).unwrap();
+}
+
+fn reg(r: Reg) -> String {
+    format!("%r{}", r.0)
+}
+
+/// A quoted metadata string.
+fn metadata_string(s: &str) -> String {
+    let mut literal = String::from("\"");
+    for byte in s.bytes() {
+        match byte {
+            b'"' | b'\\' => write!(literal, "\\{byte:02X}").unwrap(),
+            b' '..=b'~' => literal.push(byte as char),
+            _ => write!(literal, "\\{byte:02X}").unwrap(),
+        }
+    }
+    literal.push('"');
+    literal
+}
+
+/// A null-terminated `c"..."` array literal.
+fn llvm_string_literal(s: &str) -> String {
+    let mut literal = String::from("c\"");
+    for byte in s.bytes() {
+        match byte {
+            b'"' | b'\\' => write!(literal, "\\{byte:02X}").unwrap(),
+            b' '..=b'~' => literal.push(byte as char),
+            _ => write!(literal, "\\{byte:02X}").unwrap(),
+        }
+    }
+    literal.push_str("\\00\"");
+    literal
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use std::process::{Command, Stdio};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static NEXT_TEST_PROGRAM: AtomicUsize = AtomicUsize::new(0);
+
+    fn generate_for(src: &str, level: ir::OptLevel) -> String {
+        let mut sources = SourceMap::new();
+        let file = sources.add("test.cl", src);
+        let mut module = ir::lower(&parser::parse_file(src, file).unwrap());
+        ir::optimize(&mut module, level);
+        generate(&module, &sources)
+    }
+
+    fn compile(dir: &Path, name: &str, args: &[&str], tool: &str) -> Option<()> {
+        let status = Command::new(tool)
+            .current_dir(dir)
+            .args(args)
+            .stderr(Stdio::null())
+            .status()
+            .ok()?;
+        assert!(status.success(), "{tool} failed on {name}");
+        Some(())
+    }
+
+    /// Compiles the IR generated for `src` with `llc`, links it against the C runtime
+    /// and runs it, returning its exit status, stdout and stderr, or returns `None` when
+    /// there's no `llc` or C compiler to do it with.
+    fn compile_and_run(
+        src: &str,
+        level: ir::OptLevel,
+        input: &str,
+    ) -> Option<(Option<i32>, String, String)> {
+        let dir = std::env::temp_dir().join(format!("coolc-llvm-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let name = format!(
+            "program{}",
+            NEXT_TEST_PROGRAM.fetch_add(1, Ordering::Relaxed)
+        );
+        std::fs::write(dir.join(format!("{name}.ll")), generate_for(src, level)).unwrap();
+        std::fs::write(dir.join(format!("{name}-runtime.c")), crate::c::RUNTIME).unwrap();
+        let ll = format!("{name}.ll");
+        let flags = ["-relocation-model=pic", "-filetype=obj", &ll];
+        compile(
+            &dir,
+            &name,
+            &[opaque_pointer_flags("llc"), &flags].concat(),
+            "llc",
+        )?;
+        compile(
+            &dir,
+            &name,
+            &[
+                "-std=c11",
+                "-DCOOL_API=",
+                "-c",
+                &format!("{name}-runtime.c"),
+            ],
+            "cc",
+        )?;
+        compile(
+            &dir,
+            &name,
+            &[
+                "-o",
+                &name,
+                &format!("{name}.o"),
+                &format!("{name}-runtime.o"),
+            ],
+            "cc",
+        )?;
+
+        let mut child = Command::new(dir.join(&name))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        std::io::Write::write_all(child.stdin.as_mut().unwrap(), input.as_bytes()).unwrap();
+        let output = child.wait_with_output().unwrap();
+        Some((
+            output.status.code(),
+            String::from_utf8(output.stdout).unwrap(),
+            String::from_utf8(output.stderr).unwrap(),
+        ))
+    }
+
+    const PROGRAM: &str = r#"
+        class Animal {
+            name : String <- "animal";
+            speak() : String { "..." };
+            name() : String { name };
+        };
+        class Dog inherits Animal { speak() : String { "woof" }; };
+        class Main inherits IO {
+            count : Int;
+            describe(a : Object) : String {
+                case a of d : Dog => "dog"; x : Animal => "animal"; o : Object => "other"; esac
+            };
+            main() : Object { let a : Animal <- new Dog, b : Animal <- new Animal in {
+                out_string(a.speak().concat(b.speak()).concat("\n"));
+                out_string(a@Animal.speak().concat(a.name()).concat("\n"));
+                out_string(describe(a).concat(describe(b)).concat(describe(1)).concat("\n"));
+                while count < 10 loop count <- count + 1 pool;
+                out_int(count * 3 - 10 / 4).out_string(" ").out_int(~count);
+                out_string(if "ab".concat("c") = "abc" then " eq\t" else " ne\t" fi);
+                out_string(a.type_name().substr(1, 2));
+                out_string(a.copy().name());
+                out_string("\n");
+            } };
+        };
+    "#;
+
+    #[test]
+    fn test_string_literals() {
+        assert_eq!(llvm_string_literal("a\"b\\c"), r#"c"a\22b\5Cc\00""#);
+        assert_eq!(llvm_string_literal("\n1"), r#"c"\0A1\00""#);
+    }
+
+    #[test]
+    fn test_types_and_vtables() {
+        let ll = generate_for(PROGRAM, ir::OptLevel::O0);
+        // Inherited attributes come first.
+        assert!(ll.contains("%Dog = type { ptr, ptr }"));
+        assert!(ll.contains("%Main = type { ptr, ptr }"));
+        let vtable = ll
+            .lines()
+            .find(|l| l.starts_with("@vtable.Dog = internal constant"))
+            .unwrap();
+        assert!(vtable.contains("ptr @Dog_speak"));
+        assert!(vtable.contains("ptr @Animal_name"));
+        assert!(vtable.contains("ptr @Object_type_name"));
+        assert!(!vtable.contains("@IO_out_string"));
+        let descriptor = ll
+            .lines()
+            .find(|l| l.starts_with("@class_Dog = constant %cool_class"))
+            .unwrap();
+        assert!(descriptor.contains("ptr @class_Animal"));
+        assert!(descriptor.contains("getelementptr (%Dog, ptr null, i32 1)"));
+        assert!(ll.contains("getelementptr inbounds %Main, ptr "));
+        // No typed pointers are left for LLVM 17 and later to reject.
+        assert!(
+            ll.lines().all(|l| l.starts_with(';') || !l.contains('*')),
+            "{ll}"
+        );
+        // The tools fill in their own target.
+        assert!(!ll.contains("target triple") && !ll.contains("target datalayout"));
+        assert!(ll.contains(&format!("declare {SIZE_T} @cool_case(ptr, ptr, {SIZE_T})")));
+    }
+
+    #[test]
+    fn test_debug_info() {
+        let mut sources = SourceMap::new();
+        let file = sources.add(
+            "main.cl",
+            "class Main {\n  x : Int <- 1;\n\n  main() : Object {\n    {\n      x <- 2;\n      x;\n    }\n  };\n};",
+        );
+        let program = parser::parse_file(sources.file(file).src(), file).unwrap();
+        let ll = generate_with_debug_info(&ir::lower(&program), &sources);
+        assert!(ll.contains("!llvm.dbg.cu = !{!0}"));
+        assert!(ll.contains("!DIFile(filename: \"main.cl\""));
+        let define = ll
+            .lines()
+            .find(|l| l.starts_with("define internal ptr @\"Main.main\"("))
+            .unwrap();
+        let subprogram = define.split("!dbg ").nth(1).unwrap().trim_end_matches(" {");
+        let node = ll
+            .lines()
+            .find(|l| l.starts_with(&format!("{subprogram} = ")))
+            .unwrap();
+        assert!(node.contains("name: \"Main.main\""));
+        assert!(node.contains("line: 4,"));
+        // Every instruction carries a location, which the verifier insists on for calls.
+        let body = ll.split(define).nth(1).unwrap();
+        for line in body.lines().take_while(|l| *l != "}") {
+            assert!(
+                !line.starts_with("  ") || line.contains(", !dbg !"),
+                "{line}"
+            );
+        }
+        // The assignment and the read after it are on lines of their own.
+        let line_of = |instruction: &str| {
+            let text = body.lines().find(|l| l.contains(instruction)).unwrap();
+            let location = text.rsplit("!dbg ").next().unwrap();
+            let node = ll
+                .lines()
+                .find(|l| l.starts_with(&format!("{location} = ")))
+                .unwrap();
+            assert!(node.contains(&format!("scope: {subprogram}")), "{node}");
+            node.split("line: ")
+                .nth(1)
+                .unwrap()
+                .split(',')
+                .next()
+                .unwrap()
+        };
+        assert_eq!(line_of("%frame = alloca"), "4");
+        assert_eq!(line_of("store ptr %t2, ptr %t1"), "6");
+        assert_eq!(line_of("%t5 = load ptr, ptr %t4"), "7");
+        assert_eq!(line_of("ret ptr"), "4");
+        assert!(ll.contains("@\"Main._init\"(ptr %self)"));
+        assert!(ll.contains("[ptr @Object_abort, "));
+
+        let dir = std::env::temp_dir().join(format!("coolc-llvm-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("debug.ll"), &ll).unwrap();
+        let flags = [opaque_pointer_flags("llc"), &["-filetype=obj", "debug.ll"]].concat();
+        compile(&dir, "debug", &flags, "llc");
+    }
+
+    #[test]
+    fn test_compiled_program_output() {
+        let expected = "woof...\n...animal\ndoganimalother\n28 -10 eq\toganimal\n";
+        for level in [ir::OptLevel::O0, ir::OptLevel::O2] {
+            let Some((status, output, _)) = compile_and_run(PROGRAM, level, "") else {
+                return;
+            };
+            assert_eq!(status, Some(0));
+            assert_eq!(output, expected, "at {level}");
+        }
+    }
+
+    #[test]
+    fn test_compiled_runtime_error() {
+        let src = "class Main {\n  f(x : Object) : Object {\n    case x of s : String => s; esac\n  };\n\
+                   main() : Object {\n    f(1)\n  };\n};";
+        // At -O2, the case moves into `main` along with the rest of `f`.
+        for (level, backtrace) in [
+            (
+                ir::OptLevel::O0,
+                "#0  Main.f at test.cl:3\n#1  Main.main at test.cl:6\n",
+            ),
+            (ir::OptLevel::O2, "#0  Main.main at test.cl:3\n"),
+        ] {
+            let Some((status, output, errors)) = compile_and_run(src, level, "") else {
+                return;
+            };
+            assert_eq!(status, Some(4));
+            assert_eq!(output, "");
+            assert_eq!(
+                errors,
+                format!(
+                    "test.cl:3: runtime error: no match in case statement for class Int\n{backtrace}"
+                ),
+                "at {level}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_compiled_input() {
+        let src = r#"
+            class Main inherits IO {
+                main() : Object { {
+                    out_int(in_int() + 1);
+                    out_string(in_string().concat("!"));
+                } };
+            };
+        "#;
+        if let Some((status, output, _)) = compile_and_run(src, ir::OptLevel::O1, "41\nhi\n") {
+            assert_eq!(status, Some(0));
+            assert_eq!(output, "42hi!");
+        }
+    }
+
+    #[test]
+    fn test_compiled_garbage_collection() {
+        // Churns through more than the heap limit while a list stays reachable only
+        // through a register.
+        let src = r#"
+            class Node {
+                next : Node;
+                init(rest : Node) : Node { { next <- rest; self; } };
+                next() : Node { next };
+            };
+            class Main inherits IO {
+                main() : Object {
+                    let i : Int <- 0, list : Node, s : String, length : Int in {
+                        while i < 4000000 loop {
+                            s <- s.substr(0, 0).concat("0123456789012345678901234567890123456789");
+                            if i - i / 4000 * 4000 = 0 then list <- (new Node).init(list) else 0 fi;
+                            i <- i + 1;
+                        } pool;
+                        while not isvoid list loop { length <- length + 1; list <- list.next(); } pool;
+                        out_int(length).out_string(" ").out_int(s.length()).out_string("\n");
+                    }
+                };
+            };
+        "#;
+        for level in [ir::OptLevel::O0, ir::OptLevel::O2] {
+            let Some((status, output, errors)) = compile_and_run(src, level, "") else {
+                return;
+            };
+            assert_eq!((status, errors.as_str()), (Some(0), ""));
+            assert_eq!(output, "1000 40\n");
+        }
+    }
+}
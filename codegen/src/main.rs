@@ -0,0 +1,103 @@
+use clap::{Parser, ValueEnum};
+use ir::OptLevel;
+use lexer::source::SourceMap;
+use std::fs;
+use std::io::{self, Write};
+use std::process::ExitCode;
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Emit {
+    /// A single C11 file with its runtime bundled in
+    C,
+    /// Textual LLVM IR, to link against the C runtime
+    Llvm,
+    /// A WebAssembly text-format module importing its I/O from the host
+    Wat,
+    /// The same WebAssembly module in binary format, ready for a browser
+    Wasm,
+}
+
+#[derive(Parser)]
+#[command(name = "codegen")]
+#[command(about = "Compiles a Cool program to source for another toolchain")]
+#[command(version)]
+struct Args {
+    /// Input Cool (.cl) file
+    #[arg(value_name = "FILE")]
+    file: String,
+
+    /// Optimization level (0, 1 or 2)
+    #[arg(short = 'O', default_value = "0")]
+    opt_level: OptLevel,
+
+    /// What to generate
+    #[arg(long, value_enum, default_value = "c")]
+    emit: Emit,
+
+    /// Generate debug information mapping native code back to the COOL source
+    #[arg(short = 'g')]
+    debug: bool,
+
+    /// Write the output here instead of to stdout
+    #[arg(short = 'o', value_name = "OUTPUT")]
+    output: Option<String>,
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    let mut sources = SourceMap::new();
+    let file = match sources.load(&args.file) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("Error: failed to read '{}': {e}", args.file);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let program = match parser::parse_file(sources.file(file).src(), file) {
+        Ok(program) => program,
+        Err(errors) => {
+            for error in errors {
+                eprintln!("{}", parser::format_error(&error, &sources));
+            }
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let mut module = ir::lower(&program);
+    ir::optimize(&mut module, args.opt_level);
+    let code = match args.emit {
+        Emit::C if args.debug => {
+            codegen::c::generate_with_debug_info(&module, &sources).into_bytes()
+        }
+        Emit::C => codegen::c::generate(&module, &sources).into_bytes(),
+        Emit::Llvm if args.debug => {
+            codegen::llvm::generate_with_debug_info(&module, &sources).into_bytes()
+        }
+        Emit::Llvm => codegen::llvm::generate(&module, &sources).into_bytes(),
+        Emit::Wat => codegen::wasm::generate(&module, &sources).into_bytes(),
+        Emit::Wasm => match wat::parse_str(codegen::wasm::generate(&module, &sources)) {
+            Ok(wasm) => wasm,
+            Err(e) => {
+                eprintln!("Error: generated an invalid module: {e}");
+                return ExitCode::FAILURE;
+            }
+        },
+    };
+
+    match &args.output {
+        Some(path) => {
+            if let Err(e) = fs::write(path, code) {
+                eprintln!("Error: failed to write '{path}': {e}");
+                return ExitCode::FAILURE;
+            }
+        }
+        None => {
+            if let Err(e) = io::stdout().write_all(&code) {
+                eprintln!("Error: failed to write output: {e}");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,506 @@
+/* Runtime support bundled into every C file generated from a COOL program. Backends
+   that emit other languages link against it instead, built with -DCOOL_API= so its
+   functions are visible outside this file. */
+
+#include <stdarg.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#ifndef COOL_API
+#define COOL_API static
+#endif
+
+typedef struct cool_object *cool_value;
+typedef void (*cool_fn)(void);
+
+typedef struct cool_class {
+    const char *name;
+    const struct cool_class *parent;
+    size_t size;
+    cool_value (*new)(void);
+    /* Indexed by method selector; NULL where the class has no such method. */
+    const cool_fn *vtable;
+} cool_class;
+
+struct cool_object {
+    const cool_class *class;
+};
+
+struct Int {
+    const cool_class *class;
+    int32_t value;
+};
+
+struct Bool {
+    const cool_class *class;
+    int32_t value;
+};
+
+struct String {
+    const cool_class *class;
+    int32_t length;
+    const char *chars;
+};
+
+extern const cool_class class_Object, class_IO, class_Int, class_Bool, class_String;
+
+#define COOL_INT(v) (((struct Int *)(v))->value)
+#define COOL_BOOL(v) (((struct Bool *)(v))->value)
+#define COOL_STRING(v) ((struct String *)(v))
+
+/* Comes before every object, linking the heap's objects into the list the collector
+   sweeps. Statically allocated objects have one too, always marked, so the collector
+   neither writes to them nor frees them. */
+typedef struct cool_header {
+    struct cool_header *next;
+    size_t marked;
+} cool_header;
+
+#define COOL_HEADER(v) ((cool_header *)(v) - 1)
+#define COOL_STATIC_HEADER {NULL, 1}
+
+struct cool_static_Int {
+    cool_header header;
+    struct Int object;
+};
+
+struct cool_static_Bool {
+    cool_header header;
+    struct Bool object;
+};
+
+struct cool_static_String {
+    cool_header header;
+    struct String object;
+};
+
+static struct cool_static_Bool cool_true = {COOL_STATIC_HEADER, {&class_Bool, 1}};
+static struct cool_static_Bool cool_false = {COOL_STATIC_HEADER, {&class_Bool, 0}};
+
+/* A COOL method and a line in it, for backtraces. */
+typedef struct cool_site {
+    const char *function;
+    const char *file;
+    int32_t line;
+} cool_site;
+
+/* The methods being run, linked innermost first. Every generated function pushes one
+   on entry and pops it before it returns. Its site starts at the method's first line,
+   and moves to the line of each instruction that can fail or call before it runs. */
+typedef struct cool_frame {
+    const cool_site *site;
+    const struct cool_frame *caller;
+    /* The function's registers, which are the collector's roots. */
+    cool_value *const *roots;
+    size_t count;
+} cool_frame;
+
+COOL_API const cool_frame *cool_stack = NULL;
+
+/* Backtraces deeper than this show only their innermost and outermost frames. */
+#define COOL_MAX_SHOWN 20
+#define COOL_OUTERMOST_SHOWN 4
+
+/* Reports a runtime error at the innermost frame, with a backtrace, and exits. */
+COOL_API void cool_error(const char *format, ...) {
+    va_list args;
+    const cool_frame *frame;
+    size_t depth = 0, omitted, i;
+    fflush(stdout);
+    if (cool_stack) {
+        fprintf(stderr, "%s:%d: ", cool_stack->site->file, (int)cool_stack->site->line);
+    }
+    fputs("runtime error: ", stderr);
+    va_start(args, format);
+    vfprintf(stderr, format, args);
+    va_end(args);
+    fputc('\n', stderr);
+    for (frame = cool_stack; frame; frame = frame->caller) {
+        depth++;
+    }
+    omitted = depth > COOL_MAX_SHOWN ? depth - COOL_MAX_SHOWN : 0;
+    for (frame = cool_stack, i = 0; frame; frame = frame->caller, i++) {
+        if (omitted && i == COOL_MAX_SHOWN - COOL_OUTERMOST_SHOWN) {
+            fprintf(stderr, "... %zu frames omitted ...\n", omitted);
+        }
+        if (omitted && i >= COOL_MAX_SHOWN - COOL_OUTERMOST_SHOWN && i < depth - COOL_OUTERMOST_SHOWN) {
+            continue;
+        }
+        fprintf(stderr, "#%zu  %s at %s:%d\n", i, frame->site->function, frame->site->file,
+                (int)frame->site->line);
+    }
+    exit(4);
+}
+
+/* The collector is runtime/'s mark-sweep one. Allocation never collects. Instead,
+   generated code calls cool_safepoint where every live value is in a register, and once
+   enough has been allocated it marks what the frames' registers reach and frees the
+   rest. */
+#define COOL_MIN_THRESHOLD ((size_t)1024 * 1024)
+/* Live bytes beyond which the program stops with an error, like runtime/'s limit. */
+#define COOL_HEAP_LIMIT ((size_t)512 * 1024 * 1024)
+
+static cool_header *cool_heap = NULL;
+/* Bytes allocated and not yet freed, counting strings' characters. */
+static size_t cool_heap_bytes = 0;
+/* Heap size that triggers the next collection. */
+static size_t cool_threshold = COOL_MIN_THRESHOLD;
+/* Marked objects whose attributes haven't been marked yet. */
+static cool_value *cool_gray = NULL;
+static size_t cool_gray_count = 0, cool_gray_capacity = 0;
+
+COOL_API cool_value cool_alloc(const cool_class *class) {
+    cool_header *header = calloc(1, sizeof(cool_header) + class->size);
+    cool_value object;
+    if (!header) {
+        cool_error("out of memory");
+    }
+    header->next = cool_heap;
+    cool_heap = header;
+    cool_heap_bytes += sizeof(cool_header) + class->size;
+    object = (cool_value)(header + 1);
+    object->class = class;
+    return object;
+}
+
+/* Bytes `object` takes up on the heap. */
+static size_t cool_object_bytes(cool_value object) {
+    size_t bytes = sizeof(cool_header) + object->class->size;
+    if (object->class == &class_String) {
+        bytes += (size_t)COOL_STRING(object)->length + 1;
+    }
+    return bytes;
+}
+
+/* Every attribute of a class is a cool_value, following the class pointer. The basic
+   classes have none the collector needs to follow. */
+static size_t cool_attribute_count(const cool_class *class) {
+    if (class == &class_Int || class == &class_Bool || class == &class_String) {
+        return 0;
+    }
+    return (class->size - sizeof(struct cool_object)) / sizeof(cool_value);
+}
+
+static void cool_mark(cool_value value) {
+    cool_header *header;
+    if (!value) {
+        return;
+    }
+    header = COOL_HEADER(value);
+    if (header->marked) {
+        return;
+    }
+    header->marked = 1;
+    if (cool_gray_count == cool_gray_capacity) {
+        cool_gray_capacity = cool_gray_capacity ? cool_gray_capacity * 2 : 256;
+        cool_gray = realloc(cool_gray, cool_gray_capacity * sizeof(cool_value));
+        if (!cool_gray) {
+            cool_error("out of memory");
+        }
+    }
+    cool_gray[cool_gray_count++] = value;
+}
+
+static void cool_collect(void) {
+    const cool_frame *frame;
+    cool_header **link, *header;
+    cool_value object;
+    size_t i;
+    for (frame = cool_stack; frame; frame = frame->caller) {
+        for (i = 0; i < frame->count; i++) {
+            cool_mark(*frame->roots[i]);
+        }
+    }
+    while (cool_gray_count) {
+        object = cool_gray[--cool_gray_count];
+        for (i = 0; i < cool_attribute_count(object->class); i++) {
+            cool_mark(((cool_value *)((struct cool_object *)object + 1))[i]);
+        }
+    }
+    for (link = &cool_heap; (header = *link);) {
+        if (header->marked) {
+            header->marked = 0;
+            link = &header->next;
+            continue;
+        }
+        *link = header->next;
+        object = (cool_value)(header + 1);
+        cool_heap_bytes -= cool_object_bytes(object);
+        if (object->class == &class_String) {
+            free((char *)COOL_STRING(object)->chars);
+        }
+        free(header);
+    }
+    cool_threshold = cool_heap_bytes * 2 > COOL_MIN_THRESHOLD ? cool_heap_bytes * 2
+                                                              : COOL_MIN_THRESHOLD;
+    if (cool_heap_bytes > COOL_HEAP_LIMIT) {
+        cool_error("heap exhausted (%zu live bytes, limit %zu)", cool_heap_bytes,
+                   COOL_HEAP_LIMIT);
+    }
+}
+
+/* Generated functions call this once their frame is linked in, and at the top of every
+   loop, where nothing live is outside the registers. */
+COOL_API void cool_safepoint(void) {
+    if (cool_heap_bytes >= cool_threshold) {
+        cool_collect();
+    }
+}
+
+COOL_API cool_value cool_int(int32_t value) {
+    struct Int *object = (struct Int *)cool_alloc(&class_Int);
+    object->value = value;
+    return (cool_value)object;
+}
+
+COOL_API cool_value cool_bool(int value) {
+    return (cool_value)(value ? &cool_true.object : &cool_false.object);
+}
+
+/* Takes ownership of `chars`, which must hold `length` bytes plus a terminator and
+   come from malloc. */
+COOL_API cool_value cool_string_owned(char *chars, int32_t length) {
+    struct String *object = (struct String *)cool_alloc(&class_String);
+    object->length = length;
+    object->chars = chars;
+    cool_heap_bytes += (size_t)length + 1;
+    return (cool_value)object;
+}
+
+COOL_API cool_value cool_string(const char *chars, int32_t length) {
+    char *copy = malloc((size_t)length + 1);
+    if (!copy) {
+        cool_error("out of memory");
+    }
+    memcpy(copy, chars, (size_t)length);
+    copy[length] = '\0';
+    return cool_string_owned(copy, length);
+}
+
+/* Integer arithmetic wraps around like the interpreter's. */
+COOL_API cool_value cool_add(cool_value a, cool_value b) {
+    return cool_int((int32_t)((uint32_t)COOL_INT(a) + (uint32_t)COOL_INT(b)));
+}
+
+COOL_API cool_value cool_sub(cool_value a, cool_value b) {
+    return cool_int((int32_t)((uint32_t)COOL_INT(a) - (uint32_t)COOL_INT(b)));
+}
+
+COOL_API cool_value cool_mul(cool_value a, cool_value b) {
+    return cool_int((int32_t)((uint32_t)COOL_INT(a) * (uint32_t)COOL_INT(b)));
+}
+
+COOL_API cool_value cool_div(cool_value a, cool_value b) {
+    int32_t lhs = COOL_INT(a), rhs = COOL_INT(b);
+    if (rhs == 0) {
+        cool_error("division by zero");
+    }
+    if (lhs == INT32_MIN && rhs == -1) {
+        return cool_int(INT32_MIN);
+    }
+    return cool_int(lhs / rhs);
+}
+
+COOL_API cool_value cool_neg(cool_value a) {
+    return cool_int((int32_t)(0u - (uint32_t)COOL_INT(a)));
+}
+
+COOL_API cool_value cool_equal(cool_value a, cool_value b) {
+    if (a == b) {
+        return cool_bool(1);
+    }
+    if (!a || !b || a->class != b->class) {
+        return cool_bool(0);
+    }
+    if (a->class == &class_Int || a->class == &class_Bool) {
+        return cool_bool(COOL_INT(a) == COOL_INT(b));
+    }
+    if (a->class == &class_String) {
+        return cool_bool(COOL_STRING(a)->length == COOL_STRING(b)->length &&
+                         memcmp(COOL_STRING(a)->chars, COOL_STRING(b)->chars,
+                                (size_t)COOL_STRING(a)->length) == 0);
+    }
+    return cool_bool(0);
+}
+
+/* Returns `receiver`'s implementation of the method with selector `selector`. */
+COOL_API cool_fn cool_method(cool_value receiver, size_t selector, const char *method) {
+    cool_fn fn;
+    if (!receiver) {
+        cool_error("dispatch to void (%s)", method);
+    }
+    fn = receiver->class->vtable[selector];
+    if (!fn) {
+        cool_error("undefined method %s.%s", receiver->class->name, method);
+    }
+    return fn;
+}
+
+COOL_API cool_value cool_check(cool_value receiver, const char *method) {
+    if (!receiver) {
+        cool_error("dispatch to void (%s)", method);
+    }
+    return receiver;
+}
+
+/* Index of the arm whose class is the closest ancestor of `value`'s class. */
+COOL_API size_t cool_case(cool_value value, const cool_class *const *arms, size_t count) {
+    const cool_class *class;
+    size_t i;
+    if (!value) {
+        cool_error("match on void in case statement");
+    }
+    for (class = value->class; class; class = class->parent) {
+        for (i = 0; i < count; i++) {
+            if (arms[i] == class) {
+                return i;
+            }
+        }
+    }
+    cool_error("no match in case statement for class %s", value->class->name);
+    return 0;
+}
+
+COOL_API cool_value new_Object(void) {
+    return cool_alloc(&class_Object);
+}
+
+COOL_API cool_value new_IO(void) {
+    return cool_alloc(&class_IO);
+}
+
+COOL_API cool_value new_Int(void) {
+    return cool_int(0);
+}
+
+COOL_API cool_value new_Bool(void) {
+    return cool_bool(0);
+}
+
+COOL_API cool_value new_String(void) {
+    return cool_string("", 0);
+}
+
+COOL_API cool_value Object_abort(cool_value self) {
+    cool_error("abort called from class %s", self->class->name);
+    return self;
+}
+
+COOL_API cool_value Object_type_name(cool_value self) {
+    return cool_string(self->class->name, (int32_t)strlen(self->class->name));
+}
+
+COOL_API cool_value Object_copy(cool_value self) {
+    cool_value copy;
+    if (self->class == &class_Bool) {
+        return self;
+    }
+    /* Every string owns its characters. */
+    if (self->class == &class_String) {
+        return cool_string(COOL_STRING(self)->chars, COOL_STRING(self)->length);
+    }
+    copy = cool_alloc(self->class);
+    memcpy(copy, self, self->class->size);
+    return copy;
+}
+
+COOL_API cool_value IO_out_string(cool_value self, cool_value s) {
+    fwrite(COOL_STRING(s)->chars, 1, (size_t)COOL_STRING(s)->length, stdout);
+    return self;
+}
+
+COOL_API cool_value IO_out_int(cool_value self, cool_value i) {
+    printf("%d", (int)COOL_INT(i));
+    return self;
+}
+
+/* Reads a line from stdin without its line terminator. */
+COOL_API char *cool_read_line(int32_t *length) {
+    size_t capacity = 64, used = 0;
+    char *line = malloc(capacity);
+    int c;
+    fflush(stdout);
+    while (line && (c = getchar()) != EOF && c != '\n') {
+        if (used + 1 == capacity) {
+            capacity *= 2;
+            line = realloc(line, capacity);
+            if (!line) {
+                break;
+            }
+        }
+        line[used++] = (char)c;
+    }
+    if (!line) {
+        cool_error("out of memory");
+    }
+    if (used > 0 && line[used - 1] == '\r') {
+        used--;
+    }
+    line[used] = '\0';
+    *length = (int32_t)used;
+    return line;
+}
+
+COOL_API cool_value IO_in_string(cool_value self) {
+    int32_t length;
+    char *line = cool_read_line(&length);
+    (void)self;
+    return cool_string_owned(line, length);
+}
+
+/* Like the interpreter, anything that isn't an in-range integer reads as 0. */
+COOL_API cool_value IO_in_int(cool_value self) {
+    int32_t length;
+    char *line = cool_read_line(&length);
+    const char *p = line;
+    int negative = 0, digits = 0, valid;
+    int64_t value = 0;
+    (void)self;
+    while (*p == ' ' || *p == '\t' || *p == '\v' || *p == '\f' || *p == '\r') {
+        p++;
+    }
+    if (*p == '-') {
+        negative = 1;
+        p++;
+    }
+    for (; *p >= '0' && *p <= '9'; p++, digits++) {
+        value = value * 10 + (*p - '0');
+        if (value > (int64_t)INT32_MAX + 1) {
+            break;
+        }
+    }
+    if (negative) {
+        value = -value;
+    }
+    valid = digits > 0 && !(*p >= '0' && *p <= '9') && value <= INT32_MAX && value >= INT32_MIN;
+    free(line);
+    return cool_int(valid ? (int32_t)value : 0);
+}
+
+COOL_API cool_value String_length(cool_value self) {
+    return cool_int(COOL_STRING(self)->length);
+}
+
+COOL_API cool_value String_concat(cool_value self, cool_value s) {
+    int32_t length = COOL_STRING(self)->length + COOL_STRING(s)->length;
+    char *chars = malloc((size_t)length + 1);
+    if (!chars) {
+        cool_error("out of memory");
+    }
+    memcpy(chars, COOL_STRING(self)->chars, (size_t)COOL_STRING(self)->length);
+    memcpy(chars + COOL_STRING(self)->length, COOL_STRING(s)->chars,
+           (size_t)COOL_STRING(s)->length);
+    chars[length] = '\0';
+    return cool_string_owned(chars, length);
+}
+
+COOL_API cool_value String_substr(cool_value self, cool_value i, cool_value l) {
+    int32_t index = COOL_INT(i), length = COOL_INT(l);
+    if (index < 0 || length < 0 || (int64_t)index + length > COOL_STRING(self)->length) {
+        cool_error("substring out of range (index %d, length %d)", (int)index, (int)length);
+    }
+    return cool_string(COOL_STRING(self)->chars + index, length);
+}
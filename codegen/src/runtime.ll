@@ -0,0 +1,27 @@
+; Types and declarations shared by every LLVM module generated from a COOL program.
+; The definitions come from runtime.c, compiled with -DCOOL_API= and linked in; the
+; generated module defines the `class_*` descriptors it refers to. The generator fills
+; in the target's integer type for size_t.
+
+%cool_class = type { ptr, ptr, $size_t, ptr, ptr }
+%obj = type { ptr }
+%Int = type { ptr, i32 }
+%Bool = type { ptr, i32 }
+%String = type { ptr, i32, ptr }
+%cool_site = type { ptr, ptr, i32 }
+%cool_frame = type { ptr, ptr, ptr, $size_t }
+%cool_header = type { ptr, $size_t }
+
+@cool_stack = external global ptr
+
+declare void @cool_error(ptr, ...)
+declare ptr @cool_alloc(ptr)
+declare void @cool_safepoint()
+declare ptr @cool_int(i32)
+declare ptr @cool_bool(i32)
+declare ptr @cool_div(ptr, ptr)
+declare ptr @cool_equal(ptr, ptr)
+declare ptr @cool_method(ptr, $size_t, ptr)
+declare ptr @cool_check(ptr, ptr)
+declare $size_t @cool_case(ptr, ptr, $size_t)
+declare i32 @fflush(ptr)
@@ -0,0 +1,399 @@
+  ;; Runtime support bundled into every WebAssembly module generated from a COOL program.
+  ;;
+  ;; Objects start with the address of their class descriptor:
+  ;;   descriptor: name (String), parent descriptor, object size, vtable base, constructor
+  ;;   Int, Bool:  descriptor, value
+  ;;   String:     descriptor, length, bytes
+  ;;   others:     descriptor, one word per attribute
+  ;;   site:       function name (String), file name (String), line
+  ;; The generated code defines the memory, the descriptors and the `$heap`,
+  ;; `$heap_base`, `$sp`, `$stack_base`, `$class_*`, `$true`, `$false`, `$zero`,
+  ;; `$empty_string` and `$frames` globals.
+
+  (import "cool" "out_string" (func $host_out_string (param i32 i32)))
+  (import "cool" "out_int" (func $host_out_int (param i32)))
+  ;; Reads a line and returns its length; `read_line` then copies it into memory.
+  (import "cool" "in_string" (func $host_in_string (result i32)))
+  (import "cool" "read_line" (func $host_read_line (param i32)))
+  (import "cool" "in_int" (func $host_in_int (result i32)))
+  ;; Reports a runtime error; see `ERROR_*` in the host. Never returns normally.
+  (import "cool" "error" (func $host_error (param i32 i32 i32)))
+
+  (type $fn0 (func (result i32)))
+
+  ;; How many methods are running. `$frames` holds the site records of the outermost
+  ;; 1024, which is all of them under a host's usual call depth limit, for the host to
+  ;; print a backtrace from.
+  (global $depth (export "depth") (mut i32) (i32.const 0))
+
+  ;; The shadow stack holds every method's registers, so the collector can find and
+  ;; update them. It grows upwards from `$stack_base` to `$sp`.
+
+  ;; Pushes a frame of `$size` zeroed bytes onto the shadow stack, returning its address.
+  (func $push (param $size i32) (result i32)
+    (local $fp i32)
+    (local.set $fp (global.get $sp))
+    (global.set $sp (i32.add (local.get $fp) (local.get $size)))
+    (if (i32.gt_u (global.get $sp) (global.get $heap_base))
+      (then
+        (call $host_error (i32.const 8) (i32.const 0) (i32.const 0))
+        (unreachable)))
+    (memory.fill (local.get $fp) (i32.const 0) (local.get $size))
+    (local.get $fp))
+
+  (func $enter (param $site i32)
+    (if (i32.lt_u (global.get $depth) (i32.const 1024))
+      (then
+        (i32.store (i32.add (global.get $frames) (i32.shl (global.get $depth) (i32.const 2)))
+                   (local.get $site))))
+    (global.set $depth (i32.add (global.get $depth) (i32.const 1))))
+
+  ;; Moves the innermost frame to `$site`, a line of the same method.
+  (func $at (param $site i32)
+    (if (i32.le_u (global.get $depth) (i32.const 1024))
+      (then
+        (i32.store (i32.add (global.get $frames)
+                            (i32.shl (i32.sub (global.get $depth) (i32.const 1)) (i32.const 2)))
+                   (local.get $site)))))
+
+  ;; Pops the innermost frame, whose registers start at `$fp`.
+  (func $leave (param $fp i32)
+    (global.set $sp (local.get $fp))
+    (global.set $depth (i32.sub (global.get $depth) (i32.const 1))))
+
+  ;; The heap runs from `$heap_base` to `$heap`. Objects are bump-allocated, and
+  ;; `$safepoint` collects once the heap reaches `$next_collection`, which the first
+  ;; safepoint sets.
+  (global $next_collection (mut i32) (i32.const 0))
+
+  ;; Makes memory reach `$end`, failing with `$live` bytes in use once it would pass
+  ;; the 512 MiB limit.
+  (func $reserve (param $end i32) (param $live i32)
+    (local $limit i32)
+    (local.set $limit (i32.shl (memory.size) (i32.const 16)))
+    (if (i32.gt_u (local.get $end) (local.get $limit))
+      (then
+        (if (i32.eq
+              (memory.grow
+                (i32.shr_u (i32.add (i32.sub (local.get $end) (local.get $limit))
+                                    (i32.const 65535))
+                           (i32.const 16)))
+              (i32.const -1))
+          (then
+            (call $host_error (i32.const 7) (local.get $live) (i32.const 0x20000000))
+            (unreachable))))))
+
+  (func $alloc (param $size i32) (result i32)
+    (local $ptr i32)
+    (local $end i32)
+    (local.set $ptr (global.get $heap))
+    (local.set $end
+      (i32.and (i32.add (i32.add (local.get $ptr) (local.get $size)) (i32.const 3))
+               (i32.const -4)))
+    (call $reserve (local.get $end) (i32.sub (local.get $end) (global.get $heap_base)))
+    (global.set $heap (local.get $end))
+    (local.get $ptr))
+
+  ;; Methods call this on entry and at the head of every loop, where all their live
+  ;; values are in their frames. Runtime functions and constructors never do, so they
+  ;; can keep objects in locals while they allocate.
+  (func $safepoint
+    (if (i32.ge_u (global.get $heap) (global.get $next_collection))
+      (then (call $collect))))
+
+  ;; The size of the object at `$ptr`, rounded up as `$alloc` does.
+  (func $size (param $ptr i32) (result i32)
+    (local $class i32)
+    (local.set $class (i32.load (local.get $ptr)))
+    (if (i32.eq (local.get $class) (global.get $class_String))
+      (then
+        (return (i32.and (i32.add (i32.load offset=4 (local.get $ptr)) (i32.const 11))
+                         (i32.const -4)))))
+    (if (i32.or (i32.eq (local.get $class) (global.get $class_Int))
+                (i32.eq (local.get $class) (global.get $class_Bool)))
+      (then (return (i32.const 8))))
+    (i32.load offset=8 (local.get $class)))
+
+  ;; During a collection, live objects are copied from the heap to `$copy`, above it,
+  ;; then moved down to `$heap_base` in one go. `$forward` gives each copy the address
+  ;; it will end up at, `$shift` bytes below where it is copied to, so nothing needs
+  ;; updating after the move.
+  (global $copy (mut i32) (i32.const 0))
+  (global $shift (mut i32) (i32.const 0))
+
+  ;; Copies the object at `$ptr` out of the heap unless it already has been, returning
+  ;; its new address. A copied object's descriptor is replaced by its new address
+  ;; with the low bit set.
+  (func $forward (param $ptr i32) (result i32)
+    (local $class i32)
+    (local $size i32)
+    (local $new i32)
+    ;; Void and the data segment's objects stay put.
+    (if (i32.lt_u (local.get $ptr) (global.get $heap_base))
+      (then (return (local.get $ptr))))
+    (local.set $class (i32.load (local.get $ptr)))
+    (if (i32.and (local.get $class) (i32.const 1))
+      (then (return (i32.xor (local.get $class) (i32.const 1)))))
+    (local.set $size (call $size (local.get $ptr)))
+    (call $reserve (i32.add (global.get $copy) (local.get $size))
+                   (i32.sub (i32.add (global.get $copy) (local.get $size)) (global.get $heap)))
+    (memory.copy (global.get $copy) (local.get $ptr) (local.get $size))
+    (local.set $new (i32.sub (global.get $copy) (global.get $shift)))
+    (i32.store (local.get $ptr) (i32.or (local.get $new) (i32.const 1)))
+    (global.set $copy (i32.add (global.get $copy) (local.get $size)))
+    (local.get $new))
+
+  ;; Copies everything the shadow stack reaches, breadth first.
+  (func $collect
+    (local $ptr i32)
+    (local $end i32)
+    (local $class i32)
+    (local $live i32)
+    (global.set $copy (global.get $heap))
+    (global.set $shift (i32.sub (global.get $heap) (global.get $heap_base)))
+    (local.set $ptr (global.get $stack_base))
+    (block $done
+      (loop $root
+        (br_if $done (i32.ge_u (local.get $ptr) (global.get $sp)))
+        (i32.store (local.get $ptr) (call $forward (i32.load (local.get $ptr))))
+        (local.set $ptr (i32.add (local.get $ptr) (i32.const 4)))
+        (br $root)))
+    ;; Basic objects hold no references; the others' attributes follow their
+    ;; descriptor.
+    (local.set $ptr (global.get $heap))
+    (block $done
+      (loop $object
+        (br_if $done (i32.ge_u (local.get $ptr) (global.get $copy)))
+        (local.set $end (i32.add (local.get $ptr) (call $size (local.get $ptr))))
+        (local.set $class (i32.load (local.get $ptr)))
+        (if (i32.eqz (i32.or (i32.or (i32.eq (local.get $class) (global.get $class_Int))
+                                     (i32.eq (local.get $class) (global.get $class_Bool)))
+                             (i32.eq (local.get $class) (global.get $class_String))))
+          (then
+            (local.set $ptr (i32.add (local.get $ptr) (i32.const 4)))
+            (block $fields_done
+              (loop $field
+                (br_if $fields_done (i32.ge_u (local.get $ptr) (local.get $end)))
+                (i32.store (local.get $ptr) (call $forward (i32.load (local.get $ptr))))
+                (local.set $ptr (i32.add (local.get $ptr) (i32.const 4)))
+                (br $field)))))
+        (local.set $ptr (local.get $end))
+        (br $object)))
+    (local.set $live (i32.sub (global.get $copy) (global.get $heap)))
+    (memory.copy (global.get $heap_base) (global.get $heap) (local.get $live))
+    (global.set $heap (i32.add (global.get $heap_base) (local.get $live)))
+    ;; The next collection comes once the heap has doubled, or grown by 1 MiB if it
+    ;; is smaller than that.
+    (global.set $next_collection
+      (i32.add (global.get $heap)
+               (select (local.get $live) (i32.const 0x100000)
+                       (i32.gt_u (local.get $live) (i32.const 0x100000))))))
+
+  (func $int (param $value i32) (result i32)
+    (local $ptr i32)
+    (local.set $ptr (call $alloc (i32.const 8)))
+    (i32.store (local.get $ptr) (global.get $class_Int))
+    (i32.store offset=4 (local.get $ptr) (local.get $value))
+    (local.get $ptr))
+
+  (func $bool (param $value i32) (result i32)
+    (select (global.get $true) (global.get $false) (local.get $value)))
+
+  (func $string_alloc (param $length i32) (result i32)
+    (local $ptr i32)
+    (local.set $ptr (call $alloc (i32.add (i32.const 8) (local.get $length))))
+    (i32.store (local.get $ptr) (global.get $class_String))
+    (i32.store offset=4 (local.get $ptr) (local.get $length))
+    (local.get $ptr))
+
+  (func $copy_bytes (param $dst i32) (param $src i32) (param $count i32)
+    (memory.copy (local.get $dst) (local.get $src) (local.get $count)))
+
+  (func $equal (param $a i32) (param $b i32) (result i32)
+    (local $class i32)
+    (local $i i32)
+    (if (i32.eq (local.get $a) (local.get $b))
+      (then (return (global.get $true))))
+    (if (i32.or (i32.eqz (local.get $a)) (i32.eqz (local.get $b)))
+      (then (return (global.get $false))))
+    (local.set $class (i32.load (local.get $a)))
+    (if (i32.ne (local.get $class) (i32.load (local.get $b)))
+      (then (return (global.get $false))))
+    (if (i32.or (i32.eq (local.get $class) (global.get $class_Int))
+                (i32.eq (local.get $class) (global.get $class_Bool)))
+      (then
+        (return (call $bool (i32.eq (i32.load offset=4 (local.get $a))
+                                    (i32.load offset=4 (local.get $b)))))))
+    (if (i32.ne (local.get $class) (global.get $class_String))
+      (then (return (global.get $false))))
+    (if (i32.ne (i32.load offset=4 (local.get $a)) (i32.load offset=4 (local.get $b)))
+      (then (return (global.get $false))))
+    (block $done
+      (loop $next
+        (br_if $done (i32.ge_u (local.get $i) (i32.load offset=4 (local.get $a))))
+        (if (i32.ne (i32.load8_u offset=8 (i32.add (local.get $a) (local.get $i)))
+                    (i32.load8_u offset=8 (i32.add (local.get $b) (local.get $i))))
+          (then (return (global.get $false))))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $next)))
+    (global.get $true))
+
+  (func $div (param $a i32) (param $b i32) (result i32)
+    (local $divisor i32)
+    (local.set $divisor (i32.load offset=4 (local.get $b)))
+    (if (i32.eqz (local.get $divisor))
+      (then
+        (call $host_error (i32.const 4) (i32.const 0) (i32.const 0))
+        (unreachable)))
+    ;; i32.div_s traps on INT_MIN / -1, which wraps around everywhere else.
+    (if (i32.eq (local.get $divisor) (i32.const -1))
+      (then
+        (return (call $int (i32.sub (i32.const 0) (i32.load offset=4 (local.get $a)))))))
+    (call $int (i32.div_s (i32.load offset=4 (local.get $a)) (local.get $divisor))))
+
+  ;; Reports an error about the String object `$s`.
+  (func $string_error (param $code i32) (param $s i32)
+    (call $host_error (local.get $code)
+                      (i32.add (local.get $s) (i32.const 8))
+                      (i32.load offset=4 (local.get $s)))
+    (unreachable))
+
+  ;; The table index of `$receiver`'s implementation of the method with selector
+  ;; `$selector`; `$name` is the method's name for the error message.
+  (func $method (param $receiver i32) (param $selector i32) (param $name i32) (result i32)
+    (if (i32.eqz (local.get $receiver))
+      (then (call $string_error (i32.const 0) (local.get $name))))
+    (i32.add (i32.load offset=12 (i32.load (local.get $receiver))) (local.get $selector)))
+
+  (func $check (param $receiver i32) (param $name i32) (result i32)
+    (if (i32.eqz (local.get $receiver))
+      (then (call $string_error (i32.const 0) (local.get $name))))
+    (local.get $receiver))
+
+  (func $undefined (param $what i32) (result i32)
+    (call $string_error (i32.const 6) (local.get $what))
+    (unreachable))
+
+  ;; Index of the arm whose class is the closest ancestor of `$value`'s class, given
+  ;; the arms' descriptors in `$count` words at `$arms`.
+  (func $case (param $value i32) (param $arms i32) (param $count i32) (result i32)
+    (local $class i32)
+    (local $i i32)
+    (if (i32.eqz (local.get $value))
+      (then
+        (call $host_error (i32.const 1) (i32.const 0) (i32.const 0))
+        (unreachable)))
+    (local.set $class (i32.load (local.get $value)))
+    (block $no_match
+      (loop $ancestor
+        (br_if $no_match (i32.eqz (local.get $class)))
+        (local.set $i (i32.const 0))
+        (block $next_ancestor
+          (loop $arm
+            (br_if $next_ancestor (i32.ge_u (local.get $i) (local.get $count)))
+            (if (i32.eq (i32.load (i32.add (local.get $arms) (i32.shl (local.get $i) (i32.const 2))))
+                        (local.get $class))
+              (then (return (local.get $i))))
+            (local.set $i (i32.add (local.get $i) (i32.const 1)))
+            (br $arm)))
+        (local.set $class (i32.load offset=4 (local.get $class)))
+        (br $ancestor)))
+    (call $string_error (i32.const 2) (i32.load (i32.load (local.get $value))))
+    (unreachable))
+
+  (func $new.Object (result i32)
+    (local $ptr i32)
+    (local.set $ptr (call $alloc (i32.const 4)))
+    (i32.store (local.get $ptr) (global.get $class_Object))
+    (local.get $ptr))
+
+  (func $new.IO (result i32)
+    (local $ptr i32)
+    (local.set $ptr (call $alloc (i32.const 4)))
+    (i32.store (local.get $ptr) (global.get $class_IO))
+    (local.get $ptr))
+
+  (func $new.Int (result i32)
+    (global.get $zero))
+
+  (func $new.Bool (result i32)
+    (global.get $false))
+
+  (func $new.String (result i32)
+    (global.get $empty_string))
+
+  (func $Object.abort (param $self i32) (result i32)
+    (call $string_error (i32.const 5) (i32.load (i32.load (local.get $self))))
+    (unreachable))
+
+  (func $Object.type_name (param $self i32) (result i32)
+    (i32.load (i32.load (local.get $self))))
+
+  ;; Basic values are immutable, so only other objects need copying.
+  (func $Object.copy (param $self i32) (result i32)
+    (local $class i32)
+    (local $size i32)
+    (local $ptr i32)
+    (local.set $class (i32.load (local.get $self)))
+    (if (i32.or (i32.or (i32.eq (local.get $class) (global.get $class_Int))
+                        (i32.eq (local.get $class) (global.get $class_Bool)))
+                (i32.eq (local.get $class) (global.get $class_String)))
+      (then (return (local.get $self))))
+    (local.set $size (i32.load offset=8 (local.get $class)))
+    (local.set $ptr (call $alloc (local.get $size)))
+    (call $copy_bytes (local.get $ptr) (local.get $self) (local.get $size))
+    (local.get $ptr))
+
+  (func $IO.out_string (param $self i32) (param $s i32) (result i32)
+    (call $host_out_string (i32.add (local.get $s) (i32.const 8))
+                           (i32.load offset=4 (local.get $s)))
+    (local.get $self))
+
+  (func $IO.out_int (param $self i32) (param $i i32) (result i32)
+    (call $host_out_int (i32.load offset=4 (local.get $i)))
+    (local.get $self))
+
+  (func $IO.in_string (param $self i32) (result i32)
+    (local $s i32)
+    (local.set $s (call $string_alloc (call $host_in_string)))
+    (call $host_read_line (i32.add (local.get $s) (i32.const 8)))
+    (local.get $s))
+
+  (func $IO.in_int (param $self i32) (result i32)
+    (call $int (call $host_in_int)))
+
+  (func $String.length (param $self i32) (result i32)
+    (call $int (i32.load offset=4 (local.get $self))))
+
+  (func $String.concat (param $self i32) (param $s i32) (result i32)
+    (local $length i32)
+    (local $result i32)
+    (local.set $length (i32.load offset=4 (local.get $self)))
+    (local.set $result
+      (call $string_alloc (i32.add (local.get $length) (i32.load offset=4 (local.get $s)))))
+    (call $copy_bytes (i32.add (local.get $result) (i32.const 8))
+                      (i32.add (local.get $self) (i32.const 8))
+                      (local.get $length))
+    (call $copy_bytes (i32.add (i32.add (local.get $result) (i32.const 8)) (local.get $length))
+                      (i32.add (local.get $s) (i32.const 8))
+                      (i32.load offset=4 (local.get $s)))
+    (local.get $result))
+
+  (func $String.substr (param $self i32) (param $i i32) (param $l i32) (result i32)
+    (local $index i32)
+    (local $length i32)
+    (local $result i32)
+    (local.set $index (i32.load offset=4 (local.get $i)))
+    (local.set $length (i32.load offset=4 (local.get $l)))
+    (if (i32.or (i32.or (i32.lt_s (local.get $index) (i32.const 0))
+                        (i32.lt_s (local.get $length) (i32.const 0)))
+                (i32.gt_s (local.get $index)
+                          (i32.sub (i32.load offset=4 (local.get $self)) (local.get $length))))
+      (then
+        (call $host_error (i32.const 3) (local.get $index) (local.get $length))
+        (unreachable)))
+    (local.set $result (call $string_alloc (local.get $length)))
+    (call $copy_bytes (i32.add (local.get $result) (i32.const 8))
+                      (i32.add (i32.add (local.get $self) (i32.const 8)) (local.get $index))
+                      (local.get $length))
+    (local.get $result))
@@ -0,0 +1,822 @@
+//! Translates a [`Module`] into a WebAssembly text-format module.
+//!
+//! Values are `i32` addresses of boxed objects in linear memory, with 0 for void.
+//! Class descriptors and literals are laid out in a data segment, and objects are
+//! bump-allocated after it. All vtables share one function table: class `i` owns the
+//! `selectors` entries starting at `i * selectors`, and `call_indirect` dispatches
+//! through it. I/O goes through functions imported from the `cool` host module.
+//!
+//! Wasm only has structured control flow, so each function runs its CFG as a loop
+//! around a `br_table` on the current block's number.
+//!
+//! The runtime's copying collector has to find and update every live value, which it
+//! can't do in Wasm locals. So each function keeps its registers in a frame it pushes
+//! onto a shadow stack in linear memory, and calls `$safepoint`, where the collector may
+//! run, on entry and at the top of each loop.
+//!
+//! Each function records a site naming its method and line in the exported `frames`
+//! array while it runs, and the exported `depth` global counts them, so the host can
+//! print a backtrace when the program fails. The site moves to the line of each
+//! instruction that can fail or call before it runs.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use lexer::source::SourceMap;
+use lexer::{FileId, Symbol, sym};
+use parser::ast::Span;
+
+use crate::c::loop_headers;
+use ir::{BinOp, Const, Function, INIT_METHOD, Inst, Module, Reg, Target, Terminator, UnOp};
+
+/// Imports, memory, allocation and the basic classes' methods shared by every program.
+const RUNTIME: &str = include_str!("runtime.wat");
+
+/// Bytes in a class descriptor: name, parent, size, vtable base and constructor.
+const DESCRIPTOR_SIZE: u32 = 20;
+
+/// Entries in the `frames` array, as `$enter` in the runtime assumes.
+const MAX_RECORDED_FRAMES: u32 = 1024;
+
+/// Bytes reserved for the shadow stack, which overflows well past a host's usual call
+/// depth limit.
+const SHADOW_STACK_SIZE: u32 = 1 << 20;
+
+/// Returns the text of a module for `module` that exports `main`, which runs
+/// `(new Main).main()`, and its `memory`. Backtraces name the files of `sources` that
+/// the module's spans refer to.
+pub fn generate(module: &Module, sources: &SourceMap) -> String {
+    Generator::new(module, sources).generate()
+}
+
+struct Generator<'m> {
+    module: &'m Module,
+    /// Where the module's spans point.
+    sources: &'m SourceMap,
+    selectors: HashMap<Symbol, u32>,
+    /// The static data segment, which starts at address 0.
+    data: Vec<u8>,
+    strings: HashMap<String, u32>,
+    ints: HashMap<i32, u32>,
+    /// Site records, keyed by class, method, file and line.
+    sites: HashMap<(Symbol, Symbol, FileId, usize), u32>,
+    /// The largest number of parameters (`self` included) any call needs.
+    max_arity: usize,
+    /// The offset of each register in the current function's frame.
+    slots: HashMap<Reg, u32>,
+}
+
+impl<'m> Generator<'m> {
+    fn new(module: &'m Module, sources: &'m SourceMap) -> Self {
+        let mut selectors = HashMap::new();
+        for method in module.classes.iter().flat_map(|c| &c.methods) {
+            if method.name != INIT_METHOD {
+                let next = selectors.len() as u32;
+                selectors.entry(method.name).or_insert(next);
+            }
+        }
+        let mut generator = Generator {
+            module,
+            sources,
+            selectors,
+            // Address 0 is void.
+            data: vec![0; 8],
+            strings: HashMap::new(),
+            ints: HashMap::new(),
+            sites: HashMap::new(),
+            max_arity: 1,
+            slots: HashMap::new(),
+        };
+        // Descriptors come first so their addresses are known up front.
+        let descriptors = DESCRIPTOR_SIZE as usize * module.classes.len();
+        generator.data.resize(generator.data.len() + descriptors, 0);
+        generator
+    }
+
+    fn descriptor(&self, class: Symbol) -> Option<u32> {
+        let index = self.module.classes.iter().position(|c| c.name == class)?;
+        Some(8 + DESCRIPTOR_SIZE * index as u32)
+    }
+
+    fn generate(mut self) -> String {
+        let mut bodies = String::new();
+        for func in &self.module.functions {
+            self.function(&mut bodies, func);
+        }
+        for class in self.module.classes.iter().filter(|c| !c.builtin) {
+            self.constructor(&mut bodies, class.name);
+        }
+        self.main(&mut bodies);
+
+        let classes = self.module.classes.len() as u32;
+        let selectors = self.selectors.len() as u32;
+        let mut elems = String::new();
+        for (index, class) in self.module.classes.iter().enumerate() {
+            let index = index as u32;
+            let name = self.string(&class.name);
+            let parent = class.parent.and_then(|p| self.descriptor(p)).unwrap_or(0);
+            let size = match class.name {
+                sym::INT | sym::BOOL => 8,
+                // Strings vary in size, which `$size` works out from their length.
+                sym::STRING => 0,
+                _ => 4 + 4 * self.module.all_attributes(class.name).len() as u32,
+            };
+            let constructor = classes * selectors + index;
+            let descriptor = self.descriptor(class.name).unwrap();
+            for (offset, word) in [name, parent, size, index * selectors, constructor]
+                .into_iter()
+                .enumerate()
+            {
+                self.patch(descriptor + 4 * offset as u32, word);
+            }
+
+            let mut slots: Vec<(Symbol, u32)> =
+                self.selectors.iter().map(|(m, s)| (*m, *s)).collect();
+            slots.sort_by_key(|(_, s)| *s);
+            for (method, slot) in slots {
+                if let Some(defining) = self.module.resolve_method(class.name, method) {
+                    writeln!(
+                        elems,
+                        "  (elem (i32.const {}) func ${defining}.{method})",
+                        index * selectors + slot
+                    )
+                    .unwrap();
+                }
+            }
+            writeln!(
+                elems,
+                "  (elem (i32.const {constructor}) func $new.{})",
+                class.name
+            )
+            .unwrap();
+        }
+
+        let bool_class = self.descriptor(sym::BOOL).unwrap();
+        let false_addr = self.words(&[bool_class, 0]);
+        let true_addr = self.words(&[bool_class, 1]);
+        let zero = self.int(0);
+        let empty_string = self.string("");
+        // The frames and then the shadow stack go between the data segment and the heap.
+        let frames = (self.data.len() as u32).next_multiple_of(8);
+        let stack_base = frames + 4 * MAX_RECORDED_FRAMES;
+        let heap_base = stack_base + SHADOW_STACK_SIZE;
+
+        let mut out = String::from("(module\n");
+        out.push_str(RUNTIME);
+        out.push('\n');
+        for arity in 1..=self.max_arity {
+            let params = vec!["i32"; arity].join(" ");
+            writeln!(
+                out,
+                "  (type $fn{arity} (func (param {params}) (result i32)))"
+            )
+            .unwrap();
+        }
+        // 8192 pages is 512 MiB, the interpreter's default heap limit.
+        writeln!(
+            out,
+            "  (memory (export \"memory\") {} 8192)",
+            heap_base.div_ceil(0x10000)
+        )
+        .unwrap();
+        writeln!(out, "  (global $heap_base i32 (i32.const {heap_base}))").unwrap();
+        writeln!(out, "  (global $heap (mut i32) (i32.const {heap_base}))").unwrap();
+        writeln!(out, "  (global $stack_base i32 (i32.const {stack_base}))").unwrap();
+        writeln!(out, "  (global $sp (mut i32) (i32.const {stack_base}))").unwrap();
+        writeln!(
+            out,
+            "  (global $frames (export \"frames\") i32 (i32.const {frames}))"
+        )
+        .unwrap();
+        for class in &self.module.classes {
+            writeln!(
+                out,
+                "  (global $class_{} i32 (i32.const {}))",
+                class.name,
+                self.descriptor(class.name).unwrap()
+            )
+            .unwrap();
+        }
+        writeln!(out, "  (global $false i32 (i32.const {false_addr}))").unwrap();
+        writeln!(out, "  (global $true i32 (i32.const {true_addr}))").unwrap();
+        writeln!(out, "  (global $zero i32 (i32.const {zero}))").unwrap();
+        writeln!(
+            out,
+            "  (global $empty_string i32 (i32.const {empty_string}))"
+        )
+        .unwrap();
+        writeln!(out, "  (data (i32.const 0) \"{}\")", escape(&self.data)).unwrap();
+        writeln!(out, "  (table {} funcref)", classes * selectors + classes).unwrap();
+        out.push_str(&elems);
+        out.push('\n');
+        out.push_str(&bodies);
+        out.push_str(")\n");
+        out
+    }
+
+    fn main(&mut self, out: &mut String) {
+        writeln!(out, "  (func $main (export \"main\")").unwrap();
+        match self
+            .module
+            .resolve_method(sym::MAIN_CLASS, sym::MAIN_METHOD)
+        {
+            Some(defining) if self.module.class(sym::MAIN_CLASS).is_some() => {
+                writeln!(out, "    (drop (call ${defining}.main (call $new.Main))))").unwrap();
+            }
+            _ => {
+                let undefined = self.undefined("method Main.main");
+                writeln!(out, "    (drop {undefined}))").unwrap();
+            }
+        }
+    }
+
+    /// `$new.C` allocates a `C` with every attribute at its default value and runs its
+    /// initializer.
+    fn constructor(&mut self, out: &mut String, class: Symbol) {
+        let attributes = self.module.all_attributes(class);
+        writeln!(out, "  (func $new.{class} (result i32)").unwrap();
+        writeln!(out, "    (local $ptr i32)").unwrap();
+        writeln!(
+            out,
+            "    (local.set $ptr (call $alloc (i32.const {})))",
+            4 + 4 * attributes.len()
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "    (i32.store (local.get $ptr) (global.get $class_{class}))"
+        )
+        .unwrap();
+        // The memory may hold objects from before the last collection, so even void
+        // attributes need storing.
+        for (slot, (_, attr)) in attributes.iter().enumerate() {
+            let default = match attr.typ {
+                sym::INT => "(global.get $zero)",
+                sym::BOOL => "(global.get $false)",
+                sym::STRING => "(global.get $empty_string)",
+                _ => "(i32.const 0)",
+            };
+            writeln!(
+                out,
+                "    (i32.store offset={} (local.get $ptr) {default})",
+                4 + 4 * slot
+            )
+            .unwrap();
+        }
+        if self.module.function(class, INIT_METHOD).is_some() {
+            writeln!(out, "    (call ${class}.{INIT_METHOD} (local.get $ptr)))\n").unwrap();
+        } else {
+            writeln!(out, "    (local.get $ptr))\n").unwrap();
+        }
+    }
+
+    fn function(&mut self, out: &mut String, func: &Function) {
+        let mut params = String::new();
+        for param in &func.params {
+            write!(params, " (param {} i32)", reg(*param)).unwrap();
+        }
+        writeln!(
+            out,
+            "  (func ${}.{}{params} (result i32)",
+            func.class, func.method
+        )
+        .unwrap();
+        let mut regs: Vec<Reg> = func
+            .blocks
+            .iter()
+            .flat_map(|b| b.insts().flat_map(|i| i.dst().into_iter().chain(i.uses())))
+            .chain(func.params.iter().copied())
+            .collect();
+        regs.sort();
+        regs.dedup();
+        self.slots = regs
+            .iter()
+            .enumerate()
+            .map(|(slot, r)| (*r, 4 * slot as u32))
+            .collect();
+        writeln!(out, "    (local $fp i32) (local $pc i32)").unwrap();
+        writeln!(
+            out,
+            "    (local.set $fp (call $push (i32.const {})))",
+            4 * regs.len()
+        )
+        .unwrap();
+        for param in &func.params {
+            let line = self.set(*param, format!("(local.get {})", reg(*param)));
+            writeln!(out, "    {line}").unwrap();
+        }
+        let site = self.site(func, func.span);
+        writeln!(out, "    (call $enter (i32.const {site}))").unwrap();
+        writeln!(out, "    (call $safepoint)").unwrap();
+        writeln!(out, "    (local.set $pc (i32.const {}))", func.entry.0).unwrap();
+
+        // Block `i`'s code follows the end of the `i`th innermost `block`, so the
+        // `br_table` jumps straight to it.
+        writeln!(out, "    (loop $dispatch").unwrap();
+        for id in func.block_ids().collect::<Vec<_>>().iter().rev() {
+            writeln!(out, "    (block ${id}").unwrap();
+        }
+        let labels: Vec<String> = func.block_ids().map(|id| format!("${id}")).collect();
+        writeln!(
+            out,
+            "      (br_table {} (local.get $pc)))",
+            labels.join(" ")
+        )
+        .unwrap();
+        let loops = loop_headers(func);
+        for id in func.block_ids() {
+            writeln!(out, "      ;; {id}").unwrap();
+            if loops.contains(&id) {
+                writeln!(out, "      (call $safepoint)").unwrap();
+            }
+            let block = func.block(id);
+            // Only what can fail or call needs the frame's line to be up to date.
+            let mut site = None;
+            for stmt in &block.stmts {
+                if stmt.inst.has_side_effects() {
+                    self.set_site(out, func, stmt.span, &mut site);
+                }
+                let line = self.inst(func, &stmt.inst);
+                writeln!(out, "      {line}").unwrap();
+            }
+            if let Terminator::Case { .. } = block.term {
+                self.set_site(out, func, block.term_span, &mut site);
+            }
+            let line = self.terminator(&block.term);
+            writeln!(out, "      {line})").unwrap();
+        }
+        writeln!(out, "    (unreachable))\n").unwrap();
+    }
+
+    fn inst(&mut self, func: &Function, inst: &Inst) -> String {
+        match inst {
+            Inst::Const { dst, value } => {
+                let value = match value {
+                    Const::Int(i) => format!("(i32.const {})", self.int(*i)),
+                    Const::Bool(true) => "(global.get $true)".to_string(),
+                    Const::Bool(false) => "(global.get $false)".to_string(),
+                    Const::Str(s) => format!("(i32.const {})", self.string(s)),
+                    Const::Void => "(i32.const 0)".to_string(),
+                };
+                self.set(*dst, value)
+            }
+            Inst::Copy { dst, src } => self.set(*dst, self.get(*src)),
+            Inst::Binary { dst, op, lhs, rhs } => {
+                let (l, r) = (self.get(*lhs), self.get(*rhs));
+                let (li, ri) = (self.unbox(*lhs), self.unbox(*rhs));
+                let value = match op {
+                    BinOp::Add => format!("(call $int (i32.add {li} {ri}))"),
+                    BinOp::Sub => format!("(call $int (i32.sub {li} {ri}))"),
+                    BinOp::Mul => format!("(call $int (i32.mul {li} {ri}))"),
+                    BinOp::Div => format!("(call $div {l} {r})"),
+                    BinOp::Lt => format!("(call $bool (i32.lt_s {li} {ri}))"),
+                    BinOp::Le => format!("(call $bool (i32.le_s {li} {ri}))"),
+                    BinOp::Eq => format!("(call $equal {l} {r})"),
+                };
+                self.set(*dst, value)
+            }
+            Inst::Unary { dst, op, src } => {
+                let value = match op {
+                    UnOp::Neg => {
+                        format!("(call $int (i32.sub (i32.const 0) {}))", self.unbox(*src))
+                    }
+                    UnOp::Not => format!("(call $bool (i32.eqz {}))", self.unbox(*src)),
+                };
+                self.set(*dst, value)
+            }
+            Inst::IsVoid { dst, src } => {
+                self.set(*dst, format!("(call $bool (i32.eqz {}))", self.get(*src)))
+            }
+            Inst::New {
+                dst,
+                class: Some(class),
+            } => {
+                let value = if self.module.class(*class).is_some() {
+                    format!("(call $new.{class})")
+                } else {
+                    self.undefined(&format!("class {class}"))
+                };
+                self.set(*dst, value)
+            }
+            Inst::New { dst, class: None } => self.set(
+                *dst,
+                format!(
+                    "(call_indirect (type $fn0) (i32.load offset=16 (i32.load {})))",
+                    self.get(func.params[0])
+                ),
+            ),
+            Inst::GetAttr {
+                dst,
+                obj,
+                class,
+                name,
+            } => {
+                let value = match self.field_offset(*class, *name) {
+                    Some(offset) => format!("(i32.load offset={offset} {})", self.get(*obj)),
+                    None => self.undefined(&format!("attribute {class}.{name}")),
+                };
+                self.set(*dst, value)
+            }
+            Inst::SetAttr {
+                obj,
+                class,
+                name,
+                src,
+            } => match self.field_offset(*class, *name) {
+                Some(offset) => format!(
+                    "(i32.store offset={offset} {} {})",
+                    self.get(*obj),
+                    self.get(*src)
+                ),
+                None => format!(
+                    "(drop {})",
+                    self.undefined(&format!("attribute {class}.{name}"))
+                ),
+            },
+            Inst::Call {
+                dst,
+                recv,
+                target,
+                method,
+                args,
+            } => {
+                let arity = args.len() + 1;
+                self.max_arity = self.max_arity.max(arity);
+                let args: Vec<String> = args.iter().map(|a| self.get(*a)).collect();
+                let args = args.join(" ");
+                let name = self.string(method);
+                let value = match target {
+                    Target::Virtual => match self.selectors.get(method) {
+                        Some(selector) => format!(
+                            "(call_indirect (type $fn{arity}) {} {args} (call $method {} (i32.const {selector}) (i32.const {name})))",
+                            self.get(*recv),
+                            self.get(*recv)
+                        ),
+                        None => self.undefined(&format!("method {method}")),
+                    },
+                    Target::Static(class) => match self.module.resolve_method(*class, *method) {
+                        Some(defining) => format!(
+                            "(call ${defining}.{method} (call $check {} (i32.const {name})) {args})",
+                            self.get(*recv)
+                        ),
+                        None => self.undefined(&format!("method {class}.{method}")),
+                    },
+                };
+                self.set(*dst, value)
+            }
+            Inst::Phi { .. } => unreachable!("phis are removed before code generation"),
+        }
+    }
+
+    fn terminator(&mut self, term: &Terminator) -> String {
+        match term {
+            Terminator::Jump(target) => {
+                format!("(local.set $pc (i32.const {})) (br $dispatch)", target.0)
+            }
+            Terminator::Branch {
+                cond,
+                then_block,
+                else_block,
+            } => format!(
+                "(local.set $pc (select (i32.const {}) (i32.const {}) {})) (br $dispatch)",
+                then_block.0,
+                else_block.0,
+                self.unbox(*cond)
+            ),
+            Terminator::Case { scrutinee, arms } => {
+                // The arms' descriptors followed by their blocks. Arms naming classes
+                // that don't exist can never match.
+                let mut words: Vec<u32> = arms
+                    .iter()
+                    .map(|arm| self.descriptor(arm.typ).unwrap_or(0))
+                    .collect();
+                words.extend(arms.iter().map(|arm| arm.block.0 as u32));
+                let table = self.words(&words);
+                format!(
+                    "(local.set $pc (i32.load offset={} (i32.shl (call $case {} (i32.const {table}) (i32.const {})) (i32.const 2)))) (br $dispatch)",
+                    table + 4 * arms.len() as u32,
+                    self.get(*scrutinee),
+                    arms.len()
+                )
+            }
+            Terminator::Return(value) => format!(
+                "(call $leave (local.get $fp)) (return {})",
+                self.get(*value)
+            ),
+            Terminator::Unreachable => "(unreachable)".to_string(),
+        }
+    }
+
+    fn field_offset(&self, class: Symbol, name: Symbol) -> Option<usize> {
+        let slot = self
+            .module
+            .all_attributes(class)
+            .iter()
+            .position(|(_, attr)| attr.name == name)?;
+        Some(4 + 4 * slot)
+    }
+
+    /// Records the site for the line `span` starts on as the current frame's, unless
+    /// `current` says it already is.
+    fn set_site(
+        &mut self,
+        out: &mut String,
+        func: &Function,
+        span: Span,
+        current: &mut Option<u32>,
+    ) {
+        let site = self.site(func, span);
+        if *current != Some(site) {
+            writeln!(out, "      (call $at (i32.const {site}))").unwrap();
+            *current = Some(site);
+        }
+    }
+
+    /// A site record naming `func` and the line `span` starts on, returning its address.
+    fn site(&mut self, func: &Function, span: Span) -> u32 {
+        let file = self.sources.file(span.context);
+        let line = file.location(span.start).line;
+        let key = (func.class, func.method, span.context, line);
+        if let Some(&site) = self.sites.get(&key) {
+            return site;
+        }
+        let function = self.string(&format!("{}.{}", func.class, func.method));
+        let file = self.string(file.name());
+        let site = self.words(&[function, file, line as u32]);
+        self.sites.insert(key, site);
+        site
+    }
+
+    /// An expression that fails at runtime, for names the program uses but never defines.
+    fn undefined(&mut self, what: &str) -> String {
+        format!("(call $undefined (i32.const {}))", self.string(what))
+    }
+
+    /// Loads `r` from the current frame.
+    fn get(&self, r: Reg) -> String {
+        format!("(i32.load offset={} (local.get $fp))", self.slots[&r])
+    }
+
+    fn set(&self, dst: Reg, value: String) -> String {
+        format!(
+            "(i32.store offset={} (local.get $fp) {value})",
+            self.slots[&dst]
+        )
+    }
+
+    /// The value inside a boxed `Int` or `Bool`.
+    fn unbox(&self, r: Reg) -> String {
+        format!("(i32.load offset=4 {})", self.get(r))
+    }
+
+    fn patch(&mut self, addr: u32, word: u32) {
+        let addr = addr as usize;
+        self.data[addr..addr + 4].copy_from_slice(&word.to_le_bytes());
+    }
+
+    /// Appends `words` to the data segment, returning their address.
+    fn words(&mut self, words: &[u32]) -> u32 {
+        let addr = self.data.len() as u32;
+        for word in words {
+            self.data.extend_from_slice(&word.to_le_bytes());
+        }
+        addr
+    }
+
+    fn int(&mut self, value: i32) -> u32 {
+        if let Some(addr) = self.ints.get(&value) {
+            return *addr;
+        }
+        let class = self.descriptor(sym::INT).unwrap();
+        let addr = self.words(&[class, value as u32]);
+        self.ints.insert(value, addr);
+        addr
+    }
+
+    fn string(&mut self, s: &str) -> u32 {
+        if let Some(addr) = self.strings.get(s) {
+            return *addr;
+        }
+        let class = self.descriptor(sym::STRING).unwrap();
+        let addr = self.words(&[class, s.len() as u32]);
+        self.data.extend_from_slice(s.as_bytes());
+        self.data.resize(self.data.len().next_multiple_of(4), 0);
+        self.strings.insert(s.to_string(), addr);
+        addr
+    }
+}
+
+fn reg(r: Reg) -> String {
+    format!("$r{}", r.0)
+}
+
+fn escape(bytes: &[u8]) -> String {
+    let mut escaped = String::new();
+    for &byte in bytes {
+        match byte {
+            b'"' | b'\\' => write!(escaped, "\\{}", byte as char).unwrap(),
+            b' '..=b'~' => escaped.push(byte as char),
+            _ => write!(escaped, "\\{byte:02x}").unwrap(),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use runtime::RuntimeError;
+    use wasm_host::RunError;
+
+    fn run(src: &str, level: ir::OptLevel, input: &str) -> (Result<(), RunError>, String) {
+        let mut sources = SourceMap::new();
+        let file = sources.add("test.cl", src);
+        let mut module = ir::lower(&parser::parse_file(src, file).unwrap());
+        ir::optimize(&mut module, level);
+        let wat = generate(&module, &sources);
+        let input = std::io::Cursor::new(input.as_bytes().to_vec());
+        let (result, output) = wasm_host::run(wat.as_bytes(), input, Vec::new());
+        (result, String::from_utf8(output).unwrap())
+    }
+
+    const PROGRAM: &str = r#"
+        class Animal {
+            name : String <- "animal";
+            speak() : String { "..." };
+            name() : String { name };
+        };
+        class Dog inherits Animal { speak() : String { "woof" }; };
+        class Main inherits IO {
+            count : Int;
+            describe(a : Object) : String {
+                case a of d : Dog => "dog"; x : Animal => "animal"; o : Object => "other"; esac
+            };
+            main() : Object { let a : Animal <- new Dog, b : Animal <- new Animal in {
+                out_string(a.speak().concat(b.speak()).concat("\n"));
+                out_string(a@Animal.speak().concat(a.name()).concat("\n"));
+                out_string(describe(a).concat(describe(b)).concat(describe(1)).concat("\n"));
+                while count < 10 loop count <- count + 1 pool;
+                out_int(count * 3 - 10 / 4).out_string(" ").out_int(~count);
+                out_string(if "ab".concat("c") = "abc" then " eq\t" else " ne\t" fi);
+                out_string(a.type_name().substr(1, 2));
+                out_string(a.copy().name());
+                out_string("\n");
+            } };
+        };
+    "#;
+
+    #[test]
+    fn test_program_output() {
+        let expected = "woof...\n...animal\ndoganimalother\n28 -10 eq\toganimal\n";
+        for level in [ir::OptLevel::O0, ir::OptLevel::O1, ir::OptLevel::O2] {
+            let (result, output) = run(PROGRAM, level, "");
+            assert!(result.is_ok(), "{:?} at {level}", result);
+            assert_eq!(output, expected, "at {level}");
+        }
+    }
+
+    #[test]
+    fn test_input() {
+        let src = r#"
+            class Main inherits IO {
+                main() : Object { {
+                    out_int(in_int() + 1);
+                    out_string(in_string().concat("!"));
+                } };
+            };
+        "#;
+        let (result, output) = run(src, ir::OptLevel::O1, "41\nhi\n");
+        assert!(result.is_ok());
+        assert_eq!(output, "42hi!");
+    }
+
+    #[test]
+    fn test_runtime_errors() {
+        let src = "class Main inherits IO { a : Main; main() : Object { { out_string(\"x\"); a.main(); } }; };";
+        let (result, output) = run(src, ir::OptLevel::O0, "");
+        assert!(matches!(
+            result,
+            Err(RunError::Runtime(RuntimeError::DispatchOnVoid { method }, _)) if method == "main"
+        ));
+        assert_eq!(output, "x");
+
+        let src = "class Main { main() : Object { case 1 of s : String => s; esac }; };";
+        let (result, _) = run(src, ir::OptLevel::O0, "");
+        assert!(matches!(
+            result,
+            Err(RunError::Runtime(RuntimeError::CaseNoMatch { class }, _)) if class == "Int"
+        ));
+
+        let src = "class Main { main() : Object { 1 / 0 }; };";
+        let (result, _) = run(src, ir::OptLevel::O0, "");
+        assert!(matches!(
+            result,
+            Err(RunError::Runtime(RuntimeError::DivisionByZero, _))
+        ));
+
+        let src = "class Main { main() : Object { abort() }; };";
+        let (result, _) = run(src, ir::OptLevel::O0, "");
+        assert!(matches!(
+            result,
+            Err(RunError::Runtime(RuntimeError::Abort { class }, _)) if class == "Main"
+        ));
+
+        let src = "class Main { main() : Object { main() }; };";
+        let (result, _) = run(src, ir::OptLevel::O0, "");
+        assert!(matches!(
+            result,
+            Err(RunError::Runtime(RuntimeError::StackOverflow, _))
+        ));
+    }
+
+    #[test]
+    fn test_garbage_collection() {
+        // Allocates 1 GiB, twice the memory limit, while a list stays reachable only
+        // through a register.
+        let src = r#"
+            class Node {
+                name : String;
+                next : Node;
+                init(n : String, rest : Node) : Node { { name <- n; next <- rest; self; } };
+                name() : String { name };
+                next() : Node { next };
+            };
+            class Main inherits IO {
+                main() : Object {
+                    let big : String <- "x", i : Int, list : Node, s : String in {
+                        while i < 20 loop { big <- big.concat(big); i <- i + 1; } pool;
+                        i <- 0;
+                        while i < 1000 loop {
+                            s <- big.concat(i.type_name());
+                            if i - i / 100 * 100 = 0 then
+                                list <- (new Node).init(s.substr(s.length() - 3, 3), list)
+                            else 0 fi;
+                            i <- i + 1;
+                        } pool;
+                        s <- "";
+                        while not isvoid list loop {
+                            s <- s.concat(list.name());
+                            list <- list.next();
+                        } pool;
+                        out_int(s.length()).out_string(s.substr(0, 6)).out_string("\n");
+                    }
+                };
+            };
+        "#;
+        for level in [ir::OptLevel::O0, ir::OptLevel::O2] {
+            let (result, output) = run(src, level, "");
+            assert!(result.is_ok(), "{result:?} at {level}");
+            assert_eq!(output, "30IntInt\n", "at {level}");
+        }
+
+        let src = r#"
+            class Main {
+                main() : Object {
+                    let s : String <- "x" in while true loop s <- s.concat(s) pool
+                };
+            };
+        "#;
+        let (result, _) = run(src, ir::OptLevel::O0, "");
+        let Err(RunError::Runtime(RuntimeError::HeapExhausted(error), _)) = result else {
+            panic!("{result:?}");
+        };
+        assert_eq!(error.limit, 512 << 20);
+        assert!(error.live_bytes > 256 << 20, "{error:?}");
+    }
+
+    #[test]
+    fn test_backtrace() {
+        let src = "class A {\n  x : Int <- f(0);\n  f(n : Int) : Int {\n    1 / n\n  };\n};\n\
+                   class Main {\n  main() : Object { new A };\n};\n";
+        // At -O2, `f` is inlined into the initializer, and the division with it.
+        for (level, expected) in [
+            (
+                ir::OptLevel::O0,
+                &[("A.f", 4), ("A._init", 2), ("Main.main", 8)][..],
+            ),
+            (ir::OptLevel::O2, &[("A._init", 4), ("Main.main", 8)][..]),
+        ] {
+            let (result, _) = run(src, level, "");
+            let Err(RunError::Runtime(RuntimeError::DivisionByZero, backtrace)) = result else {
+                panic!("{result:?}");
+            };
+            let frames: Vec<_> = backtrace
+                .iter()
+                .map(|frame| (frame.function.as_str(), frame.line))
+                .collect();
+            assert_eq!(frames, expected, "at {level}");
+            assert!(backtrace.iter().all(|frame| frame.file == "test.cl"));
+        }
+
+        let (result, _) = run(
+            "class Main { main() : Object { main() }; };",
+            ir::OptLevel::O0,
+            "",
+        );
+        let Err(RunError::Runtime(RuntimeError::StackOverflow, backtrace)) = result else {
+            panic!("{result:?}");
+        };
+        assert!(backtrace.len() > 100);
+        assert!(backtrace.iter().all(|frame| frame.function == "Main.main"));
+    }
+}
@@ -0,0 +1,269 @@
+//! The front end shared by the `coolc` driver: reading several source files as one
+//! program, checking it, and reporting problems in it as [`Diagnostic`]s.
+
+use std::fmt;
+use std::ops::Range;
+
+use chumsky::span::Span as _;
+use lexer::source::SourceMap;
+use lexer::{FileId, Token};
+use logos::Logos;
+use parser::ast::{self, Span};
+
+pub mod lint;
+pub mod semant;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    /// Doesn't stop compilation.
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        })
+    }
+}
+
+/// An error or warning about the program, optionally pointing at the code it's about.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub span: Option<Span>,
+    pub message: String,
+    /// Other places the error involves, such as an earlier definition of something
+    /// defined twice, each with a short explanation.
+    pub notes: Vec<(Span, String)>,
+}
+
+impl Diagnostic {
+    pub fn new(message: impl Into<String>) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            span: None,
+            message: message.into(),
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn at(span: Span, message: impl Into<String>) -> Self {
+        Diagnostic {
+            span: Some(span),
+            ..Diagnostic::new(message)
+        }
+    }
+
+    pub fn with_severity(mut self, severity: Severity) -> Self {
+        self.severity = severity;
+        self
+    }
+
+    pub fn with_note(mut self, span: Span, note: impl Into<String>) -> Self {
+        self.notes.push((span, note.into()));
+        self
+    }
+
+    /// Formats the diagnostic as `path:line:column: error: message`, or just
+    /// `error: message` when it has no location, with `warning` instead of `error` for
+    /// a warning. Each note follows on a `path:line:column: note: ...` line.
+    pub fn render<'a>(&'a self, sources: &'a SourceMap) -> impl fmt::Display + 'a {
+        Rendered {
+            diagnostic: self,
+            sources,
+        }
+    }
+}
+
+struct Rendered<'a> {
+    diagnostic: &'a Diagnostic,
+    sources: &'a SourceMap,
+}
+
+impl Rendered<'_> {
+    fn location(&self, f: &mut fmt::Formatter<'_>, span: &Span) -> fmt::Result {
+        let file = self.sources.file(span.context);
+        let location = file.location(span.start);
+        write!(f, "{}:{}:{}: ", file.name(), location.line, location.column)
+    }
+}
+
+impl fmt::Display for Rendered<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(span) = &self.diagnostic.span {
+            self.location(f, span)?;
+        }
+        let Diagnostic {
+            severity, message, ..
+        } = self.diagnostic;
+        write!(f, "{severity}: {message}")?;
+        for (span, note) in &self.diagnostic.notes {
+            writeln!(f)?;
+            self.location(f, span)?;
+            write!(f, "note: {note}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Splits file `file` into tokens, returning them along with a diagnostic for every
+/// stretch of text that isn't one.
+pub fn lex(sources: &SourceMap, file: FileId) -> (Vec<(Token, Range<usize>)>, Vec<Diagnostic>) {
+    let src = sources.file(file).src();
+    let mut tokens = Vec::new();
+    let mut errors = Vec::new();
+    for (token, span) in Token::lexer(src).spanned() {
+        match token {
+            Ok(token) => tokens.push((token, span)),
+            Err(()) => {
+                let message = format!("unexpected character(s) '{}'", &src[span.clone()]);
+                errors.push(Diagnostic::at(Span::new(file, span), message));
+            }
+        }
+    }
+    (tokens, errors)
+}
+
+/// Parses every file and merges their classes into one program, in file order, the way
+/// `coolc a.cl b.cl` compiles its inputs together. Every span in the program carries
+/// the [`FileId`] of the file it came from.
+pub fn parse(sources: &SourceMap) -> Result<ast::Program, Vec<Diagnostic>> {
+    let mut classes = Vec::new();
+    let mut errors = Vec::new();
+    for (id, file) in sources.files() {
+        match parser::parse_file(file.src(), id) {
+            Ok(program) => classes.extend(program.classes),
+            Err(parse_errors) => errors.extend(
+                parse_errors
+                    .into_iter()
+                    .map(|e| Diagnostic::at(*e.span(), e.reason().to_string())),
+            ),
+        }
+    }
+    if errors.is_empty() {
+        Ok(ast::Program { classes })
+    } else {
+        Err(errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sources(files: &[(&str, &str)]) -> SourceMap {
+        let mut sources = SourceMap::new();
+        for (name, src) in files {
+            sources.add(*name, *src);
+        }
+        sources
+    }
+
+    #[test]
+    fn test_merges_files() {
+        let sources = sources(&[
+            ("a.cl", "class A { f() : Int { 1 }; };"),
+            ("b.cl", "class Main { main() : Object { (new A).f() }; };"),
+        ]);
+        let program = parse(&sources).unwrap();
+        let names: Vec<&str> = program.classes.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["A", "Main"]);
+    }
+
+    #[test]
+    fn test_diagnostics_name_their_file() {
+        let files = sources(&[
+            ("a.cl", "class A {};"),
+            ("b.cl", "class B {\n  x : Int <- ;\n};"),
+        ]);
+        let errors = parse(&files).unwrap_err();
+        assert_eq!(errors[0].span.unwrap().context, FileId(1));
+        let rendered = errors[0].render(&files).to_string();
+        assert!(rendered.starts_with("b.cl:2:"), "{rendered}");
+        assert!(rendered.contains(": error: "));
+
+        let (tokens, errors) = lex(&files, FileId(0));
+        assert_eq!(tokens.len(), 5);
+        assert!(errors.is_empty());
+        let files = sources(&[("c.cl", "class A { # };")]);
+        let (_, errors) = lex(&files, FileId(0));
+        assert_eq!(
+            errors[0].render(&files).to_string(),
+            "c.cl:1:11: error: unexpected character(s) '#'"
+        );
+    }
+
+    #[test]
+    fn test_columns_count_characters() {
+        let files = sources(&[("d.cl", "\"\u{e9}t\u{e9}\"\tclass A { # };")]);
+        let (_, errors) = lex(&files, FileId(0));
+        assert_eq!(
+            errors[0].render(&files).to_string(),
+            "d.cl:1:17: error: unexpected character(s) '#'"
+        );
+    }
+
+    #[test]
+    fn test_duplicate_class_across_files() {
+        let files = sources(&[
+            (
+                "main.cl",
+                "class Main { main() : Int { 0 }; };
+class A2I {};",
+            ),
+            (
+                "atoi.cl",
+                "(* conversions *)
+  class A2I { a2i() : Int { 0 }; };",
+            ),
+        ]);
+        let program = parse(&files).unwrap();
+        let errors = semant::check(&program);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].render(&files).to_string(),
+            "atoi.cl:2:3: error: class A2I is defined more than once\n\
+             main.cl:2:1: note: first defined here"
+        );
+    }
+
+    /// Runs what `coolc --emit ir -O2` does.
+    fn emit_ir(src: &str) -> Result<String, Vec<Diagnostic>> {
+        let files = sources(&[("chain.cl", src)]);
+        let program = parse(&files)?;
+        let errors = semant::check(&program);
+        assert!(errors.is_empty());
+        lint::check(&program, &lint::Levels::default());
+        let mut module = ir::lower(&program);
+        ir::optimize(&mut module, ir::OptLevel::O2);
+        Ok(module.to_string())
+    }
+
+    #[test]
+    fn test_long_chains_compile_or_are_rejected() {
+        let sum = |n: usize| {
+            format!(
+                "class Main inherits IO {{ main() : Object {{ out_int(0{}) }}; }};",
+                " + 1".repeat(n)
+            )
+        };
+        let calls = |n: usize| {
+            format!(
+                "class Main {{ f() : Main {{ self }}; main() : Object {{ self{} }}; }};",
+                ".f()".repeat(n)
+            )
+        };
+        // The class, the method body and the argument list take three levels.
+        for src in [sum(parser::nesting::MAX_DEPTH - 3), calls(250)] {
+            assert!(emit_ir(&src).unwrap().contains("Main.main"));
+        }
+        // Longer chains used to overflow the stack while lowering.
+        for src in [sum(5_000), calls(5_000), sum(200_000)] {
+            let errors = emit_ir(&src).unwrap_err();
+            assert!(errors[0].message.contains("nested more than"));
+        }
+    }
+}
@@ -0,0 +1,486 @@
+//! Warnings about code that is legal but probably not what was meant. Each lint has a
+//! stable name that `-A`, `-W` and `-D` on the command line take to allow it, warn
+//! about it or make it an error.
+//!
+//! The lints expect a program that passed [`crate::semant::check`]. A warning about an
+//! expression, a variable or a case branch points at it, and one about a method at the
+//! whole method.
+
+use std::collections::{HashMap, HashSet};
+
+use chumsky::span::Span as _;
+use ir::BUILTIN_CLASSES;
+use lexer::{Symbol, sym};
+use parser::ast::{self, Expr, ExprKind, Span};
+use parser::visit::{self, Visitor};
+
+use crate::{Diagnostic, Severity};
+
+pub struct Lint {
+    pub name: &'static str,
+    pub description: &'static str,
+}
+
+pub const UNUSED_VARIABLE: &str = "unused-variable";
+pub const UNUSED_FORMAL: &str = "unused-formal";
+pub const UNREACHABLE_BRANCH: &str = "unreachable-branch";
+pub const SHADOWED_ATTRIBUTE: &str = "shadowed-attribute";
+pub const SELF_ASSIGNMENT: &str = "self-assignment";
+pub const INFINITE_LOOP: &str = "infinite-loop";
+pub const UNUSED_METHOD: &str = "unused-method";
+
+pub const LINTS: &[Lint] = &[
+    Lint {
+        name: UNUSED_VARIABLE,
+        description: "a let variable that is never read",
+    },
+    Lint {
+        name: UNUSED_FORMAL,
+        description: "a formal parameter that is never read",
+    },
+    Lint {
+        name: UNREACHABLE_BRANCH,
+        description: "a case branch for the same class as an earlier branch; one after a \
+                      branch for an ancestor class is still reachable, since a case takes \
+                      the branch for the closest ancestor of the value's class",
+    },
+    Lint {
+        name: SHADOWED_ATTRIBUTE,
+        description: "a let or case variable named like an attribute of the class",
+    },
+    Lint {
+        name: SELF_ASSIGNMENT,
+        description: "an assignment of a variable to itself",
+    },
+    Lint {
+        name: INFINITE_LOOP,
+        description: "a `while true` loop whose body never calls abort",
+    },
+    Lint {
+        name: UNUSED_METHOD,
+        description: "a method that no dispatch in the program names",
+    },
+];
+
+/// Names every lint at once, like `-D warnings`.
+pub const ALL: &str = "warnings";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Allow,
+    Warn,
+    Deny,
+}
+
+/// The level of every lint. All of them warn by default.
+#[derive(Debug, Clone)]
+pub struct Levels {
+    levels: HashMap<&'static str, Level>,
+}
+
+impl Default for Levels {
+    fn default() -> Self {
+        Levels {
+            levels: LINTS.iter().map(|lint| (lint.name, Level::Warn)).collect(),
+        }
+    }
+}
+
+impl Levels {
+    /// Sets the level of lint `name`, or of every lint for [`ALL`]. Fails on a name
+    /// that isn't a lint.
+    pub fn set(&mut self, name: &str, level: Level) -> Result<(), String> {
+        if name == ALL {
+            self.levels.values_mut().for_each(|l| *l = level);
+            return Ok(());
+        }
+        match self.levels.get_mut(name) {
+            Some(l) => {
+                *l = level;
+                Ok(())
+            }
+            None => Err(format!("unknown lint '{name}'")),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Level {
+        self.levels.get(name).copied().unwrap_or(Level::Allow)
+    }
+}
+
+/// Returns a warning, or an error for a denied lint, for everything the lints that
+/// aren't allowed find in `program`, in class order.
+pub fn check(program: &ast::Program, levels: &Levels) -> Vec<Diagnostic> {
+    let mut parents: HashMap<Symbol, Symbol> = BUILTIN_CLASSES
+        .iter()
+        .filter_map(|&(name, parent, _)| Some((name, parent?)))
+        .collect();
+    let mut attributes: HashMap<Symbol, Vec<&ast::AttributeFeature>> = HashMap::new();
+    for class in &program.classes {
+        parents.insert(class.name, class.parent.unwrap_or(sym::OBJECT));
+        attributes.insert(
+            class.name,
+            class
+                .features
+                .iter()
+                .filter_map(|feature| match feature {
+                    ast::Feature::Attribute(attr) => Some(attr),
+                    ast::Feature::Method(_) => None,
+                })
+                .collect(),
+        );
+    }
+    let mut dispatched = HashSet::new();
+    MethodNames(&mut dispatched).visit_program(program);
+
+    let mut linter = Linter {
+        levels,
+        parents,
+        attributes,
+        diagnostics: Vec::new(),
+        class: sym::OBJECT,
+        at: String::new(),
+        span: Span::new(Default::default(), 0..0),
+        scope: Vec::new(),
+    };
+    for class in &program.classes {
+        linter.class = class.name;
+        for feature in &class.features {
+            match feature {
+                ast::Feature::Method(method) => {
+                    linter.at = format!("method {} of class {}", method.name, class.name);
+                    linter.span = method.span;
+                    let entry = method.name == sym::MAIN_METHOD
+                        && linter.is_ancestor(class.name, sym::MAIN_CLASS);
+                    if !entry && !dispatched.contains(&method.name) {
+                        linter.lint(UNUSED_METHOD, format!("{} is never dispatched", linter.at));
+                    }
+                    for formal in &method.formals {
+                        linter.scope.push(Variable {
+                            name: formal.name,
+                            span: formal.span,
+                            formal: true,
+                            used: false,
+                        });
+                    }
+                    linter.visit_expr(&method.body);
+                    linter.pop(method.formals.len());
+                }
+                ast::Feature::Attribute(attr) => {
+                    linter.at = format!("attribute {} of class {}", attr.name, class.name);
+                    linter.span = attr.span;
+                    if let Some(init) = &attr.init {
+                        linter.visit_expr(init);
+                    }
+                }
+            }
+        }
+    }
+    linter.diagnostics
+}
+
+struct Variable {
+    name: Symbol,
+    /// Where it's declared.
+    span: Span,
+    formal: bool,
+    used: bool,
+}
+
+struct Linter<'p, 'l> {
+    levels: &'l Levels,
+    parents: HashMap<Symbol, Symbol>,
+    /// The attributes each class declares itself.
+    attributes: HashMap<Symbol, Vec<&'p ast::AttributeFeature>>,
+    diagnostics: Vec<Diagnostic>,
+    class: Symbol,
+    /// The feature being linted, as `method f of class A`.
+    at: String,
+    span: Span,
+    /// The formals and let variables in scope, innermost last.
+    scope: Vec<Variable>,
+}
+
+impl Linter<'_, '_> {
+    fn lint(&mut self, name: &'static str, message: String) {
+        self.lint_at(self.span, name, message, None);
+    }
+
+    fn lint_at(
+        &mut self,
+        span: Span,
+        name: &'static str,
+        message: String,
+        note: Option<(Span, &str)>,
+    ) {
+        let (severity, flag) = match self.levels.get(name) {
+            Level::Allow => return,
+            Level::Warn => (Severity::Warning, 'W'),
+            Level::Deny => (Severity::Error, 'D'),
+        };
+        let mut diagnostic =
+            Diagnostic::at(span, format!("{message} [-{flag}{name}]")).with_severity(severity);
+        if let Some((span, note)) = note {
+            diagnostic = diagnostic.with_note(span, note);
+        }
+        self.diagnostics.push(diagnostic);
+    }
+
+    /// Whether `ancestor` is `class` or one of its ancestors.
+    fn is_ancestor(&self, ancestor: Symbol, class: Symbol) -> bool {
+        let mut current = Some(class);
+        // Bounded in case the hierarchy has a cycle after all.
+        for _ in 0..=self.parents.len() {
+            match current {
+                Some(c) if c == ancestor => return true,
+                Some(c) => current = self.parents.get(&c).copied(),
+                None => return false,
+            }
+        }
+        false
+    }
+
+    /// Warns if a let or case variable, declared at `span`, hides an attribute of the
+    /// class being linted.
+    fn check_shadowing(&mut self, name: Symbol, span: Span) {
+        let mut current = Some(self.class);
+        for _ in 0..=self.parents.len() {
+            let Some(class) = current else { break };
+            let attribute = self
+                .attributes
+                .get(&class)
+                .and_then(|attrs| attrs.iter().find(|attr| attr.name == name));
+            if let Some(attr) = attribute {
+                let message = format!(
+                    "variable {name} of {} shadows an attribute of class {class}",
+                    self.at
+                );
+                let note = Some((attr.span, "attribute defined here"));
+                self.lint_at(span, SHADOWED_ATTRIBUTE, message, note);
+                return;
+            }
+            current = self.parents.get(&class).copied();
+        }
+    }
+
+    /// Takes the innermost `count` variables out of scope, warning about the unused ones.
+    fn pop(&mut self, count: usize) {
+        let popped = self.scope.split_off(self.scope.len() - count);
+        for variable in popped.into_iter().filter(|v| !v.used) {
+            let (lint, kind) = match variable.formal {
+                true => (UNUSED_FORMAL, "formal parameter"),
+                false => (UNUSED_VARIABLE, "variable"),
+            };
+            let message = format!("{kind} {} of {} is never used", variable.name, self.at);
+            self.lint_at(variable.span, lint, message, None);
+        }
+    }
+}
+
+impl<'p> Visitor<'p> for Linter<'p, '_> {
+    fn visit_expr(&mut self, expr: &'p Expr) {
+        match &expr.kind {
+            ExprKind::Id(name) => {
+                if let Some(variable) = self.scope.iter_mut().rev().find(|v| v.name == *name) {
+                    variable.used = true;
+                }
+            }
+            ExprKind::Assign { name, expr: value } => {
+                if matches!(unparenthesized(value), ExprKind::Id(id) if id == name) {
+                    let message = format!("{} assigns {name} to itself", self.at);
+                    self.lint_at(expr.span, SELF_ASSIGNMENT, message, None);
+                }
+                self.visit_expr(value);
+            }
+            ExprKind::While { cond, body } => {
+                if matches!(unparenthesized(cond), ExprKind::True) && !calls_abort(body) {
+                    let message = format!("{} has a `while true` loop that never ends", self.at);
+                    self.lint_at(expr.span, INFINITE_LOOP, message, None);
+                }
+                self.visit_expr(cond);
+                self.visit_expr(body);
+            }
+            ExprKind::Let { bindings, body } => {
+                for binding in bindings {
+                    // An initializer sees the variables of the bindings before it only.
+                    if let Some(init) = &binding.init {
+                        self.visit_expr(init);
+                    }
+                    self.check_shadowing(binding.name, binding.span);
+                    self.scope.push(Variable {
+                        name: binding.name,
+                        span: binding.span,
+                        formal: false,
+                        used: false,
+                    });
+                }
+                self.visit_expr(body);
+                self.pop(bindings.len());
+            }
+            ExprKind::Case { expr, branches } => {
+                self.visit_expr(expr);
+                for (i, branch) in branches.iter().enumerate() {
+                    // A case takes the branch for the closest ancestor of the value's
+                    // class wherever it is, so only an earlier branch for the very same
+                    // class makes one unreachable.
+                    let earlier = branches[..i]
+                        .iter()
+                        .find(|earlier| earlier.typ == branch.typ);
+                    if let Some(earlier) = earlier {
+                        let message = format!(
+                            "case branch {} : {} of {} is unreachable after branch {} : {}",
+                            branch.name, branch.typ, self.at, earlier.name, earlier.typ
+                        );
+                        let note = Some((earlier.span, "earlier branch here"));
+                        self.lint_at(branch.span, UNREACHABLE_BRANCH, message, note);
+                    }
+                    self.check_shadowing(branch.name, branch.span);
+                    // Case variables are often there only for their type, so an unused
+                    // one isn't worth a warning; it still hides any outer variable.
+                    self.scope.push(Variable {
+                        name: branch.name,
+                        span: branch.span,
+                        formal: false,
+                        used: true,
+                    });
+                    self.visit_expr(&branch.expr);
+                    self.scope.pop();
+                }
+            }
+            _ => visit::walk_expr(self, expr),
+        }
+    }
+}
+
+fn unparenthesized(mut expr: &Expr) -> &ExprKind {
+    while let ExprKind::Paren(inner) = &expr.kind {
+        expr = inner;
+    }
+    &expr.kind
+}
+
+/// Collects the name of every method the expressions it visits dispatch to.
+struct MethodNames<'a>(&'a mut HashSet<Symbol>);
+
+impl Visitor<'_> for MethodNames<'_> {
+    fn visit_expr(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Dispatch { method, .. } => {
+                self.0.insert(*method);
+            }
+            ExprKind::FuncCall { name, .. } => {
+                self.0.insert(*name);
+            }
+            _ => {}
+        }
+        visit::walk_expr(self, expr);
+    }
+}
+
+fn calls_abort(expr: &Expr) -> bool {
+    let mut names = HashSet::new();
+    MethodNames(&mut names).visit_expr(expr);
+    names.contains(&sym::ABORT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lints(src: &str, levels: &Levels) -> Vec<String> {
+        check(&parser::parse(src).unwrap(), levels)
+            .into_iter()
+            .map(|d| format!("{}: {}", d.severity, d.message))
+            .collect()
+    }
+
+    #[test]
+    fn test_lints() {
+        let src = r#"
+            class A {
+                x : Int;
+                f(a : Int, b : Int) : Int { let c : Int <- a, d : Int in { x <- (x); c; } };
+                g() : Object { while (true) loop x <- x + 1 pool };
+                h() : Object { while true loop abort() pool };
+                k(o : Object) : Object {
+                    case o of a : A => a; b : B => b; x : Object => 0; i : Int => i; c : B => c; esac
+                };
+            };
+            class B inherits A {
+                y : Int <- let x : Int <- 1, y : Int <- x in y;
+            };
+            class Main {
+                main() : Object { (new A).f(1, 2).g().h().k(new B) };
+            };
+        "#;
+        assert_eq!(
+            lints(src, &Levels::default()),
+            [
+                "warning: method f of class A assigns x to itself [-Wself-assignment]",
+                "warning: variable d of method f of class A is never used [-Wunused-variable]",
+                "warning: formal parameter b of method f of class A is never used [-Wunused-formal]",
+                "warning: method g of class A has a `while true` loop that never ends [-Winfinite-loop]",
+                "warning: variable x of method k of class A shadows an attribute of class A [-Wshadowed-attribute]",
+                "warning: case branch c : B of method k of class A is unreachable after branch b : B [-Wunreachable-branch]",
+                "warning: variable x of attribute y of class B shadows an attribute of class A [-Wshadowed-attribute]",
+                "warning: variable y of attribute y of class B shadows an attribute of class B [-Wshadowed-attribute]",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_expression_lints_point_at_the_expression() {
+        let src = "class Main {\n  x : Int;\n  main() : Object {\n    x <- x\n  };\n};";
+        let diagnostics = check(&parser::parse(src).unwrap(), &Levels::default());
+        let span = diagnostics[0].span.unwrap();
+        assert_eq!(&src[span.start..span.end], "x <- x");
+    }
+
+    #[test]
+    fn test_declaration_lints_point_at_the_declaration() {
+        let src = "class Main {\n  x : Int;\n  main() : Object {\n    case 0 of\n      a : Int => a;\n      x : Int => x;\n    esac\n  };\n  f(n : Int) : Int { let m : Int <- 1 in 2 };\n};";
+        let diagnostics = check(&parser::parse(src).unwrap(), &Levels::default());
+        let at = |d: &Diagnostic| {
+            let span = d.span.unwrap();
+            &src[span.start..span.end]
+        };
+        let spans: Vec<&str> = diagnostics.iter().map(at).collect();
+        assert_eq!(
+            spans,
+            [
+                "x : Int => x",
+                "x : Int => x",
+                "f(n : Int) : Int { let m : Int <- 1 in 2 }",
+                "m : Int <- 1",
+                "n : Int",
+            ]
+        );
+        assert!(diagnostics[0].message.contains("unreachable"));
+        let (note, _) = &diagnostics[0].notes[0];
+        assert_eq!(&src[note.start..note.end], "a : Int => a");
+    }
+
+    #[test]
+    fn test_unused_methods_and_levels() {
+        let src = r#"
+            class A { f() : Int { 1 }; g() : Int { f() }; };
+            class Main inherits A { main() : Object { 0 }; };
+        "#;
+        let mut levels = Levels::default();
+        assert_eq!(
+            lints(src, &levels),
+            ["warning: method g of class A is never dispatched [-Wunused-method]"]
+        );
+        levels.set(ALL, Level::Deny).unwrap();
+        assert_eq!(
+            lints(src, &levels),
+            ["error: method g of class A is never dispatched [-Dunused-method]"]
+        );
+        levels.set(UNUSED_METHOD, Level::Allow).unwrap();
+        assert!(lints(src, &levels).is_empty());
+        assert_eq!(
+            levels.set("unused", Level::Warn),
+            Err("unknown lint 'unused'".to_string())
+        );
+    }
+}
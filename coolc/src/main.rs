@@ -0,0 +1,273 @@
+use clap::error::ErrorKind;
+use clap::{CommandFactory, FromArgMatches, Parser, ValueEnum};
+use coolc::lint::{Level, Levels};
+use coolc::{Diagnostic, Severity};
+use ir::OptLevel;
+use lexer::source::SourceMap;
+use std::fs;
+use std::io::{self, Write};
+use std::process::{Command, ExitCode, Stdio};
+
+/// Exit status when the program has errors. Bad command lines exit with clap's 2.
+const EXIT_PROGRAM_ERROR: u8 = 1;
+/// Exit status when an input can't be read or the output can't be written.
+const EXIT_IO_ERROR: u8 = 3;
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Emit {
+    /// The optimized IR
+    Ir,
+    /// A single C11 file with its runtime bundled in
+    C,
+    /// Assembly for the host, from the C output compiled with `cc -S`
+    Asm,
+    /// Textual LLVM IR, to link against the C runtime
+    Llvm,
+    /// A WebAssembly text-format module
+    Wat,
+    /// A WebAssembly binary module, the bytecode `wasm-host` runs (also `bytecode`)
+    #[value(alias = "bytecode")]
+    Wasm,
+}
+
+#[derive(Parser)]
+#[command(name = "coolc")]
+#[command(about = "Compiles a Cool program, given as one or more source files")]
+#[command(version)]
+struct Args {
+    /// Input Cool (.cl) files, compiled together as one program
+    #[arg(value_name = "FILE", required = true)]
+    files: Vec<String>,
+
+    /// Stop after lexing and print the tokens
+    #[arg(long, group = "stage")]
+    lex: bool,
+
+    /// Stop after parsing and print the syntax tree
+    #[arg(long, group = "stage")]
+    parse: bool,
+
+    /// Stop after semantic checks
+    #[arg(long, group = "stage")]
+    semant: bool,
+
+    /// What to generate
+    #[arg(long, value_enum, default_value = "c", group = "stage")]
+    emit: Emit,
+
+    /// Generate debug information mapping native code back to the COOL source
+    #[arg(short = 'g')]
+    debug: bool,
+
+    /// Optimization level (0, 1 or 2)
+    #[arg(short = 'O', default_value = "0")]
+    opt_level: OptLevel,
+
+    /// Report how many dispatch sites were resolved statically (at -O2) on stderr
+    #[arg(long)]
+    devirt_report: bool,
+
+    /// Write the output here instead of to stdout
+    #[arg(short = 'o', value_name = "OUTPUT")]
+    output: Option<String>,
+
+    /// Warn about a lint, or every lint with `warnings`
+    #[arg(short = 'W', value_name = "LINT")]
+    warn: Vec<String>,
+
+    /// Allow a lint, or every lint with `warnings`
+    #[arg(short = 'A', value_name = "LINT")]
+    allow: Vec<String>,
+
+    /// Make a lint an error, or every lint with `warnings`
+    #[arg(short = 'D', value_name = "LINT")]
+    deny: Vec<String>,
+
+    /// Lint levels from -W, -A and -D, the later flag winning when several name a lint
+    #[arg(skip)]
+    lints: Levels,
+}
+
+impl Args {
+    /// Parses the command line, applying the lint flags in the order they were given.
+    fn from_command_line() -> Self {
+        let matches = Args::command().get_matches();
+        let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+        let mut flags = Vec::new();
+        for (id, level) in [
+            ("warn", Level::Warn),
+            ("allow", Level::Allow),
+            ("deny", Level::Deny),
+        ] {
+            if let (Some(indices), Some(names)) =
+                (matches.indices_of(id), matches.get_many::<String>(id))
+            {
+                flags.extend(indices.zip(names).map(|(index, name)| (index, level, name)));
+            }
+        }
+        flags.sort_by_key(|(index, ..)| *index);
+        for (_, level, name) in flags {
+            if let Err(e) = args.lints.set(name, level) {
+                Args::command().error(ErrorKind::InvalidValue, e).exit();
+            }
+        }
+        args
+    }
+}
+
+fn main() -> ExitCode {
+    let args = Args::from_command_line();
+    let mut sources = SourceMap::new();
+    for path in &args.files {
+        if let Err(e) = sources.load(path) {
+            eprintln!("error: failed to read '{path}': {e}");
+            return ExitCode::from(EXIT_IO_ERROR);
+        }
+    }
+
+    let mut warnings = Vec::new();
+    let result = compile(&args, &sources, &mut warnings);
+    for warning in &warnings {
+        eprintln!("{}", warning.render(&sources));
+    }
+    let output = match result {
+        Ok(output) => output,
+        Err(errors) => {
+            for error in &errors {
+                eprintln!("{}", error.render(&sources));
+            }
+            let count = errors.len();
+            eprintln!(
+                "{count} error{} found, compilation halted",
+                if count == 1 { "" } else { "s" }
+            );
+            return ExitCode::from(EXIT_PROGRAM_ERROR);
+        }
+    };
+
+    let written = match &args.output {
+        Some(path) => fs::write(path, output).map_err(|e| format!("'{path}': {e}")),
+        None => io::stdout()
+            .write_all(&output)
+            .map_err(|e| format!("output: {e}")),
+    };
+    match written {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: failed to write {e}");
+            ExitCode::from(EXIT_IO_ERROR)
+        }
+    }
+}
+
+/// Runs the stages `args` asks for, returning what to write out. Warnings go in
+/// `warnings` whether or not compilation succeeds.
+fn compile(
+    args: &Args,
+    sources: &SourceMap,
+    warnings: &mut Vec<Diagnostic>,
+) -> Result<Vec<u8>, Vec<Diagnostic>> {
+    if args.lex {
+        let mut out = String::new();
+        let mut errors = Vec::new();
+        for (id, file) in sources.files() {
+            let (tokens, lex_errors) = coolc::lex(sources, id);
+            for (token, span) in tokens {
+                let location = file.location(span.start);
+                out.push_str(&format!(
+                    "{}:{}:{}\t{token:?}\n",
+                    file.name(),
+                    location.line,
+                    location.column
+                ));
+            }
+            errors.extend(lex_errors);
+        }
+        return if errors.is_empty() {
+            Ok(out.into_bytes())
+        } else {
+            Err(errors)
+        };
+    }
+
+    let program = coolc::parse(sources)?;
+    if args.parse {
+        return Ok(format!("{program:#?}\n").into_bytes());
+    }
+
+    let errors = coolc::semant::check(&program);
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    let (lint_warnings, errors): (Vec<_>, Vec<_>) = coolc::lint::check(&program, &args.lints)
+        .into_iter()
+        .partition(|d| d.severity == Severity::Warning);
+    warnings.extend(lint_warnings);
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    if args.semant {
+        return Ok(Vec::new());
+    }
+
+    let mut module = ir::lower(&program);
+    let report = ir::optimize(&mut module, args.opt_level);
+    if args.devirt_report {
+        eprintln!("{report}");
+    }
+    Ok(match args.emit {
+        Emit::Ir => module.to_string().into_bytes(),
+        Emit::C if args.debug => {
+            codegen::c::generate_with_debug_info(&module, sources).into_bytes()
+        }
+        Emit::C => codegen::c::generate(&module, sources).into_bytes(),
+        Emit::Asm if args.debug => assemble(
+            &codegen::c::generate_with_debug_info(&module, sources),
+            args,
+        )?,
+        Emit::Asm => assemble(&codegen::c::generate(&module, sources), args)?,
+        Emit::Llvm if args.debug => {
+            codegen::llvm::generate_with_debug_info(&module, sources).into_bytes()
+        }
+        Emit::Llvm => codegen::llvm::generate(&module, sources).into_bytes(),
+        Emit::Wat => codegen::wasm::generate(&module, sources).into_bytes(),
+        Emit::Wasm => wat::parse_str(codegen::wasm::generate(&module, sources))
+            .map_err(|e| vec![Diagnostic::new(format!("generated an invalid module: {e}"))])?,
+    })
+}
+
+/// Compiles the C output `c` to assembly with `cc`, at the same optimization level as
+/// the IR and with debug information if `args` asks for it.
+fn assemble(c: &str, args: &Args) -> Result<Vec<u8>, Vec<Diagnostic>> {
+    let failed = |e: String| vec![Diagnostic::new(format!("failed to run cc: {e}"))];
+    let mut command = Command::new("cc");
+    command.args([
+        "-std=c11",
+        "-fno-strict-aliasing",
+        "-S",
+        "-x",
+        "c",
+        "-o",
+        "-",
+    ]);
+    command.arg(args.opt_level.to_string());
+    if args.debug {
+        command.arg("-g");
+    }
+    let mut child = command
+        .arg("-")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| failed(e.to_string()))?;
+    // Writing everything before reading is fine: cc reads all its input first.
+    let written = child.stdin.take().unwrap().write_all(c.as_bytes());
+    let output = child
+        .wait_with_output()
+        .map_err(|e| failed(e.to_string()))?;
+    written.map_err(|e| failed(e.to_string()))?;
+    if !output.status.success() {
+        return Err(failed(format!("it exited with {}", output.status)));
+    }
+    Ok(output.stdout)
+}
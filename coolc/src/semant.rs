@@ -0,0 +1,405 @@
+//! Class-level semantic checks: the inheritance graph, feature declarations and the
+//! `Main` entry point. Expressions aren't type-checked yet, so a program that passes
+//! can still fail at runtime with an `undefined ...` error.
+
+use std::collections::{HashMap, HashSet};
+
+use ir::BUILTIN_CLASSES;
+use lexer::{Symbol, sym};
+use parser::ast::{self, Span};
+use parser::visit::{self, Visitor};
+
+use crate::Diagnostic;
+
+/// Classes that can't be inherited from.
+const FINAL_CLASSES: &[Symbol] = &[sym::INT, sym::STRING, sym::BOOL, sym::SELF_TYPE];
+
+/// What the checks need to know about a class. Spans are `None` for the basic classes.
+struct ClassEntry {
+    parent: Option<Symbol>,
+    span: Option<Span>,
+    /// Name and span of each attribute.
+    attributes: Vec<(Symbol, Span)>,
+    methods: Vec<MethodEntry>,
+}
+
+struct MethodEntry {
+    name: Symbol,
+    formal_types: Vec<Symbol>,
+    return_type: Symbol,
+    span: Option<Span>,
+}
+
+/// Adds a note pointing at `span` when there is one.
+fn noted(diagnostic: Diagnostic, span: Option<Span>, note: &str) -> Diagnostic {
+    match span {
+        Some(span) => diagnostic.with_note(span, note),
+        None => diagnostic,
+    }
+}
+
+/// Returns every problem found in `program`, in class order.
+pub fn check(program: &ast::Program) -> Vec<Diagnostic> {
+    let mut errors = Vec::new();
+    let mut classes: HashMap<Symbol, ClassEntry> = HashMap::new();
+    for &(name, parent, methods) in BUILTIN_CLASSES {
+        classes.insert(
+            name,
+            ClassEntry {
+                parent,
+                span: None,
+                attributes: Vec::new(),
+                methods: methods
+                    .iter()
+                    .map(|&(name, formals, return_type)| MethodEntry {
+                        name,
+                        formal_types: formals.to_vec(),
+                        return_type,
+                        span: None,
+                    })
+                    .collect(),
+            },
+        );
+    }
+
+    let mut user_classes = Vec::new();
+    for class in &program.classes {
+        if class.name == sym::SELF_TYPE || BUILTIN_CLASSES.iter().any(|(b, ..)| *b == class.name) {
+            errors.push(Diagnostic::at(
+                class.span,
+                format!("redefinition of basic class {}", class.name),
+            ));
+            continue;
+        }
+        if let Some(first) = classes.get(&class.name) {
+            let error = Diagnostic::at(
+                class.span,
+                format!("class {} is defined more than once", class.name),
+            );
+            errors.push(noted(error, first.span, "first defined here"));
+            continue;
+        }
+        let mut attributes = Vec::new();
+        let mut methods = Vec::new();
+        for feature in &class.features {
+            match feature {
+                ast::Feature::Attribute(attr) => attributes.push((attr.name, attr.span)),
+                ast::Feature::Method(method) => methods.push(MethodEntry {
+                    name: method.name,
+                    formal_types: method.formals.iter().map(|f| f.typ).collect(),
+                    return_type: method.return_type,
+                    span: Some(method.span),
+                }),
+            }
+        }
+        classes.insert(
+            class.name,
+            ClassEntry {
+                parent: Some(class.parent.unwrap_or(sym::OBJECT)),
+                span: Some(class.span),
+                attributes,
+                methods,
+            },
+        );
+        user_classes.push(class);
+    }
+
+    let mut hierarchy_ok = true;
+    for class in &user_classes {
+        let parent = classes[&class.name].parent.unwrap();
+        if FINAL_CLASSES.contains(&parent) {
+            errors.push(Diagnostic::at(
+                class.span,
+                format!(
+                    "class {} cannot inherit from basic class {parent}",
+                    class.name
+                ),
+            ));
+            hierarchy_ok = false;
+        } else if !classes.contains_key(&parent) {
+            errors.push(Diagnostic::at(
+                class.span,
+                format!(
+                    "class {} inherits from undefined class {parent}",
+                    class.name
+                ),
+            ));
+            hierarchy_ok = false;
+        }
+    }
+    if !hierarchy_ok {
+        return errors;
+    }
+    for class in &user_classes {
+        let mut seen = HashSet::new();
+        let mut current = Some(class.name);
+        while let Some(name) = current {
+            if !seen.insert(name) {
+                errors.push(Diagnostic::at(
+                    class.span,
+                    format!("class {} is part of an inheritance cycle", class.name),
+                ));
+                hierarchy_ok = false;
+                break;
+            }
+            current = classes[&name].parent;
+        }
+    }
+    if !hierarchy_ok {
+        return errors;
+    }
+
+    let defined = |typ: Symbol| typ == sym::SELF_TYPE || classes.contains_key(&typ);
+    for class in &user_classes {
+        let ancestors: Vec<Symbol> =
+            std::iter::successors(classes[&class.name].parent, |c| classes[c].parent).collect();
+        let mut attributes: HashMap<Symbol, Span> = HashMap::new();
+        let mut methods: HashMap<Symbol, Span> = HashMap::new();
+        for feature in &class.features {
+            match feature {
+                ast::Feature::Attribute(attr) => {
+                    let name = attr.name;
+                    let at = format!("attribute {name} of class {}", class.name);
+                    let error = |message: String| Diagnostic::at(attr.span, message);
+                    if name == sym::SELF {
+                        errors.push(error(format!("{at} cannot be named self")));
+                    } else if let Some(first) = attributes.get(&name) {
+                        let duplicate = error(format!("{at} is defined more than once"));
+                        errors.push(duplicate.with_note(*first, "first defined here"));
+                    } else if let Some((ancestor, inherited)) = ancestors.iter().find_map(|a| {
+                        let attrs = &classes[a].attributes;
+                        attrs.iter().find(|(n, _)| *n == name).map(|(_, s)| (a, s))
+                    }) {
+                        let redefined =
+                            error(format!("{at} is already defined in ancestor {ancestor}"));
+                        errors.push(redefined.with_note(*inherited, "defined here"));
+                    }
+                    attributes.entry(name).or_insert(attr.span);
+                    let mut used = Vec::new();
+                    if let Some(init) = &attr.init {
+                        types_used(init, &mut used);
+                    }
+                    let mut seen = HashSet::new();
+                    for typ in used.into_iter().filter(|t| seen.insert(*t)) {
+                        if !defined(typ) {
+                            errors.push(error(format!("{at} uses undefined class {typ}")));
+                        }
+                    }
+                    if !defined(attr.attr_type) {
+                        errors.push(error(format!("{at} has undefined type {}", attr.attr_type)));
+                    }
+                }
+                ast::Feature::Method(method) => {
+                    let name = method.name;
+                    let at = format!("method {name} of class {}", class.name);
+                    let error = |message: String| Diagnostic::at(method.span, message);
+                    if let Some(first) = methods.get(&name) {
+                        let duplicate = error(format!("{at} is defined more than once"));
+                        errors.push(duplicate.with_note(*first, "first defined here"));
+                    }
+                    methods.entry(name).or_insert(method.span);
+                    let mut formals = HashSet::new();
+                    for formal in &method.formals {
+                        if formal.name == sym::SELF {
+                            errors.push(error(format!("{at} has a formal parameter named self")));
+                        } else if !formals.insert(formal.name) {
+                            errors.push(error(format!(
+                                "{at} has more than one formal parameter named {}",
+                                formal.name
+                            )));
+                        }
+                        if formal.typ == sym::SELF_TYPE || !defined(formal.typ) {
+                            errors.push(error(format!(
+                                "formal parameter {} of {at} has undefined type {}",
+                                formal.name, formal.typ
+                            )));
+                        }
+                    }
+                    let mut used = Vec::new();
+                    types_used(&method.body, &mut used);
+                    let mut seen = HashSet::new();
+                    for typ in used.into_iter().filter(|t| seen.insert(*t)) {
+                        if !defined(typ) {
+                            errors.push(error(format!("{at} uses undefined class {typ}")));
+                        }
+                    }
+                    if !defined(method.return_type) {
+                        errors.push(error(format!(
+                            "{at} has undefined return type {}",
+                            method.return_type
+                        )));
+                    }
+                    let overridden = ancestors.iter().find_map(|a| {
+                        let methods = &classes[a].methods;
+                        methods.iter().find(|m| m.name == name).map(|m| (a, m))
+                    });
+                    if let Some((ancestor, overridden)) = overridden {
+                        let types: Vec<Symbol> = method.formals.iter().map(|f| f.typ).collect();
+                        if types != overridden.formal_types
+                            || method.return_type != overridden.return_type
+                        {
+                            let mismatch = error(format!(
+                                "{at} doesn't match the signature of the method it overrides in {ancestor}"
+                            ));
+                            errors.push(noted(mismatch, overridden.span, "overridden method"));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    match classes.get(&sym::MAIN_CLASS) {
+        None => errors.push(Diagnostic::new("class Main is not defined")),
+        Some(entry) => {
+            let main = std::iter::successors(Some(sym::MAIN_CLASS), |c| classes[c].parent)
+                .find_map(|c| {
+                    classes[&c]
+                        .methods
+                        .iter()
+                        .find(|m| m.name == sym::MAIN_METHOD)
+                });
+            let error = |span: Option<Span>, message: &str| match span {
+                Some(span) => Diagnostic::at(span, message),
+                None => Diagnostic::new(message),
+            };
+            match main {
+                None => errors.push(error(entry.span, "class Main has no method main")),
+                Some(main) if !main.formal_types.is_empty() => errors.push(error(
+                    main.span,
+                    "method main of class Main must not take any arguments",
+                )),
+                Some(_) => {}
+            }
+        }
+    }
+    errors
+}
+
+/// Collects the class names `expr` mentions in `let` and `case` declarations, `new`
+/// and static dispatch, in source order and with repeats.
+fn types_used(expr: &ast::Expr, used: &mut Vec<Symbol>) {
+    TypesUsed(used).visit_expr(expr);
+}
+
+struct TypesUsed<'a>(&'a mut Vec<Symbol>);
+
+impl Visitor<'_> for TypesUsed<'_> {
+    fn visit_expr(&mut self, expr: &ast::Expr) {
+        match &expr.kind {
+            ast::ExprKind::New(typ) => self.0.push(*typ),
+            ast::ExprKind::Dispatch {
+                expr,
+                static_type,
+                args,
+                ..
+            } => {
+                // The type comes after the receiver in the source.
+                self.visit_expr(expr);
+                self.0.extend(*static_type);
+                args.iter().for_each(|arg| self.visit_expr(arg));
+                return;
+            }
+            _ => {}
+        }
+        visit::walk_expr(self, expr);
+    }
+
+    fn visit_let_binding(&mut self, binding: &ast::LetBinding) {
+        self.0.push(binding.typ);
+        visit::walk_let_binding(self, binding);
+    }
+
+    fn visit_case_branch(&mut self, branch: &ast::CaseBranch) {
+        self.0.push(branch.typ);
+        visit::walk_case_branch(self, branch);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn errors(src: &str) -> Vec<String> {
+        check(&parser::parse(src).unwrap())
+            .into_iter()
+            .map(|d| d.message)
+            .collect()
+    }
+
+    #[test]
+    fn test_valid_program() {
+        let src = r#"
+            class A { x : Int; f(a : Int) : SELF_TYPE { self }; };
+            class B inherits A { f(b : Int) : SELF_TYPE { self }; };
+            class Main inherits IO { main() : Object { out_string("hi") }; };
+        "#;
+        assert!(errors(src).is_empty());
+    }
+
+    #[test]
+    fn test_hierarchy_errors() {
+        let src = "class A inherits Int {}; class B inherits C {}; class A {}; class Object {};";
+        assert_eq!(
+            errors(src),
+            [
+                "class A is defined more than once",
+                "redefinition of basic class Object",
+                "class A cannot inherit from basic class Int",
+                "class B inherits from undefined class C",
+            ]
+        );
+
+        let src =
+            "class A inherits B {}; class B inherits A {}; class Main { main() : Int { 0 }; };";
+        assert_eq!(
+            errors(src),
+            [
+                "class A is part of an inheritance cycle",
+                "class B is part of an inheritance cycle",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_feature_errors() {
+        let src = r#"
+            class A { x : Int; f() : Int { 0 }; };
+            class B inherits A {
+                x : Int;
+                y : Foo;
+                y : Int;
+                f(a : Int) : Int { a };
+                g(a : Int, a : SELF_TYPE) : Bar { a };
+            };
+            class Main inherits IO {
+                out_int(i : Int) : SELF_TYPE { self };
+                p : Object <- let x : Baz in new Qux;
+            };
+        "#;
+        assert_eq!(
+            errors(src),
+            [
+                "attribute x of class B is already defined in ancestor A",
+                "attribute y of class B has undefined type Foo",
+                "attribute y of class B is defined more than once",
+                "method f of class B doesn't match the signature of the method it overrides in A",
+                "method g of class B has more than one formal parameter named a",
+                "formal parameter a of method g of class B has undefined type SELF_TYPE",
+                "method g of class B has undefined return type Bar",
+                "attribute p of class Main uses undefined class Baz",
+                "attribute p of class Main uses undefined class Qux",
+                "class Main has no method main",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_missing_main() {
+        assert_eq!(errors("class A {};"), ["class Main is not defined"]);
+        assert_eq!(
+            errors("class Main { main(x : Int) : Int { x }; };"),
+            ["method main of class Main must not take any arguments"]
+        );
+    }
+}
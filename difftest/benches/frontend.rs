@@ -0,0 +1,83 @@
+//! Times the lexer and the parser over a corpus of generated programs, and counts the
+//! allocations each makes. Run with `cargo bench -p difftest`.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::hint::black_box;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use difftest::generate::generate;
+use lexer::Token;
+use logos::Logos;
+
+/// The system allocator, counting how many allocations it's asked for.
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+const PROGRAMS: u64 = 500;
+
+/// How long each benchmark repeats for.
+const DURATION: Duration = Duration::from_secs(3);
+
+/// Runs `f` over the whole corpus until [`DURATION`] is up, then prints its throughput
+/// and how many allocations one pass makes.
+fn bench(name: &str, corpus: &[String], mut f: impl FnMut(&str)) {
+    let bytes: usize = corpus.iter().map(String::len).sum();
+
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    corpus.iter().for_each(|src| f(src));
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+
+    let start = Instant::now();
+    let mut passes = 0;
+    while start.elapsed() < DURATION {
+        corpus.iter().for_each(|src| f(src));
+        passes += 1;
+    }
+    let seconds = start.elapsed().as_secs_f64();
+    let megabytes = (bytes * passes) as f64 / 1e6;
+    println!(
+        "{name:<8} {:>8.1} MB/s {:>10} allocations per pass ({:.1} per KB)",
+        megabytes / seconds,
+        allocations,
+        allocations as f64 * 1000.0 / bytes as f64,
+    );
+}
+
+fn main() {
+    let corpus: Vec<String> = (0..PROGRAMS).map(generate).collect();
+    let bytes: usize = corpus.iter().map(String::len).sum();
+    println!(
+        "{PROGRAMS} generated programs, {:.1} MB",
+        bytes as f64 / 1e6
+    );
+
+    bench("lex", &corpus, |src| {
+        for token in Token::lexer(src) {
+            black_box(token).ok();
+        }
+    });
+    bench("parse", &corpus, |src| {
+        black_box(parser::parse(src)).ok();
+    });
+}
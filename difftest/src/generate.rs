@@ -0,0 +1,765 @@
+//! Random COOL programs that parse, pass the semantic checks and are well typed, so any
+//! difference in how the engines run one is a bug in an engine.
+//!
+//! A program is a small class hierarchy whose classes have attributes and methods, some
+//! of them overriding inherited ones, and a `Main.main` that prints the values of random
+//! expressions. Every program terminates: a method only dispatches to methods declared
+//! before it, attribute initializers never dispatch and only create objects of classes
+//! declared before their own, and loops count up to a small bound. Runtime errors, like
+//! dispatch to void or division by zero, are allowed, since every engine has to report
+//! them the same way.
+
+/// A splitmix64 generator, so that a seed means the same program everywhere.
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `0..n`.
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    /// True one time in `n`.
+    pub fn one_in(&mut self, n: usize) -> bool {
+        self.below(n) == 0
+    }
+
+    pub fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len())]
+    }
+}
+
+/// How deeply expressions nest in method bodies and `main`.
+const DEPTH: usize = 4;
+/// How deeply expressions nest in attribute initializers.
+const INITIALIZER_DEPTH: usize = 2;
+/// Roughly how many dispatches a program may make. Loops and nested calls are counted
+/// as often as they can run.
+const BUDGET: u64 = 2000;
+/// What a `concat` counts as against the budget, so that a loop can't double a string
+/// more than a few times.
+const CONCAT_WORK: u64 = 200;
+/// The most times a loop runs.
+const MAX_ITERATIONS: usize = 3;
+
+const STRINGS: &[&str] = &["", "a", "cool", "hello world", "x y z"];
+
+/// The COOL program for `seed`.
+pub fn generate(seed: u64) -> String {
+    Generator {
+        rng: Rng::new(seed),
+        classes: Vec::new(),
+        methods: Vec::new(),
+        next_name: 0,
+    }
+    .program()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Type {
+    Int,
+    Bool,
+    String,
+    Object,
+    /// One of the generated classes, by index.
+    Class(usize),
+}
+
+#[derive(Debug, Clone)]
+struct Var {
+    name: String,
+    ty: Type,
+    /// Loop counters can't be assigned, so that every loop ends.
+    assignable: bool,
+}
+
+#[derive(Debug, Default)]
+struct Class {
+    /// `None` for classes that inherit from `Object`.
+    parent: Option<usize>,
+    attributes: Vec<Var>,
+    /// Indices of the methods this class overrides.
+    overrides: Vec<usize>,
+    /// The code of each method, by index, once it's generated.
+    bodies: Vec<(usize, String)>,
+}
+
+#[derive(Debug)]
+struct Method {
+    name: String,
+    /// The class that first defines the method.
+    owner: usize,
+    formals: Vec<Var>,
+    ret: Type,
+    /// The most work any definition of the method does.
+    work: u64,
+}
+
+/// Where an expression is being generated.
+struct Context {
+    /// The class whose method or attribute it's in, or `None` in `Main`.
+    class: Option<usize>,
+    /// Methods with an index below this can be dispatched to.
+    callable: usize,
+    /// Classes with an index below this can be created with `new`.
+    creatable: usize,
+    vars: Vec<Var>,
+    indent: usize,
+    /// The work done so far, and how many times the current expression can run.
+    work: u64,
+    iterations: u64,
+}
+
+impl Context {
+    fn line(&self, extra: usize) -> String {
+        format!("\n{}", "    ".repeat(self.indent + extra))
+    }
+}
+
+/// An expression's code and whether it can be an operand without parentheses.
+/// Operands that aren't atomic are always parenthesized, so precedence never matters.
+struct Code {
+    text: String,
+    atomic: bool,
+}
+
+impl Code {
+    fn atom(text: impl Into<String>) -> Self {
+        Code {
+            text: text.into(),
+            atomic: true,
+        }
+    }
+
+    fn compound(text: impl Into<String>) -> Self {
+        Code {
+            text: text.into(),
+            atomic: false,
+        }
+    }
+
+    fn operand(&self) -> String {
+        if self.atomic {
+            self.text.clone()
+        } else {
+            format!("({})", self.text)
+        }
+    }
+}
+
+struct Generator {
+    rng: Rng,
+    classes: Vec<Class>,
+    methods: Vec<Method>,
+    next_name: usize,
+}
+
+impl Generator {
+    fn program(mut self) -> String {
+        let count = 2 + self.rng.below(4);
+        for i in 0..count {
+            let parent = (i > 0 && !self.rng.one_in(3)).then(|| self.rng.below(i));
+            self.classes.push(Class {
+                parent,
+                ..Class::default()
+            });
+        }
+        for c in 0..count {
+            for _ in 0..self.rng.below(3) {
+                // Initializers can only create objects of earlier classes.
+                let attribute = self.var("a", c);
+                self.classes[c].attributes.push(attribute);
+            }
+            let inherited: Vec<usize> = (0..self.methods.len())
+                .filter(|&m| self.methods[m].owner != c && self.inherits(c, self.methods[m].owner))
+                .collect();
+            for m in inherited {
+                if self.rng.one_in(3) {
+                    self.classes[c].overrides.push(m);
+                }
+            }
+            for _ in 0..1 + self.rng.below(3) {
+                let formals = (0..self.rng.below(3))
+                    .map(|_| self.var("x", count))
+                    .collect();
+                let ret = self.any_type(count);
+                let name = self.fresh("m");
+                self.methods.push(Method {
+                    name,
+                    owner: c,
+                    formals,
+                    ret,
+                    work: 0,
+                });
+            }
+        }
+
+        // Every definition of a method is generated before any method that can call
+        // it, so that callers know the most work it does.
+        for m in 0..self.methods.len() {
+            let owner = self.methods[m].owner;
+            let overriders: Vec<usize> = (0..count)
+                .filter(|&c| self.classes[c].overrides.contains(&m))
+                .collect();
+            for c in std::iter::once(owner).chain(overriders) {
+                let mut cx = Context {
+                    class: Some(c),
+                    callable: m,
+                    creatable: count,
+                    vars: self.attributes(c),
+                    indent: 2,
+                    work: 0,
+                    iterations: 1,
+                };
+                cx.vars.extend(self.methods[m].formals.iter().cloned());
+                let body = self.expr(&mut cx, self.methods[m].ret, DEPTH);
+                self.methods[m].work = self.methods[m].work.max(cx.work);
+                self.classes[c].bodies.push((m, body.text));
+            }
+        }
+
+        let mut out = String::new();
+        for c in 0..count {
+            out.push_str(&self.class(c));
+        }
+        out.push_str(&self.main());
+        out
+    }
+
+    fn class(&mut self, c: usize) -> String {
+        let mut out = format!("class {}", self.type_name(Type::Class(c)));
+        if let Some(parent) = self.classes[c].parent {
+            out.push_str(&format!(
+                " inherits {}",
+                self.type_name(Type::Class(parent))
+            ));
+        }
+        out.push_str(" {\n");
+        for attribute in self.classes[c].attributes.clone() {
+            out.push_str(&format!(
+                "    {} : {}",
+                attribute.name,
+                self.type_name(attribute.ty)
+            ));
+            if !self.rng.one_in(8) {
+                let mut cx = Context {
+                    class: Some(c),
+                    callable: 0,
+                    creatable: c,
+                    vars: self.attributes(c),
+                    indent: 1,
+                    work: 0,
+                    iterations: 1,
+                };
+                let init = self.expr(&mut cx, attribute.ty, INITIALIZER_DEPTH);
+                out.push_str(&format!(" <- {}", init.text));
+            }
+            out.push_str(";\n");
+        }
+        let mut bodies = std::mem::take(&mut self.classes[c].bodies);
+        bodies.sort_by_key(|&(m, _)| m);
+        for (m, body) in bodies {
+            let method = &self.methods[m];
+            let formals: Vec<String> = (method.formals.iter())
+                .map(|f| format!("{} : {}", f.name, self.type_name(f.ty)))
+                .collect();
+            out.push_str(&format!(
+                "    {}({}) : {} {{\n        {body}\n    }};\n",
+                method.name,
+                formals.join(", "),
+                self.type_name(method.ret)
+            ));
+        }
+        out.push_str("};\n\n");
+        out
+    }
+
+    fn main(&mut self) -> String {
+        let mut cx = Context {
+            class: None,
+            callable: self.methods.len(),
+            creatable: self.classes.len(),
+            vars: Vec::new(),
+            indent: 3,
+            work: 0,
+            iterations: 1,
+        };
+        let mut locals = Vec::new();
+        for _ in 0..1 + self.rng.below(3) {
+            let local = self.var("v", cx.creatable);
+            let init = self.expr(&mut cx, local.ty, DEPTH - 1);
+            locals.push(format!(
+                "{} : {} <- {}",
+                local.name,
+                self.type_name(local.ty),
+                init.text
+            ));
+            cx.vars.push(local);
+        }
+        let mut statements = Vec::new();
+        for _ in 0..3 + self.rng.below(6) {
+            let statement = match self.rng.below(5) {
+                0 => format!("out_int({})", self.expr(&mut cx, Type::Int, DEPTH).text),
+                1 => format!(
+                    "out_string({})",
+                    self.expr(&mut cx, Type::String, DEPTH).text
+                ),
+                2 => format!(
+                    "out_string(if {} then \"true\" else \"false\" fi)",
+                    self.expr(&mut cx, Type::Bool, DEPTH).text
+                ),
+                3 => {
+                    let ty = self.any_type(cx.creatable);
+                    let value = self.expr(&mut cx, ty, DEPTH);
+                    format!("out_string({}.type_name())", value.operand())
+                }
+                _ => {
+                    statements.push(self.expr(&mut cx, Type::Object, DEPTH).text);
+                    continue;
+                }
+            };
+            statements.push(format!("{statement}.out_string(\"\\n\")"));
+        }
+        format!(
+            "class Main inherits IO {{\n    main() : Object {{\n        let {} in {{\n            {};\n        }}\n    }};\n}};\n",
+            locals.join(",\n            "),
+            statements.join(";\n            ")
+        )
+    }
+
+    fn expr(&mut self, cx: &mut Context, ty: Type, depth: usize) -> Code {
+        if depth > 0 {
+            // Some forms don't fit every context, so try a few.
+            for _ in 0..4 {
+                let code = match self.rng.below(10) {
+                    0 => Some(self.conditional(cx, ty, depth)),
+                    1 => Some(self.block(cx, ty, depth)),
+                    2 => Some(self.let_in(cx, ty, depth)),
+                    3 => Some(self.case(cx, ty, depth)),
+                    4 | 5 => self.dispatch(cx, ty, depth),
+                    6 => self.assign(cx, ty, depth),
+                    _ => self.operation(cx, ty, depth),
+                };
+                if let Some(code) = code {
+                    return code;
+                }
+            }
+        }
+        self.leaf(cx, ty)
+    }
+
+    fn leaf(&mut self, cx: &mut Context, ty: Type) -> Code {
+        let vars: Vec<&Var> = cx.vars.iter().filter(|v| self.conforms(v.ty, ty)).collect();
+        if !vars.is_empty() && self.rng.one_in(2) {
+            return Code::atom(self.rng.pick(&vars).name.clone());
+        }
+        match ty {
+            Type::Int => Code::atom(self.int_literal()),
+            Type::Bool => Code::atom(if self.rng.one_in(2) { "true" } else { "false" }),
+            Type::String => Code::atom(format!("\"{}\"", self.rng.pick(STRINGS))),
+            Type::Object => match (cx.class, self.rng.below(4)) {
+                (Some(_), 0) => Code::atom("self"),
+                (_, 1) => Code::atom(format!("\"{}\"", self.rng.pick(STRINGS))),
+                (_, 2) => Code::atom("true"),
+                _ => Code::atom(self.int_literal()),
+            },
+            Type::Class(c) => {
+                if cx.class.is_some_and(|k| self.inherits(k, c)) && self.rng.one_in(2) {
+                    return Code::atom("self");
+                }
+                match self.new_object(cx, c) {
+                    Some(code) => code,
+                    None => {
+                        // A void value of the class.
+                        let name = self.fresh("v");
+                        Code::compound(format!("let {name} : {} in {name}", self.type_name(ty)))
+                    }
+                }
+            }
+        }
+    }
+
+    fn int_literal(&mut self) -> String {
+        match self.rng.below(20) {
+            0 => "2147483647".to_string(),
+            1 => "1000000".to_string(),
+            n => (n - 2).to_string(),
+        }
+    }
+
+    fn new_object(&mut self, cx: &Context, c: usize) -> Option<Code> {
+        let classes: Vec<usize> = (0..cx.creatable).filter(|&d| self.inherits(d, c)).collect();
+        if classes.is_empty() {
+            return None;
+        }
+        let d = *self.rng.pick(&classes);
+        Some(Code::compound(format!(
+            "new {}",
+            self.type_name(Type::Class(d))
+        )))
+    }
+
+    fn conditional(&mut self, cx: &mut Context, ty: Type, depth: usize) -> Code {
+        let condition = self.expr(cx, Type::Bool, depth - 1);
+        let then = self.expr(cx, ty, depth - 1);
+        let otherwise = self.expr(cx, ty, depth - 1);
+        Code::atom(format!(
+            "if {} then {} else {} fi",
+            condition.text, then.text, otherwise.text
+        ))
+    }
+
+    fn block(&mut self, cx: &mut Context, ty: Type, depth: usize) -> Code {
+        cx.indent += 1;
+        let mut statements = Vec::new();
+        for _ in 0..self.rng.below(3) {
+            statements.push(self.expr(cx, Type::Object, depth - 1).text);
+        }
+        statements.push(self.expr(cx, ty, depth - 1).text);
+        cx.indent -= 1;
+        let mut text = "{".to_string();
+        for statement in statements {
+            text.push_str(&format!("{}{statement};", cx.line(1)));
+        }
+        text.push_str(&format!("{}}}", cx.line(0)));
+        Code::atom(text)
+    }
+
+    fn let_in(&mut self, cx: &mut Context, ty: Type, depth: usize) -> Code {
+        let scope = cx.vars.len();
+        let mut bindings = Vec::new();
+        for _ in 0..1 + self.rng.below(2) {
+            let var = self.var("v", cx.creatable);
+            let mut binding = format!("{} : {}", var.name, self.type_name(var.ty));
+            if !self.rng.one_in(10) {
+                let init = self.expr(cx, var.ty, depth - 1);
+                binding.push_str(&format!(" <- {}", init.text));
+            }
+            bindings.push(binding);
+            cx.vars.push(var);
+        }
+        cx.indent += 1;
+        let body = self.expr(cx, ty, depth - 1);
+        cx.indent -= 1;
+        cx.vars.truncate(scope);
+        Code::compound(format!(
+            "let {} in{}{}",
+            bindings.join(", "),
+            cx.line(1),
+            body.text
+        ))
+    }
+
+    fn case(&mut self, cx: &mut Context, ty: Type, depth: usize) -> Code {
+        let scrutinee_type = self.any_type(cx.creatable);
+        let scrutinee = self.expr(cx, scrutinee_type, depth - 1);
+        let mut types = vec![Type::Int, Type::Bool, Type::String, Type::Object];
+        types.extend((0..self.classes.len()).map(Type::Class));
+        let mut branches = Vec::new();
+        for _ in 0..1 + self.rng.below(3) {
+            branches.push(types.swap_remove(self.rng.below(types.len())));
+        }
+        // Mostly make sure some branch matches, with one for the scrutinee's static type
+        // or a type it inherits from.
+        if !branches.iter().any(|&b| self.conforms(scrutinee_type, b)) && !self.rng.one_in(40) {
+            let mut supertypes = vec![Type::Object];
+            match scrutinee_type {
+                Type::Class(c) => supertypes.extend(self.ancestors(c).map(Type::Class)),
+                Type::Object => {}
+                basic => supertypes.push(basic),
+            }
+            branches.push(*self.rng.pick(&supertypes));
+        }
+
+        let mut text = format!("case {} of", scrutinee.text);
+        cx.indent += 1;
+        for branch in branches {
+            let var = Var {
+                name: self.fresh("c"),
+                ty: branch,
+                assignable: true,
+            };
+            let head = format!("{} : {} => ", var.name, self.type_name(branch));
+            cx.vars.push(var);
+            let body = self.expr(cx, ty, depth - 1);
+            cx.vars.pop();
+            text.push_str(&format!("{}{head}{};", cx.line(0), body.text));
+        }
+        cx.indent -= 1;
+        text.push_str(&format!("{}esac", cx.line(0)));
+        Code::atom(text)
+    }
+
+    fn dispatch(&mut self, cx: &mut Context, ty: Type, depth: usize) -> Option<Code> {
+        let methods: Vec<usize> = (0..cx.callable)
+            .filter(|&m| self.conforms(self.methods[m].ret, ty))
+            .filter(|&m| cx.work + cx.iterations * (1 + self.methods[m].work) <= BUDGET)
+            .collect();
+        if methods.is_empty() {
+            return None;
+        }
+        let m = *self.rng.pick(&methods);
+        cx.work += cx.iterations * (1 + self.methods[m].work);
+        let owner = self.methods[m].owner;
+
+        let receiver = match cx.class {
+            Some(c) if self.inherits(c, owner) && self.rng.one_in(3) => None,
+            _ => {
+                let classes: Vec<usize> = (0..self.classes.len())
+                    .filter(|&d| self.inherits(d, owner))
+                    .collect();
+                let d = *self.rng.pick(&classes);
+                let receiver = self.expr(cx, Type::Class(d), depth - 1).operand();
+                if self.rng.one_in(4) {
+                    let ancestors: Vec<usize> = (self.ancestors(d))
+                        .filter(|&a| self.inherits(a, owner))
+                        .collect();
+                    let a = *self.rng.pick(&ancestors);
+                    Some(format!("{receiver}@{}.", self.type_name(Type::Class(a))))
+                } else {
+                    Some(format!("{receiver}."))
+                }
+            }
+        };
+        let formals: Vec<Type> = self.methods[m].formals.iter().map(|f| f.ty).collect();
+        let args: Vec<String> = (formals.into_iter())
+            .map(|formal| self.expr(cx, formal, depth - 1).text)
+            .collect();
+        Some(Code::atom(format!(
+            "{}{}({})",
+            receiver.unwrap_or_default(),
+            self.methods[m].name,
+            args.join(", ")
+        )))
+    }
+
+    fn assign(&mut self, cx: &mut Context, ty: Type, depth: usize) -> Option<Code> {
+        let vars: Vec<Var> = (cx.vars.iter())
+            .filter(|v| v.assignable && self.conforms(v.ty, ty))
+            .cloned()
+            .collect();
+        if vars.is_empty() {
+            return None;
+        }
+        let var = self.rng.pick(&vars).clone();
+        let value = self.expr(cx, var.ty, depth - 1);
+        Some(Code::compound(format!("{} <- {}", var.name, value.text)))
+    }
+
+    /// An operator or a basic class's method that gives a `ty`.
+    fn operation(&mut self, cx: &mut Context, ty: Type, depth: usize) -> Option<Code> {
+        let code = match ty {
+            Type::Int => match self.rng.below(6) {
+                0 => self.binary(cx, Type::Int, "+", depth),
+                1 => self.binary(cx, Type::Int, "-", depth),
+                2 => self.binary(cx, Type::Int, "*", depth),
+                3 => {
+                    let dividend = self.expr(cx, Type::Int, depth - 1).operand();
+                    // Mostly divide by a nonzero literal, so that few programs stop early.
+                    let divisor = if self.rng.one_in(4) {
+                        self.expr(cx, Type::Int, depth - 1).operand()
+                    } else {
+                        (1 + self.rng.below(9)).to_string()
+                    };
+                    Code::compound(format!("{dividend} / {divisor}"))
+                }
+                4 => Code::compound(format!(
+                    "~{}",
+                    self.expr(cx, Type::Int, depth - 1).operand()
+                )),
+                _ => Code::atom(format!(
+                    "{}.length()",
+                    self.expr(cx, Type::String, depth - 1).operand()
+                )),
+            },
+            Type::Bool => match self.rng.below(5) {
+                0 => self.binary(cx, Type::Int, "<", depth),
+                1 => self.binary(cx, Type::Int, "<=", depth),
+                2 => {
+                    // Only values of the same basic type, or of no basic type, can be
+                    // compared.
+                    let operands = match self.any_type(cx.creatable) {
+                        basic @ (Type::Int | Type::Bool | Type::String) => basic,
+                        _ => Type::Object,
+                    };
+                    self.binary(cx, operands, "=", depth)
+                }
+                3 => Code::compound(format!(
+                    "not {}",
+                    self.expr(cx, Type::Bool, depth - 1).operand()
+                )),
+                _ => {
+                    let ty = self.any_type(cx.creatable);
+                    Code::compound(format!("isvoid {}", self.expr(cx, ty, depth - 1).operand()))
+                }
+            },
+            Type::String => match self.rng.below(3) {
+                0 if cx.work + cx.iterations * CONCAT_WORK <= BUDGET => {
+                    cx.work += cx.iterations * CONCAT_WORK;
+                    let s = self.expr(cx, Type::String, depth - 1).operand();
+                    let t = self.expr(cx, Type::String, depth - 1).text;
+                    Code::atom(format!("{s}.concat({t})"))
+                }
+                1 => {
+                    let s = self.rng.pick(STRINGS);
+                    let start = self.rng.below(s.len() + 1);
+                    let length = self.rng.below(s.len() - start + 1);
+                    Code::atom(format!("\"{s}\".substr({start}, {length})"))
+                }
+                _ => {
+                    let ty = self.any_type(cx.creatable);
+                    let value = self.expr(cx, ty, depth - 1).operand();
+                    Code::atom(format!("{value}.type_name()"))
+                }
+            },
+            Type::Object => match self.rng.below(4) {
+                0 => self.countdown(cx, depth),
+                1 => {
+                    let s = self.expr(cx, Type::String, depth - 1).text;
+                    Code::atom(format!("(new IO).out_string({s})"))
+                }
+                2 => {
+                    let i = self.expr(cx, Type::Int, depth - 1).text;
+                    Code::atom(format!("(new IO).out_int({i})"))
+                }
+                _ => {
+                    let ty = self.any_type(cx.creatable);
+                    self.expr(cx, ty, depth - 1)
+                }
+            },
+            Type::Class(c) => {
+                if self.rng.one_in(3) {
+                    let value = self.expr(cx, ty, depth - 1).operand();
+                    Code::atom(format!("{value}.copy()"))
+                } else {
+                    self.new_object(cx, c)?
+                }
+            }
+        };
+        Some(code)
+    }
+
+    fn binary(&mut self, cx: &mut Context, operands: Type, op: &str, depth: usize) -> Code {
+        let lhs = self.expr(cx, operands, depth - 1).operand();
+        let rhs = self.expr(cx, operands, depth - 1).operand();
+        Code::compound(format!("{lhs} {op} {rhs}"))
+    }
+
+    /// A loop that runs its body a few times.
+    fn countdown(&mut self, cx: &mut Context, depth: usize) -> Code {
+        let counter = self.fresh("i");
+        let bound = 1 + self.rng.below(MAX_ITERATIONS);
+        cx.vars.push(Var {
+            name: counter.clone(),
+            ty: Type::Int,
+            assignable: false,
+        });
+        cx.iterations *= bound as u64;
+        cx.indent += 2;
+        let body = self.expr(cx, Type::Object, depth - 1);
+        cx.indent -= 2;
+        cx.iterations /= bound as u64;
+        cx.vars.pop();
+        Code::compound(format!(
+            "let {counter} : Int <- 0 in{}while {counter} < {bound} loop {{{}{counter} <- {counter} + 1;{}{};{}}} pool",
+            cx.line(1),
+            cx.line(2),
+            cx.line(2),
+            body.text,
+            cx.line(1)
+        ))
+    }
+
+    /// A type that's basic or one of the first `classes` classes.
+    fn any_type(&mut self, classes: usize) -> Type {
+        match self.rng.below(4 + classes) {
+            0 => Type::Int,
+            1 => Type::Bool,
+            2 => Type::String,
+            3 => Type::Object,
+            n => Type::Class(n - 4),
+        }
+    }
+
+    fn type_name(&self, ty: Type) -> String {
+        match ty {
+            Type::Int => "Int".to_string(),
+            Type::Bool => "Bool".to_string(),
+            Type::String => "String".to_string(),
+            Type::Object => "Object".to_string(),
+            Type::Class(c) => format!("C{c}"),
+        }
+    }
+
+    fn fresh(&mut self, prefix: &str) -> String {
+        self.next_name += 1;
+        format!("{prefix}{}", self.next_name)
+    }
+
+    fn var(&mut self, prefix: &str, classes: usize) -> Var {
+        Var {
+            name: self.fresh(prefix),
+            ty: self.any_type(classes),
+            assignable: true,
+        }
+    }
+
+    /// `c` and the classes it inherits from, nearest first.
+    fn ancestors(&self, c: usize) -> impl Iterator<Item = usize> + '_ {
+        std::iter::successors(Some(c), |&c| self.classes[c].parent)
+    }
+
+    fn inherits(&self, c: usize, ancestor: usize) -> bool {
+        self.ancestors(c).any(|a| a == ancestor)
+    }
+
+    fn conforms(&self, ty: Type, to: Type) -> bool {
+        match (ty, to) {
+            (_, Type::Object) => true,
+            (Type::Class(c), Type::Class(d)) => self.inherits(c, d),
+            _ => ty == to,
+        }
+    }
+
+    /// The attributes a method of `c` can see.
+    fn attributes(&self, c: usize) -> Vec<Var> {
+        let mut attributes: Vec<Var> = (self.ancestors(c))
+            .flat_map(|a| self.classes[a].attributes.iter().cloned())
+            .collect();
+        attributes.reverse();
+        attributes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lexer::source::SourceMap;
+
+    #[test]
+    fn test_programs_are_valid() {
+        for seed in 0..200 {
+            let src = generate(seed);
+            assert_eq!(src, generate(seed), "seed {seed} isn't deterministic");
+            let mut sources = SourceMap::new();
+            sources.add("gen.cl", &src);
+            let program = match coolc::parse(&sources) {
+                Ok(program) => program,
+                Err(errors) => panic!("seed {seed} doesn't parse: {errors:?}\n{src}"),
+            };
+            let errors = coolc::semant::check(&program);
+            assert!(errors.is_empty(), "seed {seed}: {errors:?}\n{src}");
+        }
+    }
+}
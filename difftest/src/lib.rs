@@ -0,0 +1,460 @@
+//! Differential testing: runs the same COOL program on every engine in the workspace
+//! and reports where their exit statuses or outputs disagree. The programs usually come
+//! from [`generate`], which makes random well-typed ones from a seed.
+//!
+//! Only the exit status and stdout are compared. Runtime error reports go to stderr,
+//! and `-O2` inlining can change the frames they show.
+
+pub mod generate;
+
+use std::fmt;
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use ir::OptLevel;
+use lexer::source::SourceMap;
+use parser::ast;
+use runtime::backtrace::{self, EXIT_RUNTIME_ERROR};
+use runtime::{Options, Vm};
+use wasm_host::RunError;
+
+/// How long a compiled program may run before it's killed.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+static NEXT_PROGRAM: AtomicUsize = AtomicUsize::new(0);
+
+/// Something that can run a COOL program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Engine {
+    /// The AST interpreter in `interp/`.
+    Interp,
+    /// The IR interpreter in `runtime/`.
+    Vm(OptLevel),
+    /// The WebAssembly backend, run by `wasm-host/`.
+    Wasm(OptLevel),
+    /// The C backend, compiled with `cc`.
+    C(OptLevel),
+    /// The LLVM backend, compiled with `llc` and linked against the C runtime with `cc`.
+    Llvm(OptLevel),
+}
+
+impl Engine {
+    /// Every engine, with the AST interpreter, the reference, first.
+    pub fn all() -> Vec<Engine> {
+        let mut engines = vec![Engine::Interp];
+        for level in [OptLevel::O0, OptLevel::O2] {
+            engines.extend([
+                Engine::Vm(level),
+                Engine::Wasm(level),
+                Engine::C(level),
+                Engine::Llvm(level),
+            ]);
+        }
+        engines
+    }
+
+    /// The engine's name without its optimization level, like `vm` or `llvm`.
+    pub fn kind(self) -> &'static str {
+        match self {
+            Engine::Interp => "interp",
+            Engine::Vm(_) => "vm",
+            Engine::Wasm(_) => "wasm",
+            Engine::C(_) => "c",
+            Engine::Llvm(_) => "llvm",
+        }
+    }
+
+    /// Whether the tools the engine needs are installed.
+    pub fn available(self) -> bool {
+        let installed = |tool: &str| {
+            Command::new(tool)
+                .arg("--version")
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status()
+                .is_ok()
+        };
+        match self {
+            Engine::Interp | Engine::Vm(_) | Engine::Wasm(_) => true,
+            Engine::C(_) => installed("cc"),
+            Engine::Llvm(_) => installed("llc") && installed("cc"),
+        }
+    }
+}
+
+impl fmt::Display for Engine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Engine::Interp => write!(f, "interp"),
+            Engine::Vm(level) | Engine::Wasm(level) | Engine::C(level) | Engine::Llvm(level) => {
+                write!(f, "{} {level}", self.kind())
+            }
+        }
+    }
+}
+
+/// How a run ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Outcome {
+    /// The exit status, or `None` if the program was killed by a signal.
+    pub status: Option<i32>,
+    pub stdout: String,
+    /// Where the program failed, if it did.
+    pub stderr: String,
+}
+
+impl Outcome {
+    fn finished(result: Result<(), String>, stdout: Vec<u8>) -> Self {
+        let (status, stderr) = match result {
+            Ok(()) => (0, String::new()),
+            Err(report) => (i32::from(EXIT_RUNTIME_ERROR), report),
+        };
+        Outcome {
+            status: Some(status),
+            stdout: String::from_utf8_lossy(&stdout).into_owned(),
+            stderr,
+        }
+    }
+}
+
+/// A program that got through the front end.
+pub struct Program {
+    sources: SourceMap,
+    ast: ast::Program,
+}
+
+impl Program {
+    /// Parses and checks `src`, called `name` in diagnostics, or returns its errors.
+    pub fn new(name: &str, src: &str) -> Result<Self, String> {
+        let mut sources = SourceMap::new();
+        sources.add(name, src);
+        let render = |errors: Vec<coolc::Diagnostic>, sources: &SourceMap| {
+            (errors.iter())
+                .map(|e| e.render(sources).to_string())
+                .collect::<Vec<_>>()
+                .join("\n")
+        };
+        let ast = coolc::parse(&sources).map_err(|errors| render(errors, &sources))?;
+        let errors = coolc::semant::check(&ast);
+        if !errors.is_empty() {
+            return Err(render(errors, &sources));
+        }
+        Ok(Program { sources, ast })
+    }
+
+    /// Runs the program on `engine` with `input` as its stdin. Errors are failures to
+    /// build or run it at all, like generated code that doesn't compile.
+    pub fn run(&self, engine: Engine, input: &str) -> Result<Outcome, String> {
+        let (mut stdin, mut stdout) = (input.as_bytes(), Vec::new());
+        match engine {
+            Engine::Interp => {
+                let mut interp = interp::Interpreter::new(&self.ast, &mut stdin, &mut stdout);
+                let result = interp
+                    .run()
+                    .map_err(|e| backtrace::report(&e, &interp.backtrace(&self.sources)));
+                drop(interp);
+                Ok(Outcome::finished(result, stdout))
+            }
+            Engine::Vm(level) => {
+                let module = self.module(level);
+                let mut vm = Vm::new(&module, Options::default(), &mut stdin, &mut stdout);
+                let result = vm
+                    .run()
+                    .map_err(|e| backtrace::report(&e, &vm.backtrace(&self.sources)));
+                drop(vm);
+                Ok(Outcome::finished(result, stdout))
+            }
+            Engine::Wasm(level) => {
+                let wat = codegen::wasm::generate(&self.module(level), &self.sources);
+                let input = io::Cursor::new(input.as_bytes().to_vec());
+                let (result, stdout) = wasm_host::run(wat.as_bytes(), input, stdout);
+                let result = match result {
+                    Ok(()) => Ok(()),
+                    Err(RunError::Runtime(e, frames)) => Err(backtrace::report(&e, &frames)),
+                    Err(e) => return Err(e.to_string()),
+                };
+                Ok(Outcome::finished(result, stdout))
+            }
+            Engine::C(level) => {
+                let c = codegen::c::generate(&self.module(level), &self.sources);
+                native(input, |dir, name| {
+                    write(&dir.join(format!("{name}.c")), &c)?;
+                    build(
+                        dir,
+                        "cc",
+                        &[
+                            "-std=c11",
+                            "-fno-strict-aliasing",
+                            "-o",
+                            name,
+                            &format!("{name}.c"),
+                        ],
+                    )
+                })
+            }
+            Engine::Llvm(level) => {
+                let ll = codegen::llvm::generate(&self.module(level), &self.sources);
+                native(input, |dir, name| {
+                    write(&dir.join(format!("{name}.ll")), &ll)?;
+                    let runtime = format!("{name}-runtime.c");
+                    write(&dir.join(&runtime), codegen::c::RUNTIME)?;
+                    let ll = format!("{name}.ll");
+                    let flags = ["-relocation-model=pic", "-filetype=obj", &ll];
+                    let llc = codegen::llvm::opaque_pointer_flags("llc");
+                    build(dir, "llc", &[llc, &flags].concat())?;
+                    build(dir, "cc", &["-std=c11", "-DCOOL_API=", "-c", &runtime])?;
+                    build(
+                        dir,
+                        "cc",
+                        &[
+                            "-o",
+                            name,
+                            &format!("{name}.o"),
+                            &format!("{name}-runtime.o"),
+                        ],
+                    )
+                })
+            }
+        }
+    }
+
+    fn module(&self, level: OptLevel) -> ir::Module {
+        let mut module = ir::lower(&self.ast);
+        ir::optimize(&mut module, level);
+        module
+    }
+}
+
+/// Builds an executable called `name` in a scratch directory with `build`, runs it, and
+/// cleans up after it.
+fn native(
+    input: &str,
+    build: impl FnOnce(&Path, &str) -> Result<(), String>,
+) -> Result<Outcome, String> {
+    let dir = std::env::temp_dir().join(format!("cool-difftest-{}", std::process::id()));
+    let name = format!("program{}", NEXT_PROGRAM.fetch_add(1, Ordering::Relaxed));
+    let result = fs::create_dir_all(&dir)
+        .map_err(|e| e.to_string())
+        .and_then(|()| build(&dir, &name))
+        .and_then(|()| execute(&dir.join(&name), input));
+    for entry in fs::read_dir(&dir).into_iter().flatten().flatten() {
+        if entry.file_name().to_string_lossy().starts_with(&name) {
+            let _ = fs::remove_file(entry.path());
+        }
+    }
+    result
+}
+
+fn write(path: &Path, contents: &str) -> Result<(), String> {
+    fs::write(path, contents).map_err(|e| format!("failed to write '{}': {e}", path.display()))
+}
+
+fn build(dir: &Path, tool: &str, args: &[&str]) -> Result<(), String> {
+    let output = Command::new(tool)
+        .current_dir(dir)
+        .args(args)
+        .output()
+        .map_err(|e| format!("couldn't run {tool}: {e}"))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(format!(
+            "{tool} failed:\n{}",
+            String::from_utf8_lossy(&output.stderr)
+        ))
+    }
+}
+
+/// Runs `exe` with `input` as its stdin. Its stdout and stderr go to files, so that a
+/// program that writes a lot can't block on a full pipe.
+fn execute(exe: &Path, input: &str) -> Result<Outcome, String> {
+    let file = |extension: &str| exe.with_extension(extension);
+    let run = || -> io::Result<Result<Outcome, String>> {
+        fs::write(file("stdin"), input)?;
+        let mut child = Command::new(exe)
+            .stdin(File::open(file("stdin"))?)
+            .stdout(File::create(file("stdout"))?)
+            .stderr(File::create(file("stderr"))?)
+            .spawn()?;
+        let start = Instant::now();
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if start.elapsed() > TIMEOUT {
+                child.kill()?;
+                child.wait()?;
+                return Ok(Err(format!("still running after {}s", TIMEOUT.as_secs())));
+            }
+            thread::sleep(Duration::from_millis(5));
+        };
+        Ok(Ok(Outcome {
+            status: status.code(),
+            stdout: String::from_utf8_lossy(&fs::read(file("stdout"))?).into_owned(),
+            stderr: String::from_utf8_lossy(&fs::read(file("stderr"))?).into_owned(),
+        }))
+    };
+    run().map_err(|e| e.to_string())?
+}
+
+/// A way an engine disagreed with the reference engine, or couldn't run a program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Difference {
+    /// The program didn't get through the front end.
+    Rejected(String),
+    /// The engine couldn't build or run the program.
+    Failed(Engine, String),
+    /// The engine's exit status, given first, isn't the reference's.
+    Status(Engine, Option<i32>, Engine, Option<i32>),
+    /// The engine's stdout isn't the reference's, as this diff from the reference's
+    /// shows.
+    Stdout(Engine, Engine, String),
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = |status: &Option<i32>| match status {
+            Some(code) => format!("exit status {code}"),
+            None => "a signal".to_string(),
+        };
+        match self {
+            Difference::Rejected(errors) => write!(f, "the program doesn't compile:\n{errors}"),
+            Difference::Failed(engine, error) => write!(f, "{engine} failed: {error}"),
+            Difference::Status(engine, got, reference, expected) => write!(
+                f,
+                "{engine} ended with {}, {reference} with {}",
+                status(got),
+                status(expected)
+            ),
+            Difference::Stdout(engine, reference, diff) => {
+                write!(
+                    f,
+                    "{engine} printed something different from {reference}:\n{diff}"
+                )
+            }
+        }
+    }
+}
+
+/// How `outcome`, from `engine`, differs from `expected`, from `reference`.
+pub fn compare(
+    (reference, expected): (Engine, &Outcome),
+    (engine, outcome): (Engine, &Outcome),
+) -> Vec<Difference> {
+    let mut differences = Vec::new();
+    if outcome.status != expected.status {
+        differences.push(Difference::Status(
+            engine,
+            outcome.status,
+            reference,
+            expected.status,
+        ));
+    }
+    if outcome.stdout != expected.stdout {
+        let diff = golden::diff::diff(&expected.stdout, &outcome.stdout);
+        differences.push(Difference::Stdout(engine, reference, diff));
+    }
+    differences
+}
+
+/// Runs `src` on each of `engines` and compares each outcome with the first engine's.
+pub fn check(name: &str, src: &str, input: &str, engines: &[Engine]) -> Vec<Difference> {
+    let program = match Program::new(name, src) {
+        Ok(program) => program,
+        Err(errors) => return vec![Difference::Rejected(errors)],
+    };
+    let mut differences = Vec::new();
+    let mut reference = None;
+    for &engine in engines {
+        match program.run(engine, input) {
+            Ok(outcome) => match &reference {
+                None => reference = Some((engine, outcome)),
+                Some((first, expected)) => {
+                    differences.extend(compare((*first, expected), (engine, &outcome)));
+                }
+            },
+            Err(error) => differences.push(Difference::Failed(engine, error)),
+        }
+    }
+    differences
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare() {
+        let outcome = |status, stdout: &str| Outcome {
+            status,
+            stdout: stdout.to_string(),
+            stderr: String::new(),
+        };
+        let (vm, c) = (Engine::Vm(OptLevel::O0), Engine::C(OptLevel::O2));
+        let expected = outcome(Some(0), "1\n2\n");
+        assert_eq!(compare((vm, &expected), (c, &expected.clone())), []);
+        assert_eq!(
+            compare((vm, &expected), (c, &outcome(Some(4), "1\n"))),
+            [
+                Difference::Status(c, Some(4), vm, Some(0)),
+                Difference::Stdout(c, vm, "@@ -1,2 +1,1 @@\n 1\n-2\n".to_string()),
+            ]
+        );
+        assert_eq!(
+            Difference::Status(c, None, vm, Some(0)).to_string(),
+            "c -O2 ended with a signal, vm -O0 with exit status 0"
+        );
+    }
+
+    #[test]
+    fn test_engines_agree() {
+        let src = r#"
+            class Main inherits IO {
+                main() : Object {
+                    let x : Int <- in_int() in {
+                        out_int(x * 2).out_string("\n");
+                        out_int(x / (x - x));
+                    }
+                };
+            };
+        "#;
+        let program = Program::new("t.cl", src).unwrap();
+        let outcome = program.run(Engine::Interp, "21\n").unwrap();
+        assert_eq!(outcome.status, Some(4));
+        assert_eq!(outcome.stdout, "42\n");
+        assert!(
+            outcome
+                .stderr
+                .starts_with("t.cl:6: runtime error: division by zero\n")
+        );
+
+        let engines: Vec<Engine> = (Engine::all().into_iter())
+            .filter(|e| e.available())
+            .collect();
+        assert_eq!(check("t.cl", src, "21\n", &engines), []);
+    }
+
+    #[test]
+    fn test_generated_programs() {
+        // Compiling with a C toolchain is slow, so these only use the engines that run
+        // in the test process.
+        let engines: Vec<Engine> = (Engine::all().into_iter())
+            .filter(|e| matches!(e, Engine::Interp | Engine::Vm(_) | Engine::Wasm(_)))
+            .collect();
+        for seed in 0..40 {
+            let src = generate::generate(seed);
+            let differences = check("gen.cl", &src, "", &engines);
+            let report: Vec<String> = differences.iter().map(|d| d.to_string()).collect();
+            assert!(
+                differences.is_empty(),
+                "seed {seed}:\n{}\n{src}",
+                report.join("\n")
+            );
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use clap::Parser;
+use difftest::Engine;
+use difftest::generate::generate;
+use std::fs;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+#[derive(Parser)]
+#[command(name = "difftest")]
+#[command(about = "Runs random COOL programs on every engine and reports where they disagree")]
+#[command(version)]
+struct Args {
+    /// The first program's seed
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+
+    /// How many programs to run, with consecutive seeds
+    #[arg(long, default_value_t = 100)]
+    count: u64,
+
+    /// Only use these engines: interp, vm, wasm, c or llvm. The first is the reference.
+    #[arg(long = "engine", value_name = "ENGINE")]
+    engines: Vec<String>,
+
+    /// Print the program for --seed instead of running anything
+    #[arg(long)]
+    print: bool,
+
+    /// Save the programs the engines disagree about in this directory
+    #[arg(long, value_name = "DIR")]
+    save: Option<PathBuf>,
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    if args.print {
+        print!("{}", generate(args.seed));
+        return ExitCode::SUCCESS;
+    }
+
+    let mut engines = Vec::new();
+    let all = Engine::all();
+    for name in &args.engines {
+        let matching: Vec<Engine> = all.iter().copied().filter(|e| e.kind() == name).collect();
+        if matching.is_empty() {
+            eprintln!("Error: unknown engine '{name}'");
+            return ExitCode::from(2);
+        }
+        engines.extend(matching);
+    }
+    if engines.is_empty() {
+        engines = all;
+    }
+    engines.retain(|engine| {
+        let available = engine.available();
+        if !available {
+            eprintln!("Skipping {engine}: its tools aren't installed");
+        }
+        available
+    });
+
+    let mut failed = 0;
+    for seed in args.seed..args.seed + args.count {
+        let src = generate(seed);
+        let differences = difftest::check(&format!("seed-{seed}.cl"), &src, "", &engines);
+        if differences.is_empty() {
+            continue;
+        }
+        failed += 1;
+        println!("FAILED seed {seed}:");
+        for difference in differences {
+            println!("{difference}");
+        }
+        if let Some(dir) = &args.save {
+            let path = dir.join(format!("seed-{seed}.cl"));
+            if let Err(e) = fs::create_dir_all(dir).and_then(|()| fs::write(&path, &src)) {
+                eprintln!("Error: failed to write '{}': {e}", path.display());
+                return ExitCode::from(3);
+            }
+        }
+    }
+
+    println!("{} agreed, {failed} disagreed", args.count - failed);
+    if failed == 0 {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
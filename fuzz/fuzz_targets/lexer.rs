@@ -0,0 +1,21 @@
+#![no_main]
+
+use lexer::Token;
+use libfuzzer_sys::fuzz_target;
+use logos::Logos;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(src) = std::str::from_utf8(data) else {
+        return;
+    };
+    // Every token, good or bad, must cover some of the input, in order, and start and
+    // end on character boundaries. That also means the lexer always finishes.
+    let mut end = 0;
+    for (_, span) in Token::lexer(src).spanned() {
+        assert!(span.start >= end, "{span:?} overlaps the token before it");
+        assert!(span.start < span.end, "{span:?} is empty");
+        assert!(span.end <= src.len(), "{span:?} is past the end");
+        assert!(src.is_char_boundary(span.start) && src.is_char_boundary(span.end));
+        end = span.end;
+    }
+});
@@ -0,0 +1,18 @@
+#![no_main]
+
+use lexer::source::SourceMap;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(src) = std::str::from_utf8(data) else {
+        return;
+    };
+    let mut sources = SourceMap::new();
+    let file = sources.add("fuzz.cl", src);
+    // Errors are fine, but reporting them mustn't fail either.
+    if let Err(errors) = parser::parse_file(src, file) {
+        for error in &errors {
+            parser::format_error(error, &sources);
+        }
+    }
+});
@@ -0,0 +1,164 @@
+//! Line diffs between an expectation and what a phase produced, in the unified format
+//! `diff -u` and `git diff` use.
+
+use std::fmt::Write;
+
+/// Unchanged lines shown around each change.
+const CONTEXT: usize = 3;
+/// Past this many cells, the changed middle of two texts is shown as entirely removed
+/// and re-added rather than aligned line by line.
+const MAX_TABLE: usize = 1 << 22;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Same,
+    Removed,
+    Added,
+}
+
+/// The hunks that turn `expected` into `actual`, each starting with an
+/// `@@ -line,count +line,count @@` header, or an empty string when they're equal.
+pub fn diff(expected: &str, actual: &str) -> String {
+    let old: Vec<&str> = expected.lines().collect();
+    let new: Vec<&str> = actual.lines().collect();
+    let ops = edit_script(&old, &new);
+
+    let mut out = String::new();
+    let changes: Vec<usize> = (0..ops.len()).filter(|&i| ops[i] != Op::Same).collect();
+    let mut next = 0;
+    while next < changes.len() {
+        // A hunk runs until the gap to the next change is too wide to share context.
+        let first = changes[next];
+        let mut last = first;
+        next += 1;
+        while next < changes.len() && changes[next] - last <= 2 * CONTEXT {
+            last = changes[next];
+            next += 1;
+        }
+        let start = first.saturating_sub(CONTEXT);
+        let end = (last + CONTEXT + 1).min(ops.len());
+
+        // Line numbers of the hunk's first line in each text.
+        let count = |op: Op, range: std::ops::Range<usize>| {
+            ops[range]
+                .iter()
+                .filter(|&&o| o == Op::Same || o == op)
+                .count()
+        };
+        let (old_start, new_start) = (count(Op::Removed, 0..start), count(Op::Added, 0..start));
+        let (old_len, new_len) = (count(Op::Removed, start..end), count(Op::Added, start..end));
+        writeln!(
+            out,
+            "@@ -{},{old_len} +{},{new_len} @@",
+            old_start + 1,
+            new_start + 1
+        )
+        .unwrap();
+        let (mut i, mut j) = (old_start, new_start);
+        for &op in &ops[start..end] {
+            match op {
+                Op::Same => {
+                    writeln!(out, " {}", old[i]).unwrap();
+                    i += 1;
+                    j += 1;
+                }
+                Op::Removed => {
+                    writeln!(out, "-{}", old[i]).unwrap();
+                    i += 1;
+                }
+                Op::Added => {
+                    writeln!(out, "+{}", new[j]).unwrap();
+                    j += 1;
+                }
+            }
+        }
+    }
+    if out.is_empty() && expected != actual {
+        // Only line endings differ.
+        out.push_str("(the texts differ only in line endings)\n");
+    }
+    out
+}
+
+/// A shortest way to edit `old` into `new`, from a longest common subsequence of the
+/// lines between their common prefix and suffix.
+fn edit_script(old: &[&str], new: &[&str]) -> Vec<Op> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let a = &old[prefix..old.len() - suffix];
+    let b = &new[prefix..new.len() - suffix];
+
+    let mut ops = vec![Op::Same; prefix];
+    if a.len() * b.len() > MAX_TABLE {
+        ops.extend(std::iter::repeat_n(Op::Removed, a.len()));
+        ops.extend(std::iter::repeat_n(Op::Added, b.len()));
+    } else {
+        // `lcs[i][j]` is the length of a longest common subsequence of `a[i..]` and
+        // `b[j..]`.
+        let width = b.len() + 1;
+        let mut lcs = vec![0u32; (a.len() + 1) * width];
+        for i in (0..a.len()).rev() {
+            for j in (0..b.len()).rev() {
+                lcs[i * width + j] = if a[i] == b[j] {
+                    lcs[(i + 1) * width + j + 1] + 1
+                } else {
+                    lcs[(i + 1) * width + j].max(lcs[i * width + j + 1])
+                };
+            }
+        }
+        let (mut i, mut j) = (0, 0);
+        while i < a.len() || j < b.len() {
+            if i < a.len() && j < b.len() && a[i] == b[j] {
+                ops.push(Op::Same);
+                i += 1;
+                j += 1;
+            } else if i < a.len()
+                && (j == b.len() || lcs[(i + 1) * width + j] >= lcs[i * width + j + 1])
+            {
+                ops.push(Op::Removed);
+                i += 1;
+            } else {
+                ops.push(Op::Added);
+                j += 1;
+            }
+        }
+    }
+    ops.extend(std::iter::repeat_n(Op::Same, suffix));
+    ops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff() {
+        assert_eq!(diff("a\nb\n", "a\nb\n"), "");
+        assert_eq!(
+            diff("a\nb\nc\n", "a\nx\nc\n"),
+            "@@ -1,3 +1,3 @@\n a\n-b\n+x\n c\n"
+        );
+        assert_eq!(diff("", "a\n"), "@@ -1,0 +1,1 @@\n+a\n");
+        assert_eq!(
+            diff("a\r\n", "a\n"),
+            "(the texts differ only in line endings)\n"
+        );
+
+        // Changes far apart get hunks of their own, with three lines of context.
+        let old: Vec<String> = (1..=20).map(|i| i.to_string()).collect();
+        let mut new = old.clone();
+        new[1] = "two".to_string();
+        new.remove(17);
+        let (old, new) = (old.join("\n") + "\n", new.join("\n") + "\n");
+        assert_eq!(
+            diff(&old, &new),
+            "@@ -1,5 +1,5 @@\n 1\n-2\n+two\n 3\n 4\n 5\n\
+             @@ -15,6 +15,5 @@\n 15\n 16\n 17\n-18\n 19\n 20\n"
+        );
+    }
+}
@@ -0,0 +1,320 @@
+//! Golden-file tests for whole COOL programs. Every `.cl` file under `samples/` and
+//! `tests/cool/` is run through each phase it gets as far as: the lexer, the parser,
+//! the semantic checks and lints, and the VM. What each phase produces is compared with
+//! an expectation file named after the program:
+//!
+//! | Extension | Holds |
+//! |-----------|-------|
+//! | `.tokens` | one `line:column<TAB>token` line per token |
+//! | `.ast` | the syntax tree in the reference parser's format |
+//! | `.diagnostics` | errors and warnings, then any runtime error and its backtrace |
+//! | `.stdout` | what the program printed |
+//!
+//! A phase that produces nothing, such as the VM for a program that doesn't compile,
+//! has no file. A `.stdin` file, if there is one, is the program's input.
+//!
+//! Expectations sit next to the programs in `tests/cool/`. The samples' are in
+//! `tests/cool/samples/`, so `samples/` only holds programs.
+
+pub mod diff;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use coolc::lint::Levels;
+use lexer::source::SourceMap;
+use runtime::backtrace;
+use runtime::{Options, Vm};
+
+/// The extensions of the expectation files, in the order the phases produce them.
+pub const EXPECTATIONS: [&str; 4] = ["tokens", "ast", "diagnostics", "stdout"];
+
+/// The extension of a program's input.
+pub const STDIN: &str = "stdin";
+
+/// The workspace this crate is part of, which holds `samples/` and `tests/cool/`.
+pub fn workspace() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .parent()
+        .expect("the crate is in a workspace")
+        .to_path_buf()
+}
+
+/// A program and where its expectations are.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Case {
+    /// The program's path relative to the workspace, with `/` separators.
+    pub name: String,
+    pub source: PathBuf,
+    /// The expectation files' path, without an extension.
+    pub expected: PathBuf,
+}
+
+impl Case {
+    pub fn expectation(&self, extension: &str) -> PathBuf {
+        self.expected.with_extension(extension)
+    }
+}
+
+/// Every program under `samples/` and `tests/cool/` in `workspace`, sorted by name.
+pub fn cases(workspace: &Path) -> io::Result<Vec<Case>> {
+    let tests = workspace.join("tests").join("cool");
+    let mut cases = Vec::new();
+    discover(workspace, "samples", &tests.join("samples"), &mut cases)?;
+    discover(workspace, "tests/cool", &tests, &mut cases)?;
+    cases.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(cases)
+}
+
+/// Adds a case for each `.cl` file under `workspace/dir`, with its expectations at the
+/// same relative path under `expected`.
+fn discover(workspace: &Path, dir: &str, expected: &Path, cases: &mut Vec<Case>) -> io::Result<()> {
+    let root = workspace.join(dir);
+    if !root.is_dir() {
+        return Ok(());
+    }
+    let mut pending = vec![root.clone()];
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                pending.push(path);
+            } else if path.extension().is_some_and(|e| e == "cl") {
+                let relative = path.strip_prefix(workspace).unwrap();
+                let within = path.strip_prefix(&root).unwrap();
+                cases.push(Case {
+                    name: relative.to_string_lossy().replace('\\', "/"),
+                    expected: expected.join(within).with_extension(""),
+                    source: path,
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// What each phase produced for a case, by expectation extension. Phases that produced
+/// nothing are left out.
+pub type Outputs = BTreeMap<&'static str, String>;
+
+/// Runs `case` through every phase it gets as far as.
+pub fn run(case: &Case) -> io::Result<Outputs> {
+    let src = fs::read_to_string(&case.source)?;
+    let input = match fs::read_to_string(case.expectation(STDIN)) {
+        Ok(input) => input,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e),
+    };
+    let name = case.source.file_name().unwrap().to_string_lossy();
+    Ok(run_source(&name, &src, &input))
+}
+
+/// Runs the program `src`, called `name` in diagnostics, with `input` as its stdin.
+pub fn run_source(name: &str, src: &str, input: &str) -> Outputs {
+    let mut sources = SourceMap::new();
+    let file = sources.add(name, src);
+    let mut outputs = Outputs::new();
+    let mut diagnostics = String::new();
+    let report = |diagnostics: &mut String, errors: &[coolc::Diagnostic]| {
+        for error in errors {
+            diagnostics.push_str(&format!("{}\n", error.render(&sources)));
+        }
+    };
+
+    let (tokens, errors) = coolc::lex(&sources, file);
+    let mut text = String::new();
+    for (token, span) in tokens {
+        let location = sources.file(file).location(span.start);
+        text.push_str(&format!(
+            "{}:{}\t{token:?}\n",
+            location.line, location.column
+        ));
+    }
+    outputs.insert("tokens", text);
+    report(&mut diagnostics, &errors);
+
+    let program = match coolc::parse(&sources) {
+        Ok(program) if errors.is_empty() => Some(program),
+        Ok(_) => None,
+        Err(errors) => {
+            report(&mut diagnostics, &errors);
+            None
+        }
+    };
+    if let Some(program) = program {
+        outputs.insert("ast", parser::reference::write(&program, &sources));
+        let errors = coolc::semant::check(&program);
+        report(&mut diagnostics, &errors);
+        if errors.is_empty() {
+            let lints = coolc::lint::check(&program, &Levels::default());
+            report(&mut diagnostics, &lints);
+
+            let module = ir::lower(&program);
+            let (mut stdin, mut stdout) = (input.as_bytes(), Vec::new());
+            let mut vm = Vm::new(&module, Options::default(), &mut stdin, &mut stdout);
+            if let Err(e) = vm.run() {
+                diagnostics.push_str(&backtrace::report(&e, &vm.backtrace(&sources)));
+            }
+            drop(vm);
+            outputs.insert("stdout", String::from_utf8_lossy(&stdout).into_owned());
+        }
+    }
+    outputs.insert("diagnostics", diagnostics);
+    outputs.retain(|_, text| !text.is_empty());
+    outputs
+}
+
+/// How an expectation disagrees with what its phase produced.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mismatch {
+    /// The phase produced output but there's no expectation file.
+    Missing(&'static str),
+    /// There's an expectation file but the phase produced nothing.
+    Unexpected(&'static str),
+    /// The output differs from the expectation, as this diff shows.
+    Differs(&'static str, String),
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mismatch::Missing(extension) => {
+                write!(
+                    f,
+                    "produced .{extension} output, but there's no expectation for it"
+                )
+            }
+            Mismatch::Unexpected(extension) => {
+                write!(f, "expected .{extension} output, but nothing was produced")
+            }
+            Mismatch::Differs(extension, diff) => write!(f, ".{extension} differs:\n{diff}"),
+        }
+    }
+}
+
+/// Compares `outputs` with `case`'s expectation files.
+pub fn check(case: &Case, outputs: &Outputs) -> io::Result<Vec<Mismatch>> {
+    let mut mismatches = Vec::new();
+    for extension in EXPECTATIONS {
+        let expected = match fs::read_to_string(case.expectation(extension)) {
+            Ok(text) => Some(text),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+        match (expected, outputs.get(extension)) {
+            (None, None) => {}
+            (None, Some(_)) => mismatches.push(Mismatch::Missing(extension)),
+            (Some(_), None) => mismatches.push(Mismatch::Unexpected(extension)),
+            (Some(expected), Some(actual)) if expected != *actual => {
+                mismatches.push(Mismatch::Differs(extension, diff::diff(&expected, actual)));
+            }
+            (Some(_), Some(_)) => {}
+        }
+    }
+    Ok(mismatches)
+}
+
+/// Makes `outputs` `case`'s expectations, writing the files that changed and removing
+/// those of phases that produced nothing. Returns whether anything changed.
+pub fn bless(case: &Case, outputs: &Outputs) -> io::Result<bool> {
+    let mismatches = check(case, outputs)?;
+    for mismatch in &mismatches {
+        match mismatch {
+            Mismatch::Missing(extension) | Mismatch::Differs(extension, _) => {
+                let path = case.expectation(extension);
+                if let Some(dir) = path.parent() {
+                    fs::create_dir_all(dir)?;
+                }
+                fs::write(path, &outputs[extension])?;
+            }
+            Mismatch::Unexpected(extension) => fs::remove_file(case.expectation(extension))?,
+        }
+    }
+    Ok(!mismatches.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_phases() {
+        let outputs = run_source("a.cl", "class Main { main() : Int { 1 }; };", "");
+        assert_eq!(
+            outputs.keys().copied().collect::<Vec<_>>(),
+            ["ast", "tokens"]
+        );
+        assert!(outputs["tokens"].starts_with("1:1\tClass\n1:7\tTypeIdentifier(\"Main\")\n"));
+
+        let outputs = run_source("a.cl", "class Main { main() : Int { 1 } };", "");
+        assert_eq!(
+            outputs.keys().copied().collect::<Vec<_>>(),
+            ["diagnostics", "tokens"]
+        );
+        assert!(outputs["diagnostics"].starts_with("a.cl:1:33: error: "));
+
+        let src = "class Main inherits IO { main() : Object { out_int(in_int() / 0) }; };";
+        let outputs = run_source("a.cl", src, "7\n");
+        assert_eq!(
+            outputs["diagnostics"],
+            "a.cl:1: runtime error: division by zero\n#0  Main.main at a.cl:1\n"
+        );
+    }
+
+    #[test]
+    fn test_check_and_bless() {
+        let dir = std::env::temp_dir().join(format!("golden-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let case = Case {
+            name: "a.cl".to_string(),
+            source: dir.join("a.cl"),
+            expected: dir.join("expected").join("a"),
+        };
+        let mut outputs = Outputs::new();
+        outputs.insert("tokens", "1:1\tClass\n".to_string());
+        outputs.insert("stdout", "hi\n".to_string());
+        assert_eq!(
+            check(&case, &outputs).unwrap(),
+            [Mismatch::Missing("tokens"), Mismatch::Missing("stdout")]
+        );
+        assert!(bless(&case, &outputs).unwrap());
+        assert_eq!(check(&case, &outputs).unwrap(), []);
+        assert!(!bless(&case, &outputs).unwrap());
+
+        outputs.remove("stdout");
+        outputs.insert("tokens", "1:1\tClass\n1:7\tInherits\n".to_string());
+        assert_eq!(
+            check(&case, &outputs).unwrap(),
+            [
+                Mismatch::Differs(
+                    "tokens",
+                    "@@ -1,1 +1,2 @@\n 1:1\tClass\n+1:7\tInherits\n".to_string()
+                ),
+                Mismatch::Unexpected("stdout"),
+            ]
+        );
+        assert!(bless(&case, &outputs).unwrap());
+        assert!(!case.expectation("stdout").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_golden_files() {
+        let mut failures = String::new();
+        let cases = cases(&workspace()).unwrap();
+        assert!(cases.iter().any(|c| c.name == "samples/hello_world.cl"));
+        for case in &cases {
+            let outputs = run(case).unwrap();
+            for mismatch in check(case, &outputs).unwrap() {
+                failures.push_str(&format!("{}: {mismatch}\n", case.name));
+            }
+        }
+        assert!(
+            failures.is_empty(),
+            "{failures}\nrun `cargo run -p golden -- --bless` to accept the new output"
+        );
+    }
+}
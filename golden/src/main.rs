@@ -0,0 +1,71 @@
+use clap::Parser;
+use std::process::ExitCode;
+
+#[derive(Parser)]
+#[command(name = "golden")]
+#[command(
+    about = "Runs the COOL programs in samples/ and tests/cool/ against their expected output"
+)]
+#[command(version)]
+struct Args {
+    /// Only run programs whose path contains one of these
+    #[arg(value_name = "FILTER")]
+    filters: Vec<String>,
+
+    /// Make what the programs produce now their expected output
+    #[arg(long)]
+    bless: bool,
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    let cases = match golden::cases(&golden::workspace()) {
+        Ok(cases) => cases,
+        Err(e) => {
+            eprintln!("Error: failed to find the programs: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let (mut passed, mut failed, mut blessed) = (0, 0, 0);
+    for case in &cases {
+        if !args.filters.is_empty() && !args.filters.iter().any(|f| case.name.contains(f)) {
+            continue;
+        }
+        let result = golden::run(case).and_then(|outputs| {
+            if args.bless {
+                golden::bless(case, &outputs).map(|changed| (changed, Vec::new()))
+            } else {
+                golden::check(case, &outputs).map(|mismatches| (false, mismatches))
+            }
+        });
+        match result {
+            Ok((true, _)) => {
+                println!("blessed {}", case.name);
+                blessed += 1;
+            }
+            Ok((false, mismatches)) if mismatches.is_empty() => passed += 1,
+            Ok((false, mismatches)) => {
+                for mismatch in mismatches {
+                    println!("FAILED {}: {mismatch}", case.name);
+                }
+                failed += 1;
+            }
+            Err(e) => {
+                println!("FAILED {}: {e}", case.name);
+                failed += 1;
+            }
+        }
+    }
+
+    if args.bless {
+        println!("{blessed} blessed, {passed} unchanged");
+    } else {
+        println!("{passed} passed, {failed} failed");
+    }
+    if failed == 0 {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
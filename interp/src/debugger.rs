@@ -0,0 +1,1031 @@
+//! A gdb-like source-level debugger for the interpreter.
+//!
+//! It reads commands from any [`BufRead`], so a test can script a whole session and
+//! compare the transcript. When the commands aren't typed on a terminal, each one is
+//! echoed after the prompt to keep the transcript readable.
+//!
+//! A frame's location is the line of the expression it is evaluating, which for a
+//! caller is the dispatch it's waiting on. A `file:line` breakpoint stops every time a
+//! statement starting on that line runs, or on the first line after it that has one.
+
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+use lexer::lexer::string_literal;
+use lexer::source::SourceMap;
+use lexer::{FileId, Symbol};
+use parser::ast::{self, Expr, ExprKind, Span};
+use parser::visit::{self, Visitor};
+
+use crate::{Event, Frame, Hook, Interpreter, Resume, RuntimeError, State, Value};
+
+const PROMPT: &str = "(cool) ";
+
+/// Statement summaries longer than this many characters are cut short.
+const SUMMARY_WIDTH: usize = 72;
+
+const HELP: &str = "\
+break LOCATION    set a breakpoint at Class.method or file:line (b)
+delete [N]        delete breakpoint N, or every breakpoint
+info breakpoints  list the breakpoints
+info args         print the formals of the selected frame
+info locals       print the let and case variables of the selected frame
+run, continue     run until a breakpoint or the end of the program (r, c)
+step              run to the next statement, entering calls (s)
+next              run to the next statement in this frame or a caller (n)
+finish            run until the selected frame returns
+print NAME        print self, a local or an attribute (p)
+backtrace         print the frames being run, innermost first (bt, where)
+frame [N]         select frame N, or print the selected frame (f)
+up, down          select the caller or the callee of the selected frame
+quit              stop the program and leave (q)
+";
+
+/// Runs `program` under a debugger that reads its commands from `commands`, then says
+/// how the program ended. `echo` repeats every command after its prompt.
+pub fn run(
+    program: &ast::Program,
+    sources: &SourceMap,
+    input: &mut dyn BufRead,
+    output: &mut dyn Write,
+    commands: &mut dyn BufRead,
+    echo: bool,
+) -> Result<(), RuntimeError> {
+    let mut debugger = Debugger::new(program, sources, commands, echo);
+    let result = Interpreter::new(program, input, output)
+        .with_hook(&mut debugger)
+        .run();
+    if !debugger.quit {
+        match &result {
+            Ok(()) => writeln!(output, "Program exited normally.")?,
+            Err(_) => writeln!(output, "Program exited with an error.")?,
+        }
+    }
+    result
+}
+
+/// When the program should next stop, besides at breakpoints.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    /// Not started: stop at the very first event.
+    Start,
+    Continue,
+    Step,
+    /// Stop at a statement of a frame at most this deep.
+    Next(usize),
+    /// Stop when the frame this deep returns.
+    Finish(usize),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Target {
+    /// The first statement of a method.
+    Method { class: Symbol, method: Symbol },
+    /// Every statement starting on a line.
+    Line { file: FileId, line: usize },
+}
+
+struct Breakpoint {
+    id: usize,
+    target: Target,
+    /// Where the breakpoint is, as `Class.feature (file:line)`.
+    location: String,
+}
+
+pub struct Debugger<'p, 'c> {
+    program: &'p ast::Program,
+    sources: &'c SourceMap,
+    commands: &'c mut dyn BufRead,
+    echo: bool,
+    mode: Mode,
+    breakpoints: Vec<Breakpoint>,
+    next_id: usize,
+    /// A method breakpoint that was called, and the depth of its frame, until the
+    /// frame's first statement.
+    entered: Option<(usize, usize)>,
+    /// The selected frame, counting from the innermost.
+    selected: usize,
+    /// The frame of the last stop, to only print it again once it changes.
+    last_frame: Option<(usize, Symbol, Symbol)>,
+    quit: bool,
+}
+
+impl<'p, 'c> Debugger<'p, 'c> {
+    pub fn new(
+        program: &'p ast::Program,
+        sources: &'c SourceMap,
+        commands: &'c mut dyn BufRead,
+        echo: bool,
+    ) -> Self {
+        Debugger {
+            program,
+            sources,
+            commands,
+            echo,
+            mode: Mode::Start,
+            breakpoints: Vec::new(),
+            next_id: 1,
+            entered: None,
+            selected: 0,
+            last_frame: None,
+            quit: false,
+        }
+    }
+
+    /// Reads and runs commands until one resumes the program.
+    fn prompt(&mut self, state: &State<'p>, output: &mut dyn Write) -> io::Result<Resume> {
+        loop {
+            output.write_all(PROMPT.as_bytes())?;
+            let mut line = String::new();
+            if !self.echo {
+                output.flush()?;
+            }
+            if self.commands.read_line(&mut line)? == 0 {
+                if self.echo {
+                    writeln!(output)?;
+                }
+                self.quit = true;
+                return Ok(Resume::Quit);
+            }
+            let line = line.trim();
+            if self.echo {
+                writeln!(output, "{line}")?;
+            }
+            if let Some(resume) = self.command(state, line, output)? {
+                return Ok(resume);
+            }
+        }
+    }
+
+    /// Runs one command, returning how to resume the program if it does.
+    fn command(
+        &mut self,
+        state: &State<'p>,
+        line: &str,
+        output: &mut dyn Write,
+    ) -> io::Result<Option<Resume>> {
+        let (command, arg) = line
+            .split_once(char::is_whitespace)
+            .map_or((line, ""), |(command, arg)| (command, arg.trim()));
+        let started = self.mode != Mode::Start;
+        let depth = state.frames().len();
+        match command {
+            "" => {}
+            "break" | "b" => self.add_breakpoint(arg, output)?,
+            "delete" | "d" => self.delete(arg, output)?,
+            "info" | "i" => match arg {
+                "breakpoints" | "b" | "break" => self.info_breakpoints(output)?,
+                "args" | "locals" if !started => writeln!(output, "No frame selected.")?,
+                "args" => self.info_variables(state, true, output)?,
+                "locals" => self.info_variables(state, false, output)?,
+                _ => writeln!(output, "Undefined info command: \"{arg}\".  Try \"help\".")?,
+            },
+            "run" | "r" | "continue" | "c" => {
+                self.mode = Mode::Continue;
+                return Ok(Some(Resume::Continue));
+            }
+            "step" | "s" => {
+                self.mode = Mode::Step;
+                return Ok(Some(Resume::Continue));
+            }
+            "next" | "n" => {
+                self.mode = if started {
+                    Mode::Next(depth)
+                } else {
+                    Mode::Step
+                };
+                return Ok(Some(Resume::Continue));
+            }
+            "finish" if !started || depth == 0 => {
+                writeln!(output, "The program is not being run.")?
+            }
+            "finish" => {
+                let frame = depth - self.selected;
+                writeln!(
+                    output,
+                    "Run till exit from #{}  {}",
+                    self.selected,
+                    self.describe(state, &state.frames()[frame - 1])
+                )?;
+                self.mode = Mode::Finish(frame);
+                return Ok(Some(Resume::Continue));
+            }
+            "print" | "p" if arg.is_empty() => writeln!(output, "Argument required.")?,
+            "print" | "p" | "backtrace" | "bt" | "where" | "frame" | "f" | "up" | "down"
+                if !started || depth == 0 =>
+            {
+                writeln!(output, "No stack.")?
+            }
+            "print" | "p" => {
+                let frame = &state.frames()[depth - 1 - self.selected];
+                match state.lookup(frame, Symbol::intern(arg)) {
+                    Some(value) => writeln!(output, "{arg} = {}", show(state, &value))?,
+                    None => writeln!(output, "No symbol \"{arg}\" in current context.")?,
+                }
+            }
+            "backtrace" | "bt" | "where" => {
+                for (i, frame) in state.frames().iter().rev().enumerate() {
+                    writeln!(output, "#{i}  {}", self.describe(state, frame))?;
+                }
+            }
+            "frame" | "f" | "up" | "down" => {
+                let level = match command {
+                    "up" => self.selected + 1,
+                    "down" if self.selected == 0 => {
+                        writeln!(
+                            output,
+                            "Bottom (innermost) frame selected; you cannot go down."
+                        )?;
+                        return Ok(None);
+                    }
+                    "down" => self.selected - 1,
+                    _ if arg.is_empty() => self.selected,
+                    _ => match arg.parse() {
+                        Ok(level) => level,
+                        Err(_) => {
+                            writeln!(output, "Invalid frame number \"{arg}\".")?;
+                            return Ok(None);
+                        }
+                    },
+                };
+                if level >= depth {
+                    match command {
+                        "up" => writeln!(output, "Initial frame selected; you cannot go up.")?,
+                        _ => writeln!(output, "No frame at level {arg}.")?,
+                    }
+                    return Ok(None);
+                }
+                self.selected = level;
+                let frame = &state.frames()[depth - 1 - level];
+                writeln!(output, "#{level}  {}", self.describe(state, frame))?;
+            }
+            "help" | "h" => output.write_all(HELP.as_bytes())?,
+            "quit" | "q" => {
+                self.quit = true;
+                return Ok(Some(Resume::Quit));
+            }
+            _ => writeln!(output, "Undefined command: \"{command}\".  Try \"help\".")?,
+        }
+        Ok(None)
+    }
+
+    fn add_breakpoint(&mut self, spec: &str, output: &mut dyn Write) -> io::Result<()> {
+        let target = match spec.rsplit_once(':') {
+            Some((file, line)) if line.parse::<usize>().is_ok() => {
+                self.line_target(file, line.parse().unwrap())
+            }
+            _ => self.method_target(spec),
+        };
+        match target {
+            Ok((target, location)) => {
+                writeln!(output, "Breakpoint {} at {location}", self.next_id)?;
+                self.breakpoints.push(Breakpoint {
+                    id: self.next_id,
+                    target,
+                    location,
+                });
+                self.next_id += 1;
+            }
+            Err(message) => writeln!(output, "{message}")?,
+        }
+        Ok(())
+    }
+
+    /// Resolves `Class.method`.
+    fn method_target(&self, spec: &str) -> Result<(Target, String), String> {
+        if spec.is_empty() {
+            return Err("Argument required (location).".to_string());
+        }
+        let not_defined = || format!("Function \"{spec}\" not defined.");
+        let (class, method) = spec.split_once('.').ok_or_else(not_defined)?;
+        let class = self
+            .program
+            .classes
+            .iter()
+            .find(|c| c.name == class)
+            .ok_or_else(not_defined)?;
+        let method = class
+            .features
+            .iter()
+            .find_map(|feature| match feature {
+                ast::Feature::Method(m) if m.name == method => Some(m),
+                _ => None,
+            })
+            .ok_or_else(not_defined)?;
+        let target = Target::Method {
+            class: class.name,
+            method: method.name,
+        };
+        let location = format!(
+            "{}.{} ({})",
+            class.name,
+            method.name,
+            self.location(method.span)
+        );
+        Ok((target, location))
+    }
+
+    /// Resolves `file:line` to the first line at or after it where a statement starts.
+    fn line_target(&self, file: &str, line: usize) -> Result<(Target, String), String> {
+        let (id, source) = self
+            .sources
+            .files()
+            .find(|(_, f)| f.name() == file || Path::new(f.name()).ends_with(file))
+            .ok_or_else(|| format!("No source file named {file}."))?;
+        // The earliest such line, and the class and feature of a statement on it.
+        let mut found: Option<(usize, Symbol, Symbol)> = None;
+        for class in &self.program.classes {
+            for feature in &class.features {
+                if feature_span(feature).context != id {
+                    continue;
+                }
+                let name = match feature {
+                    ast::Feature::Method(method) => method.name,
+                    ast::Feature::Attribute(attr) => attr.name,
+                };
+                let mut statements = Statements(|expr: &Expr| {
+                    let start = self.line(expr.span);
+                    if start >= line && found.is_none_or(|(best, ..)| start < best) {
+                        found = Some((start, class.name, name));
+                    }
+                });
+                statements.visit_feature(feature);
+            }
+        }
+        let (line, class, name) =
+            found.ok_or_else(|| format!("No line {line} in file \"{file}\"."))?;
+        let location = format!("{class}.{name} ({}:{line})", source.name());
+        Ok((Target::Line { file: id, line }, location))
+    }
+
+    fn delete(&mut self, arg: &str, output: &mut dyn Write) -> io::Result<()> {
+        if arg.is_empty() {
+            self.breakpoints.clear();
+            return Ok(());
+        }
+        for arg in arg.split_whitespace() {
+            match arg.parse::<usize>() {
+                Ok(id) if self.breakpoints.iter().any(|b| b.id == id) => {
+                    self.breakpoints.retain(|b| b.id != id);
+                }
+                Ok(id) => writeln!(output, "No breakpoint number {id}.")?,
+                Err(_) => writeln!(output, "Convenience variable must have integer value.")?,
+            }
+        }
+        Ok(())
+    }
+
+    fn info_breakpoints(&self, output: &mut dyn Write) -> io::Result<()> {
+        if self.breakpoints.is_empty() {
+            return writeln!(output, "No breakpoints.");
+        }
+        writeln!(output, "Num     What")?;
+        for breakpoint in &self.breakpoints {
+            writeln!(output, "{:<7} {}", breakpoint.id, breakpoint.location)?;
+        }
+        Ok(())
+    }
+
+    fn info_variables(
+        &self,
+        state: &State<'p>,
+        formals: bool,
+        output: &mut dyn Write,
+    ) -> io::Result<()> {
+        let Some(frame) = state.frames().iter().rev().nth(self.selected) else {
+            return writeln!(output, "No frame selected.");
+        };
+        let variables = match formals {
+            true => &frame.locals[..frame.formals],
+            false => &frame.locals[frame.formals..],
+        };
+        if variables.is_empty() {
+            return match formals {
+                true => writeln!(output, "No arguments."),
+                false => writeln!(output, "No locals."),
+            };
+        }
+        for (name, value) in variables {
+            writeln!(output, "{name} = {}", show(state, value))?;
+        }
+        Ok(())
+    }
+
+    /// `Class.method (formal = value, ...) at file:line`
+    fn describe(&self, state: &State<'p>, frame: &Frame<'p>) -> String {
+        let args: Vec<String> = frame.locals[..frame.formals]
+            .iter()
+            .map(|(name, value)| format!("{name} = {}", show(state, value)))
+            .collect();
+        format!(
+            "{}.{} ({}) at {}",
+            frame.class,
+            frame.method,
+            args.join(", "),
+            self.location(frame.location)
+        )
+    }
+
+    fn location(&self, span: Span) -> String {
+        let file = self.sources.file(span.context);
+        format!("{}:{}", file.name(), self.line(span))
+    }
+
+    /// The line `span` starts on.
+    fn line(&self, span: Span) -> usize {
+        let file = self.sources.file(span.context);
+        file.location(span.start).line
+    }
+
+    /// The breakpoint, if any, that makes the program stop at `event`.
+    fn hit(&mut self, state: &State<'p>, event: &Event<'_, 'p>) -> Option<usize> {
+        let depth = state.frames().len();
+        let frame = state.frames().last()?;
+        match event {
+            Event::Call => {
+                self.entered = self.breakpoints.iter().find_map(|b| match b.target {
+                    Target::Method { class, method }
+                        if class == frame.class && method == frame.method =>
+                    {
+                        Some((depth, b.id))
+                    }
+                    _ => None,
+                });
+                None
+            }
+            Event::Statement(expr) => {
+                if let Some((entered, id)) = self.entered.take() {
+                    if entered == depth {
+                        return Some(id);
+                    }
+                }
+                let here = Target::Line {
+                    file: expr.span.context,
+                    line: self.line(expr.span),
+                };
+                self.breakpoints
+                    .iter()
+                    .find(|b| b.target == here)
+                    .map(|b| b.id)
+            }
+            Event::Return(_) | Event::Error(_) => None,
+        }
+    }
+
+    /// Says where the program stopped, printing the frame when it isn't the one of the
+    /// last stop.
+    fn report(
+        &mut self,
+        state: &State<'p>,
+        breakpoint: Option<usize>,
+        statement: Option<&Expr>,
+        output: &mut dyn Write,
+    ) -> io::Result<()> {
+        self.selected = 0;
+        let Some(frame) = state.frames().last() else {
+            return Ok(());
+        };
+        let key = (state.frames().len(), frame.class, frame.method);
+        match breakpoint {
+            Some(id) => writeln!(output, "Breakpoint {id}, {}", self.describe(state, frame))?,
+            None if self.last_frame != Some(key) => {
+                writeln!(output, "{}", self.describe(state, frame))?
+            }
+            None => {}
+        }
+        self.last_frame = Some(key);
+        if let Some(statement) = statement {
+            writeln!(output, "-> {}", summary(statement))?;
+        }
+        Ok(())
+    }
+}
+
+impl<'p> Hook<'p> for Debugger<'p, '_> {
+    fn event(
+        &mut self,
+        state: &State<'p>,
+        event: Event<'_, 'p>,
+        output: &mut dyn Write,
+    ) -> io::Result<Resume> {
+        if self.mode == Mode::Start {
+            if let Event::Error(error) = event {
+                writeln!(output, "Runtime error: {error}")?;
+                return self.prompt(state, output);
+            }
+            // The first event comes before any command, so it still has to be looked at
+            // once the commands have set breakpoints and a mode.
+            if self.prompt(state, output)? == Resume::Quit {
+                return Ok(Resume::Quit);
+            }
+        }
+        let breakpoint = self.hit(state, &event);
+        let depth = state.frames().len();
+        match event {
+            Event::Call => return Ok(Resume::Continue),
+            Event::Statement(statement) => {
+                let stop = breakpoint.is_some()
+                    || match self.mode {
+                        Mode::Step => true,
+                        Mode::Next(next) => depth <= next,
+                        _ => false,
+                    };
+                if !stop {
+                    return Ok(Resume::Continue);
+                }
+                self.report(state, breakpoint, Some(statement), output)?;
+            }
+            Event::Return(value) => {
+                if self.mode != Mode::Finish(depth) {
+                    return Ok(Resume::Continue);
+                }
+                writeln!(output, "Value returned: {}", show(state, value))?;
+                self.selected = 0;
+            }
+            Event::Error(error) => {
+                writeln!(output, "Runtime error: {error}")?;
+                self.last_frame = None;
+                self.report(state, None, None, output)?;
+            }
+        }
+        self.prompt(state, output)
+    }
+}
+
+/// Calls its function on every expression the interpreter runs as a statement, as
+/// described by [`Event::Statement`].
+struct Statements<F>(F);
+
+impl<F: FnMut(&Expr)> Statements<F> {
+    fn statement(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Block(exprs) => exprs.iter().for_each(|expr| self.statement(expr)),
+            _ => {
+                (self.0)(expr);
+                self.visit_expr(expr);
+            }
+        }
+    }
+}
+
+impl<'ast, F: FnMut(&Expr)> Visitor<'ast> for Statements<F> {
+    fn visit_method(&mut self, method: &'ast ast::MethodFeature) {
+        self.statement(&method.body);
+    }
+
+    fn visit_attribute(&mut self, attr: &'ast ast::AttributeFeature) {
+        if let Some(init) = &attr.init {
+            self.statement(init);
+        }
+    }
+
+    fn visit_expr(&mut self, expr: &'ast Expr) {
+        match &expr.kind {
+            ExprKind::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => {
+                self.visit_expr(cond);
+                self.statement(then_branch);
+                self.statement(else_branch);
+            }
+            ExprKind::While { cond, body } => {
+                self.visit_expr(cond);
+                self.statement(body);
+            }
+            ExprKind::Let { bindings, body } => {
+                for binding in bindings {
+                    self.visit_let_binding(binding);
+                }
+                self.statement(body);
+            }
+            _ => visit::walk_expr(self, expr),
+        }
+    }
+
+    fn visit_case_branch(&mut self, branch: &'ast ast::CaseBranch) {
+        self.statement(&branch.expr);
+    }
+}
+
+fn feature_span(feature: &ast::Feature) -> Span {
+    match feature {
+        ast::Feature::Method(method) => method.span,
+        ast::Feature::Attribute(attr) => attr.span,
+    }
+}
+
+/// Shows a value, with the attributes of an object but only the class of the objects
+/// they hold.
+fn show<'p>(state: &State<'p>, value: &Value<'p>) -> String {
+    match value {
+        Value::Object(object) => {
+            let fields: Vec<String> = state
+                .fields(value)
+                .iter()
+                .map(|(name, value)| format!("{name} = {}", show_nested(value)))
+                .collect();
+            format!("{} {{{}}}", object.class, fields.join(", "))
+        }
+        _ => show_nested(value),
+    }
+}
+
+fn show_nested(value: &Value<'_>) -> String {
+    match value {
+        Value::Void => "void".to_string(),
+        Value::Int(i) => i.to_string(),
+        Value::Bool(b) => b.to_string(),
+        Value::Str(s) => format!("{:?}", &**s),
+        Value::Object(object) => format!("<{}>", object.class),
+    }
+}
+
+/// Binding strength of each kind of expression, loosest first.
+const LET: u8 = 0;
+const ASSIGN: u8 = 1;
+const NOT: u8 = 2;
+const COMPARE: u8 = 3;
+const ADD: u8 = 4;
+const MULTIPLY: u8 = 5;
+const ISVOID: u8 = 6;
+const NEGATE: u8 = 7;
+const DISPATCH: u8 = 8;
+const ATOM: u8 = 9;
+
+/// A statement as one line of COOL, cut short if it's long.
+fn summary(expr: &Expr) -> String {
+    let text = print(expr).1;
+    if text.chars().count() <= SUMMARY_WIDTH {
+        return text;
+    }
+    let cut: String = text.chars().take(SUMMARY_WIDTH - 3).collect();
+    format!("{cut}...")
+}
+
+/// Prints `expr` on one line, with its binding strength.
+fn print(expr: &Expr) -> (u8, String) {
+    // `operand` prints a subexpression that must bind at least as tightly as `min`.
+    let operand = |expr: &Expr, min: u8| {
+        let (strength, text) = print(expr);
+        if strength < min {
+            format!("({text})")
+        } else {
+            text
+        }
+    };
+    let binary = |op: &str, lhs: &Expr, rhs: &Expr, strength: u8, rhs_min: u8| {
+        let text = format!("{} {op} {}", operand(lhs, strength), operand(rhs, rhs_min));
+        (strength, text)
+    };
+    // Comparisons don't associate, so neither side may be another comparison.
+    let compare = |op: &str, lhs: &Expr, rhs: &Expr| {
+        let text = format!("{} {op} {}", operand(lhs, ADD), operand(rhs, ADD));
+        (COMPARE, text)
+    };
+    let args = |args: &[Expr]| {
+        let args: Vec<String> = args.iter().map(|arg| print(arg).1).collect();
+        args.join(", ")
+    };
+    match &expr.kind {
+        ExprKind::Assign { name, expr } => (ASSIGN, format!("{name} <- {}", operand(expr, ASSIGN))),
+        ExprKind::Dispatch {
+            expr,
+            static_type,
+            method,
+            args: arguments,
+        } => {
+            let static_type = static_type
+                .as_ref()
+                .map_or(String::new(), |t| format!("@{t}"));
+            let receiver = operand(expr, DISPATCH);
+            (
+                DISPATCH,
+                format!("{receiver}{static_type}.{method}({})", args(arguments)),
+            )
+        }
+        ExprKind::FuncCall {
+            name,
+            args: arguments,
+        } => (ATOM, format!("{name}({})", args(arguments))),
+        ExprKind::If {
+            cond,
+            then_branch,
+            else_branch,
+        } => (
+            ATOM,
+            format!(
+                "if {} then {} else {} fi",
+                print(cond).1,
+                print(then_branch).1,
+                print(else_branch).1
+            ),
+        ),
+        ExprKind::While { cond, body } => (
+            ATOM,
+            format!("while {} loop {} pool", print(cond).1, print(body).1),
+        ),
+        ExprKind::Block(exprs) => {
+            let exprs: Vec<String> = exprs.iter().map(|e| print(e).1).collect();
+            (ATOM, format!("{{ {}; }}", exprs.join("; ")))
+        }
+        ExprKind::Let { bindings, body } => {
+            let bindings: Vec<String> = bindings
+                .iter()
+                .map(|binding| match &binding.init {
+                    Some(init) => {
+                        format!("{} : {} <- {}", binding.name, binding.typ, print(init).1)
+                    }
+                    None => format!("{} : {}", binding.name, binding.typ),
+                })
+                .collect();
+            (
+                LET,
+                format!("let {} in {}", bindings.join(", "), print(body).1),
+            )
+        }
+        ExprKind::Case { expr, branches } => {
+            let branches: Vec<String> = branches
+                .iter()
+                .map(|b| format!("{} : {} => {}", b.name, b.typ, print(&b.expr).1))
+                .collect();
+            let expr = print(expr).1;
+            (
+                ATOM,
+                format!("case {expr} of {}; esac", branches.join("; ")),
+            )
+        }
+        ExprKind::New(class) => (ATOM, format!("new {class}")),
+        ExprKind::IsVoid(expr) => (ISVOID, format!("isvoid {}", operand(expr, ISVOID))),
+        ExprKind::Plus(lhs, rhs) => binary("+", lhs, rhs, ADD, MULTIPLY),
+        ExprKind::Minus(lhs, rhs) => binary("-", lhs, rhs, ADD, MULTIPLY),
+        ExprKind::Times(lhs, rhs) => binary("*", lhs, rhs, MULTIPLY, ISVOID),
+        ExprKind::Divide(lhs, rhs) => binary("/", lhs, rhs, MULTIPLY, ISVOID),
+        ExprKind::Lt(lhs, rhs) => compare("<", lhs, rhs),
+        ExprKind::Le(lhs, rhs) => compare("<=", lhs, rhs),
+        ExprKind::Eq(lhs, rhs) => compare("=", lhs, rhs),
+        ExprKind::Not(expr) => (NOT, format!("not {}", operand(expr, NOT))),
+        ExprKind::Negate(expr) => (NEGATE, format!("~{}", operand(expr, NEGATE))),
+        ExprKind::Paren(expr) => (ATOM, format!("({})", print(expr).1)),
+        ExprKind::Id(name) => (ATOM, name.to_string()),
+        ExprKind::Integer(i) => (ATOM, i.to_string()),
+        ExprKind::String(s) => (ATOM, string_literal(s)),
+        ExprKind::True => (ATOM, "true".to_string()),
+        ExprKind::False => (ATOM, "false".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FACT: &str = "\
+class Main inherits IO {
+    count : Int <- 1 + 1;
+    fact(n : Int) : Int {
+        if n = 0 then 1 else n * fact(n - 1) fi
+    };
+    main() : Object {
+        let x : Int <- fact(3), s : String <- \"done\" in {
+            out_int(x);
+            out_string(s.concat(\"\\n\"));
+        }
+    };
+};
+";
+
+    /// Runs `src` as `fact.cl` under the debugger with `commands`, returning the
+    /// transcript.
+    fn debug(src: &str, commands: &str) -> String {
+        let mut sources = SourceMap::new();
+        let file = sources.add("tests/fact.cl", src);
+        let program = parser::parse_file(src, file).unwrap();
+        let mut output = Vec::new();
+        let _ = run(
+            &program,
+            &sources,
+            &mut "".as_bytes(),
+            &mut output,
+            &mut commands.as_bytes(),
+            true,
+        );
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_method_breakpoint_and_backtrace() {
+        let transcript = debug(
+            FACT,
+            "break Main.fact\nrun\nc\nc\nbt\nprint n\np count\np self\np z\ndelete\nc\n",
+        );
+        assert_eq!(
+            transcript,
+            "\
+(cool) break Main.fact
+Breakpoint 1 at Main.fact (tests/fact.cl:3)
+(cool) run
+Breakpoint 1, Main.fact (n = 3) at tests/fact.cl:4
+-> if n = 0 then 1 else n * fact(n - 1) fi
+(cool) c
+Breakpoint 1, Main.fact (n = 2) at tests/fact.cl:4
+-> if n = 0 then 1 else n * fact(n - 1) fi
+(cool) c
+Breakpoint 1, Main.fact (n = 1) at tests/fact.cl:4
+-> if n = 0 then 1 else n * fact(n - 1) fi
+(cool) bt
+#0  Main.fact (n = 1) at tests/fact.cl:4
+#1  Main.fact (n = 2) at tests/fact.cl:4
+#2  Main.fact (n = 3) at tests/fact.cl:4
+#3  Main.main () at tests/fact.cl:7
+(cool) print n
+n = 1
+(cool) p count
+count = 2
+(cool) p self
+self = Main {count = 2}
+(cool) p z
+No symbol \"z\" in current context.
+(cool) delete
+(cool) c
+6done
+Program exited normally.
+"
+        );
+    }
+
+    #[test]
+    fn test_stepping() {
+        let transcript = debug(
+            FACT,
+            "b fact.cl:7\ninfo breakpoints\nr\nn\ninfo locals\ninfo args\ns\ns\nfinish\nn\nq\n",
+        );
+        assert_eq!(
+            transcript,
+            "\
+(cool) b fact.cl:7
+Breakpoint 1 at Main.main (tests/fact.cl:7)
+(cool) info breakpoints
+Num     What
+1       Main.main (tests/fact.cl:7)
+(cool) r
+Breakpoint 1, Main.main () at tests/fact.cl:7
+-> let x : Int <- fact(3), s : String <- \"done\" in { out_int(x); out_str...
+(cool) n
+-> out_int(x)
+(cool) info locals
+x = 6
+s = \"done\"
+(cool) info args
+No arguments.
+(cool) s
+6-> out_string(s.concat(\"\\n\"))
+(cool) s
+done
+Program exited normally.
+"
+        );
+        let transcript = debug(FACT, "b Main.main\nr\ns\ns\nup\nfinish\ndown\nn\nq\n");
+        assert_eq!(
+            transcript,
+            "\
+(cool) b Main.main
+Breakpoint 1 at Main.main (tests/fact.cl:6)
+(cool) r
+Breakpoint 1, Main.main () at tests/fact.cl:7
+-> let x : Int <- fact(3), s : String <- \"done\" in { out_int(x); out_str...
+(cool) s
+Main.fact (n = 3) at tests/fact.cl:4
+-> if n = 0 then 1 else n * fact(n - 1) fi
+(cool) s
+-> n * fact(n - 1)
+(cool) up
+#1  Main.main () at tests/fact.cl:7
+(cool) finish
+Run till exit from #1  Main.main () at tests/fact.cl:7
+6done
+Value returned: Main {count = 2}
+(cool) down
+Bottom (innermost) frame selected; you cannot go down.
+(cool) n
+Program exited normally.
+"
+        );
+    }
+
+    #[test]
+    fn test_line_breakpoint_in_a_loop() {
+        let src = "\
+class Main inherits IO {
+    i : Int;
+    main() : Object {
+        while i < 3 loop {
+            i <- i + 1;
+            out_int(i);
+        } pool
+    };
+};
+";
+        let transcript = debug(src, "b fact.cl:6\nr\nbt\nc\nc\np i\nc\n");
+        assert_eq!(
+            transcript,
+            "\
+(cool) b fact.cl:6
+Breakpoint 1 at Main.main (tests/fact.cl:6)
+(cool) r
+Breakpoint 1, Main.main () at tests/fact.cl:6
+-> out_int(i)
+(cool) bt
+#0  Main.main () at tests/fact.cl:6
+(cool) c
+1Breakpoint 1, Main.main () at tests/fact.cl:6
+-> out_int(i)
+(cool) c
+2Breakpoint 1, Main.main () at tests/fact.cl:6
+-> out_int(i)
+(cool) p i
+i = 3
+(cool) c
+3Program exited normally.
+"
+        );
+        assert_eq!(transcript.matches("Breakpoint 1, ").count(), 3);
+
+        // A line with no statement of its own stops at the next one that has one.
+        let transcript = debug(src, "b fact.cl:3\nr\nq\n");
+        assert!(
+            transcript
+                .starts_with("(cool) b fact.cl:3\nBreakpoint 1 at Main.main (tests/fact.cl:4)\n"),
+            "{transcript}"
+        );
+    }
+
+    #[test]
+    fn test_attributes_and_errors() {
+        let src = "\
+class Main {
+    a : Main;
+    b : Int <- 7;
+    -- Dispatches on void.
+    main() : Object { a.main() };
+};
+";
+        let transcript = debug(
+            src,
+            "b fact.cl:3\nb Main.nope\nb nope.cl:1\nb fact.cl:40\nr\nbt\nc\nbt\nframe 5\nfoo\nc\n",
+        );
+        assert_eq!(
+            transcript,
+            "\
+(cool) b fact.cl:3
+Breakpoint 1 at Main.b (tests/fact.cl:3)
+(cool) b Main.nope
+Function \"Main.nope\" not defined.
+(cool) b nope.cl:1
+No source file named nope.cl.
+(cool) b fact.cl:40
+No line 40 in file \"fact.cl\".
+(cool) r
+Breakpoint 1, Main._init () at tests/fact.cl:3
+-> 7
+(cool) bt
+#0  Main._init () at tests/fact.cl:3
+(cool) c
+Runtime error: dispatch to void (main)
+Main.main () at tests/fact.cl:5
+(cool) bt
+#0  Main.main () at tests/fact.cl:5
+(cool) frame 5
+No frame at level 5.
+(cool) foo
+Undefined command: \"foo\".  Try \"help\".
+(cool) c
+Program exited with an error.
+"
+        );
+    }
+
+    #[test]
+    fn test_summary() {
+        let program = parser::parse(
+            "class A { f() : Object { (1 + 2) * ~x - (not y <- isvoid z) }; \
+             g() : Object { (let a : Int in a).f() }; };",
+        )
+        .unwrap();
+        let bodies: Vec<String> = program.classes[0]
+            .features
+            .iter()
+            .map(|feature| match feature {
+                ast::Feature::Method(method) => summary(&method.body),
+                ast::Feature::Attribute(_) => unreachable!(),
+            })
+            .collect();
+        assert_eq!(
+            bodies,
+            [
+                "(1 + 2) * ~x - (not (y <- isvoid z))",
+                "(let a : Int in a).f()"
+            ]
+        );
+    }
+}
@@ -0,0 +1,954 @@
+//! A tree-walking interpreter for [`ast::Program`]s, with hooks for a debugger.
+//!
+//! The `runtime` crate's VM runs the optimized IR, which has lost the program's shape.
+//! This interpreter evaluates the AST itself, so every frame still knows its method,
+//! its formals and its `let` and `case` variables by name, and a [`Hook`] can stop it
+//! between statements to look at them. Objects are reference counted and never
+//! collected while they're part of a cycle.
+
+pub mod debugger;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::rc::Rc;
+
+use ir::INIT_METHOD;
+use lexer::source::SourceMap;
+use lexer::{Symbol, sym};
+use parser::ast::{self, Expr, ExprKind, Span};
+
+pub use runtime::RuntimeError;
+use runtime::backtrace::StackFrame;
+
+/// Frames deeper than this are reported as a stack overflow. Every frame takes a few
+/// recursive calls of the evaluator, so deep programs need a thread with a big stack.
+pub const MAX_FRAMES: usize = 10_000;
+
+/// The built-in classes, their parents and the methods the interpreter implements
+/// for them.
+const BUILTIN_CLASSES: &[(Symbol, Option<Symbol>, &[Symbol])] = &[
+    (sym::OBJECT, None, &[sym::ABORT, sym::TYPE_NAME, sym::COPY]),
+    (
+        sym::IO,
+        Some(sym::OBJECT),
+        &[sym::OUT_STRING, sym::OUT_INT, sym::IN_STRING, sym::IN_INT],
+    ),
+    (sym::INT, Some(sym::OBJECT), &[]),
+    (sym::BOOL, Some(sym::OBJECT), &[]),
+    (
+        sym::STRING,
+        Some(sym::OBJECT),
+        &[sym::LENGTH, sym::CONCAT, sym::SUBSTR],
+    ),
+];
+
+#[derive(Debug, Clone)]
+pub enum Value<'p> {
+    Void,
+    Int(i32),
+    Bool(bool),
+    Str(Rc<str>),
+    Object(Rc<Object<'p>>),
+}
+
+#[derive(Debug)]
+pub struct Object<'p> {
+    pub class: Symbol,
+    /// One value per attribute, in the order [`State::attributes`] lists them.
+    pub fields: RefCell<Vec<Value<'p>>>,
+}
+
+impl<'p> Value<'p> {
+    /// The name of the value's class, or `Void`.
+    pub fn class(&self) -> Symbol {
+        match self {
+            Value::Void => sym::VOID,
+            Value::Int(_) => sym::INT,
+            Value::Bool(_) => sym::BOOL,
+            Value::Str(_) => sym::STRING,
+            Value::Object(object) => object.class,
+        }
+    }
+
+    fn int(&self) -> Result<i32, RuntimeError> {
+        match self {
+            Value::Int(i) => Ok(*i),
+            _ => Err(RuntimeError::TypeMismatch { expected: "Int" }),
+        }
+    }
+
+    fn bool(&self) -> Result<bool, RuntimeError> {
+        match self {
+            Value::Bool(b) => Ok(*b),
+            _ => Err(RuntimeError::TypeMismatch { expected: "Bool" }),
+        }
+    }
+
+    fn str(&self) -> Result<&Rc<str>, RuntimeError> {
+        match self {
+            Value::Str(s) => Ok(s),
+            _ => Err(RuntimeError::TypeMismatch { expected: "String" }),
+        }
+    }
+
+    /// `=`: identity for objects, and equal contents for the basic classes.
+    fn equals(&self, other: &Value<'p>) -> bool {
+        match (self, other) {
+            (Value::Void, Value::Void) => true,
+            (Value::Int(a), Value::Int(b)) => a == b,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Str(a), Value::Str(b)) => a == b,
+            (Value::Object(a), Value::Object(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+/// The value a variable of type `typ` holds before it is assigned.
+fn default_value<'p>(typ: Symbol) -> Value<'p> {
+    match typ {
+        sym::INT => Value::Int(0),
+        sym::BOOL => Value::Bool(false),
+        sym::STRING => Value::Str("".into()),
+        _ => Value::Void,
+    }
+}
+
+pub struct Class<'p> {
+    pub name: Symbol,
+    pub parent: Option<Symbol>,
+    /// `None` for the built-in classes.
+    pub span: Option<Span>,
+    /// The attributes the class declares itself, in order.
+    pub attributes: Vec<&'p ast::AttributeFeature>,
+    methods: HashMap<Symbol, &'p ast::MethodFeature>,
+    builtins: &'static [Symbol],
+}
+
+/// What a dispatch resolved to.
+enum Method<'p> {
+    Defined(Symbol, &'p ast::MethodFeature),
+    Builtin(Symbol),
+}
+
+/// A method or initializer being run.
+pub struct Frame<'p> {
+    /// The class that defines the method, or whose attributes an initializer sets.
+    pub class: Symbol,
+    /// The method's name, or [`INIT_METHOD`] for an initializer.
+    pub method: Symbol,
+    /// The method's span, or the class's for an initializer.
+    pub span: Span,
+    /// The span of the expression being evaluated, or of the one that failed once the
+    /// program has.
+    pub location: Span,
+    pub this: Value<'p>,
+    /// Formals first, then the `let` and `case` variables in scope, innermost last.
+    pub locals: Vec<(Symbol, Value<'p>)>,
+    /// How many of `locals` are formals.
+    pub formals: usize,
+    /// The statement being evaluated, once the frame has started one.
+    pub statement: Option<&'p Expr>,
+}
+
+/// The classes of the program and the frames being run, for a [`Hook`] to look at.
+pub struct State<'p> {
+    classes: HashMap<Symbol, Class<'p>>,
+    /// Every class's attributes, inherited ones first.
+    layouts: HashMap<Symbol, Vec<&'p ast::AttributeFeature>>,
+    frames: Vec<Frame<'p>>,
+}
+
+impl<'p> State<'p> {
+    fn new(program: &'p ast::Program) -> Self {
+        let mut classes = HashMap::new();
+        for &(name, parent, builtins) in BUILTIN_CLASSES {
+            classes.insert(
+                name,
+                Class {
+                    name,
+                    parent,
+                    span: None,
+                    attributes: Vec::new(),
+                    methods: HashMap::new(),
+                    builtins,
+                },
+            );
+        }
+        for class in &program.classes {
+            let mut attributes = Vec::new();
+            let mut methods = HashMap::new();
+            for feature in &class.features {
+                match feature {
+                    ast::Feature::Attribute(attr) => attributes.push(attr),
+                    ast::Feature::Method(method) => {
+                        methods.insert(method.name, method);
+                    }
+                }
+            }
+            classes.insert(
+                class.name,
+                Class {
+                    name: class.name,
+                    parent: Some(class.parent.unwrap_or(sym::OBJECT)),
+                    span: Some(class.span),
+                    attributes,
+                    methods,
+                    builtins: &[],
+                },
+            );
+        }
+        let mut state = State {
+            classes,
+            layouts: HashMap::new(),
+            frames: Vec::new(),
+        };
+        let names: Vec<Symbol> = state.classes.keys().copied().collect();
+        for name in names {
+            let mut layout: Vec<&'p ast::AttributeFeature> = Vec::new();
+            for ancestor in state.ancestors(name).collect::<Vec<_>>().into_iter().rev() {
+                layout.extend(&state.classes[&ancestor].attributes);
+            }
+            state.layouts.insert(name, layout);
+        }
+        state
+    }
+
+    pub fn class(&self, name: Symbol) -> Option<&Class<'p>> {
+        self.classes.get(&name)
+    }
+
+    /// The frames being run, outermost first.
+    pub fn frames(&self) -> &[Frame<'p>] {
+        &self.frames
+    }
+
+    /// `class` and its ancestors, nearest first. Stops at a class that doesn't exist,
+    /// and at a cycle.
+    pub fn ancestors(&self, class: Symbol) -> impl Iterator<Item = Symbol> + '_ {
+        let mut next = self.classes.get(&class).map(|c| c.name);
+        let mut seen = 0;
+        std::iter::from_fn(move || {
+            let class = next?;
+            seen += 1;
+            next = self
+                .classes
+                .get(&class)
+                .and_then(|c| c.parent)
+                .filter(|_| seen <= self.classes.len());
+            Some(class)
+        })
+    }
+
+    /// Every attribute of `class`, inherited ones first.
+    pub fn attributes(&self, class: Symbol) -> &[&'p ast::AttributeFeature] {
+        self.layouts.get(&class).map_or(&[], Vec::as_slice)
+    }
+
+    /// The attributes of `value` with their values; none for the basic classes.
+    pub fn fields(&self, value: &Value<'p>) -> Vec<(Symbol, Value<'p>)> {
+        match value {
+            Value::Object(object) => self
+                .attributes(object.class)
+                .iter()
+                .map(|attr| attr.name)
+                .zip(object.fields.borrow().iter().cloned())
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// The variable `name` as `frame` sees it: `self`, a local or an attribute.
+    pub fn lookup(&self, frame: &Frame<'p>, name: Symbol) -> Option<Value<'p>> {
+        if name == sym::SELF {
+            return Some(frame.this.clone());
+        }
+        if let Some((_, value)) = frame.locals.iter().rev().find(|(n, _)| *n == name) {
+            return Some(value.clone());
+        }
+        self.fields(&frame.this)
+            .into_iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, value)| value)
+    }
+
+    fn resolve(&self, class: Symbol, method: Symbol) -> Option<Method<'p>> {
+        self.ancestors(class).find_map(|ancestor| {
+            let info = &self.classes[&ancestor];
+            match info.methods.get(&method) {
+                Some(&feature) => Some(Method::Defined(ancestor, feature)),
+                None => info
+                    .builtins
+                    .contains(&method)
+                    .then_some(Method::Builtin(ancestor)),
+            }
+        })
+    }
+
+    fn field_slot(&self, class: Symbol, name: Symbol) -> Option<usize> {
+        self.attributes(class)
+            .iter()
+            .position(|attr| attr.name == name)
+    }
+}
+
+/// Something the interpreter tells its [`Hook`] about.
+pub enum Event<'a, 'p> {
+    /// A method or initializer frame was just pushed.
+    Call,
+    /// The innermost frame is about to evaluate a statement: a method body, an attribute
+    /// initializer, an expression in a block, or the body of a `let`, a loop, a branch of
+    /// an `if` or a `case`, unless that is a block itself.
+    Statement(&'p Expr),
+    /// The innermost frame is about to return `value`.
+    Return(&'a Value<'p>),
+    /// The program failed. Every frame is still there, the innermost being the one that
+    /// failed.
+    Error(&'a RuntimeError),
+}
+
+/// What the interpreter should do after a [`Hook`] has seen an event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resume {
+    Continue,
+    /// Stop the program, as if it had finished.
+    Quit,
+}
+
+/// Watches the interpreter run, as a debugger does.
+pub trait Hook<'p> {
+    /// Called on every event, with the output the program writes to.
+    fn event(
+        &mut self,
+        state: &State<'p>,
+        event: Event<'_, 'p>,
+        output: &mut dyn Write,
+    ) -> io::Result<Resume>;
+}
+
+/// Why evaluation stopped early.
+enum Exit {
+    Error(RuntimeError),
+    Quit,
+}
+
+impl From<RuntimeError> for Exit {
+    fn from(e: RuntimeError) -> Self {
+        Exit::Error(e)
+    }
+}
+
+impl From<io::Error> for Exit {
+    fn from(e: io::Error) -> Self {
+        Exit::Error(e.into())
+    }
+}
+
+type Eval<'p> = Result<Value<'p>, Exit>;
+
+pub struct Interpreter<'p, 'io> {
+    state: State<'p>,
+    input: &'io mut dyn BufRead,
+    output: &'io mut dyn Write,
+    hook: Option<&'io mut dyn Hook<'p>>,
+    /// The class, method and location of each frame that was running when the program
+    /// failed, innermost first.
+    failed: Vec<(Symbol, Symbol, Span)>,
+}
+
+impl<'p, 'io> Interpreter<'p, 'io> {
+    pub fn new(
+        program: &'p ast::Program,
+        input: &'io mut dyn BufRead,
+        output: &'io mut dyn Write,
+    ) -> Self {
+        Interpreter {
+            state: State::new(program),
+            input,
+            output,
+            hook: None,
+            failed: Vec::new(),
+        }
+    }
+
+    /// Has `hook` watch the program run.
+    pub fn with_hook(mut self, hook: &'io mut dyn Hook<'p>) -> Self {
+        self.hook = Some(hook);
+        self
+    }
+
+    /// Runs `(new Main).main()` to completion, or until the hook quits.
+    pub fn run(&mut self) -> Result<(), RuntimeError> {
+        let result = match self.run_main() {
+            Ok(()) | Err(Exit::Quit) => Ok(()),
+            Err(Exit::Error(error)) => {
+                // The hook may quit here too, but the program failed either way.
+                let _ = self.notify(Event::Error(&error));
+                self.failed = (self.state.frames.iter().rev())
+                    .map(|frame| (frame.class, frame.method, frame.location))
+                    .collect();
+                Err(error)
+            }
+        };
+        self.state.frames.clear();
+        self.output.flush()?;
+        result
+    }
+
+    /// The methods that were running when [`Interpreter::run`] failed, innermost first.
+    pub fn backtrace(&self, sources: &SourceMap) -> Vec<StackFrame> {
+        self.failed
+            .iter()
+            .map(|&(class, method, span)| StackFrame::new(&class, &method, span, sources))
+            .collect()
+    }
+
+    fn run_main(&mut self) -> Result<(), Exit> {
+        if !matches!(
+            self.state.resolve(sym::MAIN_CLASS, sym::MAIN_METHOD),
+            Some(Method::Defined(..))
+        ) {
+            return Err(RuntimeError::Undefined("method Main.main".to_string()).into());
+        }
+        let main = self.new_object(sym::MAIN_CLASS)?;
+        self.dispatch(main, sym::MAIN_CLASS, sym::MAIN_METHOD, Vec::new())?;
+        Ok(())
+    }
+
+    fn notify(&mut self, event: Event<'_, 'p>) -> Result<(), Exit> {
+        let Some(hook) = self.hook.as_deref_mut() else {
+            return Ok(());
+        };
+        match hook.event(&self.state, event, &mut *self.output)? {
+            Resume::Continue => Ok(()),
+            Resume::Quit => Err(Exit::Quit),
+        }
+    }
+
+    fn frame(&mut self) -> &mut Frame<'p> {
+        self.state
+            .frames
+            .last_mut()
+            .expect("evaluating outside a frame")
+    }
+
+    fn push_frame(&mut self, frame: Frame<'p>) -> Result<(), Exit> {
+        if self.state.frames.len() >= MAX_FRAMES {
+            return Err(RuntimeError::StackOverflow.into());
+        }
+        self.state.frames.push(frame);
+        self.notify(Event::Call)
+    }
+
+    fn pop_frame(&mut self, value: &Value<'p>) -> Result<(), Exit> {
+        self.notify(Event::Return(value))?;
+        self.state.frames.pop();
+        Ok(())
+    }
+
+    /// Allocates an object of `class` and runs the attribute initializers of each of its
+    /// ancestors, the root first.
+    fn new_object(&mut self, class: Symbol) -> Eval<'p> {
+        let Some(info) = self.state.class(class) else {
+            return Err(RuntimeError::Undefined(format!("class {class}")).into());
+        };
+        let this = match info.name {
+            sym::INT | sym::BOOL | sym::STRING => return Ok(default_value(class)),
+            name => Value::Object(Rc::new(Object {
+                class: name,
+                fields: RefCell::new(
+                    self.state
+                        .attributes(name)
+                        .iter()
+                        .map(|attr| default_value(attr.attr_type))
+                        .collect(),
+                ),
+            })),
+        };
+        let ancestors: Vec<Symbol> = self.state.ancestors(class).collect();
+        for ancestor in ancestors.into_iter().rev() {
+            let info = &self.state.classes[&ancestor];
+            let (Some(span), inits) = (info.span, info.attributes.clone()) else {
+                continue;
+            };
+            if inits.iter().all(|attr| attr.init.is_none()) {
+                continue;
+            }
+            self.push_frame(Frame {
+                class: ancestor,
+                method: INIT_METHOD,
+                span,
+                location: span,
+                this: this.clone(),
+                locals: Vec::new(),
+                formals: 0,
+                statement: None,
+            })?;
+            for attr in inits {
+                if let Some(init) = &attr.init {
+                    let value = self.statement(init)?;
+                    self.set_attribute(&this, attr.name, value)?;
+                }
+            }
+            self.pop_frame(&this)?;
+        }
+        Ok(this)
+    }
+
+    fn set_attribute(&self, this: &Value<'p>, name: Symbol, value: Value<'p>) -> Result<(), Exit> {
+        let slot = match this {
+            Value::Object(object) => self
+                .state
+                .field_slot(object.class, name)
+                .map(|slot| (object, slot)),
+            _ => None,
+        };
+        match slot {
+            Some((object, slot)) => {
+                object.fields.borrow_mut()[slot] = value;
+                Ok(())
+            }
+            None => Err(RuntimeError::Undefined(format!("variable {name}")).into()),
+        }
+    }
+
+    /// Calls `method` on `this`, looking it up from class `lookup`.
+    fn dispatch(
+        &mut self,
+        this: Value<'p>,
+        lookup: Symbol,
+        method: Symbol,
+        args: Vec<Value<'p>>,
+    ) -> Eval<'p> {
+        if let Value::Void = this {
+            return Err(RuntimeError::DispatchOnVoid {
+                method: method.to_string(),
+            }
+            .into());
+        }
+        match self.state.resolve(lookup, method) {
+            Some(Method::Defined(class, feature)) => {
+                self.push_frame(Frame {
+                    class,
+                    method,
+                    span: feature.span,
+                    location: feature.span,
+                    this,
+                    locals: feature
+                        .formals
+                        .iter()
+                        .map(|formal| formal.name)
+                        .zip(args)
+                        .collect(),
+                    formals: feature.formals.len(),
+                    statement: None,
+                })?;
+                let value = self.statement(&feature.body)?;
+                self.pop_frame(&value)?;
+                Ok(value)
+            }
+            Some(Method::Builtin(class)) => self.builtin(class, method, this, args),
+            None => Err(RuntimeError::Undefined(format!("method {lookup}.{method}")).into()),
+        }
+    }
+
+    fn builtin(
+        &mut self,
+        class: Symbol,
+        method: Symbol,
+        this: Value<'p>,
+        args: Vec<Value<'p>>,
+    ) -> Eval<'p> {
+        Ok(match (class, method) {
+            (sym::OBJECT, sym::ABORT) => {
+                return Err(RuntimeError::Abort {
+                    class: this.class().to_string(),
+                }
+                .into());
+            }
+            (sym::OBJECT, sym::TYPE_NAME) => Value::Str(this.class().as_str().into()),
+            (sym::OBJECT, sym::COPY) => match &this {
+                Value::Object(object) => Value::Object(Rc::new(Object {
+                    class: object.class,
+                    fields: object.fields.clone(),
+                })),
+                _ => this,
+            },
+            (sym::IO, sym::OUT_STRING) => {
+                self.output.write_all(args[0].str()?.as_bytes())?;
+                this
+            }
+            (sym::IO, sym::OUT_INT) => {
+                write!(self.output, "{}", args[0].int()?)?;
+                this
+            }
+            (sym::IO, sym::IN_STRING) => Value::Str(self.read_line()?.into()),
+            (sym::IO, sym::IN_INT) => Value::Int(runtime::vm::parse_int(&self.read_line()?)),
+            (sym::STRING, sym::LENGTH) => Value::Int(this.str()?.len() as i32),
+            (sym::STRING, sym::CONCAT) => {
+                Value::Str(format!("{}{}", this.str()?, args[0].str()?).into())
+            }
+            (sym::STRING, sym::SUBSTR) => {
+                let (index, length) = (args[0].int()?, args[1].int()?);
+                let s = this.str()?;
+                let range = usize::try_from(index)
+                    .ok()
+                    .zip(usize::try_from(length).ok())
+                    .map(|(i, l)| i..i + l)
+                    .filter(|r| r.end <= s.len());
+                let Some(range) = range else {
+                    return Err(RuntimeError::SubstrOutOfRange { index, length }.into());
+                };
+                Value::Str(String::from_utf8_lossy(&s.as_bytes()[range]).into())
+            }
+            _ => return Err(RuntimeError::Undefined(format!("method {class}.{method}")).into()),
+        })
+    }
+
+    fn read_line(&mut self) -> Result<String, Exit> {
+        self.output.flush()?;
+        let mut line = String::new();
+        self.input.read_line(&mut line)?;
+        Ok(line.trim_end_matches(['\n', '\r']).to_string())
+    }
+
+    /// Evaluates `expr` as a statement, telling the hook about it first. A block's
+    /// expressions are statements of their own instead.
+    fn statement(&mut self, expr: &'p Expr) -> Eval<'p> {
+        if let ExprKind::Block(exprs) = &expr.kind {
+            let mut value = Value::Void;
+            for expr in exprs {
+                value = self.statement(expr)?;
+            }
+            return Ok(value);
+        }
+        self.frame().statement = Some(expr);
+        let outer = std::mem::replace(&mut self.frame().location, expr.span);
+        self.notify(Event::Statement(expr))?;
+        let value = self.eval(expr)?;
+        self.frame().location = outer;
+        Ok(value)
+    }
+
+    fn eval(&mut self, expr: &'p Expr) -> Eval<'p> {
+        let outer = std::mem::replace(&mut self.frame().location, expr.span);
+        let value = self.eval_kind(expr)?;
+        // Only on success, so an error is reported where it happened.
+        self.frame().location = outer;
+        Ok(value)
+    }
+
+    fn eval_kind(&mut self, expr: &'p Expr) -> Eval<'p> {
+        Ok(match &expr.kind {
+            ExprKind::Assign { name, expr } => {
+                let value = self.eval(expr)?;
+                self.assign(*name, value.clone())?;
+                value
+            }
+            ExprKind::Dispatch {
+                expr,
+                static_type,
+                method,
+                args,
+            } => {
+                // Arguments are evaluated before the receiver, as in the reference compiler.
+                let args = self.eval_all(args)?;
+                let this = self.eval(expr)?;
+                let lookup = static_type.unwrap_or(this.class());
+                self.dispatch(this, lookup, *method, args)?
+            }
+            ExprKind::FuncCall { name, args } => {
+                let args = self.eval_all(args)?;
+                let this = self.frame().this.clone();
+                self.dispatch(this.clone(), this.class(), *name, args)?
+            }
+            ExprKind::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => {
+                if self.eval(cond)?.bool()? {
+                    self.statement(then_branch)?
+                } else {
+                    self.statement(else_branch)?
+                }
+            }
+            ExprKind::While { cond, body } => {
+                while self.eval(cond)?.bool()? {
+                    self.statement(body)?;
+                }
+                Value::Void
+            }
+            ExprKind::Block(exprs) => {
+                let mut value = Value::Void;
+                for expr in exprs {
+                    value = self.eval(expr)?;
+                }
+                value
+            }
+            ExprKind::Let { bindings, body } => {
+                let scope = self.frame().locals.len();
+                for binding in bindings {
+                    let value = match &binding.init {
+                        Some(init) => self.eval(init)?,
+                        None => default_value(binding.typ),
+                    };
+                    self.frame().locals.push((binding.name, value));
+                }
+                let value = self.statement(body)?;
+                self.frame().locals.truncate(scope);
+                value
+            }
+            ExprKind::Case { expr, branches } => {
+                let value = self.eval(expr)?;
+                if let Value::Void = value {
+                    return Err(RuntimeError::CaseOnVoid.into());
+                }
+                let branch = self
+                    .state
+                    .ancestors(value.class())
+                    .find_map(|ancestor| branches.iter().find(|branch| branch.typ == ancestor));
+                let Some(branch) = branch else {
+                    return Err(RuntimeError::CaseNoMatch {
+                        class: value.class().to_string(),
+                    }
+                    .into());
+                };
+                let scope = self.frame().locals.len();
+                self.frame().locals.push((branch.name, value));
+                let value = self.statement(&branch.expr)?;
+                self.frame().locals.truncate(scope);
+                value
+            }
+            ExprKind::New(sym::SELF_TYPE) => {
+                let class = self.frame().this.class();
+                self.new_object(class)?
+            }
+            ExprKind::New(class) => self.new_object(*class)?,
+            ExprKind::IsVoid(expr) => Value::Bool(matches!(self.eval(expr)?, Value::Void)),
+            ExprKind::Plus(lhs, rhs) => {
+                let (a, b) = self.ints(lhs, rhs)?;
+                Value::Int(a.wrapping_add(b))
+            }
+            ExprKind::Minus(lhs, rhs) => {
+                let (a, b) = self.ints(lhs, rhs)?;
+                Value::Int(a.wrapping_sub(b))
+            }
+            ExprKind::Times(lhs, rhs) => {
+                let (a, b) = self.ints(lhs, rhs)?;
+                Value::Int(a.wrapping_mul(b))
+            }
+            ExprKind::Divide(lhs, rhs) => match self.ints(lhs, rhs)? {
+                (_, 0) => return Err(RuntimeError::DivisionByZero.into()),
+                (a, b) => Value::Int(a.wrapping_div(b)),
+            },
+            ExprKind::Lt(lhs, rhs) => {
+                let (a, b) = self.ints(lhs, rhs)?;
+                Value::Bool(a < b)
+            }
+            ExprKind::Le(lhs, rhs) => {
+                let (a, b) = self.ints(lhs, rhs)?;
+                Value::Bool(a <= b)
+            }
+            ExprKind::Eq(lhs, rhs) => {
+                let lhs = self.eval(lhs)?;
+                Value::Bool(lhs.equals(&self.eval(rhs)?))
+            }
+            ExprKind::Not(expr) => Value::Bool(!self.eval(expr)?.bool()?),
+            ExprKind::Negate(expr) => Value::Int(self.eval(expr)?.int()?.wrapping_neg()),
+            ExprKind::Paren(expr) => self.eval(expr)?,
+            ExprKind::Id(name) => {
+                let frame = self
+                    .state
+                    .frames
+                    .last()
+                    .expect("evaluating outside a frame");
+                self.state
+                    .lookup(frame, *name)
+                    .ok_or_else(|| RuntimeError::Undefined(format!("variable {name}")))?
+            }
+            ExprKind::Integer(i) => Value::Int(i.value()),
+            ExprKind::String(s) => Value::Str(s.as_str().into()),
+            ExprKind::True => Value::Bool(true),
+            ExprKind::False => Value::Bool(false),
+        })
+    }
+
+    fn eval_all(&mut self, exprs: &'p [Expr]) -> Result<Vec<Value<'p>>, Exit> {
+        exprs.iter().map(|expr| self.eval(expr)).collect()
+    }
+
+    fn ints(&mut self, lhs: &'p Expr, rhs: &'p Expr) -> Result<(i32, i32), Exit> {
+        let lhs = self.eval(lhs)?.int()?;
+        Ok((lhs, self.eval(rhs)?.int()?))
+    }
+
+    fn assign(&mut self, name: Symbol, value: Value<'p>) -> Result<(), Exit> {
+        let frame = self.frame();
+        if let Some((_, slot)) = frame.locals.iter_mut().rev().find(|(n, _)| *n == name) {
+            *slot = value;
+            return Ok(());
+        }
+        let this = frame.this.clone();
+        self.set_attribute(&this, name, value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_with(src: &str, input: &str) -> (Result<(), RuntimeError>, String) {
+        let program = parser::parse(src).unwrap();
+        let mut input = input.as_bytes();
+        let mut output = Vec::new();
+        let result = Interpreter::new(&program, &mut input, &mut output).run();
+        (result, String::from_utf8(output).unwrap())
+    }
+
+    fn run(src: &str) -> (Result<(), RuntimeError>, String) {
+        run_with(src, "")
+    }
+
+    #[test]
+    fn test_hello_world() {
+        let src = std::fs::read_to_string("../samples/hello_world.cl").unwrap();
+        assert_eq!(run(&src), (Ok(()), "Hello, World.\n".to_string()));
+    }
+
+    #[test]
+    fn test_arithmetic_and_strings() {
+        let (result, output) = run(r#"
+            class Main inherits IO {
+                x : Int <- 6;
+                main() : Object { {
+                    out_int(x * 7 - 10 / 3);
+                    out_string("\n".concat("abcdef".substr(2, 3)));
+                    out_int("hello".length());
+                    if "a" = "a" then out_string(" eq") else out_string(" ne") fi;
+                    out_string(if new Object = new Object then " same" else " different" fi);
+                } };
+            };
+        "#);
+        assert_eq!(result, Ok(()));
+        assert_eq!(output, "39\ncde5 eq different");
+    }
+
+    #[test]
+    fn test_dispatch_case_let_and_initializers() {
+        let (result, output) = run(r#"
+            class Animal { sound : String <- "..."; speak() : String { sound }; };
+            class Dog inherits Animal {
+                loud : String <- sound.concat("!");
+                speak() : String { "woof" };
+                me() : SELF_TYPE { new SELF_TYPE };
+            };
+            class Main inherits IO {
+                describe(a : Object) : String {
+                    case a of d : Dog => "dog"; x : Animal => "animal"; o : Object => "other"; esac
+                };
+                main() : Object { let a : Animal <- new Dog, b : Animal <- new Animal, i : Int in {
+                    out_string(a.speak().concat(b.speak()));
+                    out_string(a@Animal.speak());
+                    out_string(describe(a).concat(describe(b)).concat(describe(i)));
+                    out_string(a.type_name().concat(a.copy().type_name()));
+                    case a of d : Dog => out_string(d.me().type_name()); esac;
+                } };
+            };
+        "#);
+        assert_eq!(result, Ok(()));
+        assert_eq!(output, "woof......doganimalotherDogDogDog");
+    }
+
+    #[test]
+    fn test_runtime_errors() {
+        let (result, _) = run("class Main { a : Main; main() : Object { a.main() }; };");
+        assert!(matches!(result, Err(RuntimeError::DispatchOnVoid { .. })));
+
+        let (result, _) = run(r#"class Main { main() : Object { "abc".substr(2, 5) }; };"#);
+        assert!(matches!(result, Err(RuntimeError::SubstrOutOfRange { .. })));
+
+        let (result, _) =
+            run("class Main { main() : Object { case 1 of s : String => s; esac }; };");
+        assert!(matches!(result, Err(RuntimeError::CaseNoMatch { class }) if class == "Int"));
+
+        let (result, _) = run("class Main { main() : Object { 1 / 0 }; };");
+        assert_eq!(result, Err(RuntimeError::DivisionByZero));
+
+        let (result, _) = run("class Main { };");
+        assert_eq!(
+            result,
+            Err(RuntimeError::Undefined("method Main.main".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_backtrace_agrees_with_vm() {
+        let mut sources = SourceMap::new();
+        let file = sources.add(
+            "a.cl",
+            "class A {\n  x : Int <- f(0);\n  f(n : Int) : Int {\n    1 / n\n  };\n};\n\
+             class Main {\n  main() : Object { new A };\n};\n",
+        );
+        let program = parser::parse_file(sources.file(file).src(), file).unwrap();
+        let (mut input, mut output) = ("".as_bytes(), Vec::new());
+        let mut interpreter = Interpreter::new(&program, &mut input, &mut output);
+        assert_eq!(interpreter.run(), Err(RuntimeError::DivisionByZero));
+        let backtrace = interpreter.backtrace(&sources);
+        let lines: Vec<_> = backtrace
+            .iter()
+            .map(|frame| (frame.function.as_str(), frame.line))
+            .collect();
+        // Each frame is at the line of the expression it was running.
+        assert_eq!(lines, [("A.f", 4), ("A._init", 2), ("Main.main", 8)]);
+
+        let module = ir::lower(&program);
+        let (mut input, mut output) = ("".as_bytes(), Vec::new());
+        let mut vm = runtime::Vm::new(
+            &module,
+            runtime::Options::default(),
+            &mut input,
+            &mut output,
+        );
+        assert_eq!(vm.run(), Err(RuntimeError::DivisionByZero));
+        assert_eq!(vm.backtrace(&sources), backtrace);
+    }
+
+    #[test]
+    fn test_input() {
+        let src = r#"
+            class Main inherits IO {
+                main() : Object { {
+                    out_int(in_int() + 1);
+                    out_string(in_string().concat("!"));
+                } };
+            };
+        "#;
+        assert_eq!(run_with(src, "41\nhi\n"), (Ok(()), "42hi!".to_string()));
+    }
+
+    #[test]
+    fn test_agrees_with_the_vm_on_samples() {
+        for (sample, input) in [
+            ("arith.cl", "a\n7\nc\nq\n"),
+            ("cool.cl", ""),
+            ("life.cl", "n\n1\nn\n"),
+        ] {
+            let src = std::fs::read_to_string(format!("../samples/{sample}")).unwrap();
+            let program = parser::parse(&src).unwrap();
+            let module = ir::lower(&program);
+            let mut vm_output = Vec::new();
+            let vm_result = runtime::Vm::new(
+                &module,
+                runtime::Options::default(),
+                &mut input.as_bytes(),
+                &mut vm_output,
+            )
+            .run();
+            let (result, output) = run_with(&src, input);
+            assert_eq!(result, vm_result, "{sample}");
+            assert_eq!(output, String::from_utf8(vm_output).unwrap(), "{sample}");
+        }
+    }
+}
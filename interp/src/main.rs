@@ -0,0 +1,111 @@
+use clap::Parser;
+use interp::Interpreter;
+use lexer::source::SourceMap;
+use runtime::backtrace::{self, EXIT_RUNTIME_ERROR};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, IsTerminal};
+use std::process::ExitCode;
+use std::thread;
+
+/// Every COOL frame takes a few frames of the evaluator, so run it on a thread with a
+/// stack big enough for [`interp::MAX_FRAMES`] of them.
+const STACK_SIZE: usize = 256 << 20;
+
+#[derive(Parser)]
+#[command(name = "interp")]
+#[command(about = "Interprets a Cool program's AST, optionally under a debugger")]
+#[command(version)]
+struct Args {
+    /// Input Cool (.cl) files
+    #[arg(value_name = "FILE", required = true)]
+    files: Vec<String>,
+
+    /// Run under the debugger, reading its commands from stdin
+    #[arg(long)]
+    debug: bool,
+
+    /// With --debug, the file the program reads its input from
+    #[arg(long, value_name = "FILE")]
+    input: Option<String>,
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(move || run(args))
+        .expect("failed to spawn the interpreter thread")
+        .join()
+        .expect("the interpreter thread panicked")
+}
+
+fn run(args: Args) -> ExitCode {
+    let mut sources = SourceMap::new();
+    let mut program = parser::ast::Program {
+        classes: Vec::new(),
+    };
+    let mut failed = false;
+    for path in &args.files {
+        let file = match sources.load(path) {
+            Ok(file) => file,
+            Err(e) => {
+                eprintln!("Error: failed to read '{path}': {e}");
+                return ExitCode::FAILURE;
+            }
+        };
+        match parser::parse_file(sources.file(file).src(), file) {
+            Ok(parsed) => program.classes.extend(parsed.classes),
+            Err(errors) => {
+                for error in errors {
+                    eprintln!("{}", parser::format_error(&error, &sources));
+                }
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        return ExitCode::FAILURE;
+    }
+
+    let mut stdout = BufWriter::new(io::stdout().lock());
+    if args.debug {
+        // The debugger has stdin, so the program reads from --input or sees no input.
+        let mut input: Box<dyn io::BufRead> = match &args.input {
+            Some(path) => match File::open(path) {
+                Ok(file) => Box::new(BufReader::new(file)),
+                Err(e) => {
+                    eprintln!("Error: failed to read '{path}': {e}");
+                    return ExitCode::FAILURE;
+                }
+            },
+            None => Box::new(io::empty()),
+        };
+        let stdin = io::stdin();
+        let echo = !stdin.is_terminal();
+        // The debugger has already shown the error where the program stopped.
+        return match interp::debugger::run(
+            &program,
+            &sources,
+            &mut input,
+            &mut stdout,
+            &mut stdin.lock(),
+            echo,
+        ) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(_) => ExitCode::from(EXIT_RUNTIME_ERROR),
+        };
+    }
+
+    let mut stdin = io::stdin().lock();
+    let mut interpreter = Interpreter::new(&program, &mut stdin, &mut stdout);
+    match interpreter.run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprint!(
+                "{}",
+                backtrace::report(&e, &interpreter.backtrace(&sources))
+            );
+            ExitCode::from(EXIT_RUNTIME_ERROR)
+        }
+    }
+}
@@ -0,0 +1,398 @@
+//! Whole-program class hierarchy analysis and devirtualization.
+//!
+//! Because every class is known at compile time, a virtual call can be turned into a
+//! static one whenever all classes the receiver could have at runtime share the same
+//! definition of the method: the receiver's class is known exactly (`new T`, a
+//! literal, an `Int`/`String`/`Bool`), or no subclass of its static class overrides
+//! the method.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use lexer::{Symbol, sym};
+
+use crate::{Const, Function, Inst, Module, Reg, Target};
+
+/// Subclass relationships for every class in a module.
+pub struct ClassHierarchy<'m> {
+    module: &'m Module,
+    /// Each class mapped to itself and all its transitive subclasses.
+    descendants: HashMap<Symbol, Vec<Symbol>>,
+}
+
+impl<'m> ClassHierarchy<'m> {
+    pub fn new(module: &'m Module) -> Self {
+        let mut descendants: HashMap<Symbol, Vec<Symbol>> = HashMap::new();
+        for class in &module.classes {
+            for ancestor in module.ancestors(class.name) {
+                descendants.entry(ancestor).or_default().push(class.name);
+            }
+        }
+        ClassHierarchy {
+            module,
+            descendants,
+        }
+    }
+
+    /// The class defining the only implementation of `method` reachable from a
+    /// receiver whose static class is `class`, if there is exactly one.
+    pub fn single_target(&self, class: Symbol, method: Symbol) -> Option<Symbol> {
+        let mut targets = self
+            .descendants
+            .get(&class)?
+            .iter()
+            .map(|&c| self.module.resolve_method(c, method));
+        let first = targets.next()??;
+        targets.all(|t| t == Some(first)).then_some(first)
+    }
+
+    /// The least upper bound of two classes: their closest common ancestor.
+    pub fn lub(&self, a: Symbol, b: Symbol) -> Symbol {
+        let a_chain: Vec<Symbol> = self.module.ancestors(a).collect();
+        self.module
+            .ancestors(b)
+            .find(|c| a_chain.contains(c))
+            .unwrap_or(sym::OBJECT)
+    }
+}
+
+/// What is known statically about the object a register holds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegType {
+    /// An upper bound on the register's dynamic class, `None` if it's only ever void.
+    pub class: Option<Symbol>,
+    /// Whether the dynamic class is exactly `class` rather than a subclass of it.
+    pub exact: bool,
+    pub non_void: bool,
+}
+
+impl RegType {
+    fn exact(class: Symbol) -> Self {
+        RegType {
+            class: Some(class),
+            exact: true,
+            non_void: true,
+        }
+    }
+
+    /// A value of declared type `typ` in a method of `self_class`.
+    fn declared(typ: Symbol, self_class: Symbol) -> Self {
+        match typ {
+            // The basic classes can't be inherited from, and their values are never void.
+            sym::INT | sym::STRING | sym::BOOL => RegType::exact(typ),
+            sym::SELF_TYPE => RegType {
+                class: Some(self_class),
+                exact: false,
+                non_void: false,
+            },
+            _ => RegType {
+                class: Some(typ),
+                exact: false,
+                non_void: false,
+            },
+        }
+    }
+
+    fn join(&self, other: &RegType, hierarchy: &ClassHierarchy) -> RegType {
+        let class = match (self.class, other.class) {
+            (Some(a), Some(b)) => Some(hierarchy.lub(a, b)),
+            (a, b) => a.or(b),
+        };
+        RegType {
+            exact: class.is_some()
+                && [self, other]
+                    .iter()
+                    .all(|t| t.class.is_none() || (t.exact && t.class == class)),
+            non_void: self.non_void && other.non_void,
+            class,
+        }
+    }
+}
+
+/// Flow-insensitive type inference: the type of every register is the join of the
+/// types of all its definitions, so it is valid before, during and after SSA.
+pub fn infer_types(
+    module: &Module,
+    hierarchy: &ClassHierarchy,
+    func: &Function,
+) -> HashMap<Reg, RegType> {
+    let mut types: HashMap<Reg, RegType> = HashMap::new();
+    types.insert(
+        func.params[0],
+        RegType {
+            class: Some(func.class),
+            exact: false,
+            non_void: true,
+        },
+    );
+    if let Some(signature) = module.method_signature(func.class, func.method) {
+        for (param, &typ) in func.params[1..].iter().zip(&signature.formal_types) {
+            types.insert(*param, RegType::declared(typ, func.class));
+        }
+    }
+
+    // First assume operands we haven't typed yet will be typed later, as happens with
+    // loops. Whatever still can't be typed at that fixpoint could hold anything.
+    for pessimistic in [false, true] {
+        loop {
+            let mut changed = false;
+            for inst in func.blocks.iter().flat_map(|b| b.insts()) {
+                let Some(dst) = inst.dst() else {
+                    continue;
+                };
+                let typ = match def_type(module, hierarchy, func, &types, inst) {
+                    Some(typ) => typ,
+                    None if pessimistic => RegType::declared(sym::OBJECT, func.class),
+                    None => continue,
+                };
+                let joined = match types.get(&dst) {
+                    Some(current) => current.join(&typ, hierarchy),
+                    None => typ,
+                };
+                if types.get(&dst) != Some(&joined) {
+                    types.insert(dst, joined);
+                    changed = true;
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+    }
+    types
+}
+
+fn def_type(
+    module: &Module,
+    hierarchy: &ClassHierarchy,
+    func: &Function,
+    types: &HashMap<Reg, RegType>,
+    inst: &Inst,
+) -> Option<RegType> {
+    Some(match inst {
+        Inst::Const { value, .. } => match value {
+            Const::Int(_) => RegType::exact(sym::INT),
+            Const::Bool(_) => RegType::exact(sym::BOOL),
+            Const::Str(_) => RegType::exact(sym::STRING),
+            Const::Void => RegType {
+                class: None,
+                exact: false,
+                non_void: false,
+            },
+        },
+        Inst::Copy { src, .. } => types.get(src)?.clone(),
+        Inst::Binary { op, .. } => match op {
+            crate::BinOp::Lt | crate::BinOp::Le | crate::BinOp::Eq => RegType::exact(sym::BOOL),
+            _ => RegType::exact(sym::INT),
+        },
+        Inst::Unary { op, .. } => match op {
+            crate::UnOp::Neg => RegType::exact(sym::INT),
+            crate::UnOp::Not => RegType::exact(sym::BOOL),
+        },
+        Inst::IsVoid { .. } => RegType::exact(sym::BOOL),
+        Inst::New {
+            class: Some(class), ..
+        } => RegType::exact(*class),
+        Inst::New { class: None, .. } => RegType {
+            class: Some(func.class),
+            exact: false,
+            non_void: true,
+        },
+        Inst::GetAttr { class, name, .. } => {
+            RegType::declared(module.attribute_type(*class, *name)?, func.class)
+        }
+        Inst::Call {
+            recv,
+            target,
+            method,
+            ..
+        } => {
+            let recv_type = types.get(recv)?;
+            let lookup = match target {
+                Target::Static(class) => *class,
+                Target::Virtual => recv_type.class?,
+            };
+            let signature = module.method_signature(lookup, *method)?;
+            if signature.return_type == sym::SELF_TYPE {
+                // The result has the receiver's dynamic class, and a call never
+                // returns normally on a void receiver.
+                RegType {
+                    non_void: true,
+                    ..recv_type.clone()
+                }
+            } else {
+                RegType::declared(signature.return_type, func.class)
+            }
+        }
+        Inst::Phi { args, .. } => args
+            .iter()
+            .filter_map(|(_, r)| types.get(r))
+            .cloned()
+            .reduce(|a, b| a.join(&b, hierarchy))?,
+        Inst::SetAttr { .. } => return None,
+    })
+}
+
+/// Counts from a devirtualization and inlining run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DevirtReport {
+    /// Virtual dispatch sites in the program before devirtualization.
+    pub dispatch_sites: usize,
+    /// Sites turned into static calls.
+    pub resolved: usize,
+    /// Static call sites replaced with the callee's body.
+    pub inlined: usize,
+}
+
+impl fmt::Display for DevirtReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let percent = if self.dispatch_sites == 0 {
+            0.0
+        } else {
+            100.0 * self.resolved as f64 / self.dispatch_sites as f64
+        };
+        write!(
+            f,
+            "dispatch sites: {}, resolved statically: {} ({percent:.1}%), inlined: {}",
+            self.dispatch_sites, self.resolved, self.inlined
+        )
+    }
+}
+
+/// Rewrites every virtual call with a single possible target into a static call to
+/// that target.
+pub fn devirtualize(module: &mut Module) -> DevirtReport {
+    let mut report = DevirtReport::default();
+    let resolutions: Vec<Vec<Option<Symbol>>> = {
+        let hierarchy = ClassHierarchy::new(module);
+        module
+            .functions
+            .iter()
+            .map(|func| {
+                let types = infer_types(module, &hierarchy, func);
+                func.blocks
+                    .iter()
+                    .flat_map(|b| b.insts())
+                    .filter_map(|inst| match inst {
+                        Inst::Call {
+                            recv,
+                            target: Target::Virtual,
+                            method,
+                            ..
+                        } => Some(types.get(recv).and_then(|t| {
+                            let class = t.class?;
+                            if t.exact {
+                                module.resolve_method(class, *method)
+                            } else {
+                                hierarchy.single_target(class, *method)
+                            }
+                        })),
+                        _ => None,
+                    })
+                    .collect()
+            })
+            .collect()
+    };
+
+    for (func, resolved) in module.functions.iter_mut().zip(resolutions) {
+        let mut resolved = resolved.into_iter();
+        for inst in func.blocks.iter_mut().flat_map(|b| b.insts_mut()) {
+            if let Inst::Call {
+                target: target @ Target::Virtual,
+                ..
+            } = inst
+            {
+                report.dispatch_sites += 1;
+                if let Some(class) = resolved.next().flatten() {
+                    *target = Target::Static(class);
+                    report.resolved += 1;
+                }
+            }
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lower(src: &str) -> Module {
+        crate::lower(&parser::parse(src).unwrap())
+    }
+
+    fn call_targets(module: &Module, class: Symbol, method: Symbol) -> Vec<(Symbol, Target)> {
+        module
+            .function(class, method)
+            .unwrap()
+            .blocks
+            .iter()
+            .flat_map(|b| b.insts())
+            .filter_map(|inst| match inst {
+                Inst::Call { method, target, .. } => Some((*method, target.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    const SHAPES: &str = r#"
+        class Shape { area() : Int { 0 }; name() : String { "shape" }; };
+        class Square inherits Shape { side : Int; area() : Int { side * side }; };
+        class Main inherits IO {
+            main() : Object { {
+                let s : Shape <- new Square in s.area();
+                let s : Shape <- new Square in s.name();
+                (new Square).area();
+                "abc".length();
+                self.describe(new Shape);
+            } };
+            describe(s : Shape) : Object { out_int(s.area()) };
+        };
+    "#;
+
+    #[test]
+    fn test_single_target() {
+        let module = lower(SHAPES);
+        let hierarchy = ClassHierarchy::new(&module);
+        let [shape, square, main, name, area] =
+            ["Shape", "Square", "Main", "name", "area"].map(Symbol::intern);
+        assert_eq!(hierarchy.single_target(shape, name), Some(shape));
+        assert_eq!(hierarchy.single_target(shape, area), None);
+        assert_eq!(hierarchy.single_target(square, area), Some(square));
+        assert_eq!(hierarchy.lub(square, main), sym::OBJECT);
+        assert_eq!(hierarchy.lub(square, shape), shape);
+    }
+
+    #[test]
+    fn test_devirtualize() {
+        let mut module = lower(SHAPES);
+        let report = devirtualize(&mut module);
+
+        let [shape, square, main, name, area, describe] =
+            ["Shape", "Square", "Main", "name", "area", "describe"].map(Symbol::intern);
+        assert_eq!(
+            call_targets(&module, main, sym::MAIN_METHOD),
+            [
+                // `s` is declared `Shape`, but it's only ever assigned a `Square`.
+                (area, Target::Static(square)),
+                // Nothing overrides `name`.
+                (name, Target::Static(shape)),
+                // The receiver is exactly a `Square`.
+                (area, Target::Static(square)),
+                (sym::LENGTH, Target::Static(sym::STRING)),
+                // `describe` is only defined in `Main`, which has no subclasses.
+                (describe, Target::Static(main)),
+            ]
+        );
+        assert_eq!(
+            call_targets(&module, main, describe),
+            [
+                // `s` could be any `Shape`, and `area` is overridden in `Square`.
+                (area, Target::Virtual),
+                (sym::OUT_INT, Target::Static(sym::IO)),
+            ]
+        );
+        assert_eq!(report.dispatch_sites, 7);
+        assert_eq!(report.resolved, 6);
+    }
+}
@@ -0,0 +1,166 @@
+//! Human-readable text format for the IR, as printed by the `ir` binary.
+
+use std::fmt;
+
+use crate::{BinOp, Block, Const, Function, Inst, Module, Target, Terminator, UnOp};
+
+impl fmt::Display for Const {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Const::Int(i) => write!(f, "{i}"),
+            Const::Bool(b) => write!(f, "{b}"),
+            Const::Str(s) => write!(f, "{s:?}"),
+            Const::Void => write!(f, "void"),
+        }
+    }
+}
+
+impl fmt::Display for BinOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            BinOp::Add => "add",
+            BinOp::Sub => "sub",
+            BinOp::Mul => "mul",
+            BinOp::Div => "div",
+            BinOp::Lt => "lt",
+            BinOp::Le => "le",
+            BinOp::Eq => "eq",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl fmt::Display for UnOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnOp::Neg => write!(f, "neg"),
+            UnOp::Not => write!(f, "not"),
+        }
+    }
+}
+
+impl fmt::Display for Inst {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Inst::Const { dst, value } => write!(f, "{dst} = const {value}"),
+            Inst::Copy { dst, src } => write!(f, "{dst} = {src}"),
+            Inst::Binary { dst, op, lhs, rhs } => write!(f, "{dst} = {op} {lhs}, {rhs}"),
+            Inst::Unary { dst, op, src } => write!(f, "{dst} = {op} {src}"),
+            Inst::IsVoid { dst, src } => write!(f, "{dst} = isvoid {src}"),
+            Inst::New { dst, class } => {
+                write!(f, "{dst} = new {}", class.as_deref().unwrap_or("SELF_TYPE"))
+            }
+            Inst::GetAttr {
+                dst,
+                obj,
+                class,
+                name,
+            } => write!(f, "{dst} = getattr {obj}, {class}.{name}"),
+            Inst::SetAttr {
+                obj,
+                class,
+                name,
+                src,
+            } => write!(f, "setattr {obj}, {class}.{name}, {src}"),
+            Inst::Call {
+                dst,
+                recv,
+                target,
+                method,
+                args,
+            } => {
+                write!(f, "{dst} = call {recv}")?;
+                match target {
+                    Target::Virtual => write!(f, ".{method}(")?,
+                    Target::Static(class) => write!(f, "@{class}.{method}(")?,
+                }
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{arg}")?;
+                }
+                write!(f, ")")
+            }
+            Inst::Phi { dst, args } => {
+                write!(f, "{dst} = phi")?;
+                for (i, (block, reg)) in args.iter().enumerate() {
+                    let sep = if i == 0 { " " } else { ", " };
+                    write!(f, "{sep}[{block}: {reg}]")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl fmt::Display for Terminator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Terminator::Jump(block) => write!(f, "jump {block}"),
+            Terminator::Branch {
+                cond,
+                then_block,
+                else_block,
+            } => write!(f, "branch {cond}, {then_block}, {else_block}"),
+            Terminator::Case { scrutinee, arms } => {
+                write!(f, "case {scrutinee}")?;
+                for arm in arms {
+                    write!(f, " [{}: {}]", arm.typ, arm.block)?;
+                }
+                Ok(())
+            }
+            Terminator::Return(reg) => write!(f, "return {reg}"),
+            Terminator::Unreachable => write!(f, "unreachable"),
+        }
+    }
+}
+
+impl fmt::Display for Block {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for inst in self.insts() {
+            writeln!(f, "    {inst}")?;
+        }
+        writeln!(f, "    {}", self.term)
+    }
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "fn {}(", self.name())?;
+        for (i, param) in self.params.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{param}")?;
+        }
+        writeln!(f, ") {{")?;
+        for id in self.block_ids() {
+            let entry = if id == self.entry { " (entry)" } else { "" };
+            writeln!(f, "  {id}:{entry}")?;
+            write!(f, "{}", self.block(id))?;
+        }
+        writeln!(f, "}}")
+    }
+}
+
+impl fmt::Display for Module {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for class in self.classes.iter().filter(|c| !c.builtin) {
+            write!(f, "class {}", class.name)?;
+            if let Some(parent) = &class.parent {
+                write!(f, " inherits {parent}")?;
+            }
+            writeln!(f, " {{")?;
+            for attr in &class.attributes {
+                writeln!(f, "  {} : {}", attr.name, attr.typ)?;
+            }
+            writeln!(f, "}}")?;
+        }
+        for func in &self.functions {
+            writeln!(f)?;
+            write!(f, "{func}")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,254 @@
+//! Inlining of small statically-bound methods.
+
+use std::collections::HashSet;
+
+use lexer::Symbol;
+
+use crate::devirt::{ClassHierarchy, infer_types};
+use crate::{Block, BlockId, Function, Inst, Module, Reg, Stmt, Target, Terminator};
+
+/// Callees with at most this many instructions (terminators included) are inlined.
+pub const INLINE_THRESHOLD: usize = 12;
+
+/// Replaces static calls to small methods with a copy of the callee's body, using the
+/// bodies as they were before this pass so recursion can't blow up code size. Returns
+/// the number of call sites inlined.
+pub fn inline_small_methods(module: &mut Module) -> usize {
+    let callees: Vec<Function> = module
+        .functions
+        .iter()
+        .filter(|f| is_inlinable(f))
+        .cloned()
+        .collect();
+    let non_void: Vec<HashSet<Reg>> = {
+        let hierarchy = ClassHierarchy::new(module);
+        module
+            .functions
+            .iter()
+            .map(|func| {
+                infer_types(module, &hierarchy, func)
+                    .into_iter()
+                    .filter(|(_, t)| t.non_void)
+                    .map(|(reg, _)| reg)
+                    .collect()
+            })
+            .collect()
+    };
+
+    // Take the functions out so the class table can be consulted while they change.
+    let mut functions = std::mem::take(&mut module.functions);
+    let mut inlined = 0;
+    for (func, non_void) in functions.iter_mut().zip(&non_void) {
+        inlined += inline_calls(module, &callees, non_void, func);
+    }
+    module.functions = functions;
+    inlined
+}
+
+fn is_inlinable(func: &Function) -> bool {
+    func.inst_count() <= INLINE_THRESHOLD
+        && func.blocks.iter().flat_map(|b| b.insts()).all(|inst| {
+            // `new SELF_TYPE` depends on the callee's `self`, which stops being
+            // distinguishable from the caller's once inlined.
+            !matches!(inst, Inst::New { class: None, .. } | Inst::Phi { .. })
+        })
+}
+
+fn inline_calls(
+    module: &Module,
+    callees: &[Function],
+    non_void: &HashSet<Reg>,
+    func: &mut Function,
+) -> usize {
+    let mut inlined = 0;
+    // Blocks copied from a callee aren't scanned again, so inlining never nests.
+    let original_blocks = func.blocks.len();
+    let mut scan: Vec<BlockId> = (0..original_blocks).map(BlockId).collect();
+    while let Some(block) = scan.pop() {
+        let site = func.block(block).insts().position(|inst| {
+            matches!(inst, Inst::Call { target: Target::Static(class), method, .. }
+                if find_callee(module, callees, *class, *method)
+                    .is_some_and(|callee| callee.name() != func.name()))
+        });
+        let Some(index) = site else {
+            continue;
+        };
+        let tail = inline_at(module, callees, non_void, func, block, index);
+        inlined += 1;
+        scan.push(tail);
+    }
+    inlined
+}
+
+fn find_callee<'a>(
+    module: &Module,
+    callees: &'a [Function],
+    class: Symbol,
+    method: Symbol,
+) -> Option<&'a Function> {
+    let defining = module.resolve_method(class, method)?;
+    callees
+        .iter()
+        .find(|f| f.class == defining && f.method == method)
+}
+
+/// Inlines the call at `func.blocks[block].stmts[index]`, returning the block holding
+/// the instructions that followed it.
+fn inline_at(
+    module: &Module,
+    callees: &[Function],
+    non_void: &HashSet<Reg>,
+    func: &mut Function,
+    block: BlockId,
+    index: usize,
+) -> BlockId {
+    let rest = func.block_mut(block).stmts.split_off(index + 1);
+    let Stmt { inst: call, span } = func.block_mut(block).stmts.pop().unwrap();
+    let Inst::Call {
+        dst,
+        recv,
+        target: Target::Static(class),
+        method,
+        args,
+    } = &call
+    else {
+        unreachable!("inline site is a static call");
+    };
+    let callee = find_callee(module, callees, *class, *method).unwrap();
+
+    let tail = BlockId(func.blocks.len());
+    let term = std::mem::replace(&mut func.block_mut(block).term, Terminator::Unreachable);
+    let term_span = func.block(block).term_span;
+    func.blocks.push(Block {
+        stmts: rest,
+        term,
+        term_span,
+    });
+
+    let reg_base = func.next_reg;
+    func.next_reg += callee.next_reg;
+    let map_reg = |r: Reg| Reg(reg_base + r.0);
+    let block_base = func.blocks.len();
+    let map_block = |b: BlockId| BlockId(block_base + b.0);
+
+    for callee_block in &callee.blocks {
+        // The callee's instructions keep their own spans, so an error in one is still
+        // reported where it is in the callee.
+        let mut stmts = callee_block.stmts.clone();
+        for Stmt { inst, .. } in &mut stmts {
+            inst.map_uses(map_reg);
+            if let Some(d) = inst.dst_mut() {
+                *d = map_reg(*d);
+            }
+        }
+        let mut term = callee_block.term.clone();
+        term.map_uses(map_reg);
+        for succ in term.successors_mut() {
+            *succ = map_block(*succ);
+        }
+        if let Terminator::Return(result) = term {
+            let copy = Inst::Copy {
+                dst: *dst,
+                src: result,
+            };
+            stmts.push(Stmt::new(copy, callee_block.term_span));
+            term = Terminator::Jump(tail);
+        }
+        func.blocks.push(Block {
+            stmts,
+            term,
+            term_span: callee_block.term_span,
+        });
+    }
+
+    // Bind the callee's parameters to copies so assignments to formals stay local.
+    let bindings = std::iter::once(*recv).chain(args.iter().copied());
+    for (param, value) in callee.params.iter().zip(bindings) {
+        let copy = Inst::Copy {
+            dst: map_reg(*param),
+            src: value,
+        };
+        func.block_mut(block).stmts.push(Stmt::new(copy, span));
+    }
+
+    let body = map_block(callee.entry);
+    if non_void.contains(recv) {
+        let entry = func.block_mut(block);
+        entry.term = Terminator::Jump(body);
+        entry.term_span = span;
+    } else {
+        // A void receiver must still fail the way the call would have, so keep the
+        // original call on that path.
+        let is_void = func.new_reg();
+        let slow = BlockId(func.blocks.len());
+        func.blocks.push(Block {
+            stmts: vec![Stmt::new(call.clone(), span)],
+            term: Terminator::Jump(tail),
+            term_span: span,
+        });
+        let entry = func.block_mut(block);
+        let is_void_test = Inst::IsVoid {
+            dst: is_void,
+            src: *recv,
+        };
+        entry.stmts.push(Stmt::new(is_void_test, span));
+        entry.term = Terminator::Branch {
+            cond: is_void,
+            then_block: slow,
+            else_block: body,
+        };
+        entry.term_span = span;
+    }
+    tail
+}
+
+#[cfg(test)]
+mod tests {
+    use lexer::sym;
+
+    use super::*;
+    use crate::devirt::devirtualize;
+
+    fn calls(func: &Function) -> Vec<Symbol> {
+        func.blocks
+            .iter()
+            .flat_map(|b| b.insts())
+            .filter_map(|inst| match inst {
+                Inst::Call { method, .. } => Some(*method),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_inline_small_static_calls() {
+        let src = r#"
+            class Counter {
+                n : Int;
+                incr() : Int { n <- n + 1 };
+                big() : Int { { n <- n + 1; n <- n + 1; n <- n + 1; n <- n + 1; n <- n + 1; } };
+            };
+            class Main {
+                c : Counter <- new Counter;
+                main() : Int { { (new Counter).incr(); c.incr(); c.big(); } };
+            };
+        "#;
+        let mut module = crate::lower(&parser::parse(src).unwrap());
+        devirtualize(&mut module);
+        let inlined = inline_small_methods(&mut module);
+        assert_eq!(inlined, 2);
+
+        let main = module.function(sym::MAIN_CLASS, sym::MAIN_METHOD).unwrap();
+        // `c` may be void, so its inlined call keeps the original call as a fallback;
+        // `big` is over the size threshold.
+        let mut remaining = calls(main);
+        remaining.sort();
+        assert_eq!(remaining, ["big", "incr"]);
+        assert!(
+            main.blocks
+                .iter()
+                .flat_map(|b| b.insts())
+                .any(|inst| matches!(inst, Inst::SetAttr { class, .. } if class == "Counter"))
+        );
+    }
+}
@@ -0,0 +1,615 @@
+//! A control-flow-graph intermediate representation for COOL programs.
+//!
+//! Every method body (and every class's attribute initializer) is desugared into the
+//! [`parser::core_ast`] and lowered into a [`Function`]: a list of basic blocks holding
+//! three-address instructions over virtual registers. The optimizer converts functions
+//! to SSA form, runs its passes and converts them back before handing them to a backend.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use lexer::{StrSymbol, Symbol, sym};
+use parser::ast;
+
+pub mod devirt;
+mod display;
+pub mod inline;
+pub mod lower;
+pub mod opt;
+pub mod ssa;
+
+pub use lower::lower;
+pub use opt::{OptLevel, optimize};
+
+/// Name of the synthesized method that runs a class's attribute initializers.
+pub const INIT_METHOD: Symbol = sym::INIT;
+
+/// The classes every COOL program gets for free, with their parents and the
+/// `(name, formal types, return type)` of each method.
+pub const BUILTIN_CLASSES: &[BuiltinClass] = &[
+    (
+        sym::OBJECT,
+        None,
+        &[
+            (sym::ABORT, &[], sym::OBJECT),
+            (sym::TYPE_NAME, &[], sym::STRING),
+            (sym::COPY, &[], sym::SELF_TYPE),
+        ],
+    ),
+    (
+        sym::IO,
+        Some(sym::OBJECT),
+        &[
+            (sym::OUT_STRING, &[sym::STRING], sym::SELF_TYPE),
+            (sym::OUT_INT, &[sym::INT], sym::SELF_TYPE),
+            (sym::IN_STRING, &[], sym::STRING),
+            (sym::IN_INT, &[], sym::INT),
+        ],
+    ),
+    (sym::INT, Some(sym::OBJECT), &[]),
+    (
+        sym::STRING,
+        Some(sym::OBJECT),
+        &[
+            (sym::LENGTH, &[], sym::INT),
+            (sym::CONCAT, &[sym::STRING], sym::STRING),
+            (sym::SUBSTR, &[sym::INT, sym::INT], sym::STRING),
+        ],
+    ),
+    (sym::BOOL, Some(sym::OBJECT), &[]),
+];
+
+pub type BuiltinClass = (
+    Symbol,
+    Option<Symbol>,
+    &'static [(Symbol, &'static [Symbol], Symbol)],
+);
+
+/// A virtual register. Before SSA construction a register may be assigned many times
+/// (one per COOL variable); afterwards every register has exactly one definition.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Reg(pub u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BlockId(pub usize);
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Const {
+    Int(i32),
+    Bool(bool),
+    Str(StrSymbol),
+    Void,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Lt,
+    Le,
+    Eq,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UnOp {
+    Neg,
+    Not,
+}
+
+/// How a [`Inst::Call`] picks the method it runs.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Target {
+    /// Look the method up in the receiver's dynamic class.
+    Virtual,
+    /// Look the method up starting at the named class, as in `e@T.m()`.
+    Static(Symbol),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Inst {
+    Const {
+        dst: Reg,
+        value: Const,
+    },
+    Copy {
+        dst: Reg,
+        src: Reg,
+    },
+    Binary {
+        dst: Reg,
+        op: BinOp,
+        lhs: Reg,
+        rhs: Reg,
+    },
+    Unary {
+        dst: Reg,
+        op: UnOp,
+        src: Reg,
+    },
+    IsVoid {
+        dst: Reg,
+        src: Reg,
+    },
+    /// Allocates and initializes an object. `None` stands for `new SELF_TYPE`.
+    New {
+        dst: Reg,
+        class: Option<Symbol>,
+    },
+    /// Reads attribute `name` of `obj`, whose static class is `class`.
+    GetAttr {
+        dst: Reg,
+        obj: Reg,
+        class: Symbol,
+        name: Symbol,
+    },
+    SetAttr {
+        obj: Reg,
+        class: Symbol,
+        name: Symbol,
+        src: Reg,
+    },
+    Call {
+        dst: Reg,
+        recv: Reg,
+        target: Target,
+        method: Symbol,
+        args: Vec<Reg>,
+    },
+    Phi {
+        dst: Reg,
+        args: Vec<(BlockId, Reg)>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaseArm {
+    pub typ: Symbol,
+    pub block: BlockId,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Terminator {
+    Jump(BlockId),
+    Branch {
+        cond: Reg,
+        then_block: BlockId,
+        else_block: BlockId,
+    },
+    /// Jumps to the arm whose type is the closest ancestor of the scrutinee's dynamic
+    /// class. A void scrutinee or a missing arm is a runtime error.
+    Case {
+        scrutinee: Reg,
+        arms: Vec<CaseArm>,
+    },
+    Return(Reg),
+    Unreachable,
+}
+
+/// An instruction and the span of the expression it was lowered from, which is where a
+/// runtime error it raises is reported.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stmt {
+    pub inst: Inst,
+    pub span: ast::Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub stmts: Vec<Stmt>,
+    pub term: Terminator,
+    /// The span of the expression the terminator was lowered from.
+    pub term_span: ast::Span,
+}
+
+#[derive(Debug, Clone)]
+pub struct Function {
+    /// The class the method is defined in.
+    pub class: Symbol,
+    pub method: Symbol,
+    /// `params[0]` is always `self`.
+    pub params: Vec<Reg>,
+    pub blocks: Vec<Block>,
+    pub entry: BlockId,
+    pub next_reg: u32,
+    /// Where the method is defined, or the class for an [`INIT_METHOD`]. Code generators
+    /// use it for debug information.
+    pub span: ast::Span,
+}
+
+#[derive(Debug, Clone)]
+pub struct Attribute {
+    pub name: Symbol,
+    pub typ: Symbol,
+}
+
+#[derive(Debug, Clone)]
+pub struct Method {
+    pub name: Symbol,
+    pub formal_types: Vec<Symbol>,
+    pub return_type: Symbol,
+}
+
+#[derive(Debug, Clone)]
+pub struct ClassInfo {
+    pub name: Symbol,
+    pub parent: Option<Symbol>,
+    /// Attributes declared by this class only, in source order.
+    pub attributes: Vec<Attribute>,
+    /// Methods declared by this class only, in source order.
+    pub methods: Vec<Method>,
+    pub builtin: bool,
+}
+
+/// A whole lowered program: the class table plus a function per method.
+#[derive(Debug, Clone, Default)]
+pub struct Module {
+    pub classes: Vec<ClassInfo>,
+    pub functions: Vec<Function>,
+}
+
+impl Inst {
+    /// The register this instruction defines, if any.
+    pub fn dst(&self) -> Option<Reg> {
+        match self {
+            Inst::Const { dst, .. }
+            | Inst::Copy { dst, .. }
+            | Inst::Binary { dst, .. }
+            | Inst::Unary { dst, .. }
+            | Inst::IsVoid { dst, .. }
+            | Inst::New { dst, .. }
+            | Inst::GetAttr { dst, .. }
+            | Inst::Call { dst, .. }
+            | Inst::Phi { dst, .. } => Some(*dst),
+            Inst::SetAttr { .. } => None,
+        }
+    }
+
+    pub fn dst_mut(&mut self) -> Option<&mut Reg> {
+        match self {
+            Inst::Const { dst, .. }
+            | Inst::Copy { dst, .. }
+            | Inst::Binary { dst, .. }
+            | Inst::Unary { dst, .. }
+            | Inst::IsVoid { dst, .. }
+            | Inst::New { dst, .. }
+            | Inst::GetAttr { dst, .. }
+            | Inst::Call { dst, .. }
+            | Inst::Phi { dst, .. } => Some(dst),
+            Inst::SetAttr { .. } => None,
+        }
+    }
+
+    /// Every register this instruction reads.
+    pub fn uses(&self) -> Vec<Reg> {
+        match self {
+            Inst::Const { .. } | Inst::New { .. } => vec![],
+            Inst::Copy { src, .. } | Inst::Unary { src, .. } | Inst::IsVoid { src, .. } => {
+                vec![*src]
+            }
+            Inst::Binary { lhs, rhs, .. } => vec![*lhs, *rhs],
+            Inst::GetAttr { obj, .. } => vec![*obj],
+            Inst::SetAttr { obj, src, .. } => vec![*obj, *src],
+            Inst::Call { recv, args, .. } => {
+                std::iter::once(*recv).chain(args.iter().copied()).collect()
+            }
+            Inst::Phi { args, .. } => args.iter().map(|(_, r)| *r).collect(),
+        }
+    }
+
+    /// Applies `f` to every register this instruction reads.
+    pub fn map_uses(&mut self, mut f: impl FnMut(Reg) -> Reg) {
+        match self {
+            Inst::Const { .. } | Inst::New { .. } => {}
+            Inst::Copy { src, .. } | Inst::Unary { src, .. } | Inst::IsVoid { src, .. } => {
+                *src = f(*src)
+            }
+            Inst::Binary { lhs, rhs, .. } => {
+                *lhs = f(*lhs);
+                *rhs = f(*rhs);
+            }
+            Inst::GetAttr { obj, .. } => *obj = f(*obj),
+            Inst::SetAttr { obj, src, .. } => {
+                *obj = f(*obj);
+                *src = f(*src);
+            }
+            Inst::Call { recv, args, .. } => {
+                *recv = f(*recv);
+                for arg in args {
+                    *arg = f(*arg);
+                }
+            }
+            Inst::Phi { args, .. } => {
+                for (_, arg) in args {
+                    *arg = f(*arg);
+                }
+            }
+        }
+    }
+
+    /// Whether removing this instruction could change the program's behaviour, assuming
+    /// its result is unused.
+    pub fn has_side_effects(&self) -> bool {
+        match self {
+            Inst::Const { .. }
+            | Inst::Copy { .. }
+            | Inst::Unary { .. }
+            | Inst::IsVoid { .. }
+            | Inst::GetAttr { .. }
+            | Inst::Phi { .. } => false,
+            // Division by zero is a runtime error.
+            Inst::Binary { op, .. } => *op == BinOp::Div,
+            // Allocating a basic class runs no user code.
+            Inst::New { class, .. } => !matches!(
+                class,
+                Some(sym::OBJECT | sym::IO | sym::INT | sym::STRING | sym::BOOL)
+            ),
+            Inst::SetAttr { .. } | Inst::Call { .. } => true,
+        }
+    }
+}
+
+impl Stmt {
+    pub fn new(inst: Inst, span: ast::Span) -> Self {
+        Stmt { inst, span }
+    }
+}
+
+impl Block {
+    /// An empty block that ends with `term`.
+    pub fn new(term: Terminator, term_span: ast::Span) -> Self {
+        Block {
+            stmts: Vec::new(),
+            term,
+            term_span,
+        }
+    }
+
+    /// The block's instructions, without their spans.
+    pub fn insts(&self) -> impl Iterator<Item = &Inst> {
+        self.stmts.iter().map(|stmt| &stmt.inst)
+    }
+
+    pub fn insts_mut(&mut self) -> impl Iterator<Item = &mut Inst> {
+        self.stmts.iter_mut().map(|stmt| &mut stmt.inst)
+    }
+}
+
+impl Terminator {
+    pub fn successors(&self) -> Vec<BlockId> {
+        match self {
+            Terminator::Jump(b) => vec![*b],
+            Terminator::Branch {
+                then_block,
+                else_block,
+                ..
+            } => vec![*then_block, *else_block],
+            Terminator::Case { arms, .. } => arms.iter().map(|arm| arm.block).collect(),
+            Terminator::Return(_) | Terminator::Unreachable => vec![],
+        }
+    }
+
+    pub fn successors_mut(&mut self) -> Vec<&mut BlockId> {
+        match self {
+            Terminator::Jump(b) => vec![b],
+            Terminator::Branch {
+                then_block,
+                else_block,
+                ..
+            } => vec![then_block, else_block],
+            Terminator::Case { arms, .. } => arms.iter_mut().map(|arm| &mut arm.block).collect(),
+            Terminator::Return(_) | Terminator::Unreachable => vec![],
+        }
+    }
+
+    pub fn uses(&self) -> Vec<Reg> {
+        match self {
+            Terminator::Branch { cond, .. } => vec![*cond],
+            Terminator::Case { scrutinee, .. } => vec![*scrutinee],
+            Terminator::Return(r) => vec![*r],
+            Terminator::Jump(_) | Terminator::Unreachable => vec![],
+        }
+    }
+
+    pub fn map_uses(&mut self, mut f: impl FnMut(Reg) -> Reg) {
+        match self {
+            Terminator::Branch { cond, .. } => *cond = f(*cond),
+            Terminator::Case { scrutinee, .. } => *scrutinee = f(*scrutinee),
+            Terminator::Return(r) => *r = f(*r),
+            Terminator::Jump(_) | Terminator::Unreachable => {}
+        }
+    }
+}
+
+impl Function {
+    /// The mangled `Class.method` name used for symbols and diagnostics.
+    pub fn name(&self) -> String {
+        format!("{}.{}", self.class, self.method)
+    }
+
+    pub fn new_reg(&mut self) -> Reg {
+        let reg = Reg(self.next_reg);
+        self.next_reg += 1;
+        reg
+    }
+
+    pub fn block(&self, id: BlockId) -> &Block {
+        &self.blocks[id.0]
+    }
+
+    pub fn block_mut(&mut self, id: BlockId) -> &mut Block {
+        &mut self.blocks[id.0]
+    }
+
+    pub fn block_ids(&self) -> impl Iterator<Item = BlockId> + use<> {
+        (0..self.blocks.len()).map(BlockId)
+    }
+
+    /// Predecessor lists for every block, indexed by block id. A block that reaches a
+    /// successor through several edges is listed once per edge.
+    pub fn predecessors(&self) -> Vec<Vec<BlockId>> {
+        let mut preds = vec![Vec::new(); self.blocks.len()];
+        for id in self.block_ids() {
+            for succ in self.block(id).term.successors() {
+                preds[succ.0].push(id);
+            }
+        }
+        preds
+    }
+
+    /// Blocks reachable from the entry in reverse postorder.
+    pub fn reverse_postorder(&self) -> Vec<BlockId> {
+        let mut visited = vec![false; self.blocks.len()];
+        let mut order = Vec::with_capacity(self.blocks.len());
+        // Iterative DFS keeping, for each block on the stack, the next successor to visit.
+        let mut stack = vec![(self.entry, 0)];
+        visited[self.entry.0] = true;
+        while let Some((block, next)) = stack.last_mut() {
+            let succs = self.blocks[block.0].term.successors();
+            if let Some(&succ) = succs.get(*next) {
+                *next += 1;
+                if !visited[succ.0] {
+                    visited[succ.0] = true;
+                    stack.push((succ, 0));
+                }
+            } else {
+                order.push(*block);
+                stack.pop();
+            }
+        }
+        order.reverse();
+        order
+    }
+
+    /// Drops blocks the entry can't reach and renumbers the rest, keeping phis in step.
+    pub fn remove_unreachable_blocks(&mut self) {
+        let mut remap = vec![None; self.blocks.len()];
+        for (new, old) in self.reverse_postorder().into_iter().enumerate() {
+            remap[old.0] = Some(BlockId(new));
+        }
+        let mut blocks: Vec<Option<Block>> = std::mem::take(&mut self.blocks)
+            .into_iter()
+            .map(Some)
+            .collect();
+        let mut kept = vec![None; remap.iter().flatten().count()];
+        for (old, new) in remap.iter().enumerate() {
+            if let Some(new) = new {
+                kept[new.0] = blocks[old].take();
+            }
+        }
+        self.blocks = kept.into_iter().map(Option::unwrap).collect();
+        self.entry = remap[self.entry.0].unwrap();
+        for block in &mut self.blocks {
+            for succ in block.term.successors_mut() {
+                *succ = remap[succ.0].unwrap();
+            }
+            for inst in block.insts_mut() {
+                if let Inst::Phi { args, .. } = inst {
+                    args.retain_mut(|(pred, _)| match remap[pred.0] {
+                        Some(new) => {
+                            *pred = new;
+                            true
+                        }
+                        None => false,
+                    });
+                }
+            }
+        }
+    }
+
+    /// Number of instructions (including terminators) across all blocks.
+    pub fn inst_count(&self) -> usize {
+        self.blocks.iter().map(|b| b.stmts.len() + 1).sum()
+    }
+}
+
+impl Module {
+    pub fn class(&self, name: Symbol) -> Option<&ClassInfo> {
+        self.classes.iter().find(|c| c.name == name)
+    }
+
+    pub fn function(&self, class: Symbol, method: Symbol) -> Option<&Function> {
+        self.functions
+            .iter()
+            .find(|f| f.class == class && f.method == method)
+    }
+
+    /// `name` followed by its ancestors, ending with `Object`.
+    pub fn ancestors(&self, name: Symbol) -> impl Iterator<Item = Symbol> + '_ {
+        std::iter::successors(Some(name), |&current| {
+            self.class(current).and_then(|c| c.parent)
+        })
+    }
+
+    pub fn is_subclass(&self, class: Symbol, ancestor: Symbol) -> bool {
+        self.ancestors(class).any(|c| c == ancestor)
+    }
+
+    /// The class whose definition of `method` is used for objects of class `class`.
+    pub fn resolve_method(&self, class: Symbol, method: Symbol) -> Option<Symbol> {
+        self.ancestors(class).find(|&c| {
+            self.class(c)
+                .is_some_and(|info| info.methods.iter().any(|m| m.name == method))
+        })
+    }
+
+    /// The signature of `method` as seen from objects of class `class`.
+    pub fn method_signature(&self, class: Symbol, method: Symbol) -> Option<&Method> {
+        let defining = self.resolve_method(class, method)?;
+        self.class(defining)?
+            .methods
+            .iter()
+            .find(|m| m.name == method)
+    }
+
+    /// The declared type of attribute `name` as seen from class `class`.
+    pub fn attribute_type(&self, class: Symbol, name: Symbol) -> Option<Symbol> {
+        self.all_attributes(class)
+            .into_iter()
+            .rev()
+            .find(|(_, attr)| attr.name == name)
+            .map(|(_, attr)| attr.typ)
+    }
+
+    /// All attributes of `class`, inherited ones first, paired with the class declaring them.
+    pub fn all_attributes(&self, class: Symbol) -> Vec<(Symbol, &Attribute)> {
+        let mut chain: Vec<Symbol> = self.ancestors(class).collect();
+        chain.reverse();
+        chain
+            .into_iter()
+            .filter_map(|c| self.class(c))
+            .flat_map(|info| info.attributes.iter().map(|a| (info.name, a)))
+            .collect()
+    }
+
+    /// Index of the `case` arm selected for an object of class `class`: the arm whose
+    /// type is the closest ancestor.
+    pub fn case_arm(&self, class: Symbol, arms: &[CaseArm]) -> Option<usize> {
+        self.ancestors(class)
+            .find_map(|ancestor| arms.iter().position(|arm| arm.typ == ancestor))
+    }
+
+    /// Direct subclasses of every class, keyed by parent name.
+    pub fn children(&self) -> HashMap<Symbol, Vec<Symbol>> {
+        let mut children: HashMap<Symbol, Vec<Symbol>> = HashMap::new();
+        for class in &self.classes {
+            if let Some(parent) = class.parent {
+                children.entry(parent).or_default().push(class.name);
+            }
+        }
+        children
+    }
+}
+
+impl fmt::Display for Reg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "%{}", self.0)
+    }
+}
+
+impl fmt::Display for BlockId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "bb{}", self.0)
+    }
+}
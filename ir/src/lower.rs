@@ -0,0 +1,476 @@
+//! Lowering from the [`parser::core_ast`] to per-method control flow graphs.
+
+use lexer::{StrSymbol, Symbol, sym};
+use parser::ast;
+use parser::core_ast::{self, Expr, ExprKind, Feature};
+
+use crate::{
+    Attribute, BUILTIN_CLASSES, BinOp, Block, BlockId, CaseArm, ClassInfo, Const, Function,
+    INIT_METHOD, Inst, Method, Module, Reg, Stmt, Target, Terminator, UnOp,
+};
+
+/// Lowers a whole program. Every method becomes a [`Function`], and every class gets an
+/// extra [`INIT_METHOD`] function that runs its parent's initializer and then its own
+/// attribute initializers in declaration order. The program is desugared first, so only
+/// the core language needs lowering.
+pub fn lower(program: &ast::Program) -> Module {
+    let program = core_ast::desugar(program);
+    let mut module = Module::default();
+
+    for &(name, parent, methods) in BUILTIN_CLASSES {
+        module.classes.push(ClassInfo {
+            name,
+            parent,
+            attributes: Vec::new(),
+            methods: methods
+                .iter()
+                .map(|&(name, formals, return_type)| Method {
+                    name,
+                    formal_types: formals.to_vec(),
+                    return_type,
+                })
+                .collect(),
+            builtin: true,
+        });
+    }
+
+    for class in &program.classes {
+        let mut attributes = Vec::new();
+        let mut methods = vec![Method {
+            name: INIT_METHOD,
+            formal_types: Vec::new(),
+            return_type: sym::SELF_TYPE,
+        }];
+        for feature in &class.features {
+            match feature {
+                Feature::Attribute(attr) => attributes.push(Attribute {
+                    name: attr.name,
+                    typ: attr.attr_type,
+                }),
+                Feature::Method(method) => methods.push(Method {
+                    name: method.name,
+                    formal_types: method.formals.iter().map(|f| f.typ).collect(),
+                    return_type: method.return_type,
+                }),
+            }
+        }
+        module.classes.push(ClassInfo {
+            name: class.name,
+            parent: Some(class.parent.unwrap_or(sym::OBJECT)),
+            attributes,
+            methods,
+            builtin: false,
+        });
+    }
+
+    for class in &program.classes {
+        let parent = class.parent.unwrap_or(sym::OBJECT);
+        let parent_is_builtin = BUILTIN_CLASSES.iter().any(|(name, ..)| *name == parent);
+        module
+            .functions
+            .push(lower_init(class, (!parent_is_builtin).then_some(parent)));
+        for feature in &class.features {
+            if let Feature::Method(method) = feature {
+                module.functions.push(lower_method(class.name, method));
+            }
+        }
+    }
+
+    module
+}
+
+fn lower_init(class: &core_ast::Class, parent: Option<Symbol>) -> Function {
+    let mut builder = Builder::new(class.name, INIT_METHOD, &[], class.span);
+    let this = builder.self_reg;
+    if let Some(parent) = parent {
+        let dst = builder.new_reg();
+        builder.push(Inst::Call {
+            dst,
+            recv: this,
+            target: Target::Static(parent),
+            method: INIT_METHOD,
+            args: vec![],
+        });
+    }
+    for feature in &class.features {
+        if let Feature::Attribute(core_ast::AttributeFeature {
+            name,
+            init: Some(init),
+            ..
+        }) = feature
+        {
+            let src = builder.expr(init);
+            builder.push(Inst::SetAttr {
+                obj: this,
+                class: class.name,
+                name: *name,
+                src,
+            });
+        }
+    }
+    builder.finish(this)
+}
+
+fn lower_method(class: Symbol, method: &core_ast::MethodFeature) -> Function {
+    let mut builder = Builder::new(class, method.name, &method.formals, method.span);
+    let result = builder.expr(&method.body);
+    builder.finish(result)
+}
+
+/// The value a variable of type `typ` holds before it is assigned.
+pub fn default_value(typ: Symbol) -> Const {
+    match typ {
+        sym::INT => Const::Int(0),
+        sym::BOOL => Const::Bool(false),
+        sym::STRING => Const::Str(StrSymbol::EMPTY),
+        _ => Const::Void,
+    }
+}
+
+struct Builder {
+    func: Function,
+    current: BlockId,
+    self_reg: Reg,
+    scopes: Vec<(Symbol, Reg)>,
+    /// The span of the expression being lowered, which every instruction pushed for it
+    /// is tagged with.
+    span: ast::Span,
+}
+
+impl Builder {
+    fn new(class: Symbol, method: Symbol, formals: &[ast::Formal], span: ast::Span) -> Self {
+        let mut func = Function {
+            class,
+            method,
+            params: Vec::new(),
+            blocks: vec![Block::new(Terminator::Unreachable, span)],
+            entry: BlockId(0),
+            next_reg: 0,
+            span,
+        };
+        let self_reg = func.new_reg();
+        func.params.push(self_reg);
+        let mut scopes = Vec::new();
+        for formal in formals {
+            let reg = func.new_reg();
+            func.params.push(reg);
+            scopes.push((formal.name, reg));
+        }
+        Builder {
+            func,
+            current: BlockId(0),
+            self_reg,
+            scopes,
+            span,
+        }
+    }
+
+    fn finish(mut self, result: Reg) -> Function {
+        self.terminate(Terminator::Return(result));
+        self.func
+    }
+
+    fn new_reg(&mut self) -> Reg {
+        self.func.new_reg()
+    }
+
+    fn new_block(&mut self) -> BlockId {
+        self.func
+            .blocks
+            .push(Block::new(Terminator::Unreachable, self.span));
+        BlockId(self.func.blocks.len() - 1)
+    }
+
+    fn push(&mut self, inst: Inst) {
+        let stmt = Stmt::new(inst, self.span);
+        self.func.block_mut(self.current).stmts.push(stmt);
+    }
+
+    fn terminate(&mut self, term: Terminator) {
+        let block = self.func.block_mut(self.current);
+        block.term = term;
+        block.term_span = self.span;
+    }
+
+    fn constant(&mut self, value: Const) -> Reg {
+        let dst = self.new_reg();
+        self.push(Inst::Const { dst, value });
+        dst
+    }
+
+    fn lookup(&self, name: Symbol) -> Option<Reg> {
+        self.scopes
+            .iter()
+            .rev()
+            .find(|(n, _)| *n == name)
+            .map(|(_, reg)| *reg)
+    }
+
+    /// Declares a new variable initialized from `init` and brings it into scope.
+    fn bind(&mut self, name: Symbol, init: Reg) {
+        let var = self.new_reg();
+        self.push(Inst::Copy {
+            dst: var,
+            src: init,
+        });
+        self.scopes.push((name, var));
+    }
+
+    fn binary(&mut self, op: BinOp, lhs: &Expr, rhs: &Expr) -> Reg {
+        let lhs = self.expr(lhs);
+        let rhs = self.expr(rhs);
+        let dst = self.new_reg();
+        self.push(Inst::Binary { dst, op, lhs, rhs });
+        dst
+    }
+
+    fn unary(&mut self, op: UnOp, src: &Expr) -> Reg {
+        let src = self.expr(src);
+        let dst = self.new_reg();
+        self.push(Inst::Unary { dst, op, src });
+        dst
+    }
+
+    fn call(&mut self, recv: Reg, target: Target, method: Symbol, args: Vec<Reg>) -> Reg {
+        let dst = self.new_reg();
+        self.push(Inst::Call {
+            dst,
+            recv,
+            target,
+            method,
+            args,
+        });
+        dst
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Reg {
+        let outer = std::mem::replace(&mut self.span, expr.span);
+        let result = self.expr_kind(&expr.kind);
+        self.span = outer;
+        result
+    }
+
+    fn expr_kind(&mut self, kind: &ExprKind) -> Reg {
+        match kind {
+            ExprKind::Assign { name, expr } => {
+                let src = self.expr(expr);
+                match self.lookup(*name) {
+                    Some(var) => self.push(Inst::Copy { dst: var, src }),
+                    None => self.push(Inst::SetAttr {
+                        obj: self.self_reg,
+                        class: self.func.class,
+                        name: *name,
+                        src,
+                    }),
+                }
+                src
+            }
+            ExprKind::Dispatch {
+                expr,
+                static_type,
+                method,
+                args,
+            } => {
+                // Arguments are evaluated before the receiver, as in the reference compiler.
+                let args = args.iter().map(|arg| self.expr(arg)).collect();
+                let recv = self.expr(expr);
+                let target = match static_type {
+                    Some(class) => Target::Static(*class),
+                    None => Target::Virtual,
+                };
+                self.call(recv, target, *method, args)
+            }
+            ExprKind::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => {
+                let cond = self.expr(cond);
+                let then_block = self.new_block();
+                let else_block = self.new_block();
+                let join = self.new_block();
+                let result = self.new_reg();
+                self.terminate(Terminator::Branch {
+                    cond,
+                    then_block,
+                    else_block,
+                });
+                for (block, branch) in [(then_block, then_branch), (else_block, else_branch)] {
+                    self.current = block;
+                    let src = self.expr(branch);
+                    self.push(Inst::Copy { dst: result, src });
+                    self.terminate(Terminator::Jump(join));
+                }
+                self.current = join;
+                result
+            }
+            ExprKind::While { cond, body } => {
+                let header = self.new_block();
+                let body_block = self.new_block();
+                let exit = self.new_block();
+                self.terminate(Terminator::Jump(header));
+                self.current = header;
+                let cond = self.expr(cond);
+                self.terminate(Terminator::Branch {
+                    cond,
+                    then_block: body_block,
+                    else_block: exit,
+                });
+                self.current = body_block;
+                self.expr(body);
+                self.terminate(Terminator::Jump(header));
+                self.current = exit;
+                self.constant(Const::Void)
+            }
+            ExprKind::Block(exprs) => {
+                let mut result = None;
+                for expr in exprs {
+                    result = Some(self.expr(expr));
+                }
+                result.unwrap_or_else(|| self.constant(Const::Void))
+            }
+            ExprKind::Let {
+                name,
+                typ,
+                init,
+                body,
+            } => {
+                let init = match init {
+                    Some(init) => self.expr(init),
+                    None => self.constant(default_value(*typ)),
+                };
+                self.bind(*name, init);
+                let result = self.expr(body);
+                self.scopes.pop();
+                result
+            }
+            ExprKind::Case { expr, branches } => {
+                let scrutinee = self.expr(expr);
+                let join = self.new_block();
+                let result = self.new_reg();
+                let arms: Vec<CaseArm> = branches
+                    .iter()
+                    .map(|branch| CaseArm {
+                        typ: branch.typ,
+                        block: self.new_block(),
+                    })
+                    .collect();
+                self.terminate(Terminator::Case {
+                    scrutinee,
+                    arms: arms.clone(),
+                });
+                for (branch, arm) in branches.iter().zip(arms) {
+                    self.current = arm.block;
+                    self.bind(branch.name, scrutinee);
+                    let src = self.expr(&branch.expr);
+                    self.scopes.pop();
+                    self.push(Inst::Copy { dst: result, src });
+                    self.terminate(Terminator::Jump(join));
+                }
+                self.current = join;
+                result
+            }
+            ExprKind::New(class) => {
+                let dst = self.new_reg();
+                let class = (*class != sym::SELF_TYPE).then_some(*class);
+                self.push(Inst::New { dst, class });
+                dst
+            }
+            ExprKind::IsVoid(expr) => {
+                let src = self.expr(expr);
+                let dst = self.new_reg();
+                self.push(Inst::IsVoid { dst, src });
+                dst
+            }
+            ExprKind::Plus(lhs, rhs) => self.binary(BinOp::Add, lhs, rhs),
+            ExprKind::Minus(lhs, rhs) => self.binary(BinOp::Sub, lhs, rhs),
+            ExprKind::Times(lhs, rhs) => self.binary(BinOp::Mul, lhs, rhs),
+            ExprKind::Divide(lhs, rhs) => self.binary(BinOp::Div, lhs, rhs),
+            ExprKind::Lt(lhs, rhs) => self.binary(BinOp::Lt, lhs, rhs),
+            ExprKind::Le(lhs, rhs) => self.binary(BinOp::Le, lhs, rhs),
+            ExprKind::Eq(lhs, rhs) => self.binary(BinOp::Eq, lhs, rhs),
+            ExprKind::Not(expr) => self.unary(UnOp::Not, expr),
+            ExprKind::Negate(expr) => self.unary(UnOp::Neg, expr),
+            ExprKind::SelfRef => self.self_reg,
+            ExprKind::Id(name) => {
+                let dst = self.new_reg();
+                match self.lookup(*name) {
+                    // Read variables through a copy so a later assignment in the same
+                    // expression can't change a value that was already evaluated.
+                    Some(var) => self.push(Inst::Copy { dst, src: var }),
+                    None => self.push(Inst::GetAttr {
+                        dst,
+                        obj: self.self_reg,
+                        class: self.func.class,
+                        name: *name,
+                    }),
+                }
+                dst
+            }
+            ExprKind::Integer(i) => self.constant(Const::Int(i.value())),
+            ExprKind::String(s) => self.constant(Const::Str(*s)),
+            ExprKind::True => self.constant(Const::Bool(true)),
+            ExprKind::False => self.constant(Const::Bool(false)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lower_builds_init_and_methods() {
+        let program = parser::parse(
+            r#"class A { x : Int <- 1; get() : Int { x }; };
+               class B inherits A { y : Int <- 2; };"#,
+        )
+        .unwrap();
+        let module = lower(&program);
+
+        let [a, b, get] = ["A", "B", "get"].map(Symbol::intern);
+        assert!(module.function(a, INIT_METHOD).is_some());
+        assert!(module.function(a, get).is_some());
+        assert_eq!(module.resolve_method(b, get), Some(a));
+        assert_eq!(module.resolve_method(b, sym::ABORT), Some(sym::OBJECT));
+
+        let attrs: Vec<_> = module
+            .all_attributes(b)
+            .into_iter()
+            .map(|(class, attr)| format!("{class}.{}", attr.name))
+            .collect();
+        assert_eq!(attrs, ["A.x", "B.y"]);
+
+        // B's initializer runs A's before its own.
+        let init = module.function(b, INIT_METHOD).unwrap();
+        assert!(matches!(
+            &init.blocks[0].stmts[0].inst,
+            Inst::Call { target: Target::Static(parent), .. } if parent == "A"
+        ));
+    }
+
+    #[test]
+    fn test_instructions_keep_their_expression_spans() {
+        let src = "class Main {\n  f(a : Int, b : Int) : Int {\n    1 + a / b\n  };\n};";
+        let module = lower(&parser::parse(src).unwrap());
+        let f = module
+            .function(Symbol::intern("Main"), Symbol::intern("f"))
+            .unwrap();
+        let text = |span: ast::Span| &src[span.start..span.end];
+        let span_of = |pred: fn(&Inst) -> bool| {
+            let stmt = f.blocks[0].stmts.iter().find(|stmt| pred(&stmt.inst));
+            text(stmt.unwrap().span)
+        };
+        assert_eq!(
+            span_of(|inst| matches!(inst, Inst::Binary { op: BinOp::Div, .. })),
+            "a / b"
+        );
+        assert_eq!(
+            span_of(|inst| matches!(inst, Inst::Binary { op: BinOp::Add, .. })),
+            "1 + a / b"
+        );
+        assert_eq!(span_of(|inst| matches!(inst, Inst::Const { .. })), "1");
+        // The return has no expression of its own, so it goes with the method.
+        assert!(text(f.blocks[0].term_span).starts_with("f(a : Int"));
+    }
+}
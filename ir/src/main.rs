@@ -0,0 +1,66 @@
+use clap::Parser;
+use ir::OptLevel;
+use lexer::source::SourceMap;
+use std::process::ExitCode;
+
+#[derive(Parser)]
+#[command(name = "ir")]
+#[command(about = "Lowers a Cool program to IR and optimizes it")]
+#[command(version)]
+struct Args {
+    /// Input Cool (.cl) file
+    #[arg(value_name = "FILE")]
+    file: String,
+
+    /// Optimization level (0, 1 or 2)
+    #[arg(short = 'O', default_value = "0")]
+    opt_level: OptLevel,
+
+    /// Print instruction counts per function instead of the IR
+    #[arg(long)]
+    stats: bool,
+
+    /// Report how many dispatch sites were resolved statically (at -O2)
+    #[arg(long)]
+    devirt_report: bool,
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    let mut sources = SourceMap::new();
+    let file = match sources.load(&args.file) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("Error: failed to read '{}': {e}", args.file);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let program = match parser::parse_file(sources.file(file).src(), file) {
+        Ok(program) => program,
+        Err(errors) => {
+            for error in errors {
+                eprintln!("{}", parser::format_error(&error, &sources));
+            }
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let mut module = ir::lower(&program);
+    let report = ir::optimize(&mut module, args.opt_level);
+
+    if args.stats {
+        let mut total = 0;
+        for func in &module.functions {
+            println!("{:>6}  {}", func.inst_count(), func.name());
+            total += func.inst_count();
+        }
+        println!("{total:>6}  total ({})", args.opt_level);
+    } else {
+        print!("{module}");
+    }
+    if args.devirt_report {
+        eprintln!("{report}");
+    }
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,570 @@
+//! Scalar optimizations over SSA form.
+//!
+//! [`optimize`] converts each function to SSA, runs the passes selected by the
+//! [`OptLevel`] until nothing changes, and converts the function back. At `-O2` the
+//! whole program is also devirtualized and small methods are inlined.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::devirt::{self, DevirtReport};
+use crate::inline;
+use crate::ssa::{self, DomTree};
+use crate::{BinOp, Block, Const, Function, Inst, Module, Reg, Stmt, Terminator, UnOp};
+
+/// Upper bound on pass-pipeline iterations per function; each iteration only ever
+/// shrinks the function, so this is a safety net rather than a tuning knob.
+const MAX_ITERATIONS: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum OptLevel {
+    /// No optimization: the CFG exactly as lowered.
+    #[default]
+    O0,
+    /// Constant folding and propagation, copy propagation, branch simplification and
+    /// dead code elimination.
+    O1,
+    /// Everything in `O1` plus common subexpression elimination, block merging,
+    /// devirtualization and inlining.
+    O2,
+}
+
+impl FromStr for OptLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim_start_matches("-O") {
+            "0" => Ok(OptLevel::O0),
+            "1" => Ok(OptLevel::O1),
+            "2" => Ok(OptLevel::O2),
+            other => Err(format!("unknown optimization level '{other}'")),
+        }
+    }
+}
+
+impl fmt::Display for OptLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OptLevel::O0 => write!(f, "-O0"),
+            OptLevel::O1 => write!(f, "-O1"),
+            OptLevel::O2 => write!(f, "-O2"),
+        }
+    }
+}
+
+/// Optimizes every function in `module` at the given level, returning what
+/// devirtualization achieved (all zeros below `-O2`).
+pub fn optimize(module: &mut Module, level: OptLevel) -> DevirtReport {
+    let mut report = DevirtReport::default();
+    if level >= OptLevel::O2 {
+        report = devirt::devirtualize(module);
+    }
+    for func in &mut module.functions {
+        optimize_function(func, level);
+    }
+    if level >= OptLevel::O2 {
+        // Measure callees after optimization, then clean up what inlining exposed.
+        report.inlined = inline::inline_small_methods(module);
+        if report.inlined > 0 {
+            for func in &mut module.functions {
+                optimize_function(func, level);
+            }
+        }
+    }
+    report
+}
+
+pub fn optimize_function(func: &mut Function, level: OptLevel) {
+    if level == OptLevel::O0 {
+        return;
+    }
+    ssa::construct(func);
+    for _ in 0..MAX_ITERATIONS {
+        let mut changed = false;
+        changed |= fold_constants(func);
+        changed |= simplify_branches(func);
+        if level >= OptLevel::O2 {
+            changed |= eliminate_common_subexpressions(func);
+        }
+        changed |= propagate_copies(func);
+        if level >= OptLevel::O2 {
+            changed |= merge_blocks(func);
+        }
+        changed |= eliminate_dead_code(func);
+        if !changed {
+            break;
+        }
+    }
+    ssa::destruct(func);
+}
+
+/// Evaluates `op` over constant operands, or `None` if the result isn't known at
+/// compile time (including division by zero, which must fail at runtime).
+fn eval_binary(op: BinOp, lhs: &Const, rhs: &Const) -> Option<Const> {
+    use Const::*;
+    Some(match (op, lhs, rhs) {
+        (BinOp::Add, Int(a), Int(b)) => Int(a.wrapping_add(*b)),
+        (BinOp::Sub, Int(a), Int(b)) => Int(a.wrapping_sub(*b)),
+        (BinOp::Mul, Int(a), Int(b)) => Int(a.wrapping_mul(*b)),
+        (BinOp::Div, Int(_), Int(0)) => return None,
+        (BinOp::Div, Int(a), Int(b)) => Int(a.wrapping_div(*b)),
+        (BinOp::Lt, Int(a), Int(b)) => Bool(a < b),
+        (BinOp::Le, Int(a), Int(b)) => Bool(a <= b),
+        (BinOp::Eq, a, b) => Bool(a == b),
+        _ => return None,
+    })
+}
+
+fn eval_unary(op: UnOp, src: &Const) -> Option<Const> {
+    match (op, src) {
+        (UnOp::Neg, Const::Int(i)) => Some(Const::Int(i.wrapping_neg())),
+        (UnOp::Not, Const::Bool(b)) => Some(Const::Bool(!b)),
+        _ => None,
+    }
+}
+
+/// Constant folding and propagation: replaces every instruction whose result is known
+/// at compile time with a [`Inst::Const`].
+pub fn fold_constants(func: &mut Function) -> bool {
+    let mut known: HashMap<Reg, Const> = HashMap::new();
+    let order = func.reverse_postorder();
+    // Phis at loop headers can see their back edges' values only on a later sweep.
+    loop {
+        let before = known.len();
+        for &b in &order {
+            for inst in func.block(b).insts() {
+                let value = match inst {
+                    Inst::Const { value, .. } => Some(value.clone()),
+                    Inst::Copy { src, .. } => known.get(src).cloned(),
+                    Inst::Binary { op, lhs, rhs, .. } => match (known.get(lhs), known.get(rhs)) {
+                        (Some(l), Some(r)) => eval_binary(*op, l, r),
+                        _ => None,
+                    },
+                    Inst::Unary { op, src, .. } => known.get(src).and_then(|c| eval_unary(*op, c)),
+                    Inst::IsVoid { src, .. } => {
+                        known.get(src).map(|c| Const::Bool(*c == Const::Void))
+                    }
+                    Inst::Phi { args, .. } => {
+                        let mut values = args.iter().map(|(_, r)| known.get(r));
+                        match values.next().flatten() {
+                            Some(first) if values.all(|v| v == Some(first)) => Some(first.clone()),
+                            _ => None,
+                        }
+                    }
+                    _ => None,
+                };
+                if let (Some(dst), Some(value)) = (inst.dst(), value) {
+                    known.insert(dst, value);
+                }
+            }
+        }
+        if known.len() == before {
+            break;
+        }
+    }
+
+    let mut changed = false;
+    for block in &mut func.blocks {
+        for inst in block.insts_mut() {
+            if matches!(inst, Inst::Const { .. }) {
+                continue;
+            }
+            if let Some(value) = inst.dst().and_then(|dst| known.get(&dst)) {
+                *inst = Inst::Const {
+                    dst: inst.dst().unwrap(),
+                    value: value.clone(),
+                };
+                changed = true;
+            }
+        }
+        if let Terminator::Branch {
+            cond,
+            then_block,
+            else_block,
+        } = block.term
+        {
+            if let Some(Const::Bool(taken)) = known.get(&cond) {
+                block.term = Terminator::Jump(if *taken { then_block } else { else_block });
+                changed = true;
+            }
+        }
+    }
+    changed
+}
+
+/// Removes blocks made unreachable by constant branches and the phi operands that
+/// flowed in along the edges that no longer exist.
+pub fn simplify_branches(func: &mut Function) -> bool {
+    let mut changed = false;
+    let preds = func.predecessors();
+    for b in func.block_ids() {
+        for inst in func.block_mut(b).insts_mut() {
+            if let Inst::Phi { args, .. } = inst {
+                let before = args.len();
+                args.retain(|(pred, _)| preds[b.0].contains(pred));
+                changed |= args.len() != before;
+            }
+        }
+    }
+    let before = func.blocks.len();
+    func.remove_unreachable_blocks();
+    changed || func.blocks.len() != before
+}
+
+/// Copy propagation: rewrites every use of a copy's destination to its source, and
+/// turns phis whose operands are all the same register into copies.
+pub fn propagate_copies(func: &mut Function) -> bool {
+    let mut replace: HashMap<Reg, Reg> = HashMap::new();
+    for block in &func.blocks {
+        for inst in block.insts() {
+            match inst {
+                Inst::Copy { dst, src } => {
+                    replace.insert(*dst, *src);
+                }
+                Inst::Phi { dst, args } => {
+                    let mut sources = args.iter().map(|(_, r)| *r).filter(|r| r != dst);
+                    if let Some(first) = sources.next() {
+                        if sources.all(|r| r == first) {
+                            replace.insert(*dst, first);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    if replace.is_empty() {
+        return false;
+    }
+    let resolve = |mut reg: Reg| {
+        // Chains are acyclic in SSA, except for phis that only refer to each other,
+        // which the bound protects against.
+        for _ in 0..replace.len() {
+            match replace.get(&reg) {
+                Some(&next) if next != reg => reg = next,
+                _ => break,
+            }
+        }
+        reg
+    };
+    for block in &mut func.blocks {
+        block.stmts.retain(|stmt| match &stmt.inst {
+            Inst::Copy { dst, .. } | Inst::Phi { dst, .. } => !replace.contains_key(dst),
+            _ => true,
+        });
+        for inst in block.insts_mut() {
+            inst.map_uses(resolve);
+        }
+        block.term.map_uses(resolve);
+    }
+    true
+}
+
+/// Dead code elimination: removes side-effect-free instructions whose results are
+/// never used.
+pub fn eliminate_dead_code(func: &mut Function) -> bool {
+    let mut defs: HashMap<Reg, &Inst> = HashMap::new();
+    let mut worklist: Vec<Reg> = Vec::new();
+    for block in &func.blocks {
+        for inst in block.insts() {
+            if let Some(dst) = inst.dst() {
+                defs.insert(dst, inst);
+            }
+            if inst.has_side_effects() {
+                worklist.extend(inst.uses());
+            }
+        }
+        worklist.extend(block.term.uses());
+    }
+    let mut live: HashSet<Reg> = HashSet::new();
+    while let Some(reg) = worklist.pop() {
+        if live.insert(reg) {
+            if let Some(inst) = defs.get(&reg) {
+                worklist.extend(inst.uses());
+            }
+        }
+    }
+
+    let mut changed = false;
+    for block in &mut func.blocks {
+        let before = block.stmts.len();
+        block.stmts.retain(|Stmt { inst, .. }| {
+            inst.has_side_effects() || inst.dst().is_none_or(|dst| live.contains(&dst))
+        });
+        changed |= block.stmts.len() != before;
+    }
+    changed
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum ValueKey {
+    Const(Const),
+    Binary(BinOp, Reg, Reg),
+    Unary(UnOp, Reg),
+    IsVoid(Reg),
+}
+
+/// The value computed by a pure instruction, or `None` if it isn't a candidate for CSE.
+fn value_key(inst: &Inst) -> Option<ValueKey> {
+    Some(match inst {
+        Inst::Const { value, .. } => ValueKey::Const(value.clone()),
+        // The commutative operators don't care about operand order.
+        Inst::Binary { op, lhs, rhs, .. } => match op {
+            BinOp::Add | BinOp::Mul | BinOp::Eq if rhs < lhs => ValueKey::Binary(*op, *rhs, *lhs),
+            _ => ValueKey::Binary(*op, *lhs, *rhs),
+        },
+        Inst::Unary { op, src, .. } => ValueKey::Unary(*op, *src),
+        Inst::IsVoid { src, .. } => ValueKey::IsVoid(*src),
+        _ => return None,
+    })
+}
+
+/// Common subexpression elimination: a dominator-tree scoped value numbering over
+/// pure instructions. A recomputation dominated by an identical one becomes a copy.
+pub fn eliminate_common_subexpressions(func: &mut Function) -> bool {
+    let dom = DomTree::new(func);
+    let mut available: HashMap<ValueKey, Vec<Reg>> = HashMap::new();
+    let mut changed = false;
+    // Explicit stack of (block, entering?) so deep dominator trees can't overflow, with
+    // the keys each block made available so they can be popped on the way out.
+    let mut stack = vec![(func.entry, true)];
+    let mut scopes: Vec<Vec<ValueKey>> = Vec::new();
+    while let Some((block, entering)) = stack.pop() {
+        if !entering {
+            for key in scopes.pop().unwrap() {
+                let regs = available.get_mut(&key).unwrap();
+                regs.pop();
+                if regs.is_empty() {
+                    available.remove(&key);
+                }
+            }
+            continue;
+        }
+        let mut introduced = Vec::new();
+        for inst in func.block_mut(block).insts_mut() {
+            let Some(key) = value_key(inst) else {
+                continue;
+            };
+            let dst = inst.dst().unwrap();
+            if let Some(&src) = available.get(&key).and_then(|regs| regs.last()) {
+                *inst = Inst::Copy { dst, src };
+                changed = true;
+            } else {
+                available.entry(key.clone()).or_default().push(dst);
+                introduced.push(key);
+            }
+        }
+        scopes.push(introduced);
+        stack.push((block, false));
+        for &child in dom.children(block).iter().rev() {
+            stack.push((child, true));
+        }
+    }
+    changed
+}
+
+/// Merges every block into its predecessor when it is that predecessor's only
+/// successor and has no other predecessors.
+pub fn merge_blocks(func: &mut Function) -> bool {
+    let mut changed = false;
+    loop {
+        let preds = func.predecessors();
+        let candidate = func.block_ids().find(|&b| {
+            b != func.entry
+                && preds[b.0].len() == 1
+                && preds[b.0][0] != b
+                && matches!(func.block(preds[b.0][0]).term, Terminator::Jump(_))
+        });
+        let Some(block) = candidate else {
+            break;
+        };
+        let pred = preds[block.0][0];
+        let empty = Block::new(Terminator::Unreachable, func.span);
+        let merged = std::mem::replace(func.block_mut(block), empty);
+        // A single-predecessor block's phis are trivial: each is a copy.
+        let stmts = merged.stmts.into_iter().map(|stmt| match stmt.inst {
+            Inst::Phi { dst, args } => Stmt::new(
+                Inst::Copy {
+                    dst,
+                    src: args[0].1,
+                },
+                stmt.span,
+            ),
+            _ => stmt,
+        });
+        let successors = merged.term.successors();
+        let target = func.block_mut(pred);
+        target.stmts.extend(stmts);
+        target.term = merged.term;
+        target.term_span = merged.term_span;
+        for succ in successors {
+            for inst in func.block_mut(succ).insts_mut() {
+                if let Inst::Phi { args, .. } = inst {
+                    for (p, _) in args.iter_mut() {
+                        if *p == block {
+                            *p = pred;
+                        }
+                    }
+                }
+            }
+        }
+        func.remove_unreachable_blocks();
+        changed = true;
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Target;
+    use lexer::{StrSymbol, Symbol};
+
+    fn lower(src: &str) -> Module {
+        crate::lower(&parser::parse(src).unwrap())
+    }
+
+    fn method<'a>(module: &'a Module, class: &str, method: &str) -> &'a Function {
+        module
+            .function(Symbol::intern(class), Symbol::intern(method))
+            .unwrap()
+    }
+
+    fn consts(func: &Function) -> Vec<Const> {
+        func.blocks
+            .iter()
+            .flat_map(|b| b.insts())
+            .filter_map(|inst| match inst {
+                Inst::Const { value, .. } => Some(value.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_opt_level_from_str() {
+        assert_eq!("2".parse::<OptLevel>(), Ok(OptLevel::O2));
+        assert_eq!("-O1".parse::<OptLevel>(), Ok(OptLevel::O1));
+        assert!("3".parse::<OptLevel>().is_err());
+    }
+
+    #[test]
+    fn test_constant_folding_through_variables() {
+        let mut module =
+            lower("class Main { f() : Int { let x : Int <- 2, y : Int <- x * 3 in y + ~1 }; };");
+        optimize(&mut module, OptLevel::O1);
+        let f = method(&module, "Main", "f");
+        assert_eq!(f.blocks.len(), 1);
+        assert_eq!(consts(f), [Const::Int(5)]);
+        assert!(matches!(
+            f.blocks[0].stmts.as_slice(),
+            [Stmt {
+                inst: Inst::Const { .. },
+                ..
+            }]
+        ));
+    }
+
+    #[test]
+    fn test_division_by_zero_is_not_folded() {
+        let mut module = lower("class Main { f() : Int { 1 / 0 }; };");
+        optimize(&mut module, OptLevel::O2);
+        let f = method(&module, "Main", "f");
+        assert!(
+            f.blocks[0]
+                .insts()
+                .any(|inst| matches!(inst, Inst::Binary { op: BinOp::Div, .. }))
+        );
+    }
+
+    #[test]
+    fn test_constant_if_and_while_are_simplified() {
+        let mut module = lower(
+            r#"class Main inherits IO {
+                f() : Object { {
+                    if 1 < 2 then out_string("yes") else out_string("no") fi;
+                    while false loop out_string("never") pool;
+                } };
+            };"#,
+        );
+        optimize(&mut module, OptLevel::O1);
+        let f = method(&module, "Main", "f");
+        let strings: Vec<_> = consts(f)
+            .into_iter()
+            .filter(|c| matches!(c, Const::Str(_)))
+            .collect();
+        assert_eq!(strings, [Const::Str(StrSymbol::intern("yes"))]);
+        assert!(
+            f.blocks
+                .iter()
+                .all(|b| !matches!(b.term, Terminator::Branch { .. }))
+        );
+    }
+
+    #[test]
+    fn test_loop_keeps_variable_updates() {
+        let mut module = lower(
+            "class Main { f(n : Int) : Int { let i : Int <- 0 in { while i < n loop i <- i + 1 pool; i; } }; };",
+        );
+        optimize(&mut module, OptLevel::O2);
+        let f = method(&module, "Main", "f");
+        assert!(
+            f.blocks
+                .iter()
+                .any(|b| matches!(b.term, Terminator::Branch { .. }))
+        );
+        assert!(
+            f.blocks
+                .iter()
+                .flat_map(|b| b.insts())
+                .any(|inst| matches!(inst, Inst::Binary { op: BinOp::Add, .. }))
+        );
+    }
+
+    #[test]
+    fn test_common_subexpressions_are_shared() {
+        let mut module = lower("class Main { f(a : Int, b : Int) : Int { (a + b) * (b + a) }; };");
+        optimize(&mut module, OptLevel::O2);
+        let adds = method(&module, "Main", "f")
+            .blocks
+            .iter()
+            .flat_map(|b| b.insts())
+            .filter(|inst| matches!(inst, Inst::Binary { op: BinOp::Add, .. }))
+            .count();
+        assert_eq!(adds, 1);
+    }
+
+    #[test]
+    fn test_optimization_shrinks_arith_sample() {
+        let src = std::fs::read_to_string("../samples/arith.cl").unwrap();
+        let optimized = |level| {
+            let mut module = lower(&src);
+            let report = optimize(&mut module, level);
+            let insts: usize = module.functions.iter().map(Function::inst_count).sum();
+            let virtual_calls = module
+                .functions
+                .iter()
+                .flat_map(|f| &f.blocks)
+                .flat_map(|b| b.insts())
+                .filter(|inst| {
+                    matches!(
+                        inst,
+                        Inst::Call {
+                            target: Target::Virtual,
+                            ..
+                        }
+                    )
+                })
+                .count();
+            (insts, virtual_calls, report)
+        };
+        let (o0, o0_virtual, _) = optimized(OptLevel::O0);
+        let (o1, o1_virtual, _) = optimized(OptLevel::O1);
+        let (_, o2_virtual, report) = optimized(OptLevel::O2);
+        assert!(o1 < o0, "-O1 ({o1}) should be smaller than -O0 ({o0})");
+        assert_eq!(o1_virtual, o0_virtual);
+        assert!(report.resolved > 0 && report.inlined > 0);
+        assert!(o2_virtual < o1_virtual);
+    }
+}
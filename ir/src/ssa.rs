@@ -0,0 +1,400 @@
+//! Conversion into and out of static single assignment form.
+//!
+//! Construction follows Cytron et al.: phis are placed on the iterated dominance
+//! frontier of every register assigned more than once, then registers are renamed
+//! with a walk over the dominator tree. Destruction replaces each phi with a copy
+//! from a fresh temporary that every predecessor writes, which sidesteps the
+//! lost-copy and swap problems without splitting critical edges.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::{BlockId, Const, Function, Inst, Reg, Stmt};
+
+/// Immediate dominators of every block reachable from the entry.
+pub struct DomTree {
+    idom: Vec<Option<BlockId>>,
+    children: Vec<Vec<BlockId>>,
+}
+
+impl DomTree {
+    /// Computes dominators with the Cooper-Harvey-Kennedy iterative algorithm.
+    pub fn new(func: &Function) -> Self {
+        let rpo = func.reverse_postorder();
+        let mut order = vec![usize::MAX; func.blocks.len()];
+        for (i, b) in rpo.iter().enumerate() {
+            order[b.0] = i;
+        }
+        let preds = func.predecessors();
+
+        let mut idom: Vec<Option<BlockId>> = vec![None; func.blocks.len()];
+        idom[func.entry.0] = Some(func.entry);
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &b in rpo.iter().skip(1) {
+                let mut new_idom = None;
+                for &p in &preds[b.0] {
+                    if idom[p.0].is_none() {
+                        continue;
+                    }
+                    new_idom = Some(match new_idom {
+                        None => p,
+                        Some(current) => intersect(&idom, &order, p, current),
+                    });
+                }
+                if new_idom.is_some() && idom[b.0] != new_idom {
+                    idom[b.0] = new_idom;
+                    changed = true;
+                }
+            }
+        }
+
+        let mut children = vec![Vec::new(); func.blocks.len()];
+        for &b in &rpo {
+            if let Some(parent) = idom[b.0] {
+                if parent != b {
+                    children[parent.0].push(b);
+                }
+            }
+        }
+        idom[func.entry.0] = None;
+        DomTree { idom, children }
+    }
+
+    pub fn idom(&self, block: BlockId) -> Option<BlockId> {
+        self.idom[block.0]
+    }
+
+    pub fn children(&self, block: BlockId) -> &[BlockId] {
+        &self.children[block.0]
+    }
+
+    pub fn dominates(&self, a: BlockId, mut b: BlockId) -> bool {
+        loop {
+            if a == b {
+                return true;
+            }
+            match self.idom[b.0] {
+                Some(parent) => b = parent,
+                None => return false,
+            }
+        }
+    }
+
+    /// Dominance frontier of every block.
+    pub fn frontiers(&self, func: &Function) -> Vec<HashSet<BlockId>> {
+        let preds = func.predecessors();
+        let mut frontiers = vec![HashSet::new(); func.blocks.len()];
+        for b in func.block_ids() {
+            if preds[b.0].len() < 2 {
+                continue;
+            }
+            for &p in &preds[b.0] {
+                let mut runner = Some(p);
+                while let Some(r) = runner {
+                    if Some(r) == self.idom[b.0] {
+                        break;
+                    }
+                    frontiers[r.0].insert(b);
+                    runner = self.idom[r.0];
+                }
+            }
+        }
+        frontiers
+    }
+}
+
+fn intersect(idom: &[Option<BlockId>], order: &[usize], a: BlockId, b: BlockId) -> BlockId {
+    let (mut a, mut b) = (a, b);
+    while a != b {
+        while order[a.0] > order[b.0] {
+            a = idom[a.0].expect("processed block has an idom");
+        }
+        while order[b.0] > order[a.0] {
+            b = idom[b.0].expect("processed block has an idom");
+        }
+    }
+    a
+}
+
+/// Rewrites `func` into SSA form.
+pub fn construct(func: &mut Function) {
+    func.remove_unreachable_blocks();
+    let dom = DomTree::new(func);
+    let frontiers = dom.frontiers(func);
+
+    // Registers with more than one definition are the ones that need phis.
+    let mut def_blocks: HashMap<Reg, Vec<BlockId>> = HashMap::new();
+    for &param in &func.params {
+        def_blocks.entry(param).or_default().push(func.entry);
+    }
+    for b in func.block_ids() {
+        for inst in func.block(b).insts() {
+            if let Some(dst) = inst.dst() {
+                def_blocks.entry(dst).or_default().push(b);
+            }
+        }
+    }
+    let mut sorted_vars: Vec<Reg> = def_blocks
+        .iter()
+        .filter(|(_, blocks)| blocks.len() > 1)
+        .map(|(reg, _)| *reg)
+        .collect();
+    sorted_vars.sort();
+    let variables: HashSet<Reg> = sorted_vars.iter().copied().collect();
+
+    // Phi placement on the iterated dominance frontier. `phi_vars[b]` records which
+    // variable each of the leading phis in block `b` stands for.
+    let mut phi_vars: Vec<Vec<Reg>> = vec![Vec::new(); func.blocks.len()];
+    for var in sorted_vars {
+        let mut worklist = def_blocks[&var].clone();
+        let mut placed = HashSet::new();
+        while let Some(b) = worklist.pop() {
+            for &f in &frontiers[b.0] {
+                if placed.insert(f) {
+                    phi_vars[f.0].push(var);
+                    worklist.push(f);
+                }
+            }
+        }
+    }
+    for b in func.block_ids() {
+        // A phi runs as the block is entered, so it goes with the block's first statement.
+        let block = func.block_mut(b);
+        let span = block
+            .stmts
+            .first()
+            .map_or(block.term_span, |stmt| stmt.span);
+        let phis = phi_vars[b.0].iter().map(|&var| {
+            let phi = Inst::Phi {
+                dst: var,
+                args: Vec::new(),
+            };
+            Stmt::new(phi, span)
+        });
+        block.stmts.splice(0..0, phis);
+    }
+
+    let mut renamer = Renamer {
+        variables,
+        stacks: HashMap::new(),
+        phi_vars,
+        undef: None,
+    };
+    for &param in &func.params {
+        renamer.stacks.entry(param).or_default().push(param);
+    }
+    renamer.rename(func, &dom, func.entry);
+
+    if let Some(undef) = renamer.undef {
+        let entry = func.entry;
+        let span = func.span;
+        let undef = Inst::Const {
+            dst: undef,
+            value: Const::Void,
+        };
+        func.block_mut(entry)
+            .stmts
+            .insert(0, Stmt::new(undef, span));
+    }
+}
+
+struct Renamer {
+    variables: HashSet<Reg>,
+    stacks: HashMap<Reg, Vec<Reg>>,
+    phi_vars: Vec<Vec<Reg>>,
+    /// Stands in for reads of a variable on paths where it was never assigned.
+    undef: Option<Reg>,
+}
+
+impl Renamer {
+    fn current(&mut self, func: &mut Function, var: Reg) -> Reg {
+        if !self.variables.contains(&var) {
+            return var;
+        }
+        if let Some(&top) = self.stacks.get(&var).and_then(|s| s.last()) {
+            return top;
+        }
+        *self.undef.get_or_insert_with(|| func.new_reg())
+    }
+
+    /// Renames the blocks in a preorder walk over the dominator tree, which is as deep as
+    /// the longest chain of blocks in the function, so the walk keeps its own stack.
+    /// Each block's entry is visited a second time on the way out, to pop the names it
+    /// pushed.
+    fn rename(&mut self, func: &mut Function, dom: &DomTree, entry: BlockId) {
+        let mut stack = vec![(entry, true)];
+        let mut scopes: Vec<Vec<Reg>> = Vec::new();
+        while let Some((block, entering)) = stack.pop() {
+            if !entering {
+                for var in scopes.pop().unwrap() {
+                    self.stacks.get_mut(&var).unwrap().pop();
+                }
+                continue;
+            }
+            scopes.push(self.rename_block(func, block));
+            stack.push((block, false));
+            for &child in dom.children(block).iter().rev() {
+                stack.push((child, true));
+            }
+        }
+    }
+
+    /// Renames the definitions and uses in `block` and fills in its successors' phis.
+    /// Returns the variables it pushed a new name for.
+    fn rename_block(&mut self, func: &mut Function, block: BlockId) -> Vec<Reg> {
+        let mut pushed = Vec::new();
+        let mut stmts = std::mem::take(&mut func.block_mut(block).stmts);
+        for Stmt { inst, .. } in &mut stmts {
+            if !matches!(inst, Inst::Phi { .. }) {
+                inst.map_uses(|r| self.current(func, r));
+            }
+            if let Some(dst) = inst.dst_mut() {
+                if self.variables.contains(dst) {
+                    let var = *dst;
+                    *dst = func.new_reg();
+                    self.stacks.entry(var).or_default().push(*dst);
+                    pushed.push(var);
+                }
+            }
+        }
+        func.block_mut(block).stmts = stmts;
+
+        let mut term = func.block(block).term.clone();
+        term.map_uses(|r| self.current(func, r));
+        func.block_mut(block).term = term;
+
+        let mut succs = func.block(block).term.successors();
+        succs.dedup();
+        for succ in succs {
+            for i in 0..self.phi_vars[succ.0].len() {
+                let var = self.phi_vars[succ.0][i];
+                let value = self.current(func, var);
+                if let Inst::Phi { args, .. } = &mut func.block_mut(succ).stmts[i].inst {
+                    if !args.iter().any(|(pred, _)| *pred == block) {
+                        args.push((block, value));
+                    }
+                }
+            }
+        }
+        pushed
+    }
+}
+
+/// Replaces every phi with copies, taking `func` out of SSA form.
+pub fn destruct(func: &mut Function) {
+    for b in func.block_ids() {
+        let phi_count = func
+            .block(b)
+            .insts()
+            .take_while(|inst| matches!(inst, Inst::Phi { .. }))
+            .count();
+        let phis: Vec<Stmt> = func.block_mut(b).stmts.drain(..phi_count).collect();
+        let mut copies = Vec::with_capacity(phis.len());
+        for Stmt { inst, span } in phis {
+            let Inst::Phi { dst, args } = inst else {
+                unreachable!()
+            };
+            let tmp = func.new_reg();
+            for (pred, src) in args {
+                // The copy runs as the predecessor leaves, where its jump is.
+                let pred = func.block_mut(pred);
+                let copy = Stmt::new(Inst::Copy { dst: tmp, src }, pred.term_span);
+                pred.stmts.push(copy);
+            }
+            copies.push(Stmt::new(Inst::Copy { dst, src: tmp }, span));
+        }
+        func.block_mut(b).stmts.splice(0..0, copies);
+    }
+}
+
+/// Whether every register in `func` has exactly one definition.
+pub fn is_ssa(func: &Function) -> bool {
+    let mut defined: HashSet<Reg> = func.params.iter().copied().collect();
+    func.blocks
+        .iter()
+        .flat_map(|b| b.insts())
+        .filter_map(Inst::dst)
+        .all(|dst| defined.insert(dst))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Terminator;
+    use lexer::Symbol;
+
+    fn lower_method(src: &str, class: &str, method: &str) -> Function {
+        let program = parser::parse(src).unwrap();
+        crate::lower(&program)
+            .function(Symbol::intern(class), Symbol::intern(method))
+            .unwrap()
+            .clone()
+    }
+
+    #[test]
+    fn test_construct_places_phi_at_loop_header() {
+        let mut func = lower_method(
+            "class Main { f(n : Int) : Int { let i : Int <- 0 in { while i < n loop i <- i + 1 pool; i; } }; };",
+            "Main",
+            "f",
+        );
+        assert!(!is_ssa(&func));
+        construct(&mut func);
+        assert!(is_ssa(&func));
+
+        let dom = DomTree::new(&func);
+        let header = func
+            .block_ids()
+            .find(|&b| {
+                func.block(b)
+                    .insts()
+                    .any(|inst| matches!(inst, Inst::Phi { .. }))
+            })
+            .expect("loop header has a phi");
+        // The header dominates the loop body that jumps back to it.
+        let latch = func
+            .block_ids()
+            .find(|&b| func.block(b).term == Terminator::Jump(header) && b.0 > header.0)
+            .unwrap();
+        assert!(dom.dominates(header, latch));
+    }
+
+    #[test]
+    fn test_destruct_removes_phis() {
+        let mut func = lower_method(
+            "class Main { f(b : Bool) : Int { let x : Int in { if b then x <- 1 else x <- 2 fi; x; } }; };",
+            "Main",
+            "f",
+        );
+        construct(&mut func);
+        destruct(&mut func);
+        assert!(
+            func.blocks
+                .iter()
+                .flat_map(|b| b.insts())
+                .all(|inst| !matches!(inst, Inst::Phi { .. }))
+        );
+    }
+
+    #[test]
+    fn test_deep_dominator_tree() {
+        // Every `if` joins into a block the next one starts from, so the dominator tree
+        // is a chain thousands of blocks deep.
+        let statements = "if b then x <- x + 1 else x <- x - 1 fi; ".repeat(5_000);
+        let src = format!(
+            "class Main {{ f(b : Bool) : Int {{ let x : Int in {{ {statements}x; }} }}; }};"
+        );
+        let mut func = lower_method(&src, "Main", "f");
+        // Run where the default stack for a spawned thread would have overflowed.
+        std::thread::Builder::new()
+            .stack_size(256 * 1024)
+            .spawn(move || {
+                construct(&mut func);
+                assert!(is_ssa(&func));
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+}
@@ -130,4 +130,47 @@ mod tests {
         let mut lex = Token::lexer(comment);
         assert_eq!(lex.next(), Some(Ok(Token::Comment)));
     }
+
+    // Found by fuzzing: the comment scanner used to slice the source a byte at a time and
+    // panicked on the first multi-byte character.
+    #[test]
+    fn test_comment_with_non_ascii() {
+        let mut lex = Token::lexer("(* héllo — wörld *) class");
+        assert_eq!(lex.next(), Some(Ok(Token::Comment)));
+        assert_eq!(lex.slice(), "(* héllo — wörld *)");
+        assert_eq!(lex.next(), Some(Ok(Token::Class)));
+    }
+
+    #[test]
+    fn test_unclosed_comment_with_non_ascii() {
+        let mut lex = Token::lexer("(*\u{e9}\u{1f600}");
+        lex.next().unwrap().unwrap_err();
+        assert_eq!(lex.slice(), "(*\u{e9}\u{1f600}");
+        assert_eq!(lex.next(), None);
+    }
+
+    #[test]
+    fn test_comment_marker_split_by_non_ascii() {
+        let mut lex = Token::lexer("(*\u{e9}*\u{e9})*)");
+        assert_eq!(lex.next(), Some(Ok(Token::Comment)));
+        assert_eq!(lex.slice(), "(*\u{e9}*\u{e9})*)");
+    }
+
+    #[test]
+    fn test_deeply_nested_comments() {
+        let n = 100_000;
+        let src = format!("{}{}", "(*".repeat(n), "*)".repeat(n));
+        let mut lex = Token::lexer(&src);
+        assert_eq!(lex.next(), Some(Ok(Token::Comment)));
+        assert_eq!(lex.slice().len(), src.len());
+        assert_eq!(lex.next(), None);
+    }
+
+    #[test]
+    fn test_deeply_nested_unclosed_comments() {
+        let src = "(*".repeat(100_000);
+        let mut lex = Token::lexer(&src);
+        lex.next().unwrap().unwrap_err();
+        assert_eq!(lex.next(), None);
+    }
 }
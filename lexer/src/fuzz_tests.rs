@@ -0,0 +1,62 @@
+#[cfg(test)]
+mod tests {
+    use crate::lexer::Token;
+    use logos::Logos;
+
+    /// What the `lexer` fuzz target checks: every token, good or bad, covers some of the
+    /// input, in order, and starts and ends on character boundaries.
+    fn check_spans(src: &str) {
+        let mut end = 0;
+        for (_, span) in Token::lexer(src).spanned() {
+            assert!(span.start >= end, "{span:?} overlaps the token before it");
+            assert!(span.start < span.end, "{span:?} is empty");
+            assert!(span.end <= src.len(), "{span:?} is past the end");
+            assert!(src.is_char_boundary(span.start) && src.is_char_boundary(span.end));
+            end = span.end;
+        }
+    }
+
+    /// Inputs that reproduce every crash fuzzing has found, in the order they were found.
+    /// The ones that crashed the parser lex fine, but stay here so the lexer keeps
+    /// handling them.
+    const CRASHES: &[&str] = &[
+        // The comment scanner sliced the source a byte at a time.
+        "(*\u{e9}",
+        "(*\u{e9}\u{1f600}",
+        "(*\u{e9}*\u{e9})*)",
+        "(*(*\u{e9}*)",
+        // Nesting overflowed the parser's stack.
+        "class A{f():A{((((((((((((((((((((((((((((((((((((((((((((((((((0",
+        "class A{f():A{{{{{{{{{{{{{{{{{{{{{{{{{{{{{{{{{{{{{{{{{{{{{{{{{{{{{",
+        "class A{f():A{let a:A in let a:A in let a:A in let a:A in let a:A in 0",
+        // Chains of operators and dispatches overflowed lowering's stack.
+        "class A{f():A{0+0+0+0+0+0+0+0+0+0+0+0+0+0+0+0+0+0+0+0+0+0+0+0+0};};",
+        "class A{f():A{a.f().f().f().f().f().f().f().f().f().f().f().f()};};",
+    ];
+
+    #[test]
+    fn test_crashes() {
+        for src in CRASHES {
+            check_spans(src);
+        }
+    }
+
+    #[test]
+    fn test_crashes_repeated() {
+        // The inputs that found the crashes went on for thousands of repetitions.
+        for src in CRASHES {
+            check_spans(&src.repeat(1_000));
+        }
+    }
+
+    #[test]
+    fn test_cut_at_every_boundary() {
+        // Prefixes of a valid program, including ones cut inside a comment or a string,
+        // are what the fuzzer tries most.
+        let src = "class Main { (* \u{e9} (* *) *) s : String <- \"\u{1f600}\\n\"; };";
+        for (i, _) in src.char_indices() {
+            check_spans(&src[..i]);
+            check_spans(&src[i..]);
+        }
+    }
+}
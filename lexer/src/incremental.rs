@@ -0,0 +1,270 @@
+//! Relexing a buffer after an edit without lexing all of it again, for editors that
+//! keep a file's tokens up to date as it's typed into.
+//!
+//! Lexing is restarted from the end of the last token the edit can't have changed and
+//! stops as soon as it lines up with a token from before the edit again. Strings and
+//! `(* *)` comments, however deeply nested, are single tokens, so a restart point
+//! between two tokens is never inside one.
+
+use std::ops::Range;
+
+use logos::Logos;
+
+use crate::lexer::Token;
+
+/// A token, or `Err(())` for text that isn't one, with the byte range it covers. This is
+/// what `Token::lexer(src).spanned()` yields.
+pub type Spanned = (Result<Token, ()>, Range<usize>);
+
+/// Replaces the bytes in `range` of a text with `text`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edit {
+    pub range: Range<usize>,
+    pub text: String,
+}
+
+impl Edit {
+    pub fn new(range: Range<usize>, text: impl Into<String>) -> Self {
+        Edit {
+            range,
+            text: text.into(),
+        }
+    }
+
+    /// The text after the edit.
+    pub fn apply(&self, src: &str) -> String {
+        let mut edited = src.to_string();
+        edited.replace_range(self.range.clone(), &self.text);
+        edited
+    }
+
+    /// How far the edit moves text that follows it.
+    fn delta(&self) -> isize {
+        self.text.len() as isize - self.range.len() as isize
+    }
+}
+
+/// Lexes the whole of `src`.
+pub fn lex(src: &str) -> Vec<Spanned> {
+    Token::lexer(src).spanned().collect()
+}
+
+/// Brings `tokens`, lexed from some text, up to date with `src`, which is that text with
+/// `edit` applied. Only the tokens around the edit are relexed; the result is the same as
+/// `lex(src)`. Returns where in `tokens` the relexed tokens now are.
+pub fn relex(tokens: &mut Vec<Spanned>, src: &str, edit: &Edit) -> Range<usize> {
+    let delta = edit.delta();
+    // The lexer looks one byte past the end of every token, including failed ones, to
+    // see that it can't go on, so tokens ending before the edit don't depend on it.
+    let first = tokens.partition_point(|(_, span)| span.end < edit.range.start);
+    let restart = first.checked_sub(1).map_or(0, |i| tokens[i].1.end);
+    let edit_end = edit.range.start + edit.text.len();
+
+    let mut old = first;
+    let mut synced = false;
+    let mut relexed = Vec::new();
+    for (token, span) in Token::lexer(&src[restart..]).spanned() {
+        let span = span.start + restart..span.end + restart;
+        let end = span.end;
+        relexed.push((token, span));
+        if end < edit_end {
+            continue;
+        }
+        // Past the edit the text is unchanged, so once a token ends where an old one
+        // did, everything after it lexes the same as before.
+        let old_end = end.wrapping_add_signed(-delta);
+        while old < tokens.len() && tokens[old].1.end < old_end {
+            old += 1;
+        }
+        if old < tokens.len() && tokens[old].1.end == old_end {
+            old += 1;
+            synced = true;
+            break;
+        }
+    }
+    if !synced {
+        old = tokens.len();
+    }
+
+    for (_, span) in &mut tokens[old..] {
+        *span = span.start.wrapping_add_signed(delta)..span.end.wrapping_add_signed(delta);
+    }
+    let count = relexed.len();
+    tokens.splice(first..old, relexed);
+    first..first + count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::symbol::Symbol;
+
+    /// Replays `edits` on `src`, checking after each that relexing gives what lexing
+    /// from scratch does.
+    fn check(src: &str, edits: &[Edit]) {
+        let mut src = src.to_string();
+        let mut tokens = lex(&src);
+        for edit in edits {
+            src = edit.apply(&src);
+            let changed = relex(&mut tokens, &src, edit);
+            assert_eq!(tokens, lex(&src), "after {edit:?}, giving {src:?}");
+            assert!(changed.end <= tokens.len());
+        }
+    }
+
+    #[test]
+    fn test_edits() {
+        let src = "class Main {\n  x : Int <- 1;\n  s : String <- \"hi\";\n};\n";
+        check(src, &[Edit::new(25..26, "23")]);
+        check(src, &[Edit::new(23..24, ""), Edit::new(23..23, "x")]);
+        check(
+            src,
+            &[
+                Edit::new(0..0, "(* "),
+                Edit::new(src.len() + 3..src.len() + 3, " *)"),
+            ],
+        );
+        check(src, &[Edit::new(48..49, ""), Edit::new(48..48, "\"")]);
+        check(
+            src,
+            &[Edit::new(6..10, "Mainly"), Edit::new(0..src.len(), "")],
+        );
+    }
+
+    #[test]
+    fn test_relexes_only_around_the_edit() {
+        let src = "a b c d e f g h";
+        let mut tokens = lex(src);
+        let edit = Edit::new(6..7, "dd");
+        let src = edit.apply(src);
+        assert_eq!(relex(&mut tokens, &src, &edit), 3..4);
+        assert_eq!(
+            tokens[3],
+            (Ok(Token::ObjectIdentifier(Symbol::intern("dd"))), 6..8)
+        );
+        assert_eq!(
+            tokens[7],
+            (Ok(Token::ObjectIdentifier(Symbol::intern("h"))), 15..16)
+        );
+    }
+
+    #[test]
+    fn test_opening_a_comment() {
+        let src = "a (* b *) c\nd e";
+        let mut tokens = lex(src);
+        let edit = Edit::new(2..2, "(* ");
+        let src = edit.apply(src);
+        relex(&mut tokens, &src, &edit);
+        assert_eq!(
+            tokens,
+            [
+                (Ok(Token::ObjectIdentifier(Symbol::intern("a"))), 0..1),
+                (Err(()), 2..18)
+            ]
+        );
+    }
+
+    /// A xorshift generator, so failures reproduce from the seed.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+    }
+
+    /// Pieces of text likely to change how their surroundings lex, especially the ones
+    /// that open or close strings and comments.
+    const PIECES: &[&str] = &[
+        "class",
+        "Main",
+        "x",
+        "t",
+        "rue",
+        "if",
+        "fi",
+        "42",
+        "99999999999",
+        " ",
+        "  ",
+        "\n",
+        "\t",
+        "\"",
+        "\"s\"",
+        "\\",
+        "\\\n",
+        "\\0",
+        "(*",
+        "*)",
+        "(",
+        "*",
+        ")",
+        "--",
+        "-",
+        "<",
+        "=",
+        ">",
+        "<-",
+        "{",
+        "}",
+        ";",
+        ":",
+        ".",
+        "@",
+        "#",
+        "_",
+    ];
+
+    fn text(rng: &mut Rng, pieces: usize) -> String {
+        (0..pieces)
+            .map(|_| PIECES[rng.below(PIECES.len())])
+            .collect()
+    }
+
+    fn edit(rng: &mut Rng, src: &str) -> Edit {
+        let start = rng.below(src.len() + 1);
+        let end = start + rng.below((src.len() - start).min(12) + 1);
+        let pieces = rng.below(4);
+        Edit::new(start..end, text(rng, pieces))
+    }
+
+    #[test]
+    fn test_random_edits_match_full_relex() {
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        for _ in 0..2000 {
+            let pieces = rng.below(40);
+            let src = text(&mut rng, pieces);
+            let mut edits = Vec::new();
+            let mut edited = src.clone();
+            for _ in 0..1 + rng.below(5) {
+                let edit = edit(&mut rng, &edited);
+                edited = edit.apply(&edited);
+                edits.push(edit);
+            }
+            check(&src, &edits);
+        }
+    }
+
+    #[test]
+    fn test_random_edits_to_samples() {
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+        for name in ["arith.cl", "life.cl", "hello_world.cl"] {
+            let src = std::fs::read_to_string(format!("../samples/{name}")).unwrap();
+            let mut edited = src.clone();
+            let mut edits = Vec::new();
+            for _ in 0..200 {
+                let edit = edit(&mut rng, &edited);
+                edited = edit.apply(&edited);
+                edits.push(edit);
+            }
+            check(&src, &edits);
+        }
+    }
+}
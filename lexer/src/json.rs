@@ -0,0 +1,106 @@
+//! The JSON form of a file's tokens, for tools that aren't written in Rust. The schema is
+//! described in the readme.
+
+use logos::Logos;
+use serde::Serialize;
+
+use crate::lexer::Token;
+use crate::source::SourceFile;
+
+/// The version of the JSON the compiler's tools print, given in every document. It goes
+/// up when a field is removed or changes meaning; fields may be added without a bump.
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// Every token of one file.
+#[derive(Debug, Serialize)]
+pub struct Tokens<'a> {
+    pub schema_version: u32,
+    pub file: &'a str,
+    pub tokens: Vec<TokenRecord<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TokenRecord<'a> {
+    /// The [`Token`] variant, or `"Error"` for text that isn't a token.
+    pub kind: &'static str,
+    pub text: &'a str,
+    pub span: ByteSpan,
+    /// 1-based, like `column`, which counts characters.
+    pub line: usize,
+    pub column: usize,
+}
+
+/// A half-open range of byte offsets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ByteSpan {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Lexes `file` into its JSON form.
+pub fn tokens(file: &SourceFile) -> Tokens<'_> {
+    let src = file.src();
+    let tokens = Token::lexer(src)
+        .spanned()
+        .map(|(token, span)| {
+            let location = file.location(span.start);
+            TokenRecord {
+                kind: token.as_ref().map_or("Error", Token::kind),
+                text: &src[span.clone()],
+                span: ByteSpan {
+                    start: span.start,
+                    end: span.end,
+                },
+                line: location.line,
+                column: location.column,
+            }
+        })
+        .collect();
+    Tokens {
+        schema_version: SCHEMA_VERSION,
+        file: file.name(),
+        tokens,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokens_json() {
+        let file = SourceFile::new("a.cl", "x <- 1;\n\t# \"s\"");
+        let json = serde_json::to_value(tokens(&file)).unwrap();
+        assert_eq!(json["schema_version"], SCHEMA_VERSION);
+        assert_eq!(json["file"], "a.cl");
+        assert_eq!(
+            json["tokens"][1],
+            serde_json::json!({
+                "kind": "Assign",
+                "text": "<-",
+                "span": { "start": 2, "end": 4 },
+                "line": 1,
+                "column": 3,
+            })
+        );
+        let kinds: Vec<&str> = json["tokens"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|token| token["kind"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                "ObjectIdentifier",
+                "Assign",
+                "Integer",
+                "Semicolon",
+                "Error",
+                "String"
+            ]
+        );
+        assert_eq!(json["tokens"][4]["line"], 2);
+        assert_eq!(json["tokens"][4]["column"], 2);
+    }
+}
@@ -1,7 +1,13 @@
 use logos::{Lexer, Logos, Skip};
+use std::borrow::Cow;
 use std::fmt;
 
-#[derive(Logos, Debug, PartialEq, Clone)]
+use crate::symbol::{StrSymbol, Symbol};
+
+/// A token of COOL source. Identifiers and string literals are interned as they're
+/// lexed, so a token is a few bytes that don't borrow the source, and a name that
+/// appears many times is only copied the first time.
+#[derive(Logos, Debug, PartialEq, Clone, Copy)]
 #[logos(extras = (usize, usize))]
 #[logos(skip r"[ \t\r\f]+")]
 #[regex(r"\n", newline_callback)]
@@ -14,12 +20,12 @@ pub enum Token {
     Integer(i32),
 
     // type identifiers (begin with a capital letter)
-    #[regex(r"[A-Z][A-Za-z0-9_]*", callback = |lex| lex.slice().to_string())]
-    TypeIdentifier(String),
+    #[regex(r"[A-Z][A-Za-z0-9_]*", callback = |lex| Symbol::intern(lex.slice()))]
+    TypeIdentifier(Symbol),
 
     // object identifiers (begin with a lower case letter)
-    #[regex(r"[a-z][A-Za-z0-9_]*", callback = |lex| lex.slice().to_string())]
-    ObjectIdentifier(String),
+    #[regex(r"[a-z][A-Za-z0-9_]*", callback = |lex| Symbol::intern(lex.slice()))]
+    ObjectIdentifier(Symbol),
 
     // special identifiers
     #[token("self")]
@@ -28,8 +34,8 @@ pub enum Token {
     #[token("SELF_TYPE")]
     SelfType,
 
-    #[regex(r#""([^"\\\n]|\\[^0\n]|\\[ \t]*\n)*""#, callback = |lex| lex.slice().to_string())]
-    String(String),
+    #[regex(r#""([^"\\\n]|\\[^0\n]|\\[ \t]*\n)*""#, callback = |lex| StrSymbol::intern(&string_value(lex.slice())))]
+    String(StrSymbol),
 
     // keywords
     #[token("class", ignore(case))]
@@ -159,7 +165,7 @@ pub enum Token {
     Newline,
 }
 
-fn newline_callback(lex: &mut Lexer<Token>) -> Skip {
+fn newline_callback(lex: &mut Lexer<'_, Token>) -> Skip {
     lex.extras.0 += 1;
     lex.extras.1 = lex.span().start + 1;
     Skip
@@ -167,17 +173,18 @@ fn newline_callback(lex: &mut Lexer<Token>) -> Skip {
 
 /// Processes a multi-line comment with support for nesting
 /// Returns true if the comment was properly terminated, false otherwise
-fn comment_multi(lex: &mut logos::Lexer<Token>) -> bool {
-    let remainder = lex.remainder();
+fn comment_multi(lex: &mut logos::Lexer<'_, Token>) -> bool {
+    // Scan bytes rather than the str, since `pos` can land inside a multi-byte character.
+    let remainder = lex.remainder().as_bytes();
     let mut depth = 1;
     let mut pos = 0;
 
     while pos < remainder.len() {
         // Look for opening or closing comment markers
-        if remainder[pos..].starts_with("(*") {
+        if remainder[pos..].starts_with(b"(*") {
             depth += 1;
             pos += 2;
-        } else if remainder[pos..].starts_with("*)") {
+        } else if remainder[pos..].starts_with(b"*)") {
             depth -= 1;
             pos += 2;
             if depth == 0 {
@@ -197,6 +204,113 @@ fn comment_multi(lex: &mut logos::Lexer<Token>) -> bool {
     false
 }
 
+/// The value of the string literal `raw`, which still has its quotes and escapes. `\n`,
+/// `\t`, `\b` and `\f` are control characters, a backslash before a newline continues
+/// the string on the next line, and a backslash before anything else stands for that
+/// character. A literal without escapes is its own value, so it isn't copied.
+pub fn string_value(raw: &str) -> Cow<'_, str> {
+    let inner = raw
+        .strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .unwrap_or(raw);
+    if !inner.contains('\\') {
+        return Cow::Borrowed(inner);
+    }
+    let mut out = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some('b') => out.push('\u{8}'),
+            Some('f') => out.push('\u{c}'),
+            Some(other) => out.push(other),
+            None => {}
+        }
+    }
+    Cow::Owned(out)
+}
+
+/// A string literal whose value is `value`: the inverse of [`string_value`].
+pub fn string_literal(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\u{8}' => out.push_str("\\b"),
+            '\u{c}' => out.push_str("\\f"),
+            '"' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            }
+            _ => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+impl Token {
+    /// The name of the token's variant, which is how it's identified in JSON output.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Token::Error => "Error",
+            Token::Integer(_) => "Integer",
+            Token::TypeIdentifier(_) => "TypeIdentifier",
+            Token::ObjectIdentifier(_) => "ObjectIdentifier",
+            Token::SelfLit => "SelfLit",
+            Token::SelfType => "SelfType",
+            Token::String(_) => "String",
+            Token::Class => "Class",
+            Token::Else => "Else",
+            Token::Fi => "Fi",
+            Token::If => "If",
+            Token::In => "In",
+            Token::Inherits => "Inherits",
+            Token::Isvoid => "Isvoid",
+            Token::Let => "Let",
+            Token::Loop => "Loop",
+            Token::Pool => "Pool",
+            Token::Then => "Then",
+            Token::While => "While",
+            Token::Case => "Case",
+            Token::Esac => "Esac",
+            Token::New => "New",
+            Token::Of => "Of",
+            Token::Not => "Not",
+            Token::True => "True",
+            Token::False => "False",
+            Token::Plus => "Plus",
+            Token::Minus => "Minus",
+            Token::Assign => "Assign",
+            Token::Comma => "Comma",
+            Token::Semicolon => "Semicolon",
+            Token::Dot => "Dot",
+            Token::Comment => "Comment",
+            Token::Multiply => "Multiply",
+            Token::Divide => "Divide",
+            Token::Tilde => "Tilde",
+            Token::LessThan => "LessThan",
+            Token::LessThanOrEqual => "LessThanOrEqual",
+            Token::Equal => "Equal",
+            Token::LeftParen => "LeftParen",
+            Token::RightParen => "RightParen",
+            Token::DoubleArrow => "DoubleArrow",
+            Token::Colon => "Colon",
+            Token::TypeId => "TypeId",
+            Token::LeftBrace => "LeftBrace",
+            Token::RightBrace => "RightBrace",
+            Token::Newline => "Newline",
+        }
+    }
+}
+
 impl fmt::Display for Token {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -206,7 +320,7 @@ impl fmt::Display for Token {
             Token::ObjectIdentifier(id) => write!(f, "{id}"),
             Token::SelfLit => write!(f, "self"),
             Token::SelfType => write!(f, "SELF_TYPE"),
-            Token::String(s) => write!(f, "{s}"),
+            Token::String(s) => f.write_str(&string_literal(s)),
             Token::Class => write!(f, "class"),
             Token::Else => write!(f, "else"),
             Token::Fi => write!(f, "fi"),
@@ -333,4 +447,33 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_tokens_are_interned() {
+        let src = String::from("x <- \"h\\ti\"; Main x");
+        let tokens: Vec<Token> = Token::lexer(&src).map(Result::unwrap).collect();
+        drop(src);
+        assert_eq!(
+            tokens,
+            [
+                Token::ObjectIdentifier(Symbol::intern("x")),
+                Token::Assign,
+                Token::String(StrSymbol::intern("h\ti")),
+                Token::Semicolon,
+                Token::TypeIdentifier(Symbol::intern("Main")),
+                Token::ObjectIdentifier(Symbol::intern("x")),
+            ]
+        );
+        assert_eq!(tokens[2].to_string(), "\"h\\ti\"");
+    }
+
+    #[test]
+    fn test_string_value_escapes() {
+        assert_eq!(string_value(r#""Hello, World.\n""#), "Hello, World.\n");
+        assert_eq!(string_value(r#""a\tb\\c\"d""#), "a\tb\\c\"d");
+        assert_eq!(string_value("\"line\\\nnext\""), "line\nnext");
+        for value in ["Hello, World.\n", "a\tb\\c\"d", "\u{8}\u{c}é"] {
+            assert_eq!(string_value(&string_literal(value)), value);
+        }
+    }
 }
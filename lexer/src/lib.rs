@@ -1,3 +1,9 @@
+pub mod incremental;
+pub mod json;
 pub mod lexer;
+pub mod source;
+pub mod symbol;
 
 pub use crate::lexer::Token;
+pub use crate::source::FileId;
+pub use crate::symbol::{IntSymbol, StrSymbol, Symbol, sym};
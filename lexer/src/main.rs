@@ -1,10 +1,20 @@
 mod boolean_tests;
 mod comments_tests;
+mod fuzz_tests;
+// The tests reach the token definitions through `crate::lexer`; everything else uses the
+// library's.
+#[cfg(test)]
+#[allow(dead_code)]
 mod lexer;
 mod string_tests;
+// ...and they intern into the library's tables.
+#[cfg(test)]
+use ::lexer::symbol;
 
-use clap::Parser;
-use lexer::Token;
+use ::lexer::Token;
+use ::lexer::json;
+use ::lexer::source::SourceMap;
+use clap::{Parser, ValueEnum};
 use logos::Logos;
 use std::fs;
 use std::path::Path;
@@ -21,15 +31,43 @@ struct Args {
     /// Print verbose output including all tokens
     #[arg(short, long)]
     verbose: bool,
+
+    /// How to print the tokens
+    #[arg(long, value_enum, default_value = "text")]
+    format: Format,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum Format {
+    /// Errors, and with --verbose every token, for people to read
+    Text,
+    /// Every token of each file as a JSON document on its own line
+    Json,
 }
 
-fn lex_file(file_path: &Path, verbose: bool) {
+fn lex_file(file_path: &Path, args: &Args) {
+    let verbose = args.verbose && args.format == Format::Text;
     if verbose {
         println!("Lexing file: {}", file_path.display());
     }
-    let input = fs::read_to_string(file_path).expect("Failed to read file");
+    let mut sources = SourceMap::new();
+    let id = match sources.load(file_path) {
+        Ok(id) => id,
+        Err(e) => {
+            eprintln!("Error: failed to read '{}': {e}", file_path.display());
+            return;
+        }
+    };
+    let file = sources.file(id);
+    let input = file.src();
+
+    if args.format == Format::Json {
+        let json = serde_json::to_string(&json::tokens(file)).expect("tokens serialize");
+        println!("{json}");
+        return;
+    }
 
-    let mut lexer = Token::lexer(&input);
+    let mut lexer = Token::lexer(input);
     let mut success = true;
 
     while let Some(token) = lexer.next() {
@@ -48,37 +86,23 @@ fn lex_file(file_path: &Path, verbose: bool) {
             Err(_) => {
                 success = false;
 
-                // Get line and line start from lexer extras
-                let line_num = lexer.extras.0;
-                let line_start = lexer.extras.1;
-
-                let line_text = input.lines().nth(line_num).unwrap_or("");
-                let col_start = span.start - line_start;
-                let col_end = span.end - line_start;
-
-                // Calculate visual column position by expanding tabs
-                let mut visual_col = 0;
-                for (i, ch) in line_text.char_indices() {
-                    if i >= col_start {
-                        break;
-                    }
-                    if ch == '\t' {
-                        visual_col += 8 - (visual_col % 8); // Tab stops every 8 characters
-                    } else {
-                        visual_col += 1;
-                    }
-                }
-
-                let indicator = " ".repeat(visual_col) + &"^".repeat((col_end - col_start).max(1));
+                let start = file.location(span.start);
+                let end = file.location(span.end);
+                let width = if end.line == start.line {
+                    end.visual_column - start.visual_column
+                } else {
+                    1
+                };
+                let indicator = " ".repeat(start.visual_column - 1) + &"^".repeat(width.max(1));
 
                 eprintln!(
                     "Error lexing '{}': '{}' at {:?} on line {} column {}\n\n{}\n{}\n",
-                    file_path.display(),
+                    file.name(),
                     &input[span.clone()],
                     span,
-                    line_num + 1,  // 1-indexed for display
-                    col_start + 1, // 1-indexed for display
-                    line_text,
+                    start.line,
+                    start.column,
+                    file.line_text(start.line),
                     indicator
                 );
             }
@@ -104,7 +128,7 @@ fn main() {
     }
 
     if path.is_dir() {
-        if args.verbose {
+        if args.verbose && args.format == Format::Text {
             println!("Lexing all .cl files in directory: {}", args.file);
         }
         for entry in fs::read_dir(path).expect("Failed to read directory") {
@@ -113,12 +137,12 @@ fn main() {
             if file_path.is_file() {
                 if let Some(ext) = file_path.extension() {
                     if ext == "cl" {
-                        lex_file(&file_path, args.verbose);
+                        lex_file(&file_path, &args);
                     }
                 }
             }
         }
     } else {
-        lex_file(path, args.verbose);
+        lex_file(path, &args);
     }
 }
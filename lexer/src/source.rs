@@ -0,0 +1,218 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::Serialize;
+use unicode_width::UnicodeWidthChar;
+
+/// Columns between tab stops when computing visual columns.
+pub const TAB_WIDTH: usize = 8;
+
+/// Identifies one source file of a program, so spans from different files can't be
+/// confused. The first file added to a [`SourceMap`] is `FileId(0)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default, Serialize)]
+#[serde(transparent)]
+pub struct FileId(pub u32);
+
+impl FileId {
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// Where a byte offset falls in a file. All three numbers are 1-based.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Location {
+    pub line: usize,
+    /// Characters, not bytes, from the start of the line.
+    pub column: usize,
+    /// The column a terminal shows, with tabs expanded to every [`TAB_WIDTH`] columns
+    /// and wide characters taking two.
+    pub visual_column: usize,
+}
+
+/// A source file with a table of where its lines start, built once when it's added.
+#[derive(Debug, Clone)]
+pub struct SourceFile {
+    name: String,
+    src: String,
+    line_starts: Vec<usize>,
+}
+
+impl SourceFile {
+    pub fn new(name: impl Into<String>, src: impl Into<String>) -> Self {
+        let src = src.into();
+        let line_starts = std::iter::once(0)
+            .chain(src.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        SourceFile {
+            name: name.into(),
+            src,
+            line_starts,
+        }
+    }
+
+    /// The path or other name the file was added under.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn src(&self) -> &str {
+        &self.src
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.line_starts.len()
+    }
+
+    /// The 0-based index of the line containing byte `offset`. Offsets past the end
+    /// belong to the last line.
+    pub fn line_index(&self, offset: usize) -> usize {
+        self.line_starts.partition_point(|&start| start <= offset) - 1
+    }
+
+    /// The byte offset where 1-based line `line` starts, or `None` past the last line.
+    pub fn line_start(&self, line: usize) -> Option<usize> {
+        self.line_starts.get(line.checked_sub(1)?).copied()
+    }
+
+    /// The text of 1-based line `line`, without its line terminator.
+    pub fn line_text(&self, line: usize) -> &str {
+        let start = self.line_starts[line - 1];
+        let end = self
+            .line_starts
+            .get(line)
+            .map_or(self.src.len(), |&next| next - 1);
+        self.src[start..end].trim_end_matches('\r')
+    }
+
+    pub fn location(&self, offset: usize) -> Location {
+        let offset = self.floor_char_boundary(offset.min(self.src.len()));
+        let line = self.line_index(offset);
+        let before = &self.src[self.line_starts[line]..offset];
+        Location {
+            line: line + 1,
+            column: before.chars().count() + 1,
+            visual_column: visual_width(before) + 1,
+        }
+    }
+
+    fn floor_char_boundary(&self, mut offset: usize) -> usize {
+        while !self.src.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        offset
+    }
+}
+
+/// How many columns `text` takes on a terminal when it starts at the beginning of a
+/// line.
+pub fn visual_width(text: &str) -> usize {
+    text.chars().fold(0, |width, c| match c {
+        '\t' => width + TAB_WIDTH - width % TAB_WIDTH,
+        c => width + c.width().unwrap_or(0),
+    })
+}
+
+/// Every source file a tool has read, looked up by [`FileId`].
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, name: impl Into<String>, src: impl Into<String>) -> FileId {
+        self.files.push(SourceFile::new(name, src));
+        FileId(self.files.len() as u32 - 1)
+    }
+
+    /// Reads the file at `path` and adds it under that name.
+    pub fn load(&mut self, path: impl AsRef<Path>) -> io::Result<FileId> {
+        let path = path.as_ref();
+        let src = fs::read_to_string(path)?;
+        Ok(self.add(path.display().to_string(), src))
+    }
+
+    pub fn file(&self, id: FileId) -> &SourceFile {
+        &self.files[id.index()]
+    }
+
+    /// The first file added under `name`.
+    pub fn find(&self, name: &str) -> Option<FileId> {
+        self.files()
+            .find(|(_, file)| file.name() == name)
+            .map(|(id, _)| id)
+    }
+
+    /// The files in the order they were added, with their ids.
+    pub fn files(&self) -> impl Iterator<Item = (FileId, &SourceFile)> {
+        self.files
+            .iter()
+            .enumerate()
+            .map(|(i, file)| (FileId(i as u32), file))
+    }
+
+    pub fn location(&self, id: FileId, offset: usize) -> Location {
+        self.file(id).location(offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lines() {
+        let file = SourceFile::new("a.cl", "class A {\r\n};\n\nx");
+        assert_eq!(file.line_count(), 4);
+        assert_eq!(file.line_text(1), "class A {");
+        assert_eq!(file.line_text(2), "};");
+        assert_eq!(file.line_text(3), "");
+        assert_eq!(file.line_text(4), "x");
+        assert_eq!(file.line_index(0), 0);
+        assert_eq!(file.line_index(10), 0);
+        assert_eq!(file.line_index(11), 1);
+        assert_eq!(file.line_index(100), 3);
+    }
+
+    #[test]
+    fn test_locations() {
+        let file = SourceFile::new("a.cl", "ab\n\tx\u{e9}y\n\u{4e2d}z");
+        let at = |offset| {
+            let l = file.location(offset);
+            (l.line, l.column, l.visual_column)
+        };
+        assert_eq!(at(0), (1, 1, 1));
+        assert_eq!(at(2), (1, 3, 3));
+        assert_eq!(at(3), (2, 1, 1));
+        assert_eq!(at(4), (2, 2, 9));
+        // 'é' is two bytes but one character and one column.
+        assert_eq!(at(7), (2, 4, 11));
+        // A CJK character takes two columns, and an offset inside it rounds down.
+        assert_eq!(at(9), (3, 1, 1));
+        assert_eq!(at(10), (3, 1, 1));
+        assert_eq!(at(12), (3, 2, 3));
+        assert_eq!(at(100), (3, 3, 4));
+    }
+
+    #[test]
+    fn test_source_map() {
+        let mut map = SourceMap::new();
+        let a = map.add("a.cl", "class A {};");
+        let b = map.add("b.cl", "\n\nclass B {};");
+        assert_eq!((a, b), (FileId(0), FileId(1)));
+        assert_eq!(map.file(b).name(), "b.cl");
+        assert_eq!(map.location(b, 2).line, 3);
+        assert_eq!(map.find("b.cl"), Some(b));
+        assert_eq!(map.find("c.cl"), None);
+        assert_eq!(map.file(b).line_start(3), Some(2));
+        assert_eq!(map.file(b).line_start(4), None);
+        let names: Vec<&str> = map.files().map(|(_, f)| f.name()).collect();
+        assert_eq!(names, ["a.cl", "b.cl"]);
+        assert!(map.load("/nonexistent/file.cl").is_err());
+    }
+}
@@ -0,0 +1,438 @@
+//! Interned names and constants. Like the reference compiler's `idtable`, `inttable` and
+//! `stringtable`, there's one table for identifiers (type names included), one for
+//! integer constants and one for string constants. The lexer interns every identifier
+//! and string literal it reads, so from the tokens onward a name is a [`Symbol`]: the
+//! index of its entry and nothing else. Comparing and hashing a symbol is comparing and
+//! hashing that integer, and reading its text is an array lookup that never takes the
+//! table's lock.
+//!
+//! The tables are global and only ever grow. The first time a string is interned, a
+//! copy of it is leaked so it lives as long as the process, which is what lets
+//! [`Symbol::as_str`] hand out a `&'static str`. Compiling a program leaks its names
+//! and string constants once, however often they appear, but a process that compiles
+//! many different programs, like the differential tester, keeps every one it has seen.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{BuildHasherDefault, Hash, Hasher};
+use std::ops::Deref;
+use std::sync::{LazyLock, OnceLock, RwLock};
+
+use serde::{Serialize, Serializer};
+
+/// The size of an [`Entries`] list's first segment; each one after it is twice as big.
+const FIRST_SEGMENT: usize = 64;
+/// Enough segments for every index a `u32` can hold.
+const SEGMENTS: usize = 33 - FIRST_SEGMENT.trailing_zeros() as usize;
+
+/// A list that's only ever appended to, in segments that never move once allocated, so
+/// an entry can be read while another thread appends without either taking a lock.
+struct Entries<T> {
+    segments: [OnceLock<Box<[OnceLock<T>]>>; SEGMENTS],
+}
+
+impl<T: Copy> Entries<T> {
+    fn new() -> Self {
+        Entries {
+            segments: std::array::from_fn(|_| OnceLock::new()),
+        }
+    }
+
+    /// The segment `index` is in, and its position there.
+    fn locate(index: u32) -> (usize, usize) {
+        let n = index as usize + FIRST_SEGMENT;
+        let bit = n.ilog2();
+        let segment = (bit - FIRST_SEGMENT.trailing_zeros()) as usize;
+        (segment, n - (1 << bit))
+    }
+
+    fn get(&self, index: u32) -> T {
+        let (segment, offset) = Self::locate(index);
+        self.segments[segment]
+            .get()
+            .and_then(|entries| entries[offset].get())
+            .copied()
+            .expect("symbol was never interned")
+    }
+
+    /// Only called with the table's lock held, one index after another.
+    fn set(&self, index: u32, value: T) {
+        let (segment, offset) = Self::locate(index);
+        let entries = self.segments[segment].get_or_init(|| {
+            (0..FIRST_SEGMENT << segment)
+                .map(|_| OnceLock::new())
+                .collect()
+        });
+        let _ = entries[offset].set(value);
+    }
+}
+
+/// The hash function rustc uses for its own interners: a multiply and a rotate per word,
+/// which is much cheaper than the default SipHash on short names. Nothing an attacker
+/// controls gets near the tables, so there's no flooding to defend against.
+#[derive(Default)]
+struct FxHasher {
+    hash: u64,
+}
+
+impl FxHasher {
+    fn add(&mut self, word: u64) {
+        self.hash = (self.hash.rotate_left(5) ^ word).wrapping_mul(0x51_7c_c1_b7_27_22_0a_95);
+    }
+}
+
+impl Hasher for FxHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        let mut chunks = bytes.chunks_exact(8);
+        for chunk in &mut chunks {
+            self.add(u64::from_le_bytes(chunk.try_into().unwrap()));
+        }
+        for &byte in chunks.remainder() {
+            self.add(u64::from(byte));
+        }
+    }
+
+    fn write_u8(&mut self, n: u8) {
+        self.add(u64::from(n));
+    }
+
+    fn write_u32(&mut self, n: u32) {
+        self.add(u64::from(n));
+    }
+
+    fn write_u64(&mut self, n: u64) {
+        self.add(n);
+    }
+
+    fn write_usize(&mut self, n: usize) {
+        self.add(n as u64);
+    }
+
+    fn finish(&self) -> u64 {
+        self.hash
+    }
+}
+
+/// One table: the values interned so far, by index, and the index of each value.
+struct Table<T> {
+    values: Entries<T>,
+    indices: RwLock<HashMap<T, u32, BuildHasherDefault<FxHasher>>>,
+}
+
+impl<T: Copy + Eq + Hash> Table<T> {
+    fn new(predefined: &[T]) -> Self {
+        let table = Table {
+            values: Entries::new(),
+            indices: RwLock::new(HashMap::default()),
+        };
+        for value in predefined {
+            table.intern(value, |&value| value);
+        }
+        table
+    }
+
+    /// Interns `value`, calling `store` to make a copy that lives forever the first time
+    /// it's seen. Returns its index.
+    fn intern<K>(&self, value: &K, store: impl FnOnce(&K) -> T) -> u32
+    where
+        K: ?Sized + Eq + Hash,
+        T: std::borrow::Borrow<K>,
+    {
+        if let Some(&index) = self.indices.read().unwrap().get(value) {
+            return index;
+        }
+        let mut indices = self.indices.write().unwrap();
+        // Another thread may have added it between the two locks.
+        if let Some(&index) = indices.get(value) {
+            return index;
+        }
+        let stored = store(value);
+        let index = u32::try_from(indices.len()).expect("symbol table overflows");
+        self.values.set(index, stored);
+        indices.insert(stored, index);
+        index
+    }
+
+    fn get(&self, index: u32) -> T {
+        self.values.get(index)
+    }
+}
+
+/// Never freed; see the module documentation.
+fn leak(s: &str) -> &'static str {
+    Box::leak(s.into())
+}
+
+macro_rules! predefined {
+    ($($name:ident = $text:literal,)*) => {
+        #[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+        enum Predefined {
+            $($name,)*
+        }
+
+        /// Names every phase refers to, interned before anything else so they're
+        /// constants.
+        pub mod sym {
+            use super::{Predefined, Symbol};
+            $(
+                #[doc = concat!("`", $text, "`")]
+                pub const $name: Symbol = Symbol(Predefined::$name as u32);
+            )*
+        }
+
+        const PREDEFINED: &[&str] = &[$($text,)*];
+    };
+}
+predefined! {
+    OBJECT = "Object",
+    IO = "IO",
+    INT = "Int",
+    STRING = "String",
+    BOOL = "Bool",
+    SELF_TYPE = "SELF_TYPE",
+    SELF = "self",
+    MAIN_CLASS = "Main",
+    MAIN_METHOD = "main",
+    ABORT = "abort",
+    TYPE_NAME = "type_name",
+    COPY = "copy",
+    OUT_STRING = "out_string",
+    OUT_INT = "out_int",
+    IN_STRING = "in_string",
+    IN_INT = "in_int",
+    LENGTH = "length",
+    CONCAT = "concat",
+    SUBSTR = "substr",
+    INIT = "_init",
+    VOID = "Void",
+}
+
+static IDTABLE: LazyLock<Table<&'static str>> = LazyLock::new(|| Table::new(PREDEFINED));
+static INTTABLE: LazyLock<Table<i32>> = LazyLock::new(|| Table::new(&[]));
+static STRINGTABLE: LazyLock<Table<&'static str>> = LazyLock::new(|| Table::new(&[""]));
+
+/// An identifier or type name from the identifier table.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Symbol(u32);
+
+impl Symbol {
+    /// Looks `name` up in the identifier table, adding it if it's new.
+    pub fn intern(name: &str) -> Symbol {
+        Symbol(IDTABLE.intern(name, leak))
+    }
+
+    pub fn as_str(self) -> &'static str {
+        IDTABLE.get(self.0)
+    }
+
+    /// The symbol's position in the identifier table.
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// An integer constant from the integer table.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct IntSymbol(u32);
+
+impl IntSymbol {
+    pub fn intern(value: i32) -> IntSymbol {
+        IntSymbol(INTTABLE.intern(&value, |&value| value))
+    }
+
+    pub fn value(self) -> i32 {
+        INTTABLE.get(self.0)
+    }
+
+    /// The constant's position in the integer table.
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// A string constant from the string table. The entry is the string's value, with the
+/// quotes and escapes of the literal it was written as already processed.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct StrSymbol(u32);
+
+impl StrSymbol {
+    /// `""`, the value of a `String` nobody has assigned to.
+    pub const EMPTY: StrSymbol = StrSymbol(0);
+
+    /// Looks `value` up in the string table, adding it if it's new.
+    pub fn intern(value: &str) -> StrSymbol {
+        StrSymbol(STRINGTABLE.intern(value, leak))
+    }
+
+    pub fn as_str(self) -> &'static str {
+        STRINGTABLE.get(self.0)
+    }
+
+    /// The constant's position in the string table.
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+macro_rules! impl_str_symbol {
+    ($symbol:ident) => {
+        impl Deref for $symbol {
+            type Target = str;
+
+            fn deref(&self) -> &str {
+                self.as_str()
+            }
+        }
+
+        impl PartialEq<str> for $symbol {
+            fn eq(&self, other: &str) -> bool {
+                self.as_str() == other
+            }
+        }
+
+        impl PartialEq<&str> for $symbol {
+            fn eq(&self, other: &&str) -> bool {
+                self.as_str() == *other
+            }
+        }
+
+        impl PartialEq<$symbol> for str {
+            fn eq(&self, other: &$symbol) -> bool {
+                self == other.as_str()
+            }
+        }
+
+        impl PartialEq<$symbol> for &str {
+            fn eq(&self, other: &$symbol) -> bool {
+                *self == other.as_str()
+            }
+        }
+
+        /// Symbols sort by their text, not by when they were interned, so anything
+        /// ordered by name comes out the same on every run.
+        impl PartialOrd for $symbol {
+            fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+                Some(self.cmp(other))
+            }
+        }
+
+        impl Ord for $symbol {
+            fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+                self.as_str().cmp(other.as_str())
+            }
+        }
+
+        impl fmt::Display for $symbol {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(self.as_str())
+            }
+        }
+
+        impl fmt::Debug for $symbol {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                fmt::Debug::fmt(self.as_str(), f)
+            }
+        }
+
+        impl Serialize for $symbol {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.serialize_str(self.as_str())
+            }
+        }
+    };
+}
+
+impl_str_symbol!(Symbol);
+impl_str_symbol!(StrSymbol);
+
+impl fmt::Display for IntSymbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.value(), f)
+    }
+}
+
+impl fmt::Debug for IntSymbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.value(), f)
+    }
+}
+
+impl Serialize for IntSymbol {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_i32(self.value())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interning_is_idempotent() {
+        let a = Symbol::intern("interning_test");
+        let b = Symbol::intern(&String::from("interning_test"));
+        assert_eq!(a, b);
+        assert_eq!(a.as_str(), "interning_test");
+        // The text is only leaked the first time.
+        assert!(std::ptr::eq(a.as_str(), b.as_str()));
+        assert_ne!(a, Symbol::intern("interning_test2"));
+    }
+
+    #[test]
+    fn test_predefined_symbols() {
+        assert_eq!(Symbol::intern("Object"), sym::OBJECT);
+        assert_eq!(Symbol::intern("SELF_TYPE"), sym::SELF_TYPE);
+        assert_eq!(sym::INIT.as_str(), "_init");
+        assert_eq!(StrSymbol::intern(""), StrSymbol::EMPTY);
+        for (i, name) in PREDEFINED.iter().enumerate() {
+            assert_eq!(Symbol::intern(name).index(), i);
+        }
+    }
+
+    #[test]
+    fn test_tables_are_separate() {
+        let id = Symbol::intern("separate_tables");
+        let string = StrSymbol::intern("separate_tables");
+        assert_eq!(id.as_str(), string.as_str());
+        assert_eq!(IntSymbol::intern(-7).value(), -7);
+        assert_eq!(IntSymbol::intern(-7), IntSymbol::intern(-7));
+    }
+
+    #[test]
+    fn test_symbols_are_indices() {
+        assert_eq!(std::mem::size_of::<Symbol>(), 4);
+    }
+
+    #[test]
+    fn test_entries_span_segments() {
+        let names: Vec<String> = (0..3 * FIRST_SEGMENT)
+            .map(|i| format!("segment_{i}"))
+            .collect();
+        let symbols: Vec<Symbol> = names.iter().map(|name| Symbol::intern(name)).collect();
+        for (symbol, name) in symbols.iter().zip(&names) {
+            assert_eq!(symbol.as_str(), name);
+        }
+        assert_eq!(Entries::<u8>::locate(0), (0, 0));
+        assert_eq!(Entries::<u8>::locate(63), (0, 63));
+        assert_eq!(Entries::<u8>::locate(64), (1, 0));
+        assert_eq!(Entries::<u8>::locate(u32::MAX).0, SEGMENTS - 1);
+    }
+
+    #[test]
+    fn test_symbols_order_by_text() {
+        let mut names = ["b_order", "a_order", "c_order"].map(Symbol::intern);
+        names.sort();
+        assert_eq!(names.map(Symbol::as_str), ["a_order", "b_order", "c_order"]);
+    }
+
+    #[test]
+    fn test_interning_from_threads() {
+        let symbols: Vec<Symbol> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..8)
+                .map(|_| scope.spawn(|| Symbol::intern("threaded_symbol")))
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        assert!(symbols.iter().all(|&s| s == symbols[0]));
+    }
+}
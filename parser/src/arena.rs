@@ -0,0 +1,606 @@
+//! An arena form of the AST. Every expression lives in one `Vec` owned by the [`Ast`] and
+//! is named by its [`NodeId`], an index into it; children are `NodeId`s, and the
+//! argument lists, `let` bindings and `case` branches of all expressions share a pool
+//! per kind. Converting a program makes a handful of allocations rather than one per
+//! expression, and a pass can keep what it learns about each expression, such as its
+//! type or the binding a name resolves to, in a [`NodeMap`] beside the tree instead of
+//! in it. Their spans are kept that way too, in a table of the `Ast`'s own.
+//!
+//! Expressions are numbered bottom-up: a node's children always have smaller ids than
+//! it does, so going through the ids in order visits every child before its parent.
+
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::{Index, IndexMut};
+
+use lexer::{IntSymbol, StrSymbol, Symbol};
+
+use crate::ast::{self, Formal, Span};
+
+/// Identifies an expression of an [`Ast`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(u32);
+
+impl NodeId {
+    /// The expression's position in the arena, for indexing side tables of its own.
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+impl fmt::Display for NodeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+/// A run of consecutive entries in one of an [`Ast`]'s pools.
+pub struct List<T> {
+    start: u32,
+    len: u32,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T> List<T> {
+    pub fn len(self) -> usize {
+        self.len as usize
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.len == 0
+    }
+
+    fn range(self) -> std::ops::Range<usize> {
+        self.start as usize..(self.start + self.len) as usize
+    }
+}
+
+// Derives would only implement these when `T` does.
+impl<T> Clone for List<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for List<T> {}
+
+impl<T> PartialEq for List<T> {
+    fn eq(&self, other: &Self) -> bool {
+        (self.start, self.len) == (other.start, other.len)
+    }
+}
+
+impl<T> Eq for List<T> {}
+
+impl<T> fmt::Debug for List<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.range())
+    }
+}
+
+/// A program whose expressions are stored in an arena.
+#[derive(Debug, Clone, Default)]
+pub struct Ast {
+    pub classes: Vec<Class>,
+    exprs: Vec<Expr>,
+    spans: Vec<Span>,
+    ids: Vec<NodeId>,
+    bindings: Vec<LetBinding>,
+    branches: Vec<CaseBranch>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Class {
+    pub name: Symbol,
+    pub parent: Option<Symbol>,
+    pub features: Vec<Feature>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub enum Feature {
+    Method(Method),
+    Attribute(Attribute),
+}
+
+#[derive(Debug, Clone)]
+pub struct Method {
+    pub name: Symbol,
+    pub formals: Vec<Formal>,
+    pub return_type: Symbol,
+    pub body: NodeId,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub struct Attribute {
+    pub name: Symbol,
+    pub attr_type: Symbol,
+    pub init: Option<NodeId>,
+    pub span: Span,
+}
+
+/// An [`ast::ExprKind`] with its children replaced by ids.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expr {
+    Assign {
+        name: Symbol,
+        expr: NodeId,
+    },
+    Dispatch {
+        expr: NodeId,
+        static_type: Option<Symbol>,
+        method: Symbol,
+        args: List<NodeId>,
+    },
+    FuncCall {
+        name: Symbol,
+        args: List<NodeId>,
+    },
+    If {
+        cond: NodeId,
+        then_branch: NodeId,
+        else_branch: NodeId,
+    },
+    While {
+        cond: NodeId,
+        body: NodeId,
+    },
+    Block(List<NodeId>),
+    Let {
+        bindings: List<LetBinding>,
+        body: NodeId,
+    },
+    Case {
+        expr: NodeId,
+        branches: List<CaseBranch>,
+    },
+    New(Symbol),
+    IsVoid(NodeId),
+    Plus(NodeId, NodeId),
+    Minus(NodeId, NodeId),
+    Times(NodeId, NodeId),
+    Divide(NodeId, NodeId),
+    Lt(NodeId, NodeId),
+    Le(NodeId, NodeId),
+    Eq(NodeId, NodeId),
+    Not(NodeId),
+    Negate(NodeId),
+    Paren(NodeId),
+    Id(Symbol),
+    Integer(IntSymbol),
+    String(StrSymbol),
+    True,
+    False,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LetBinding {
+    pub name: Symbol,
+    pub typ: Symbol,
+    pub init: Option<NodeId>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CaseBranch {
+    pub name: Symbol,
+    pub typ: Symbol,
+    pub expr: NodeId,
+}
+
+impl Ast {
+    /// The number of expressions in the program.
+    pub fn len(&self) -> usize {
+        self.exprs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.exprs.is_empty()
+    }
+
+    pub fn expr(&self, id: NodeId) -> &Expr {
+        &self.exprs[id.index()]
+    }
+
+    pub fn span(&self, id: NodeId) -> Span {
+        self.spans[id.index()]
+    }
+
+    /// Every expression's id, children before their parents.
+    pub fn node_ids(&self) -> impl Iterator<Item = NodeId> + use<> {
+        (0..self.exprs.len() as u32).map(NodeId)
+    }
+
+    pub fn list(&self, list: List<NodeId>) -> &[NodeId] {
+        &self.ids[list.range()]
+    }
+
+    pub fn bindings(&self, list: List<LetBinding>) -> &[LetBinding] {
+        &self.bindings[list.range()]
+    }
+
+    pub fn branches(&self, list: List<CaseBranch>) -> &[CaseBranch] {
+        &self.branches[list.range()]
+    }
+
+    fn add(&mut self, expr: Expr, span: Span) -> NodeId {
+        let id = u32::try_from(self.exprs.len()).expect("too many expressions");
+        self.exprs.push(expr);
+        self.spans.push(span);
+        NodeId(id)
+    }
+
+    fn add_list<T>(pool: &mut Vec<T>, items: impl IntoIterator<Item = T>) -> List<T> {
+        let start = pool.len();
+        pool.extend(items);
+        List {
+            start: start as u32,
+            len: (pool.len() - start) as u32,
+            marker: PhantomData,
+        }
+    }
+
+    fn convert(&mut self, expr: &ast::Expr) -> NodeId {
+        use ast::ExprKind as E;
+        let span = expr.span;
+        let expr = match &expr.kind {
+            E::Assign { name, expr } => Expr::Assign {
+                name: *name,
+                expr: self.convert(expr),
+            },
+            E::Dispatch {
+                expr,
+                static_type,
+                method,
+                args,
+            } => Expr::Dispatch {
+                expr: self.convert(expr),
+                static_type: *static_type,
+                method: *method,
+                args: self.convert_all(args),
+            },
+            E::FuncCall { name, args } => Expr::FuncCall {
+                name: *name,
+                args: self.convert_all(args),
+            },
+            E::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => Expr::If {
+                cond: self.convert(cond),
+                then_branch: self.convert(then_branch),
+                else_branch: self.convert(else_branch),
+            },
+            E::While { cond, body } => Expr::While {
+                cond: self.convert(cond),
+                body: self.convert(body),
+            },
+            E::Block(exprs) => Expr::Block(self.convert_all(exprs)),
+            E::Let { bindings, body } => {
+                // The initializers may hold lets of their own, which have to be in the
+                // pool before this one's bindings so that those stay together.
+                let bindings: Vec<LetBinding> = bindings
+                    .iter()
+                    .map(|binding| LetBinding {
+                        name: binding.name,
+                        typ: binding.typ,
+                        init: binding.init.as_ref().map(|init| self.convert(init)),
+                    })
+                    .collect();
+                Expr::Let {
+                    bindings: Ast::add_list(&mut self.bindings, bindings),
+                    body: self.convert(body),
+                }
+            }
+            E::Case { expr, branches } => {
+                let expr = self.convert(expr);
+                let branches: Vec<CaseBranch> = branches
+                    .iter()
+                    .map(|branch| CaseBranch {
+                        name: branch.name,
+                        typ: branch.typ,
+                        expr: self.convert(&branch.expr),
+                    })
+                    .collect();
+                Expr::Case {
+                    expr,
+                    branches: Ast::add_list(&mut self.branches, branches),
+                }
+            }
+            E::New(typ) => Expr::New(*typ),
+            E::IsVoid(expr) => Expr::IsVoid(self.convert(expr)),
+            E::Plus(a, b) => Expr::Plus(self.convert(a), self.convert(b)),
+            E::Minus(a, b) => Expr::Minus(self.convert(a), self.convert(b)),
+            E::Times(a, b) => Expr::Times(self.convert(a), self.convert(b)),
+            E::Divide(a, b) => Expr::Divide(self.convert(a), self.convert(b)),
+            E::Lt(a, b) => Expr::Lt(self.convert(a), self.convert(b)),
+            E::Le(a, b) => Expr::Le(self.convert(a), self.convert(b)),
+            E::Eq(a, b) => Expr::Eq(self.convert(a), self.convert(b)),
+            E::Not(expr) => Expr::Not(self.convert(expr)),
+            E::Negate(expr) => Expr::Negate(self.convert(expr)),
+            E::Paren(expr) => Expr::Paren(self.convert(expr)),
+            E::Id(name) => Expr::Id(*name),
+            E::Integer(i) => Expr::Integer(*i),
+            E::String(s) => Expr::String(*s),
+            E::True => Expr::True,
+            E::False => Expr::False,
+        };
+        self.add(expr, span)
+    }
+
+    fn convert_all(&mut self, exprs: &[ast::Expr]) -> List<NodeId> {
+        let ids: Vec<NodeId> = exprs.iter().map(|expr| self.convert(expr)).collect();
+        Ast::add_list(&mut self.ids, ids)
+    }
+}
+
+impl From<&ast::Program> for Ast {
+    fn from(program: &ast::Program) -> Self {
+        let mut ast = Ast::default();
+        for class in &program.classes {
+            let features = class
+                .features
+                .iter()
+                .map(|feature| match feature {
+                    ast::Feature::Method(method) => Feature::Method(Method {
+                        name: method.name,
+                        formals: method.formals.clone(),
+                        return_type: method.return_type,
+                        body: ast.convert(&method.body),
+                        span: method.span,
+                    }),
+                    ast::Feature::Attribute(attr) => Feature::Attribute(Attribute {
+                        name: attr.name,
+                        attr_type: attr.attr_type,
+                        init: attr.init.as_ref().map(|init| ast.convert(init)),
+                        span: attr.span,
+                    }),
+                })
+                .collect();
+            ast.classes.push(Class {
+                name: class.name,
+                parent: class.parent,
+                features,
+                span: class.span,
+            });
+        }
+        ast
+    }
+}
+
+/// Data about some of an [`Ast`]'s expressions, stored densely by [`NodeId`].
+#[derive(Debug, Clone)]
+pub struct NodeMap<T> {
+    values: Vec<Option<T>>,
+}
+
+impl<T> NodeMap<T> {
+    /// An empty map with room for every expression of `ast`.
+    pub fn new(ast: &Ast) -> Self {
+        let mut values = Vec::new();
+        values.resize_with(ast.len(), || None);
+        NodeMap { values }
+    }
+
+    /// Sets the data for `id`, returning what was there before.
+    pub fn insert(&mut self, id: NodeId, value: T) -> Option<T> {
+        self.values[id.index()].replace(value)
+    }
+
+    pub fn get(&self, id: NodeId) -> Option<&T> {
+        self.values[id.index()].as_ref()
+    }
+
+    pub fn get_mut(&mut self, id: NodeId) -> Option<&mut T> {
+        self.values[id.index()].as_mut()
+    }
+
+    pub fn contains(&self, id: NodeId) -> bool {
+        self.values[id.index()].is_some()
+    }
+}
+
+impl<T> Index<NodeId> for NodeMap<T> {
+    type Output = T;
+
+    fn index(&self, id: NodeId) -> &T {
+        self.get(id)
+            .unwrap_or_else(|| panic!("no entry for expression {id}"))
+    }
+}
+
+impl<T> IndexMut<NodeId> for NodeMap<T> {
+    fn index_mut(&mut self, id: NodeId) -> &mut T {
+        self.get_mut(id)
+            .unwrap_or_else(|| panic!("no entry for expression {id}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn convert(src: &str) -> Ast {
+        Ast::from(&crate::parse(src).unwrap())
+    }
+
+    fn body(ast: &Ast, class: usize, feature: usize) -> NodeId {
+        match &ast.classes[class].features[feature] {
+            Feature::Method(method) => method.body,
+            Feature::Attribute(attr) => attr.init.unwrap(),
+        }
+    }
+
+    /// The ids of `id`'s children, in the order the boxed tree has them.
+    fn children(ast: &Ast, id: NodeId) -> Vec<NodeId> {
+        match *ast.expr(id) {
+            Expr::Assign { expr, .. }
+            | Expr::IsVoid(expr)
+            | Expr::Not(expr)
+            | Expr::Negate(expr)
+            | Expr::Paren(expr) => vec![expr],
+            Expr::Dispatch { expr, args, .. } => [expr]
+                .into_iter()
+                .chain(ast.list(args).iter().copied())
+                .collect(),
+            Expr::FuncCall { args, .. } | Expr::Block(args) => ast.list(args).to_vec(),
+            Expr::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => vec![cond, then_branch, else_branch],
+            Expr::While { cond, body } => vec![cond, body],
+            Expr::Let { bindings, body } => ast
+                .bindings(bindings)
+                .iter()
+                .filter_map(|binding| binding.init)
+                .chain([body])
+                .collect(),
+            Expr::Case { expr, branches } => [expr]
+                .into_iter()
+                .chain(ast.branches(branches).iter().map(|branch| branch.expr))
+                .collect(),
+            Expr::Plus(a, b)
+            | Expr::Minus(a, b)
+            | Expr::Times(a, b)
+            | Expr::Divide(a, b)
+            | Expr::Lt(a, b)
+            | Expr::Le(a, b)
+            | Expr::Eq(a, b) => vec![a, b],
+            Expr::New(_)
+            | Expr::Id(_)
+            | Expr::Integer(_)
+            | Expr::String(_)
+            | Expr::True
+            | Expr::False => Vec::new(),
+        }
+    }
+
+    const PROGRAM: &str = r#"
+        class A inherits IO {
+            x : Int <- 1 + 2;
+            f(n : Int, s : String) : Object { {
+                out_string(s.concat("!"));
+                let a : Int <- let b : Int <- n in b * 2, c : Int in
+                    case a of i : Int => i; o : Object => 0; esac;
+                while not isvoid self loop x <- ~x pool;
+                if x < n then new A else self@IO.out_int(x) fi;
+            } };
+        };
+    "#;
+
+    #[test]
+    fn test_conversion_keeps_the_shape() {
+        let ast = convert(PROGRAM);
+        let class = &ast.classes[0];
+        assert_eq!(class.name, "A");
+        assert_eq!(class.parent, Some(Symbol::intern("IO")));
+
+        let Expr::Plus(lhs, rhs) = *ast.expr(body(&ast, 0, 0)) else {
+            panic!("expected a sum");
+        };
+        assert!(matches!(ast.expr(lhs), Expr::Integer(i) if i.value() == 1));
+        assert!(matches!(ast.expr(rhs), Expr::Integer(i) if i.value() == 2));
+
+        let Feature::Method(method) = &class.features[1] else {
+            panic!("expected a method");
+        };
+        assert_eq!(method.formals.len(), 2);
+        let Expr::Block(statements) = *ast.expr(method.body) else {
+            panic!("expected a block");
+        };
+        let statements = ast.list(statements);
+        assert_eq!(statements.len(), 4);
+
+        let Expr::FuncCall { name, args } = *ast.expr(statements[0]) else {
+            panic!("expected a call");
+        };
+        assert_eq!(name, "out_string");
+        let Expr::Dispatch { method, args, .. } = *ast.expr(ast.list(args)[0]) else {
+            panic!("expected a dispatch");
+        };
+        assert_eq!(method, "concat");
+        assert!(matches!(ast.expr(ast.list(args)[0]), Expr::String(s) if *s == "!"));
+
+        let Expr::Let { bindings, body } = *ast.expr(statements[1]) else {
+            panic!("expected a let");
+        };
+        let bindings = ast.bindings(bindings);
+        assert_eq!(
+            bindings.iter().map(|b| b.name).collect::<Vec<_>>(),
+            ["a", "c"]
+        );
+        assert!(matches!(
+            ast.expr(bindings[0].init.unwrap()),
+            Expr::Let { .. }
+        ));
+        assert_eq!(bindings[1].init, None);
+        let Expr::Case { branches, .. } = *ast.expr(body) else {
+            panic!("expected a case");
+        };
+        let types: Vec<_> = ast.branches(branches).iter().map(|b| b.typ).collect();
+        assert_eq!(types, ["Int", "Object"]);
+
+        let Expr::If { else_branch, .. } = *ast.expr(statements[3]) else {
+            panic!("expected an if");
+        };
+        let span = ast.span(else_branch);
+        assert_eq!(&PROGRAM[span.start..span.end], "self@IO.out_int(x)");
+        assert!(matches!(
+            ast.expr(else_branch),
+            Expr::Dispatch { static_type: Some(t), .. } if *t == "IO"
+        ));
+    }
+
+    #[test]
+    fn test_every_node_is_reached_once_after_its_children() {
+        let ast = convert(PROGRAM);
+        let mut seen = NodeMap::new(&ast);
+        let mut stack: Vec<NodeId> = (0..ast.classes[0].features.len())
+            .map(|feature| body(&ast, 0, feature))
+            .collect();
+        while let Some(id) = stack.pop() {
+            assert_eq!(seen.insert(id, ()), None, "{id} reached twice");
+            for child in children(&ast, id) {
+                assert!(child < id, "{child} isn't numbered before its parent {id}");
+                stack.push(child);
+            }
+        }
+        assert!(ast.node_ids().all(|id| seen.contains(id)));
+    }
+
+    #[test]
+    fn test_side_tables() {
+        let ast = convert(PROGRAM);
+        // Going through the ids in order sees every child before its parent, so a
+        // bottom-up pass needs no recursion.
+        let mut sizes: NodeMap<usize> = NodeMap::new(&ast);
+        for id in ast.node_ids() {
+            let size = 1 + children(&ast, id).iter().map(|&c| sizes[c]).sum::<usize>();
+            sizes.insert(id, size);
+        }
+        let roots = [body(&ast, 0, 0), body(&ast, 0, 1)];
+        assert_eq!(sizes[roots[0]], 3);
+        assert_eq!(roots.iter().map(|&r| sizes[r]).sum::<usize>(), ast.len());
+
+        sizes[roots[0]] = 0;
+        assert_eq!(sizes.get(roots[0]), Some(&0));
+    }
+
+    #[test]
+    fn test_empty_lists() {
+        let ast = convert("class Main { main() : Object { { f(); self.g(); } }; };");
+        let Expr::Block(statements) = *ast.expr(body(&ast, 0, 0)) else {
+            panic!("expected a block");
+        };
+        for &statement in ast.list(statements) {
+            let (Expr::FuncCall { args, .. } | Expr::Dispatch { args, .. }) = *ast.expr(statement)
+            else {
+                panic!("expected a call");
+            };
+            assert!(args.is_empty());
+            assert!(ast.list(args).is_empty());
+        }
+    }
+}
@@ -1,59 +1,90 @@
 #![allow(unused)]
 use std::boxed::Box;
 
-#[derive(Debug, Clone)]
+use chumsky::span::SimpleSpan;
+use lexer::{FileId, IntSymbol, StrSymbol, Symbol};
+use serde::Serialize;
+
+/// A byte range in one of the program's source files.
+pub type Span = SimpleSpan<usize, FileId>;
+
+#[derive(Debug, Clone, Serialize)]
 pub struct Program {
     pub classes: Vec<Class>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct Class {
-    pub name: String,
-    pub parent: Option<String>,
+    pub name: Symbol,
+    pub parent: Option<Symbol>,
     pub features: Vec<Feature>,
+    #[serde(serialize_with = "crate::json::span")]
+    pub span: Span,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
 pub enum Feature {
     Method(MethodFeature),
     Attribute(AttributeFeature),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct MethodFeature {
-    pub name: String,
+    pub name: Symbol,
     pub formals: Vec<Formal>,
-    pub return_type: String,
+    pub return_type: Symbol,
     pub body: Expr,
+    #[serde(serialize_with = "crate::json::span")]
+    pub span: Span,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct AttributeFeature {
-    pub name: String,
-    pub attr_type: String,
+    pub name: Symbol,
+    #[serde(rename = "type")]
+    pub attr_type: Symbol,
     pub init: Option<Expr>,
+    #[serde(serialize_with = "crate::json::span")]
+    pub span: Span,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct Formal {
-    pub name: String,
-    pub typ: String,
+    pub name: Symbol,
+    #[serde(rename = "type")]
+    pub typ: Symbol,
+    #[serde(serialize_with = "crate::json::span")]
+    pub span: Span,
 }
 
+/// An expression, with the span of the source it was parsed from.
 #[derive(Debug, Clone)]
-pub enum Expr {
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+impl Expr {
+    pub fn new(kind: ExprKind, span: Span) -> Self {
+        Expr { kind, span }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum ExprKind {
     Assign {
-        name: String,
+        name: Symbol,
         expr: Box<Expr>,
     },
     Dispatch {
         expr: Box<Expr>,
-        static_type: Option<String>,
-        method: String,
+        static_type: Option<Symbol>,
+        method: Symbol,
         args: Vec<Expr>,
     },
     FuncCall {
-        name: String,
+        name: Symbol,
         args: Vec<Expr>,
     },
     If {
@@ -74,7 +105,7 @@ pub enum Expr {
         expr: Box<Expr>,
         branches: Vec<CaseBranch>,
     },
-    New(String),
+    New(Symbol),
     IsVoid(Box<Expr>),
     Plus(Box<Expr>, Box<Expr>),
     Minus(Box<Expr>, Box<Expr>),
@@ -84,24 +115,31 @@ pub enum Expr {
     Le(Box<Expr>, Box<Expr>),
     Eq(Box<Expr>, Box<Expr>),
     Not(Box<Expr>),
+    Negate(Box<Expr>),
     Paren(Box<Expr>),
-    Id(String),
-    Integer(i32),
-    String(String),
+    Id(Symbol),
+    Integer(IntSymbol),
+    String(StrSymbol),
     True,
     False,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct LetBinding {
-    pub name: String,
-    pub typ: String,
+    pub name: Symbol,
+    #[serde(rename = "type")]
+    pub typ: Symbol,
     pub init: Option<Expr>,
+    #[serde(serialize_with = "crate::json::span")]
+    pub span: Span,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct CaseBranch {
-    pub name: String,
-    pub typ: String,
+    pub name: Symbol,
+    #[serde(rename = "type")]
+    pub typ: Symbol,
     pub expr: Expr,
+    #[serde(serialize_with = "crate::json::span")]
+    pub span: Span,
 }
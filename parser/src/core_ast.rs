@@ -0,0 +1,350 @@
+//! The core language: the subset of the AST that code generation has to handle, and
+//! [`desugar`], which rewrites a program into it.
+//!
+//! Compared to [`ast::Expr`], a call without a receiver is a [`Expr::Dispatch`] on
+//! [`Expr::SelfRef`], every `let` binds exactly one variable, with the later bindings
+//! of a multi-binding `let` nested in its body, parentheses are gone, and `self` is
+//! [`Expr::SelfRef`] rather than an identifier. Every node keeps the span of the source
+//! it came from: the receiver a call gets and the `let`s a multi-binding `let` turns into
+//! take the span of the expression they replace.
+
+use std::boxed::Box;
+
+use lexer::{IntSymbol, StrSymbol, Symbol, sym};
+
+use crate::ast::{self, Formal, Span};
+
+#[derive(Debug, Clone)]
+pub struct Program {
+    pub classes: Vec<Class>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Class {
+    pub name: Symbol,
+    pub parent: Option<Symbol>,
+    pub features: Vec<Feature>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub enum Feature {
+    Method(MethodFeature),
+    Attribute(AttributeFeature),
+}
+
+#[derive(Debug, Clone)]
+pub struct MethodFeature {
+    pub name: Symbol,
+    pub formals: Vec<Formal>,
+    pub return_type: Symbol,
+    pub body: Expr,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub struct AttributeFeature {
+    pub name: Symbol,
+    pub attr_type: Symbol,
+    pub init: Option<Expr>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+impl Expr {
+    pub fn new(kind: ExprKind, span: Span) -> Self {
+        Expr { kind, span }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum ExprKind {
+    Assign {
+        name: Symbol,
+        expr: Box<Expr>,
+    },
+    Dispatch {
+        expr: Box<Expr>,
+        static_type: Option<Symbol>,
+        method: Symbol,
+        args: Vec<Expr>,
+    },
+    If {
+        cond: Box<Expr>,
+        then_branch: Box<Expr>,
+        else_branch: Box<Expr>,
+    },
+    While {
+        cond: Box<Expr>,
+        body: Box<Expr>,
+    },
+    Block(Vec<Expr>),
+    Let {
+        name: Symbol,
+        typ: Symbol,
+        init: Option<Box<Expr>>,
+        body: Box<Expr>,
+    },
+    Case {
+        expr: Box<Expr>,
+        branches: Vec<CaseBranch>,
+    },
+    New(Symbol),
+    IsVoid(Box<Expr>),
+    Plus(Box<Expr>, Box<Expr>),
+    Minus(Box<Expr>, Box<Expr>),
+    Times(Box<Expr>, Box<Expr>),
+    Divide(Box<Expr>, Box<Expr>),
+    Lt(Box<Expr>, Box<Expr>),
+    Le(Box<Expr>, Box<Expr>),
+    Eq(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Negate(Box<Expr>),
+    SelfRef,
+    Id(Symbol),
+    Integer(IntSymbol),
+    String(StrSymbol),
+    True,
+    False,
+}
+
+#[derive(Debug, Clone)]
+pub struct CaseBranch {
+    pub name: Symbol,
+    pub typ: Symbol,
+    pub expr: Expr,
+}
+
+/// Rewrites `program` into the core language.
+pub fn desugar(program: &ast::Program) -> Program {
+    Program {
+        classes: program.classes.iter().map(desugar_class).collect(),
+    }
+}
+
+fn desugar_class(class: &ast::Class) -> Class {
+    let features = class
+        .features
+        .iter()
+        .map(|feature| match feature {
+            ast::Feature::Method(method) => Feature::Method(MethodFeature {
+                name: method.name,
+                formals: method.formals.clone(),
+                return_type: method.return_type,
+                body: desugar_expr(&method.body),
+                span: method.span,
+            }),
+            ast::Feature::Attribute(attr) => Feature::Attribute(AttributeFeature {
+                name: attr.name,
+                attr_type: attr.attr_type,
+                init: attr.init.as_ref().map(desugar_expr),
+                span: attr.span,
+            }),
+        })
+        .collect();
+    Class {
+        name: class.name,
+        parent: class.parent,
+        features,
+        span: class.span,
+    }
+}
+
+fn desugar_expr(expr: &ast::Expr) -> Expr {
+    use ast::ExprKind as E;
+    let boxed = |expr: &ast::Expr| Box::new(desugar_expr(expr));
+    let all = |exprs: &[ast::Expr]| exprs.iter().map(desugar_expr).collect();
+    let span = expr.span;
+    let kind = match &expr.kind {
+        E::Assign { name, expr } => ExprKind::Assign {
+            name: *name,
+            expr: boxed(expr),
+        },
+        E::Dispatch {
+            expr,
+            static_type,
+            method,
+            args,
+        } => ExprKind::Dispatch {
+            expr: boxed(expr),
+            static_type: *static_type,
+            method: *method,
+            args: all(args),
+        },
+        E::FuncCall { name, args } => ExprKind::Dispatch {
+            expr: Box::new(Expr::new(ExprKind::SelfRef, span)),
+            static_type: None,
+            method: *name,
+            args: all(args),
+        },
+        E::If {
+            cond,
+            then_branch,
+            else_branch,
+        } => ExprKind::If {
+            cond: boxed(cond),
+            then_branch: boxed(then_branch),
+            else_branch: boxed(else_branch),
+        },
+        E::While { cond, body } => ExprKind::While {
+            cond: boxed(cond),
+            body: boxed(body),
+        },
+        E::Block(exprs) => ExprKind::Block(all(exprs)),
+        E::Let { bindings, body } => {
+            // Each binding's initializer already sees the bindings before it, so
+            // nesting them one per `let` keeps the scoping the same.
+            return bindings
+                .iter()
+                .rev()
+                .fold(desugar_expr(body), |body, binding| {
+                    let kind = ExprKind::Let {
+                        name: binding.name,
+                        typ: binding.typ,
+                        init: binding.init.as_ref().map(boxed),
+                        body: Box::new(body),
+                    };
+                    Expr::new(kind, span)
+                });
+        }
+        E::Case { expr, branches } => ExprKind::Case {
+            expr: boxed(expr),
+            branches: branches
+                .iter()
+                .map(|branch| CaseBranch {
+                    name: branch.name,
+                    typ: branch.typ,
+                    expr: desugar_expr(&branch.expr),
+                })
+                .collect(),
+        },
+        E::New(typ) => ExprKind::New(*typ),
+        E::IsVoid(expr) => ExprKind::IsVoid(boxed(expr)),
+        E::Plus(a, b) => ExprKind::Plus(boxed(a), boxed(b)),
+        E::Minus(a, b) => ExprKind::Minus(boxed(a), boxed(b)),
+        E::Times(a, b) => ExprKind::Times(boxed(a), boxed(b)),
+        E::Divide(a, b) => ExprKind::Divide(boxed(a), boxed(b)),
+        E::Lt(a, b) => ExprKind::Lt(boxed(a), boxed(b)),
+        E::Le(a, b) => ExprKind::Le(boxed(a), boxed(b)),
+        E::Eq(a, b) => ExprKind::Eq(boxed(a), boxed(b)),
+        E::Not(expr) => ExprKind::Not(boxed(expr)),
+        E::Negate(expr) => ExprKind::Negate(boxed(expr)),
+        E::Paren(expr) => return desugar_expr(expr),
+        E::Id(sym::SELF) => ExprKind::SelfRef,
+        E::Id(name) => ExprKind::Id(*name),
+        E::Integer(i) => ExprKind::Integer(*i),
+        E::String(s) => ExprKind::String(*s),
+        E::True => ExprKind::True,
+        E::False => ExprKind::False,
+    };
+    Expr::new(kind, span)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PREFIX: &str = "class A { f(n : Int) : Object { ";
+
+    fn desugar_body(body: &str) -> Expr {
+        let src = format!("{PREFIX}{body} }}; }};");
+        let program = desugar(&crate::parse(&src).unwrap());
+        match &program.classes[0].features[0] {
+            Feature::Method(method) => method.body.clone(),
+            Feature::Attribute(_) => unreachable!(),
+        }
+    }
+
+    /// The source `expr` spans in `body`.
+    fn text<'a>(body: &'a str, expr: &Expr) -> &'a str {
+        &body[expr.span.start - PREFIX.len()..expr.span.end - PREFIX.len()]
+    }
+
+    #[test]
+    fn test_func_call_dispatches_on_self() {
+        let body = "g(1, self)";
+        let expr = desugar_body(body);
+        let ExprKind::Dispatch {
+            expr: receiver,
+            static_type: None,
+            method,
+            args,
+        } = &expr.kind
+        else {
+            panic!("expected a dispatch");
+        };
+        assert!(matches!(receiver.kind, ExprKind::SelfRef));
+        assert_eq!(*method, "g");
+        assert!(matches!(
+            (&args[0].kind, &args[1].kind),
+            (ExprKind::Integer(_), ExprKind::SelfRef)
+        ));
+        assert_eq!(text(body, &expr), body);
+        assert_eq!(text(body, receiver), body);
+        assert_eq!(text(body, &args[1]), "self");
+    }
+
+    #[test]
+    fn test_let_binds_one_variable_at_a_time() {
+        let body = desugar_body("let a : Int <- n, b : Int, c : Int <- a in c");
+        let ExprKind::Let {
+            name, init, body, ..
+        } = body.kind
+        else {
+            panic!("expected a let");
+        };
+        assert_eq!(name, "a");
+        assert!(matches!(init.map(|init| init.kind), Some(ExprKind::Id(n)) if n == "n"));
+        let ExprKind::Let {
+            name,
+            init: None,
+            body,
+            ..
+        } = body.kind
+        else {
+            panic!("expected a let without an initializer");
+        };
+        assert_eq!(name, "b");
+        let ExprKind::Let {
+            name, init, body, ..
+        } = body.kind
+        else {
+            panic!("expected a let");
+        };
+        assert_eq!(name, "c");
+        assert!(matches!(init.map(|init| init.kind), Some(ExprKind::Id(a)) if a == "a"));
+        assert!(matches!(body.kind, ExprKind::Id(c) if c == "c"));
+    }
+
+    #[test]
+    fn test_parens_and_self() {
+        let body = "((self)).g((n) + ((1)))";
+        let expr = desugar_body(body);
+        let ExprKind::Dispatch {
+            expr: receiver,
+            args,
+            ..
+        } = &expr.kind
+        else {
+            panic!("expected a dispatch");
+        };
+        assert!(matches!(receiver.kind, ExprKind::SelfRef));
+        assert_eq!(text(body, receiver), "((self))");
+        let ExprKind::Plus(a, b) = &args[0].kind else {
+            panic!("expected a sum");
+        };
+        assert!(matches!(a.kind, ExprKind::Id(_)) && matches!(b.kind, ExprKind::Integer(_)));
+        assert_eq!(text(body, &args[0]), "(n) + ((1))");
+        assert_eq!(text(body, &expr), body);
+        // `SELF_TYPE` is a type, and stays what it is.
+        assert!(matches!(
+            desugar_body("new SELF_TYPE").kind,
+            ExprKind::New(sym::SELF_TYPE)
+        ));
+    }
+}
@@ -0,0 +1,506 @@
+//! A lossless concrete syntax tree, for tools that need the source exactly as written:
+//! formatters, refactorings and editor support.
+//!
+//! Unlike [`ast`](crate::ast), the tree keeps every byte of the input. Whitespace the
+//! lexer skips, comments, and text that doesn't lex all become tokens, so a tree's text
+//! is always the source it was parsed from, even when the source has errors. The tree is
+//! built on [rowan]: an immutable "green" tree that [`Parse`] owns, and a "red"
+//! [`SyntaxNode`] view of it with parents and offsets. The [`nodes`] module layers typed
+//! accessors on top and converts a tree back into an [`ast::Program`].
+
+use std::fmt;
+use std::ops::Range;
+
+use lexer::{FileId, Token};
+use logos::Logos;
+use rowan::GreenNode;
+
+use crate::ast;
+
+mod grammar;
+pub mod nodes;
+
+macro_rules! syntax_kinds {
+    ($($(#[$doc:meta])* $kind:ident,)*) => {
+        /// The kind of a token or node in the tree.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        #[repr(u16)]
+        pub enum SyntaxKind {
+            $($(#[$doc])* $kind,)*
+        }
+
+        impl SyntaxKind {
+            const ALL: &[SyntaxKind] = &[$(SyntaxKind::$kind,)*];
+        }
+    };
+}
+
+syntax_kinds! {
+    /// Spaces, tabs and line breaks between tokens.
+    Whitespace,
+    /// A `--` or `(* *)` comment.
+    Comment,
+    /// Text that isn't a token.
+    LexError,
+
+    Integer,
+    String,
+    TypeIdentifier,
+    ObjectIdentifier,
+    SelfLit,
+    SelfType,
+
+    ClassKw,
+    ElseKw,
+    FiKw,
+    IfKw,
+    InKw,
+    InheritsKw,
+    IsvoidKw,
+    LetKw,
+    LoopKw,
+    PoolKw,
+    ThenKw,
+    WhileKw,
+    CaseKw,
+    EsacKw,
+    NewKw,
+    OfKw,
+    NotKw,
+    TrueKw,
+    FalseKw,
+
+    Plus,
+    Minus,
+    Multiply,
+    Divide,
+    Tilde,
+    LessThan,
+    LessThanOrEqual,
+    Equal,
+    LeftParen,
+    RightParen,
+    DoubleArrow,
+    Assign,
+    Colon,
+    /// `@`, which the lexer calls [`Token::TypeId`].
+    At,
+    LeftBrace,
+    RightBrace,
+    Semicolon,
+    Dot,
+    Comma,
+
+    /// The root: every class of a file, and everything between them.
+    Program,
+    Class,
+    Method,
+    Attribute,
+    Formal,
+    /// The parenthesized arguments of a call or dispatch.
+    ArgList,
+    AssignExpr,
+    /// `e.f(...)` or `e@T.f(...)`.
+    DispatchExpr,
+    /// `f(...)`, a dispatch to `self`.
+    CallExpr,
+    IfExpr,
+    WhileExpr,
+    BlockExpr,
+    LetExpr,
+    LetBinding,
+    CaseExpr,
+    CaseBranch,
+    NewExpr,
+    IsvoidExpr,
+    /// An arithmetic operator or comparison.
+    BinaryExpr,
+    /// `not` or `~`.
+    PrefixExpr,
+    ParenExpr,
+    /// A variable or `self`.
+    NameExpr,
+    /// An integer, string, `true` or `false`.
+    Literal,
+    /// Tokens the parser skipped over to recover from a syntax error.
+    Error,
+}
+
+impl SyntaxKind {
+    /// Whether the parser passes over tokens of this kind: they're kept in the tree but
+    /// mean nothing to the grammar.
+    pub fn is_trivia(self) -> bool {
+        matches!(
+            self,
+            SyntaxKind::Whitespace | SyntaxKind::Comment | SyntaxKind::LexError
+        )
+    }
+
+    /// How the kind reads in an error message.
+    pub fn describe(self) -> &'static str {
+        use SyntaxKind as K;
+        match self {
+            K::Integer => "an integer",
+            K::String => "a string",
+            K::TypeIdentifier => "a type name",
+            K::ObjectIdentifier => "an identifier",
+            K::SelfLit => "'self'",
+            K::SelfType => "'SELF_TYPE'",
+            K::ClassKw => "'class'",
+            K::ElseKw => "'else'",
+            K::FiKw => "'fi'",
+            K::IfKw => "'if'",
+            K::InKw => "'in'",
+            K::InheritsKw => "'inherits'",
+            K::IsvoidKw => "'isvoid'",
+            K::LetKw => "'let'",
+            K::LoopKw => "'loop'",
+            K::PoolKw => "'pool'",
+            K::ThenKw => "'then'",
+            K::WhileKw => "'while'",
+            K::CaseKw => "'case'",
+            K::EsacKw => "'esac'",
+            K::NewKw => "'new'",
+            K::OfKw => "'of'",
+            K::NotKw => "'not'",
+            K::TrueKw => "'true'",
+            K::FalseKw => "'false'",
+            K::Plus => "'+'",
+            K::Minus => "'-'",
+            K::Multiply => "'*'",
+            K::Divide => "'/'",
+            K::Tilde => "'~'",
+            K::LessThan => "'<'",
+            K::LessThanOrEqual => "'<='",
+            K::Equal => "'='",
+            K::LeftParen => "'('",
+            K::RightParen => "')'",
+            K::DoubleArrow => "'=>'",
+            K::Assign => "'<-'",
+            K::Colon => "':'",
+            K::At => "'@'",
+            K::LeftBrace => "'{'",
+            K::RightBrace => "'}'",
+            K::Semicolon => "';'",
+            K::Dot => "'.'",
+            K::Comma => "','",
+            K::Whitespace => "whitespace",
+            K::Comment => "a comment",
+            _ => "invalid text",
+        }
+    }
+}
+
+impl From<&Token> for SyntaxKind {
+    fn from(token: &Token) -> Self {
+        use SyntaxKind as K;
+        match token {
+            Token::Error => K::LexError,
+            Token::Integer(_) => K::Integer,
+            Token::TypeIdentifier(_) => K::TypeIdentifier,
+            Token::ObjectIdentifier(_) => K::ObjectIdentifier,
+            Token::SelfLit => K::SelfLit,
+            Token::SelfType => K::SelfType,
+            Token::String(_) => K::String,
+            Token::Class => K::ClassKw,
+            Token::Else => K::ElseKw,
+            Token::Fi => K::FiKw,
+            Token::If => K::IfKw,
+            Token::In => K::InKw,
+            Token::Inherits => K::InheritsKw,
+            Token::Isvoid => K::IsvoidKw,
+            Token::Let => K::LetKw,
+            Token::Loop => K::LoopKw,
+            Token::Pool => K::PoolKw,
+            Token::Then => K::ThenKw,
+            Token::While => K::WhileKw,
+            Token::Case => K::CaseKw,
+            Token::Esac => K::EsacKw,
+            Token::New => K::NewKw,
+            Token::Of => K::OfKw,
+            Token::Not => K::NotKw,
+            Token::True => K::TrueKw,
+            Token::False => K::FalseKw,
+            Token::Comment => K::Comment,
+            Token::Plus => K::Plus,
+            Token::Minus => K::Minus,
+            Token::Multiply => K::Multiply,
+            Token::Divide => K::Divide,
+            Token::Tilde => K::Tilde,
+            Token::LessThan => K::LessThan,
+            Token::LessThanOrEqual => K::LessThanOrEqual,
+            Token::Equal => K::Equal,
+            Token::LeftParen => K::LeftParen,
+            Token::RightParen => K::RightParen,
+            Token::DoubleArrow => K::DoubleArrow,
+            Token::Assign => K::Assign,
+            Token::Colon => K::Colon,
+            Token::TypeId => K::At,
+            Token::LeftBrace => K::LeftBrace,
+            Token::RightBrace => K::RightBrace,
+            Token::Semicolon => K::Semicolon,
+            Token::Dot => K::Dot,
+            Token::Comma => K::Comma,
+            Token::Newline => K::Whitespace,
+        }
+    }
+}
+
+impl From<SyntaxKind> for rowan::SyntaxKind {
+    fn from(kind: SyntaxKind) -> Self {
+        rowan::SyntaxKind(kind as u16)
+    }
+}
+
+/// Ties rowan's untyped trees to [`SyntaxKind`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum CoolLanguage {}
+
+impl rowan::Language for CoolLanguage {
+    type Kind = SyntaxKind;
+
+    fn kind_from_raw(raw: rowan::SyntaxKind) -> SyntaxKind {
+        SyntaxKind::ALL[raw.0 as usize]
+    }
+
+    fn kind_to_raw(kind: SyntaxKind) -> rowan::SyntaxKind {
+        kind.into()
+    }
+}
+
+pub type SyntaxNode = rowan::SyntaxNode<CoolLanguage>;
+pub type SyntaxToken = rowan::SyntaxToken<CoolLanguage>;
+pub type SyntaxElement = rowan::SyntaxElement<CoolLanguage>;
+
+/// A problem found while building the tree, at a byte range of the source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyntaxError {
+    pub message: String,
+    pub span: Range<usize>,
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+/// The tree for one source file, along with any syntax errors in it.
+#[derive(Debug, Clone)]
+pub struct Parse {
+    green: GreenNode,
+    errors: Vec<SyntaxError>,
+}
+
+impl Parse {
+    /// The root [`SyntaxKind::Program`] node.
+    pub fn syntax(&self) -> SyntaxNode {
+        SyntaxNode::new_root(self.green.clone())
+    }
+
+    pub fn program(&self) -> nodes::Program {
+        nodes::Program::new(self.syntax())
+    }
+
+    pub fn errors(&self) -> &[SyntaxError] {
+        &self.errors
+    }
+
+    /// Converts the tree into the abstract syntax the rest of the compiler uses, tagging
+    /// its spans with `file`. Fails with the syntax errors if there were any.
+    pub fn to_ast(&self, file: FileId) -> Result<ast::Program, Vec<SyntaxError>> {
+        if !self.errors.is_empty() {
+            return Err(self.errors.clone());
+        }
+        Ok(self
+            .program()
+            .to_ast(file)
+            .expect("a tree without syntax errors has all its parts"))
+    }
+}
+
+/// Builds the concrete syntax tree for `src`. This never fails: text the grammar can't
+/// make sense of ends up in [`SyntaxKind::Error`] nodes, and the problems in
+/// [`Parse::errors`].
+pub fn parse(src: &str) -> Parse {
+    let mut tokens = Vec::new();
+    let mut errors = Vec::new();
+    let mut end = 0;
+    for (token, span) in Token::lexer(src).spanned() {
+        // Whatever the lexer skipped between two tokens is whitespace.
+        if span.start > end {
+            tokens.push((SyntaxKind::Whitespace, end..span.start));
+        }
+        let kind = match &token {
+            Ok(token) => SyntaxKind::from(token),
+            Err(()) => {
+                errors.push(SyntaxError {
+                    message: format!("unexpected character(s) '{}'", &src[span.clone()]),
+                    span: span.clone(),
+                });
+                SyntaxKind::LexError
+            }
+        };
+        end = span.end;
+        tokens.push((kind, span));
+    }
+    if src.len() > end {
+        tokens.push((SyntaxKind::Whitespace, end..src.len()));
+    }
+
+    let (green, parse_errors) = grammar::Parser::new(src, tokens).parse();
+    errors.extend(parse_errors);
+    errors.sort_by_key(|e| e.span.start);
+    Parse { green, errors }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn samples() -> Vec<(String, String)> {
+        let mut samples: Vec<_> = fs::read_dir("../samples")
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "cl"))
+            .map(|path| {
+                let src = fs::read_to_string(&path).unwrap();
+                (path.display().to_string(), src)
+            })
+            .collect();
+        samples.sort();
+        samples
+    }
+
+    #[test]
+    fn test_tree_shape() {
+        let parse = parse("class A { x : Int <- 1 + 2 * 3; };");
+        assert!(parse.errors().is_empty());
+        let tree = format!("{:#?}", parse.syntax());
+        let shape: Vec<&str> = tree
+            .lines()
+            .filter(|line| line.trim_start().starts_with(|c: char| c.is_uppercase()))
+            .map(|line| line.split('@').next().unwrap().trim_end())
+            .collect();
+        assert_eq!(
+            shape,
+            [
+                "Program",
+                "  Class",
+                "    ClassKw",
+                "    Whitespace",
+                "    TypeIdentifier",
+                "    Whitespace",
+                "    LeftBrace",
+                "    Whitespace",
+                "    Attribute",
+                "      ObjectIdentifier",
+                "      Whitespace",
+                "      Colon",
+                "      Whitespace",
+                "      TypeIdentifier",
+                "      Whitespace",
+                "      Assign",
+                "      Whitespace",
+                "      BinaryExpr",
+                "        Literal",
+                "          Integer",
+                "        Whitespace",
+                "        Plus",
+                "        Whitespace",
+                "        BinaryExpr",
+                "          Literal",
+                "            Integer",
+                "          Whitespace",
+                "          Multiply",
+                "          Whitespace",
+                "          Literal",
+                "            Integer",
+                "      Semicolon",
+                "    Whitespace",
+                "    RightBrace",
+                "    Semicolon",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_keeps_comments_and_whitespace() {
+        let src = "-- leading\n(* a (* nested *) comment *)\nclass A {\r\n\tf() : Int { 1 (* one *) };\n}; \t";
+        let parse = parse(src);
+        assert!(parse.errors().is_empty());
+        assert_eq!(parse.syntax().to_string(), src);
+        let comments: Vec<String> = parse
+            .syntax()
+            .descendants_with_tokens()
+            .filter(|element| element.kind() == SyntaxKind::Comment)
+            .map(|element| element.to_string())
+            .collect();
+        assert_eq!(
+            comments,
+            ["-- leading", "(* a (* nested *) comment *)", "(* one *)"]
+        );
+    }
+
+    #[test]
+    fn test_samples_match_the_parser() {
+        for (path, src) in samples() {
+            let parse = parse(&src);
+            assert_eq!(parse.syntax().to_string(), src, "{path}");
+            assert!(parse.errors().is_empty(), "{path}: {:?}", parse.errors());
+            let from_tree = parse.to_ast(FileId(0)).unwrap();
+            let parsed = crate::parse(&src).unwrap();
+            assert_eq!(format!("{from_tree:?}"), format!("{parsed:?}"), "{path}");
+        }
+
+        let src = "class A inherits IO {\n  x : Int <- 1;\n  f(a : Int, b : A) : SELF_TYPE { {\n    x <- a + ~b.g();\n    self@IO.out_int(x);\n  } };\n};\n\nclass B { };";
+        let from_tree = parse(src).to_ast(FileId(3)).unwrap();
+        let parsed = crate::parse_file(src, FileId(3)).unwrap();
+        assert_eq!(format!("{from_tree:?}"), format!("{parsed:?}"));
+    }
+
+    #[test]
+    fn test_errors_keep_every_byte() {
+        for (path, src) in samples() {
+            // Cutting a program off anywhere leaves it broken somewhere.
+            for end in (0..src.len()).step_by(17) {
+                let prefix = &src[..end];
+                let parse = parse(prefix);
+                assert_eq!(parse.syntax().to_string(), prefix, "{path} up to {end}");
+            }
+        }
+        for src in [
+            "class",
+            "class A { f( : Int { }; };",
+            "x : Int; class A {};",
+            "class A { x : Int <- ; y : Int <- (1 + ; };",
+            "class A { f() : Int { let in 1 }; # };",
+            "class A { f() : Int { case x of esac }; };",
+            "} ) ; class B inherits { };",
+        ] {
+            let parse = parse(src);
+            assert_eq!(parse.syntax().to_string(), src);
+            assert!(!parse.errors().is_empty(), "{src}");
+        }
+    }
+
+    #[test]
+    fn test_error_messages() {
+        let parse = parse("class A {\n  x : <- 1;\n  f() : Int { # };\n};");
+        let errors: Vec<(&str, Range<usize>)> = parse
+            .errors()
+            .iter()
+            .map(|e| (e.message.as_str(), e.span.clone()))
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                ("expected a type, found '<-'", 16..18),
+                ("unexpected character(s) '#'", 36..37),
+                ("expected an expression, found '}'", 38..39),
+            ]
+        );
+        assert_eq!(parse.to_ast(FileId(0)).unwrap_err().len(), 3);
+    }
+}
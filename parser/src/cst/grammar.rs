@@ -0,0 +1,448 @@
+//! The recursive-descent parser that builds the tree. It follows the grammar of
+//! [`crate::parser`], precedence included, but never gives up: a missing token is
+//! reported and skipped, and a token that can't start what's expected is wrapped in an
+//! [`SyntaxKind::Error`] node.
+
+use std::ops::Range;
+
+use rowan::{Checkpoint, GreenNode, GreenNodeBuilder};
+
+use super::{SyntaxError, SyntaxKind as K};
+
+/// Binary operators from the loosest binding to the tightest. All are left-associative.
+const PRECEDENCE: [&[K]; 3] = [
+    &[K::LessThan, K::LessThanOrEqual, K::Equal],
+    &[K::Plus, K::Minus],
+    &[K::Multiply, K::Divide],
+];
+
+/// Tokens that end an expression in some enclosing construct. An expression that's
+/// missing right before one of these is reported without consuming it, so the construct
+/// can still find its end.
+const EXPR_FOLLOW: &[K] = &[
+    K::Semicolon,
+    K::Dot,
+    K::Comma,
+    K::RightParen,
+    K::RightBrace,
+    K::ThenKw,
+    K::ElseKw,
+    K::FiKw,
+    K::LoopKw,
+    K::PoolKw,
+    K::InKw,
+    K::OfKw,
+    K::EsacKw,
+    K::DoubleArrow,
+    K::ClassKw,
+];
+
+const TYPES: &[K] = &[K::TypeIdentifier, K::SelfType];
+
+pub(super) struct Parser<'src> {
+    src: &'src str,
+    tokens: Vec<(K, Range<usize>)>,
+    pos: usize,
+    builder: GreenNodeBuilder<'static>,
+    errors: Vec<SyntaxError>,
+}
+
+impl<'src> Parser<'src> {
+    pub(super) fn new(src: &'src str, tokens: Vec<(K, Range<usize>)>) -> Self {
+        Parser {
+            src,
+            tokens,
+            pos: 0,
+            builder: GreenNodeBuilder::new(),
+            errors: Vec::new(),
+        }
+    }
+
+    pub(super) fn parse(mut self) -> (GreenNode, Vec<SyntaxError>) {
+        self.builder.start_node(K::Program.into());
+        while let Some(kind) = self.current() {
+            if kind == K::ClassKw {
+                self.class();
+            } else {
+                self.error_expected("a class");
+                self.skip_until(&[K::ClassKw]);
+            }
+        }
+        self.eat_trivia();
+        self.builder.finish_node();
+        (self.builder.finish(), self.errors)
+    }
+
+    // Looking at and consuming tokens. Trivia is invisible to the grammar, and is put
+    // into the tree just before the next token or node that follows it.
+
+    fn nth_index(&self, n: usize) -> Option<usize> {
+        (self.pos..self.tokens.len())
+            .filter(|&i| !self.tokens[i].0.is_trivia())
+            .nth(n)
+    }
+
+    fn nth(&self, n: usize) -> Option<K> {
+        self.nth_index(n).map(|i| self.tokens[i].0)
+    }
+
+    fn current(&self) -> Option<K> {
+        self.nth(0)
+    }
+
+    fn at(&self, kind: K) -> bool {
+        self.current() == Some(kind)
+    }
+
+    fn at_any(&self, kinds: &[K]) -> bool {
+        self.current().is_some_and(|kind| kinds.contains(&kind))
+    }
+
+    fn eat_trivia(&mut self) {
+        while let Some((kind, span)) = self.tokens.get(self.pos) {
+            if !kind.is_trivia() {
+                break;
+            }
+            self.builder.token((*kind).into(), &self.src[span.clone()]);
+            self.pos += 1;
+        }
+    }
+
+    fn bump(&mut self) {
+        self.eat_trivia();
+        let (kind, span) = &self.tokens[self.pos];
+        self.builder.token((*kind).into(), &self.src[span.clone()]);
+        self.pos += 1;
+    }
+
+    fn eat(&mut self, kind: K) -> bool {
+        let found = self.at(kind);
+        if found {
+            self.bump();
+        }
+        found
+    }
+
+    fn expect(&mut self, kind: K) {
+        if !self.eat(kind) {
+            self.error_expected(kind.describe());
+        }
+    }
+
+    fn expect_type(&mut self) {
+        if self.at_any(TYPES) {
+            self.bump();
+        } else {
+            self.error_expected("a type");
+        }
+    }
+
+    fn start(&mut self, kind: K) {
+        self.eat_trivia();
+        self.builder.start_node(kind.into());
+    }
+
+    fn checkpoint(&mut self) -> Checkpoint {
+        self.eat_trivia();
+        self.builder.checkpoint()
+    }
+
+    fn start_at(&mut self, checkpoint: Checkpoint, kind: K) {
+        self.builder.start_node_at(checkpoint, kind.into());
+    }
+
+    fn finish(&mut self) {
+        self.builder.finish_node();
+    }
+
+    fn error_expected(&mut self, expected: &str) {
+        let (found, span) = match self.nth_index(0) {
+            Some(i) => (self.tokens[i].0.describe(), self.tokens[i].1.clone()),
+            None => ("end of input", self.src.len()..self.src.len()),
+        };
+        self.errors.push(SyntaxError {
+            message: format!("expected {expected}, found {found}"),
+            span,
+        });
+    }
+
+    /// Wraps tokens in an error node up to the next one of `stop`, or to the end. Always
+    /// takes at least one token so the caller makes progress.
+    fn skip_until(&mut self, stop: &[K]) {
+        self.start(K::Error);
+        self.bump();
+        while self.current().is_some() && !self.at_any(stop) {
+            self.bump();
+        }
+        self.finish();
+    }
+
+    // Classes and features.
+
+    fn class(&mut self) {
+        self.start(K::Class);
+        self.bump();
+        self.expect_type();
+        if self.eat(K::InheritsKw) {
+            self.expect_type();
+        }
+        self.expect(K::LeftBrace);
+        while self.current().is_some() && !self.at_any(&[K::RightBrace, K::ClassKw]) {
+            self.feature();
+        }
+        self.expect(K::RightBrace);
+        self.expect(K::Semicolon);
+        self.finish();
+    }
+
+    fn feature(&mut self) {
+        if !self.at(K::ObjectIdentifier) {
+            self.error_expected("a method or attribute");
+            self.skip_until(&[K::Semicolon, K::RightBrace, K::ClassKw]);
+            self.eat(K::Semicolon);
+            return;
+        }
+        if self.nth(1) == Some(K::LeftParen) {
+            self.start(K::Method);
+            self.bump();
+            self.bump();
+            if !self.at(K::RightParen) {
+                loop {
+                    self.formal();
+                    if !self.eat(K::Comma) || self.at(K::RightParen) {
+                        break;
+                    }
+                }
+            }
+            self.expect(K::RightParen);
+            self.expect(K::Colon);
+            self.expect_type();
+            self.expect(K::LeftBrace);
+            self.expr();
+            self.expect(K::RightBrace);
+        } else {
+            self.start(K::Attribute);
+            self.bump();
+            self.expect(K::Colon);
+            self.expect_type();
+            if self.eat(K::Assign) {
+                self.expr();
+            }
+        }
+        self.expect(K::Semicolon);
+        self.finish();
+    }
+
+    fn formal(&mut self) {
+        self.start(K::Formal);
+        self.expect(K::ObjectIdentifier);
+        self.expect(K::Colon);
+        self.expect_type();
+        self.finish();
+    }
+
+    // Expressions.
+
+    fn expr(&mut self) {
+        self.binary(0);
+    }
+
+    fn binary(&mut self, level: usize) {
+        let Some(operators) = PRECEDENCE.get(level) else {
+            return self.unary();
+        };
+        let checkpoint = self.checkpoint();
+        self.binary(level + 1);
+        while self.at_any(operators) {
+            self.start_at(checkpoint, K::BinaryExpr);
+            self.bump();
+            self.binary(level + 1);
+            self.finish();
+        }
+    }
+
+    fn unary(&mut self) {
+        if self.at_any(&[K::NotKw, K::Tilde]) {
+            self.start(K::PrefixExpr);
+            self.bump();
+            self.unary();
+            self.finish();
+        } else {
+            self.dispatches();
+        }
+    }
+
+    /// An atom followed by any number of `.f(...)` and `@T.f(...)`.
+    fn dispatches(&mut self) {
+        let checkpoint = self.checkpoint();
+        self.atom();
+        while self.at_any(&[K::Dot, K::At]) {
+            self.start_at(checkpoint, K::DispatchExpr);
+            if self.eat(K::At) {
+                self.expect_type();
+                self.expect(K::Dot);
+            } else {
+                self.bump();
+            }
+            self.expect(K::ObjectIdentifier);
+            self.args();
+            self.finish();
+        }
+    }
+
+    fn atom(&mut self) {
+        let Some(kind) = self.current() else {
+            return self.error_expected("an expression");
+        };
+        match kind {
+            K::Integer | K::String | K::TrueKw | K::FalseKw => self.single(K::Literal),
+            K::SelfLit => self.single(K::NameExpr),
+            K::ObjectIdentifier => match self.nth(1) {
+                Some(K::Assign) => {
+                    self.start(K::AssignExpr);
+                    self.bump();
+                    self.bump();
+                    self.expr();
+                    self.finish();
+                }
+                Some(K::LeftParen) => {
+                    self.start(K::CallExpr);
+                    self.bump();
+                    self.args();
+                    self.finish();
+                }
+                _ => self.single(K::NameExpr),
+            },
+            K::IsvoidKw => {
+                self.start(K::IsvoidExpr);
+                self.bump();
+                self.expr();
+                self.finish();
+            }
+            K::NewKw => {
+                self.start(K::NewExpr);
+                self.bump();
+                self.expect_type();
+                self.finish();
+            }
+            K::LeftParen => {
+                self.start(K::ParenExpr);
+                self.bump();
+                self.expr();
+                self.expect(K::RightParen);
+                self.finish();
+            }
+            K::LeftBrace => self.block(),
+            K::LetKw => self.let_expr(),
+            K::IfKw => {
+                self.start(K::IfExpr);
+                self.bump();
+                self.expr();
+                self.expect(K::ThenKw);
+                self.expr();
+                self.expect(K::ElseKw);
+                self.expr();
+                self.expect(K::FiKw);
+                self.finish();
+            }
+            K::WhileKw => {
+                self.start(K::WhileExpr);
+                self.bump();
+                self.expr();
+                self.expect(K::LoopKw);
+                self.expr();
+                self.expect(K::PoolKw);
+                self.finish();
+            }
+            K::CaseKw => self.case_expr(),
+            _ => {
+                self.error_expected("an expression");
+                if !EXPR_FOLLOW.contains(&kind) {
+                    self.single(K::Error);
+                }
+            }
+        }
+    }
+
+    /// A node holding just the next token.
+    fn single(&mut self, kind: K) {
+        self.start(kind);
+        self.bump();
+        self.finish();
+    }
+
+    fn args(&mut self) {
+        self.start(K::ArgList);
+        if self.eat(K::LeftParen) {
+            if !self.at(K::RightParen) {
+                loop {
+                    self.expr();
+                    if !self.eat(K::Comma) || self.at(K::RightParen) {
+                        break;
+                    }
+                }
+            }
+            self.expect(K::RightParen);
+        } else {
+            self.error_expected(K::LeftParen.describe());
+        }
+        self.finish();
+    }
+
+    /// `{ e1; e2; ... }`. Expressions may also be separated by `.`, and the last
+    /// separator is optional.
+    fn block(&mut self) {
+        self.start(K::BlockExpr);
+        self.bump();
+        while self.current().is_some() && !self.at(K::RightBrace) {
+            self.expr();
+            if !self.eat(K::Semicolon) && !self.eat(K::Dot) {
+                break;
+            }
+        }
+        self.expect(K::RightBrace);
+        self.finish();
+    }
+
+    fn let_expr(&mut self) {
+        self.start(K::LetExpr);
+        self.bump();
+        loop {
+            self.start(K::LetBinding);
+            self.expect(K::ObjectIdentifier);
+            self.expect(K::Colon);
+            self.expect_type();
+            if self.eat(K::Assign) {
+                self.expr();
+            }
+            self.finish();
+            if !self.eat(K::Comma) {
+                break;
+            }
+        }
+        self.expect(K::InKw);
+        self.expr();
+        self.finish();
+    }
+
+    fn case_expr(&mut self) {
+        self.start(K::CaseExpr);
+        self.bump();
+        self.expr();
+        self.expect(K::OfKw);
+        loop {
+            self.start(K::CaseBranch);
+            self.expect(K::ObjectIdentifier);
+            self.expect(K::Colon);
+            self.expect_type();
+            self.expect(K::DoubleArrow);
+            self.expr();
+            self.finish();
+            if !self.eat(K::Semicolon) || !self.at(K::ObjectIdentifier) {
+                break;
+            }
+        }
+        self.expect(K::EsacKw);
+        self.finish();
+    }
+}
@@ -0,0 +1,643 @@
+//! Typed views of [`SyntaxNode`]s, and their conversion to [`ast`].
+//!
+//! Each view wraps a node of the matching [`SyntaxKind`] and finds its parts among the
+//! node's children. Parts are optional because a tree with syntax errors can be missing
+//! any of them.
+
+use chumsky::span::Span as _;
+use rowan::ast::{AstChildren, AstNode, support};
+
+use lexer::lexer::string_value;
+use lexer::{FileId, IntSymbol, StrSymbol, Symbol};
+
+use super::{CoolLanguage, SyntaxKind, SyntaxNode, SyntaxToken};
+use crate::ast::{self, Span};
+
+macro_rules! ast_node {
+    ($(#[$doc:meta])* $name:ident) => {
+        $(#[$doc])*
+        #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+        pub struct $name(SyntaxNode);
+
+        impl AstNode for $name {
+            type Language = CoolLanguage;
+
+            fn can_cast(kind: SyntaxKind) -> bool {
+                kind == SyntaxKind::$name
+            }
+
+            fn cast(node: SyntaxNode) -> Option<Self> {
+                Self::can_cast(node.kind()).then(|| $name(node))
+            }
+
+            fn syntax(&self) -> &SyntaxNode {
+                &self.0
+            }
+        }
+    };
+}
+
+ast_node!(Program);
+ast_node!(Class);
+ast_node!(Method);
+ast_node!(Attribute);
+ast_node!(Formal);
+ast_node!(ArgList);
+ast_node!(LetBinding);
+ast_node!(CaseBranch);
+ast_node!(AssignExpr);
+ast_node!(DispatchExpr);
+ast_node!(CallExpr);
+ast_node!(IfExpr);
+ast_node!(WhileExpr);
+ast_node!(BlockExpr);
+ast_node!(LetExpr);
+ast_node!(CaseExpr);
+ast_node!(NewExpr);
+ast_node!(IsvoidExpr);
+ast_node!(BinaryExpr);
+ast_node!(PrefixExpr);
+ast_node!(ParenExpr);
+ast_node!(NameExpr);
+ast_node!(Literal);
+
+const TYPES: &[SyntaxKind] = &[SyntaxKind::TypeIdentifier, SyntaxKind::SelfType];
+
+/// The first token among `node`'s children that's one of `kinds`.
+fn token_of(node: &SyntaxNode, kinds: &[SyntaxKind]) -> Option<SyntaxToken> {
+    node.children_with_tokens()
+        .filter_map(|element| element.into_token())
+        .find(|token| kinds.contains(&token.kind()))
+}
+
+/// The first token among `node`'s children that's one of `kinds` and comes after a
+/// `marker` token.
+fn token_after(node: &SyntaxNode, marker: SyntaxKind, kinds: &[SyntaxKind]) -> Option<SyntaxToken> {
+    node.children_with_tokens()
+        .filter_map(|element| element.into_token())
+        .skip_while(|token| token.kind() != marker)
+        .find(|token| kinds.contains(&token.kind()))
+}
+
+fn nth_expr(node: &SyntaxNode, n: usize) -> Option<Expr> {
+    node.children().filter_map(Expr::cast).nth(n)
+}
+
+fn text(token: Option<SyntaxToken>) -> Option<Symbol> {
+    token.map(|token| Symbol::intern(token.text()))
+}
+
+/// The span of `node` up to its last token other than a closing `;`, the way the parser
+/// reports classes, features and expressions.
+fn span(node: &SyntaxNode, file: FileId, include_semicolon: bool) -> Span {
+    let end = node
+        .children_with_tokens()
+        .filter(|element| !element.kind().is_trivia())
+        .filter(|element| include_semicolon || element.kind() != SyntaxKind::Semicolon)
+        .last()
+        .map_or(node.text_range().end(), |element| {
+            element.text_range().end()
+        });
+    Span::new(file, node.text_range().start().into()..end.into())
+}
+
+impl Program {
+    pub(super) fn new(node: SyntaxNode) -> Self {
+        Program(node)
+    }
+
+    pub fn classes(&self) -> AstChildren<Class> {
+        support::children(&self.0)
+    }
+
+    /// Converts the program to its abstract syntax, or `None` if it's missing a part,
+    /// which only a program with syntax errors can be.
+    pub fn to_ast(&self, file: FileId) -> Option<ast::Program> {
+        let classes = self
+            .classes()
+            .map(|class| class.to_ast(file))
+            .collect::<Option<_>>()?;
+        Some(ast::Program { classes })
+    }
+}
+
+impl Class {
+    pub fn name(&self) -> Option<SyntaxToken> {
+        token_of(&self.0, TYPES)
+    }
+
+    /// The type after `inherits`.
+    pub fn parent(&self) -> Option<SyntaxToken> {
+        token_after(&self.0, SyntaxKind::InheritsKw, TYPES)
+    }
+
+    pub fn features(&self) -> AstChildren<Feature> {
+        support::children(&self.0)
+    }
+
+    fn to_ast(&self, file: FileId) -> Option<ast::Class> {
+        Some(ast::Class {
+            name: text(self.name())?,
+            parent: text(self.parent()),
+            features: self
+                .features()
+                .map(|feature| feature.to_ast(file))
+                .collect::<Option<_>>()?,
+            span: span(&self.0, file, true),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Feature {
+    Method(Method),
+    Attribute(Attribute),
+}
+
+impl AstNode for Feature {
+    type Language = CoolLanguage;
+
+    fn can_cast(kind: SyntaxKind) -> bool {
+        Method::can_cast(kind) || Attribute::can_cast(kind)
+    }
+
+    fn cast(node: SyntaxNode) -> Option<Self> {
+        match node.kind() {
+            SyntaxKind::Method => Some(Feature::Method(Method(node))),
+            SyntaxKind::Attribute => Some(Feature::Attribute(Attribute(node))),
+            _ => None,
+        }
+    }
+
+    fn syntax(&self) -> &SyntaxNode {
+        match self {
+            Feature::Method(method) => method.syntax(),
+            Feature::Attribute(attribute) => attribute.syntax(),
+        }
+    }
+}
+
+impl Feature {
+    fn to_ast(&self, file: FileId) -> Option<ast::Feature> {
+        Some(match self {
+            Feature::Method(method) => ast::Feature::Method(ast::MethodFeature {
+                name: text(method.name())?,
+                formals: method
+                    .formals()
+                    .map(|formal| {
+                        Some(ast::Formal {
+                            name: text(formal.name())?,
+                            typ: text(formal.type_name())?,
+                            span: span(&formal.0, file, false),
+                        })
+                    })
+                    .collect::<Option<_>>()?,
+                return_type: text(method.return_type())?,
+                body: method.body()?.to_ast(file)?,
+                span: span(&method.0, file, false),
+            }),
+            Feature::Attribute(attribute) => ast::Feature::Attribute(ast::AttributeFeature {
+                name: text(attribute.name())?,
+                attr_type: text(attribute.type_name())?,
+                init: match attribute.init() {
+                    Some(init) => Some(init.to_ast(file)?),
+                    None => None,
+                },
+                span: span(&attribute.0, file, false),
+            }),
+        })
+    }
+}
+
+impl Method {
+    pub fn name(&self) -> Option<SyntaxToken> {
+        token_of(&self.0, &[SyntaxKind::ObjectIdentifier])
+    }
+
+    pub fn formals(&self) -> AstChildren<Formal> {
+        support::children(&self.0)
+    }
+
+    pub fn return_type(&self) -> Option<SyntaxToken> {
+        token_of(&self.0, TYPES)
+    }
+
+    pub fn body(&self) -> Option<Expr> {
+        support::child(&self.0)
+    }
+}
+
+impl Attribute {
+    pub fn name(&self) -> Option<SyntaxToken> {
+        token_of(&self.0, &[SyntaxKind::ObjectIdentifier])
+    }
+
+    pub fn type_name(&self) -> Option<SyntaxToken> {
+        token_of(&self.0, TYPES)
+    }
+
+    pub fn init(&self) -> Option<Expr> {
+        support::child(&self.0)
+    }
+}
+
+impl Formal {
+    pub fn name(&self) -> Option<SyntaxToken> {
+        token_of(&self.0, &[SyntaxKind::ObjectIdentifier])
+    }
+
+    pub fn type_name(&self) -> Option<SyntaxToken> {
+        token_of(&self.0, TYPES)
+    }
+}
+
+impl ArgList {
+    pub fn args(&self) -> AstChildren<Expr> {
+        support::children(&self.0)
+    }
+
+    fn to_ast(&self, file: FileId) -> Option<Vec<ast::Expr>> {
+        self.args().map(|arg| arg.to_ast(file)).collect()
+    }
+}
+
+impl LetBinding {
+    pub fn name(&self) -> Option<SyntaxToken> {
+        token_of(&self.0, &[SyntaxKind::ObjectIdentifier])
+    }
+
+    pub fn type_name(&self) -> Option<SyntaxToken> {
+        token_of(&self.0, TYPES)
+    }
+
+    pub fn init(&self) -> Option<Expr> {
+        support::child(&self.0)
+    }
+}
+
+impl CaseBranch {
+    pub fn name(&self) -> Option<SyntaxToken> {
+        token_of(&self.0, &[SyntaxKind::ObjectIdentifier])
+    }
+
+    pub fn type_name(&self) -> Option<SyntaxToken> {
+        token_of(&self.0, TYPES)
+    }
+
+    pub fn body(&self) -> Option<Expr> {
+        support::child(&self.0)
+    }
+}
+
+impl AssignExpr {
+    pub fn name(&self) -> Option<SyntaxToken> {
+        token_of(&self.0, &[SyntaxKind::ObjectIdentifier])
+    }
+
+    pub fn value(&self) -> Option<Expr> {
+        support::child(&self.0)
+    }
+}
+
+impl DispatchExpr {
+    pub fn receiver(&self) -> Option<Expr> {
+        support::child(&self.0)
+    }
+
+    /// The type after `@` in a static dispatch.
+    pub fn static_type(&self) -> Option<SyntaxToken> {
+        token_of(&self.0, TYPES)
+    }
+
+    pub fn method(&self) -> Option<SyntaxToken> {
+        token_of(&self.0, &[SyntaxKind::ObjectIdentifier])
+    }
+
+    pub fn args(&self) -> Option<ArgList> {
+        support::child(&self.0)
+    }
+}
+
+impl CallExpr {
+    pub fn method(&self) -> Option<SyntaxToken> {
+        token_of(&self.0, &[SyntaxKind::ObjectIdentifier])
+    }
+
+    pub fn args(&self) -> Option<ArgList> {
+        support::child(&self.0)
+    }
+}
+
+impl IfExpr {
+    pub fn condition(&self) -> Option<Expr> {
+        nth_expr(&self.0, 0)
+    }
+
+    pub fn then_branch(&self) -> Option<Expr> {
+        nth_expr(&self.0, 1)
+    }
+
+    pub fn else_branch(&self) -> Option<Expr> {
+        nth_expr(&self.0, 2)
+    }
+}
+
+impl WhileExpr {
+    pub fn condition(&self) -> Option<Expr> {
+        nth_expr(&self.0, 0)
+    }
+
+    pub fn body(&self) -> Option<Expr> {
+        nth_expr(&self.0, 1)
+    }
+}
+
+impl BlockExpr {
+    pub fn exprs(&self) -> AstChildren<Expr> {
+        support::children(&self.0)
+    }
+}
+
+impl LetExpr {
+    pub fn bindings(&self) -> AstChildren<LetBinding> {
+        support::children(&self.0)
+    }
+
+    pub fn body(&self) -> Option<Expr> {
+        support::child(&self.0)
+    }
+}
+
+impl CaseExpr {
+    /// The expression whose type picks the branch.
+    pub fn scrutinee(&self) -> Option<Expr> {
+        support::child(&self.0)
+    }
+
+    pub fn branches(&self) -> AstChildren<CaseBranch> {
+        support::children(&self.0)
+    }
+}
+
+impl NewExpr {
+    pub fn type_name(&self) -> Option<SyntaxToken> {
+        token_of(&self.0, TYPES)
+    }
+}
+
+impl IsvoidExpr {
+    pub fn operand(&self) -> Option<Expr> {
+        support::child(&self.0)
+    }
+}
+
+impl BinaryExpr {
+    pub fn lhs(&self) -> Option<Expr> {
+        nth_expr(&self.0, 0)
+    }
+
+    pub fn operator(&self) -> Option<SyntaxToken> {
+        token_of(
+            &self.0,
+            &[
+                SyntaxKind::Plus,
+                SyntaxKind::Minus,
+                SyntaxKind::Multiply,
+                SyntaxKind::Divide,
+                SyntaxKind::LessThan,
+                SyntaxKind::LessThanOrEqual,
+                SyntaxKind::Equal,
+            ],
+        )
+    }
+
+    pub fn rhs(&self) -> Option<Expr> {
+        nth_expr(&self.0, 1)
+    }
+}
+
+impl PrefixExpr {
+    pub fn operator(&self) -> Option<SyntaxToken> {
+        token_of(&self.0, &[SyntaxKind::NotKw, SyntaxKind::Tilde])
+    }
+
+    pub fn operand(&self) -> Option<Expr> {
+        support::child(&self.0)
+    }
+}
+
+impl ParenExpr {
+    pub fn inner(&self) -> Option<Expr> {
+        support::child(&self.0)
+    }
+}
+
+impl NameExpr {
+    /// The identifier, or `self`.
+    pub fn name(&self) -> Option<SyntaxToken> {
+        token_of(
+            &self.0,
+            &[SyntaxKind::ObjectIdentifier, SyntaxKind::SelfLit],
+        )
+    }
+}
+
+impl Literal {
+    pub fn token(&self) -> Option<SyntaxToken> {
+        self.0.first_token()
+    }
+}
+
+/// Any expression node.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Expr {
+    Assign(AssignExpr),
+    Dispatch(DispatchExpr),
+    Call(CallExpr),
+    If(IfExpr),
+    While(WhileExpr),
+    Block(BlockExpr),
+    Let(LetExpr),
+    Case(CaseExpr),
+    New(NewExpr),
+    Isvoid(IsvoidExpr),
+    Binary(BinaryExpr),
+    Prefix(PrefixExpr),
+    Paren(ParenExpr),
+    Name(NameExpr),
+    Literal(Literal),
+}
+
+impl AstNode for Expr {
+    type Language = CoolLanguage;
+
+    fn can_cast(kind: SyntaxKind) -> bool {
+        matches!(
+            kind,
+            SyntaxKind::AssignExpr
+                | SyntaxKind::DispatchExpr
+                | SyntaxKind::CallExpr
+                | SyntaxKind::IfExpr
+                | SyntaxKind::WhileExpr
+                | SyntaxKind::BlockExpr
+                | SyntaxKind::LetExpr
+                | SyntaxKind::CaseExpr
+                | SyntaxKind::NewExpr
+                | SyntaxKind::IsvoidExpr
+                | SyntaxKind::BinaryExpr
+                | SyntaxKind::PrefixExpr
+                | SyntaxKind::ParenExpr
+                | SyntaxKind::NameExpr
+                | SyntaxKind::Literal
+        )
+    }
+
+    fn cast(node: SyntaxNode) -> Option<Self> {
+        Some(match node.kind() {
+            SyntaxKind::AssignExpr => Expr::Assign(AssignExpr(node)),
+            SyntaxKind::DispatchExpr => Expr::Dispatch(DispatchExpr(node)),
+            SyntaxKind::CallExpr => Expr::Call(CallExpr(node)),
+            SyntaxKind::IfExpr => Expr::If(IfExpr(node)),
+            SyntaxKind::WhileExpr => Expr::While(WhileExpr(node)),
+            SyntaxKind::BlockExpr => Expr::Block(BlockExpr(node)),
+            SyntaxKind::LetExpr => Expr::Let(LetExpr(node)),
+            SyntaxKind::CaseExpr => Expr::Case(CaseExpr(node)),
+            SyntaxKind::NewExpr => Expr::New(NewExpr(node)),
+            SyntaxKind::IsvoidExpr => Expr::Isvoid(IsvoidExpr(node)),
+            SyntaxKind::BinaryExpr => Expr::Binary(BinaryExpr(node)),
+            SyntaxKind::PrefixExpr => Expr::Prefix(PrefixExpr(node)),
+            SyntaxKind::ParenExpr => Expr::Paren(ParenExpr(node)),
+            SyntaxKind::NameExpr => Expr::Name(NameExpr(node)),
+            SyntaxKind::Literal => Expr::Literal(Literal(node)),
+            _ => return None,
+        })
+    }
+
+    fn syntax(&self) -> &SyntaxNode {
+        match self {
+            Expr::Assign(e) => e.syntax(),
+            Expr::Dispatch(e) => e.syntax(),
+            Expr::Call(e) => e.syntax(),
+            Expr::If(e) => e.syntax(),
+            Expr::While(e) => e.syntax(),
+            Expr::Block(e) => e.syntax(),
+            Expr::Let(e) => e.syntax(),
+            Expr::Case(e) => e.syntax(),
+            Expr::New(e) => e.syntax(),
+            Expr::Isvoid(e) => e.syntax(),
+            Expr::Binary(e) => e.syntax(),
+            Expr::Prefix(e) => e.syntax(),
+            Expr::Paren(e) => e.syntax(),
+            Expr::Name(e) => e.syntax(),
+            Expr::Literal(e) => e.syntax(),
+        }
+    }
+}
+
+impl Expr {
+    /// Converts the expression to its abstract syntax, with spans in `file`. Like the
+    /// parser, this drops parentheses, which only group, but keeps them in the span of
+    /// the expression inside.
+    pub fn to_ast(&self, file: FileId) -> Option<ast::Expr> {
+        let boxed = |expr: Option<Expr>| expr?.to_ast(file).map(Box::new);
+        let kind = match self {
+            Expr::Assign(e) => ast::ExprKind::Assign {
+                name: text(e.name())?,
+                expr: boxed(e.value())?,
+            },
+            Expr::Dispatch(e) => ast::ExprKind::Dispatch {
+                expr: boxed(e.receiver())?,
+                static_type: text(e.static_type()),
+                method: text(e.method())?,
+                args: e.args()?.to_ast(file)?,
+            },
+            Expr::Call(e) => ast::ExprKind::FuncCall {
+                name: text(e.method())?,
+                args: e.args()?.to_ast(file)?,
+            },
+            Expr::If(e) => ast::ExprKind::If {
+                cond: boxed(e.condition())?,
+                then_branch: boxed(e.then_branch())?,
+                else_branch: boxed(e.else_branch())?,
+            },
+            Expr::While(e) => ast::ExprKind::While {
+                cond: boxed(e.condition())?,
+                body: boxed(e.body())?,
+            },
+            Expr::Block(e) => ast::ExprKind::Block(
+                e.exprs()
+                    .map(|expr| expr.to_ast(file))
+                    .collect::<Option<_>>()?,
+            ),
+            Expr::Let(e) => ast::ExprKind::Let {
+                bindings: e
+                    .bindings()
+                    .map(|binding| {
+                        Some(ast::LetBinding {
+                            name: text(binding.name())?,
+                            typ: text(binding.type_name())?,
+                            init: match binding.init() {
+                                Some(init) => Some(init.to_ast(file)?),
+                                None => None,
+                            },
+                            span: span(&binding.0, file, false),
+                        })
+                    })
+                    .collect::<Option<_>>()?,
+                body: boxed(e.body())?,
+            },
+            Expr::Case(e) => ast::ExprKind::Case {
+                expr: boxed(e.scrutinee())?,
+                branches: e
+                    .branches()
+                    .map(|branch| {
+                        Some(ast::CaseBranch {
+                            name: text(branch.name())?,
+                            typ: text(branch.type_name())?,
+                            expr: branch.body()?.to_ast(file)?,
+                            span: span(&branch.0, file, false),
+                        })
+                    })
+                    .collect::<Option<_>>()?,
+            },
+            Expr::New(e) => ast::ExprKind::New(text(e.type_name())?),
+            Expr::Isvoid(e) => ast::ExprKind::IsVoid(boxed(e.operand())?),
+            Expr::Binary(e) => {
+                let lhs = boxed(e.lhs())?;
+                let rhs = boxed(e.rhs())?;
+                match e.operator()?.kind() {
+                    SyntaxKind::Plus => ast::ExprKind::Plus(lhs, rhs),
+                    SyntaxKind::Minus => ast::ExprKind::Minus(lhs, rhs),
+                    SyntaxKind::Multiply => ast::ExprKind::Times(lhs, rhs),
+                    SyntaxKind::Divide => ast::ExprKind::Divide(lhs, rhs),
+                    SyntaxKind::LessThan => ast::ExprKind::Lt(lhs, rhs),
+                    SyntaxKind::LessThanOrEqual => ast::ExprKind::Le(lhs, rhs),
+                    _ => ast::ExprKind::Eq(lhs, rhs),
+                }
+            }
+            Expr::Prefix(e) => {
+                let operand = boxed(e.operand())?;
+                match e.operator()?.kind() {
+                    SyntaxKind::NotKw => ast::ExprKind::Not(operand),
+                    _ => ast::ExprKind::Negate(operand),
+                }
+            }
+            Expr::Paren(e) => e.inner()?.to_ast(file)?.kind,
+            Expr::Name(e) => ast::ExprKind::Id(text(e.name())?),
+            Expr::Literal(e) => {
+                let token = e.token()?;
+                match token.kind() {
+                    SyntaxKind::Integer => {
+                        ast::ExprKind::Integer(IntSymbol::intern(token.text().parse().ok()?))
+                    }
+                    SyntaxKind::String => {
+                        ast::ExprKind::String(StrSymbol::intern(&string_value(token.text())))
+                    }
+                    SyntaxKind::TrueKw => ast::ExprKind::True,
+                    _ => ast::ExprKind::False,
+                }
+            }
+        };
+        Some(ast::Expr::new(kind, span(self.syntax(), file, true)))
+    }
+}
@@ -0,0 +1,264 @@
+//! The JSON form of a parsed file, for tools that aren't written in Rust. The schema is
+//! described in the README; its version is shared with the lexer's token output.
+
+use chumsky::error::Rich;
+use serde::ser::{SerializeMap, SerializeStruct};
+use serde::{Serialize, Serializer};
+
+use lexer::Token;
+use lexer::json::{ByteSpan, SCHEMA_VERSION};
+use lexer::source::SourceFile;
+
+use crate::ast::{self, Expr, ExprKind, Span};
+
+/// The result of parsing one file: its program, or the errors that stopped it.
+#[derive(Debug, Serialize)]
+pub struct Parsed<'a> {
+    pub schema_version: u32,
+    pub file: &'a str,
+    /// `null` when there are errors.
+    pub program: Option<&'a ast::Program>,
+    pub errors: Vec<ErrorRecord>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ErrorRecord {
+    pub message: String,
+    pub span: ByteSpan,
+    /// 1-based, like `column`, which counts characters.
+    pub line: usize,
+    pub column: usize,
+}
+
+/// The JSON form of `result`, which came from parsing `file`.
+pub fn parsed<'a>(
+    file: &'a SourceFile,
+    result: &'a Result<ast::Program, Vec<Rich<'static, Token, Span>>>,
+) -> Parsed<'a> {
+    let errors = match result {
+        Ok(_) => Vec::new(),
+        Err(errors) => errors
+            .iter()
+            .map(|error| {
+                let span = error.span();
+                let location = file.location(span.start);
+                ErrorRecord {
+                    message: error.reason().to_string(),
+                    span: ByteSpan {
+                        start: span.start,
+                        end: span.end,
+                    },
+                    line: location.line,
+                    column: location.column,
+                }
+            })
+            .collect(),
+    };
+    Parsed {
+        schema_version: SCHEMA_VERSION,
+        file: file.name(),
+        program: result.as_ref().ok(),
+        errors,
+    }
+}
+
+/// Writes a span as `{"file": 0, "start": 10, "end": 20}`.
+pub(crate) fn span<S: Serializer>(span: &Span, serializer: S) -> Result<S::Ok, S::Error> {
+    let mut out = serializer.serialize_struct("Span", 3)?;
+    out.serialize_field("file", &span.context)?;
+    out.serialize_field("start", &span.start)?;
+    out.serialize_field("end", &span.end)?;
+    out.end()
+}
+
+/// Writes a span that's a map entry rather than a struct field.
+struct SpanEntry<'a>(&'a Span);
+
+impl Serialize for SpanEntry<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        span(self.0, serializer)
+    }
+}
+
+/// Every expression is an object whose `kind` is its variant's name in snake case, with a
+/// field per operand and its `span` last. Operators' operands are `lhs` and `rhs`, or
+/// `expr` for a unary one.
+impl Serialize for Expr {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut out = serializer.serialize_map(None)?;
+        match &self.kind {
+            ExprKind::Assign { name, expr } => {
+                out.serialize_entry("kind", "assign")?;
+                out.serialize_entry("name", name)?;
+                out.serialize_entry("expr", expr)?;
+            }
+            ExprKind::Dispatch {
+                expr,
+                static_type,
+                method,
+                args,
+            } => {
+                out.serialize_entry("kind", "dispatch")?;
+                out.serialize_entry("expr", expr)?;
+                out.serialize_entry("static_type", static_type)?;
+                out.serialize_entry("method", method)?;
+                out.serialize_entry("args", args)?;
+            }
+            ExprKind::FuncCall { name, args } => {
+                out.serialize_entry("kind", "func_call")?;
+                out.serialize_entry("name", name)?;
+                out.serialize_entry("args", args)?;
+            }
+            ExprKind::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => {
+                out.serialize_entry("kind", "if")?;
+                out.serialize_entry("cond", cond)?;
+                out.serialize_entry("then_branch", then_branch)?;
+                out.serialize_entry("else_branch", else_branch)?;
+            }
+            ExprKind::While { cond, body } => {
+                out.serialize_entry("kind", "while")?;
+                out.serialize_entry("cond", cond)?;
+                out.serialize_entry("body", body)?;
+            }
+            ExprKind::Block(exprs) => {
+                out.serialize_entry("kind", "block")?;
+                out.serialize_entry("exprs", exprs)?;
+            }
+            ExprKind::Let { bindings, body } => {
+                out.serialize_entry("kind", "let")?;
+                out.serialize_entry("bindings", bindings)?;
+                out.serialize_entry("body", body)?;
+            }
+            ExprKind::Case { expr, branches } => {
+                out.serialize_entry("kind", "case")?;
+                out.serialize_entry("expr", expr)?;
+                out.serialize_entry("branches", branches)?;
+            }
+            ExprKind::New(typ) => {
+                out.serialize_entry("kind", "new")?;
+                out.serialize_entry("type", typ)?;
+            }
+            ExprKind::IsVoid(expr) => unary(&mut out, "is_void", expr)?,
+            ExprKind::Plus(lhs, rhs) => binary(&mut out, "plus", lhs, rhs)?,
+            ExprKind::Minus(lhs, rhs) => binary(&mut out, "minus", lhs, rhs)?,
+            ExprKind::Times(lhs, rhs) => binary(&mut out, "times", lhs, rhs)?,
+            ExprKind::Divide(lhs, rhs) => binary(&mut out, "divide", lhs, rhs)?,
+            ExprKind::Lt(lhs, rhs) => binary(&mut out, "lt", lhs, rhs)?,
+            ExprKind::Le(lhs, rhs) => binary(&mut out, "le", lhs, rhs)?,
+            ExprKind::Eq(lhs, rhs) => binary(&mut out, "eq", lhs, rhs)?,
+            ExprKind::Not(expr) => unary(&mut out, "not", expr)?,
+            ExprKind::Negate(expr) => unary(&mut out, "negate", expr)?,
+            ExprKind::Paren(expr) => unary(&mut out, "paren", expr)?,
+            ExprKind::Id(name) => {
+                out.serialize_entry("kind", "id")?;
+                out.serialize_entry("name", name)?;
+            }
+            ExprKind::Integer(value) => {
+                out.serialize_entry("kind", "integer")?;
+                out.serialize_entry("value", value)?;
+            }
+            ExprKind::String(value) => {
+                out.serialize_entry("kind", "string")?;
+                out.serialize_entry("value", value)?;
+            }
+            ExprKind::True => out.serialize_entry("kind", "true")?,
+            ExprKind::False => out.serialize_entry("kind", "false")?,
+        }
+        out.serialize_entry("span", &SpanEntry(&self.span))?;
+        out.end()
+    }
+}
+
+fn unary<M: SerializeMap>(out: &mut M, kind: &str, expr: &Expr) -> Result<(), M::Error> {
+    out.serialize_entry("kind", kind)?;
+    out.serialize_entry("expr", expr)
+}
+
+fn binary<M: SerializeMap>(
+    out: &mut M,
+    kind: &str,
+    lhs: &Expr,
+    rhs: &Expr,
+) -> Result<(), M::Error> {
+    out.serialize_entry("kind", kind)?;
+    out.serialize_entry("lhs", lhs)?;
+    out.serialize_entry("rhs", rhs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_program_json() {
+        let file = SourceFile::new(
+            "a.cl",
+            "class A inherits IO {\n  x : Int <- ~1 + 2;\n  f(y : A) : Object { x <- self@IO.g() };\n};",
+        );
+        let result = crate::parse(file.src());
+        let json = serde_json::to_value(parsed(&file, &result)).unwrap();
+        assert_eq!(json["schema_version"], SCHEMA_VERSION);
+        assert_eq!(json["errors"], json!([]));
+        let class = &json["program"]["classes"][0];
+        assert_eq!(class["parent"], "IO");
+        assert_eq!(class["span"], json!({ "file": 0, "start": 0, "end": 87 }));
+        let span = |start: usize, end: usize| json!({ "file": 0, "start": start, "end": end });
+        assert_eq!(
+            class["features"][0],
+            json!({
+                "kind": "attribute",
+                "name": "x",
+                "type": "Int",
+                "init": {
+                    "kind": "plus",
+                    "lhs": {
+                        "kind": "negate",
+                        "expr": { "kind": "integer", "value": 1, "span": span(36, 37) },
+                        "span": span(35, 37),
+                    },
+                    "rhs": { "kind": "integer", "value": 2, "span": span(40, 41) },
+                    "span": span(35, 41),
+                },
+                "span": span(24, 41),
+            })
+        );
+        let method = &class["features"][1];
+        assert_eq!(
+            method["formals"],
+            json!([{ "name": "y", "type": "A", "span": span(47, 52) }])
+        );
+        assert_eq!(
+            method["body"],
+            json!({
+                "kind": "assign",
+                "name": "x",
+                "expr": {
+                    "kind": "dispatch",
+                    "expr": { "kind": "id", "name": "self", "span": span(70, 74) },
+                    "static_type": "IO",
+                    "method": "g",
+                    "args": [],
+                    "span": span(70, 81),
+                },
+                "span": span(65, 81),
+            })
+        );
+    }
+
+    #[test]
+    fn test_errors_json() {
+        let file = SourceFile::new("b.cl", "class A {\n  x : Int <- ;\n};");
+        let result = crate::parse(file.src());
+        let json = serde_json::to_value(parsed(&file, &result)).unwrap();
+        assert_eq!(json["program"], json!(null));
+        let error = &json["errors"][0];
+        assert_eq!(error["line"], 2);
+        assert_eq!(error["column"], 14);
+        assert!(error["message"].as_str().unwrap().contains("found ';'"));
+    }
+}
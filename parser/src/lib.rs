@@ -0,0 +1,469 @@
+use chumsky::{input::ValueInput, prelude::*};
+
+use lexer::{FileId, IntSymbol, Token, source::SourceMap, sym};
+use logos::Logos;
+
+pub mod arena;
+pub mod ast;
+pub mod core_ast;
+pub mod cst;
+pub mod json;
+pub mod nesting;
+pub mod reference;
+pub mod visit;
+
+use ast::Span;
+
+/// Lexes and parses `src` as the only file of a program, returning every parse error on
+/// failure.
+pub fn parse(src: &str) -> Result<ast::Program, Vec<Rich<'static, Token, Span>>> {
+    parse_file(src, FileId::default())
+}
+
+/// Lexes and parses `src`, tagging every span with `file`.
+pub fn parse_file(
+    src: &str,
+    file: FileId,
+) -> Result<ast::Program, Vec<Rich<'static, Token, Span>>> {
+    let tokens: Vec<(Token, Span)> = Token::lexer(src)
+        .spanned()
+        // Convert logos errors into tokens. We want parsing to be recoverable and not fail at the lexing stage, so
+        // we have a dedicated `Token::Error` variant that represents a token error that was previously encountered
+        .map(|(tok, span)| match tok {
+            // Turn the `Range<usize>` spans logos gives us into chumsky spans that remember which file they're in
+            Ok(tok) => (tok, Span::new(file, span)),
+            Err(()) => (Token::Error, Span::new(file, span)),
+        })
+        // Comments can go anywhere, so the grammar is simpler without them, and spans
+        // start at the first token that isn't one
+        .filter(|(tok, _)| *tok != Token::Comment)
+        .collect();
+    let depth = nesting::check(&tokens).map_err(|error| vec![error])?;
+
+    // Parse the tokens straight out of the vector, rather than through a `Stream` that
+    // would buffer a second copy of them for backtracking
+    let token_stream = tokens
+        .as_slice()
+        // Tell chumsky to split the (Token, Span) stream into its parts so that it can handle the spans for us
+        // This involves giving chumsky an 'end of input' span: we just use a zero-width span at the end of the string
+        .map(Span::new(file, src.len()..src.len()), |(t, s)| (t, s));
+    let parse = || {
+        parser()
+            .parse(token_stream)
+            .into_result()
+            .map_err(|errors| errors.into_iter().map(|e| e.into_owned()).collect())
+    };
+    if depth <= nesting::INLINE_DEPTH {
+        return parse();
+    }
+
+    // The parser recurses once per level of nesting, which the check above only bounds
+    // to more than the caller's stack can be trusted to hold in a debug build.
+    std::thread::scope(|scope| {
+        std::thread::Builder::new()
+            .stack_size(nesting::STACK_SIZE)
+            .spawn_scoped(scope, parse)
+            .expect("parser thread spawns")
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    })
+}
+
+/// Formats a parse error as `path:line:column: error: reason`, looking up the file its
+/// span names in `sources`.
+pub fn format_error(error: &Rich<'_, Token, Span>, sources: &SourceMap) -> String {
+    let span = error.span();
+    let file = sources.file(span.context);
+    let location = file.location(span.start);
+    format!(
+        "{}:{}:{}: error: {}",
+        file.name(),
+        location.line,
+        location.column,
+        error.reason()
+    )
+}
+
+/// Joins two operands with the operator `kind` makes, spanning from one to the other.
+fn binary(
+    lhs: ast::Expr,
+    rhs: ast::Expr,
+    kind: impl FnOnce(Box<ast::Expr>, Box<ast::Expr>) -> ast::ExprKind,
+) -> ast::Expr {
+    let span = lhs.span.union(rhs.span);
+    ast::Expr::new(kind(Box::new(lhs), Box::new(rhs)), span)
+}
+
+pub fn parser<'tokens, I>()
+-> impl Parser<'tokens, I, ast::Program, extra::Err<Rich<'tokens, Token, Span>>>
+where
+    I: ValueInput<'tokens, Token = Token, Span = Span>,
+{
+    let ident = select! { Token::ObjectIdentifier(s) => s };
+    let type_id = select! { Token::TypeIdentifier(s) => s, Token::SelfType => sym::SELF_TYPE };
+
+    let expr = recursive(|expr| {
+        let new_expr = just(Token::New)
+            .ignore_then(type_id)
+            .map(ast::ExprKind::New);
+
+        let block = expr
+            .clone()
+            .separated_by(choice((just(Token::Dot), just(Token::Semicolon))))
+            .allow_trailing()
+            .collect::<Vec<_>>()
+            .delimited_by(just(Token::LeftBrace), just(Token::RightBrace))
+            .map(ast::ExprKind::Block);
+
+        let assign = ident
+            .then_ignore(just(Token::Assign))
+            .then(expr.clone())
+            .map(|(name, expr)| ast::ExprKind::Assign {
+                name,
+                expr: Box::new(expr),
+            });
+
+        let let_binding = ident
+            .then_ignore(just(Token::Colon))
+            .then(type_id)
+            .then(just(Token::Assign).ignore_then(expr.clone()).or_not())
+            .map_with(|((name, typ), init), e| ast::LetBinding {
+                name,
+                typ,
+                init,
+                span: e.span(),
+            });
+
+        let let_expr = just(Token::Let)
+            .ignore_then(
+                let_binding
+                    .separated_by(just(Token::Comma))
+                    .at_least(1)
+                    .collect(),
+            )
+            .then_ignore(just(Token::In))
+            .then(expr.clone())
+            .map(|(bindings, body)| ast::ExprKind::Let {
+                bindings,
+                body: Box::new(body),
+            });
+
+        let if_expr = just(Token::If)
+            .ignore_then(expr.clone())
+            .then_ignore(just(Token::Then))
+            .then(expr.clone())
+            .then_ignore(just(Token::Else))
+            .then(expr.clone())
+            .then_ignore(just(Token::Fi))
+            .map(|((cond, then_branch), else_branch)| ast::ExprKind::If {
+                cond: Box::new(cond),
+                then_branch: Box::new(then_branch),
+                else_branch: Box::new(else_branch),
+            });
+
+        let while_expr = just(Token::While)
+            .ignore_then(expr.clone())
+            .then_ignore(just(Token::Loop))
+            .then(expr.clone())
+            .then_ignore(just(Token::Pool))
+            .map(|(cond, body)| ast::ExprKind::While {
+                cond: Box::new(cond),
+                body: Box::new(body),
+            });
+
+        let case_branch = ident
+            .then_ignore(just(Token::Colon))
+            .then(type_id)
+            .then_ignore(just(Token::DoubleArrow))
+            .then(expr.clone())
+            .map_with(|((name, typ), expr), e| ast::CaseBranch {
+                name,
+                typ,
+                expr,
+                span: e.span(),
+            });
+
+        let case_expr = just(Token::Case)
+            .ignore_then(expr.clone())
+            .then_ignore(just(Token::Of))
+            .then(
+                case_branch
+                    .separated_by(just(Token::Semicolon))
+                    .allow_trailing()
+                    .at_least(1)
+                    .collect(),
+            )
+            .then_ignore(just(Token::Esac))
+            .map(|(expr, branches)| ast::ExprKind::Case {
+                expr: Box::new(expr),
+                branches,
+            });
+
+        let call = ident
+            .then(
+                expr.clone()
+                    .separated_by(just(Token::Comma))
+                    .allow_trailing()
+                    .collect::<Vec<_>>()
+                    .delimited_by(just(Token::LeftParen), just(Token::RightParen)),
+            )
+            .map(|(name, args)| ast::ExprKind::FuncCall { name, args });
+
+        // Parentheses only group, so they leave no node of their own, but the expression
+        // in them spans them too.
+        let atom = choice((
+            select! { Token::String(s) => ast::ExprKind::String(s) },
+            select! { Token::Integer(i) => ast::ExprKind::Integer(IntSymbol::intern(i)) },
+            just(Token::True).to(ast::ExprKind::True),
+            just(Token::False).to(ast::ExprKind::False),
+            just(Token::Isvoid)
+                .ignore_then(expr.clone())
+                .map(|e| ast::ExprKind::IsVoid(Box::new(e))),
+            just(Token::SelfLit).to(ast::ExprKind::Id(sym::SELF)),
+            assign,
+            let_expr,
+            if_expr,
+            while_expr,
+            case_expr,
+            call,
+            ident.map(ast::ExprKind::Id),
+            new_expr,
+            block,
+        ))
+        .or(expr
+            .clone()
+            .delimited_by(just(Token::LeftParen), just(Token::RightParen))
+            .map(|expr| expr.kind))
+        .map_with(|kind, e| ast::Expr::new(kind, e.span()));
+
+        let term = atom.foldl(
+            just(Token::Dot)
+                .ignore_then(ident)
+                .then(
+                    expr.clone()
+                        .separated_by(just(Token::Comma))
+                        .allow_trailing()
+                        .collect::<Vec<_>>()
+                        .delimited_by(just(Token::LeftParen), just(Token::RightParen)),
+                )
+                .map(|(method, args)| (None, method, args))
+                .or(just(Token::TypeId)
+                    .ignore_then(type_id)
+                    .then_ignore(just(Token::Dot))
+                    .then(ident)
+                    .then(
+                        expr.clone()
+                            .separated_by(just(Token::Comma))
+                            .allow_trailing()
+                            .collect::<Vec<_>>()
+                            .delimited_by(just(Token::LeftParen), just(Token::RightParen)),
+                    )
+                    .map(|((static_type, method), args)| (Some(static_type), method, args)))
+                .map_with(|(static_type, method, args), e| (static_type, method, args, e.span()))
+                .repeated(),
+            |expr, (static_type, method, args, span): (_, _, _, Span)| {
+                let span = expr.span.union(span);
+                ast::Expr::new(
+                    ast::ExprKind::Dispatch {
+                        expr: Box::new(expr),
+                        static_type,
+                        method,
+                        args,
+                    },
+                    span,
+                )
+            },
+        );
+
+        // Binary operators with precedence
+        let unary = just(Token::Not)
+            .or(just(Token::Tilde))
+            .map_with(|op, e| (op, e.span()))
+            .repeated()
+            .foldr(term, |(op, span): (_, Span), expr| {
+                let span = span.union(expr.span);
+                let kind = match op {
+                    Token::Not => ast::ExprKind::Not(Box::new(expr)),
+                    Token::Tilde => ast::ExprKind::Negate(Box::new(expr)),
+                    _ => unreachable!(),
+                };
+                ast::Expr::new(kind, span)
+            });
+
+        let multiplicative = unary.clone().foldl(
+            choice((just(Token::Multiply), just(Token::Divide)))
+                .then(unary.clone())
+                .repeated(),
+            |lhs, (op, rhs)| {
+                binary(lhs, rhs, |lhs, rhs| match op {
+                    Token::Multiply => ast::ExprKind::Times(lhs, rhs),
+                    Token::Divide => ast::ExprKind::Divide(lhs, rhs),
+                    _ => unreachable!(),
+                })
+            },
+        );
+
+        let additive = multiplicative.clone().foldl(
+            choice((just(Token::Plus), just(Token::Minus)))
+                .then(multiplicative.clone())
+                .repeated(),
+            |lhs, (op, rhs)| {
+                binary(lhs, rhs, |lhs, rhs| match op {
+                    Token::Plus => ast::ExprKind::Plus(lhs, rhs),
+                    Token::Minus => ast::ExprKind::Minus(lhs, rhs),
+                    _ => unreachable!(),
+                })
+            },
+        );
+
+        additive.clone().foldl(
+            choice((
+                just(Token::LessThan),
+                just(Token::LessThanOrEqual),
+                just(Token::Equal),
+            ))
+            .then(additive.clone())
+            .repeated(),
+            |lhs, (op, rhs)| {
+                binary(lhs, rhs, |lhs, rhs| match op {
+                    Token::LessThan => ast::ExprKind::Lt(lhs, rhs),
+                    Token::LessThanOrEqual => ast::ExprKind::Le(lhs, rhs),
+                    Token::Equal => ast::ExprKind::Eq(lhs, rhs),
+                    _ => unreachable!(),
+                })
+            },
+        )
+    });
+
+    let formal = ident
+        .then_ignore(just(Token::Colon))
+        .then(type_id)
+        .map_with(|(name, typ), e| ast::Formal {
+            name,
+            typ,
+            span: e.span(),
+        });
+
+    let method_feature = ident
+        .then(
+            formal
+                .separated_by(just(Token::Comma))
+                .allow_trailing()
+                .collect::<Vec<_>>()
+                .delimited_by(just(Token::LeftParen), just(Token::RightParen)),
+        )
+        .then_ignore(just(Token::Colon))
+        .then(type_id)
+        .then(
+            expr.clone()
+                .delimited_by(just(Token::LeftBrace), just(Token::RightBrace)),
+        )
+        .map_with(|(((name, formals), return_type), body), e| {
+            ast::Feature::Method(ast::MethodFeature {
+                name,
+                formals,
+                return_type,
+                body,
+                span: e.span(),
+            })
+        });
+
+    let attribute_feature = ident
+        .then_ignore(just(Token::Colon))
+        .then(type_id)
+        .then(just(Token::Assign).ignore_then(expr.clone()).or_not())
+        .map_with(|((name, attr_type), init), e| {
+            ast::Feature::Attribute(ast::AttributeFeature {
+                name,
+                attr_type,
+                init,
+                span: e.span(),
+            })
+        });
+
+    let feature = choice((method_feature, attribute_feature)).then_ignore(just(Token::Semicolon));
+
+    let class = just(Token::Class)
+        .ignore_then(type_id)
+        .then(just(Token::Inherits).ignore_then(type_id).or_not())
+        .then(
+            feature
+                .repeated()
+                .collect()
+                .delimited_by(just(Token::LeftBrace), just(Token::RightBrace)),
+        )
+        .then_ignore(just(Token::Semicolon))
+        .map_with(|((name, parent), features), e| ast::Class {
+            name,
+            parent,
+            features,
+            span: e.span(),
+        });
+
+    class
+        .repeated()
+        .collect()
+        .map(|classes| ast::Program { classes })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn body(src: &str) -> ast::Expr {
+        let program = parse(&format!("class A {{ f() : Object {{ {src} }}; }};")).unwrap();
+        match &program.classes[0].features[0] {
+            ast::Feature::Method(method) => method.body.clone(),
+            ast::Feature::Attribute(_) => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_dispatch_on_call_result() {
+        let ast::ExprKind::Dispatch {
+            expr,
+            static_type: None,
+            method,
+            ..
+        } = body("f(x).g()").kind
+        else {
+            panic!("expected a dispatch");
+        };
+        assert_eq!(method.as_str(), "g");
+        assert!(
+            matches!(&expr.kind, ast::ExprKind::FuncCall { name, args } if name.as_str() == "f" && args.len() == 1)
+        );
+    }
+
+    #[test]
+    fn test_static_dispatch_on_call_result() {
+        let ast::ExprKind::Dispatch {
+            expr,
+            static_type: Some(typ),
+            method,
+            ..
+        } = body("f(x)@T.g()").kind
+        else {
+            panic!("expected a static dispatch");
+        };
+        assert_eq!((typ.as_str(), method.as_str()), ("T", "g"));
+        assert!(matches!(&expr.kind, ast::ExprKind::FuncCall { name, .. } if name.as_str() == "f"));
+    }
+
+    #[test]
+    fn test_comments_go_anywhere_and_stay_out_of_spans() {
+        let src = "-- a\nclass (* b *) A -- c\n{\n  -- d\n  x : Int <- (* e *) 0;\n  (* f *)\n  g() : Int { x -- h\n  };\n};";
+        let program = parse(src).unwrap();
+        let class = &program.classes[0];
+        assert_eq!(&src[class.span.start..class.span.start + 5], "class");
+        let starts: Vec<&str> = class
+            .features
+            .iter()
+            .map(|feature| match feature {
+                ast::Feature::Method(method) => &src[method.span.start..method.span.start + 1],
+                ast::Feature::Attribute(attr) => &src[attr.span.start..attr.span.start + 1],
+            })
+            .collect();
+        assert_eq!(starts, ["x", "g"]);
+    }
+}
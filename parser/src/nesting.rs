@@ -0,0 +1,193 @@
+//! A cheap pass over the tokens that rejects programs nested too deeply for the parser.
+//! The parser, and everything that walks the tree after it, recurses once per level of
+//! nesting, so without a limit a few kilobytes of `(` are enough to overflow the stack.
+//! Chains of binary operators and dispatches nest too, since `1 + 2 + 3` is
+//! `(1 + 2) + 3` and `a.f().g()` is `(a.f()).g()`, so they count the same way.
+
+use chumsky::error::Rich;
+use lexer::Token;
+
+use crate::ast::Span;
+
+/// How deeply expressions may nest. Every bracket-like construct (parentheses, blocks,
+/// `if`, `while`, `case` and a `let`'s bindings) counts as a level, and so does every
+/// prefix (a `let`'s body, `<-`, `isvoid`, `not`, `~`, `=>`), binary operator and
+/// dispatch `.` up to the next separator inside the construct around it.
+pub const MAX_DEPTH: usize = 256;
+
+/// How deeply a program may nest and still be parsed on the caller's thread. Debug
+/// builds use about 50 KB of stack per level, so this fits in the 2 MiB a spawned thread
+/// gets by default, with room to spare for the caller.
+pub(crate) const INLINE_DEPTH: usize = 16;
+
+/// The stack the parser runs on when a program nests deeper than [`INLINE_DEPTH`].
+pub(crate) const STACK_SIZE: usize = 64 * 1024 * 1024;
+
+/// Returns how deeply the tokens nest, or finds the first token that takes the nesting
+/// past [`MAX_DEPTH`]. The count errs on the high side: a closing token that doesn't match the innermost opening one is
+/// ignored, prefixes count until the end of the statement even when they've already
+/// ended, and so do operators whose precedence puts them side by side in the tree.
+pub(crate) fn check(tokens: &[(Token, Span)]) -> Result<usize, Rich<'static, Token, Span>> {
+    // One entry per open construct: the token that closes it and the prefixes seen in it
+    // since the last separator.
+    let mut frames: Vec<(Token, usize)> = Vec::new();
+    let mut depth = 0;
+    let mut deepest = 0;
+    for (token, span) in tokens {
+        let closer = match token {
+            Token::LeftParen => Some(Token::RightParen),
+            Token::LeftBrace => Some(Token::RightBrace),
+            Token::If => Some(Token::Fi),
+            Token::While => Some(Token::Pool),
+            Token::Case => Some(Token::Esac),
+            Token::Let => Some(Token::In),
+            _ => None,
+        };
+        if let Some(closer) = closer {
+            frames.push((closer, 0));
+            depth += 1;
+        } else if let Some(&(_, prefixes)) = frames.last().filter(|(closer, _)| closer == token) {
+            frames.pop();
+            depth -= 1 + prefixes;
+            // A let's body goes on until the end of the expression it's in.
+            if *token == Token::In {
+                if let Some((_, prefixes)) = frames.last_mut() {
+                    *prefixes += 1;
+                }
+                depth += 1;
+            }
+        } else {
+            match token {
+                Token::Assign
+                | Token::Isvoid
+                | Token::Not
+                | Token::Tilde
+                | Token::DoubleArrow
+                | Token::Plus
+                | Token::Minus
+                | Token::Multiply
+                | Token::Divide
+                | Token::LessThan
+                | Token::LessThanOrEqual
+                | Token::Equal
+                | Token::Dot => {
+                    if let Some((_, prefixes)) = frames.last_mut() {
+                        *prefixes += 1;
+                    }
+                    depth += 1;
+                }
+                Token::Semicolon
+                | Token::Comma
+                | Token::Then
+                | Token::Else
+                | Token::Loop
+                | Token::Of => {
+                    if let Some((_, prefixes)) = frames.last_mut() {
+                        depth -= *prefixes;
+                        *prefixes = 0;
+                    }
+                }
+                _ => {}
+            }
+        }
+        if depth > MAX_DEPTH {
+            return Err(Rich::custom(
+                *span,
+                format!("expressions are nested more than {MAX_DEPTH} levels deep"),
+            ));
+        }
+        deepest = deepest.max(depth);
+    }
+    Ok(deepest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chumsky::span::Span as _;
+
+    fn parses(src: &str) -> bool {
+        crate::parse(src).is_ok()
+    }
+
+    fn program(body: &str) -> String {
+        format!("class Main {{ main() : Object {{ {body} }}; }};")
+    }
+
+    fn depth(src: &str) -> usize {
+        let tokens: Vec<(Token, Span)> = logos::Logos::lexer(src)
+            .map(|token| (token.unwrap(), Span::new(Default::default(), 0..0)))
+            .collect();
+        check(&tokens).unwrap()
+    }
+
+    #[test]
+    fn test_depth() {
+        assert_eq!(depth(&program("0")), 2);
+        assert_eq!(depth(&program("(a.f(1 + 2))")), 6);
+        assert_eq!(depth(&program("{ x <- 1; y <- (2); }")), 5);
+        // Small programs are parsed without a thread of their own.
+        let src = std::fs::read_to_string("../samples/hello_world.cl").unwrap();
+        assert!(depth(&src) <= INLINE_DEPTH);
+    }
+
+    #[test]
+    fn test_nesting_at_the_limit_parses() {
+        // The class and method bodies are two levels already.
+        let n = MAX_DEPTH - 2;
+        assert!(parses(&program(&format!(
+            "{}0{}",
+            "(".repeat(n),
+            ")".repeat(n)
+        ))));
+    }
+
+    #[test]
+    fn test_deep_nesting_is_an_error() {
+        for (open, close) in [("(", ")"), ("{ ", "; }"), ("if true then ", " else 0 fi")] {
+            let n = 100_000;
+            let src = program(&format!("{}0{}", open.repeat(n), close.repeat(n)));
+            let errors = crate::parse(&src).unwrap_err();
+            assert_eq!(errors.len(), 1);
+            assert!(errors[0].to_string().contains("nested more than"), "{open}");
+        }
+    }
+
+    #[test]
+    fn test_deep_prefixes_are_an_error() {
+        for prefix in [
+            "isvoid ",
+            "not ",
+            "~",
+            "x <- ",
+            "let x : Int in ",
+            "let x : Int, y : Int in ",
+        ] {
+            let src = program(&format!("{}0", prefix.repeat(100_000)));
+            assert!(crate::parse(&src).is_err(), "{prefix}");
+        }
+    }
+
+    #[test]
+    fn test_long_chains_are_an_error() {
+        for link in [" + 1", " * 1", " = 1", ".f()", "@A.f()", ".f(0)"] {
+            let src = program(&format!("0{}", link.repeat(5_000)));
+            let errors = crate::parse(&src).unwrap_err();
+            assert!(errors[0].to_string().contains("nested more than"), "{link}");
+        }
+    }
+
+    #[test]
+    fn test_separators_end_prefixes() {
+        let statement = "x <- isvoid not 0 + a.f(b.g() + 1); ";
+        let src = program(&format!("{{ {}0; }}", statement.repeat(1_000)));
+        assert!(parses(&src));
+    }
+
+    #[test]
+    fn test_unbalanced_closers_are_harmless() {
+        let src = format!("{}{}", ")".repeat(1_000), "(".repeat(1_000));
+        let errors = crate::parse(&src).unwrap_err();
+        assert!(!errors.is_empty());
+    }
+}
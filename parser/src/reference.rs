@@ -0,0 +1,846 @@
+//! The indented AST format the reference COOL tools pass between phases, so this
+//! compiler's phases can be mixed with theirs: `write` turns a program into what the
+//! reference parser prints, and `read` turns that back into a program.
+//!
+//! The format holds less than [`ast::Program`](crate::ast::Program), so a round trip
+//! normalizes a few things:
+//!
+//! - Only line numbers are kept. `read` gives classes, features and expressions an empty
+//!   span at the start of their line when the class's file is in the `SourceMap`, and at
+//!   offset 0 of file 0 when it isn't.
+//! - `f(x)` is written as a dispatch on `self`, and every dispatch on `self` is read back
+//!   as a call, including `self.f(x)`.
+//! - A `let` with several bindings is written, and read back, as nested `let`s with one
+//!   binding each.
+//! - A class that inherits `Object` is read back with no parent.
+//! - A string holding bytes that aren't UTF-8, which octal escapes can write, is read
+//!   back with each of them replaced by U+FFFD.
+
+use std::fmt::{self, Display, Write as _};
+
+use chumsky::span::Span as _;
+use lexer::source::SourceMap;
+use lexer::{FileId, IntSymbol, StrSymbol, Symbol, sym};
+
+use crate::ast::{
+    AttributeFeature, CaseBranch, Class, Expr, ExprKind, Feature, Formal, LetBinding,
+    MethodFeature, Program, Span,
+};
+
+/// The type the reference parser gives every expression, before semantic analysis.
+const NO_TYPE: &str = "_no_type";
+
+/// Writes `program` the way the reference parser prints it. Line numbers are where the
+/// spans of its classes, features and expressions start, looked up in `sources`.
+pub fn write(program: &Program, sources: &SourceMap) -> String {
+    let mut writer = Writer {
+        out: String::new(),
+        sources,
+        line: 1,
+    };
+    if let Some(class) = program.classes.first() {
+        writer.line = writer.line_of(class.span);
+    }
+    writer.line_number(0);
+    writer.put(0, "_program");
+    for class in &program.classes {
+        writer.class(2, class);
+    }
+    writer.out
+}
+
+struct Writer<'a> {
+    out: String,
+    sources: &'a SourceMap,
+    /// The line of the node being written.
+    line: usize,
+}
+
+impl Writer<'_> {
+    fn put(&mut self, indent: usize, text: impl Display) {
+        writeln!(self.out, "{:indent$}{text}", "").unwrap();
+    }
+
+    fn line_number(&mut self, indent: usize) {
+        let line = self.line;
+        self.put(indent, format_args!("#{line}"));
+    }
+
+    fn line_of(&self, span: Span) -> usize {
+        self.sources.location(span.context, span.start).line
+    }
+
+    fn class(&mut self, n: usize, class: &Class) {
+        self.line = self.line_of(class.span);
+        self.line_number(n);
+        self.put(n, "_class");
+        self.put(n + 2, class.name);
+        self.put(n + 2, class.parent.as_deref().unwrap_or("Object"));
+        let file = escape(self.sources.file(class.span.context).name());
+        self.put(n + 2, format_args!("\"{file}\""));
+        self.put(n + 2, "(");
+        for feature in &class.features {
+            self.feature(n + 2, feature);
+        }
+        self.put(n + 2, ")");
+    }
+
+    fn feature(&mut self, n: usize, feature: &Feature) {
+        match feature {
+            Feature::Method(method) => {
+                self.line = self.line_of(method.span);
+                self.line_number(n);
+                self.put(n, "_method");
+                self.put(n + 2, method.name);
+                for formal in &method.formals {
+                    self.line_number(n + 2);
+                    self.put(n + 2, "_formal");
+                    self.put(n + 4, formal.name);
+                    self.put(n + 4, formal.typ);
+                }
+                self.put(n + 2, method.return_type);
+                self.expr(n + 2, &method.body);
+            }
+            Feature::Attribute(attr) => {
+                self.line = self.line_of(attr.span);
+                self.line_number(n);
+                self.put(n, "_attr");
+                self.put(n + 2, attr.name);
+                self.put(n + 2, attr.attr_type);
+                self.optional_expr(n + 2, attr.init.as_ref());
+            }
+        }
+    }
+
+    fn optional_expr(&mut self, n: usize, expr: Option<&Expr>) {
+        match expr {
+            Some(expr) => self.expr(n, expr),
+            None => {
+                self.line_number(n);
+                self.put(n, "_no_expr");
+                self.put(n, format_args!(": {NO_TYPE}"));
+            }
+        }
+    }
+
+    fn expr(&mut self, n: usize, expr: &Expr) {
+        if let ExprKind::Paren(inner) = &expr.kind {
+            return self.expr(n, inner);
+        }
+        self.line = self.line_of(expr.span);
+        self.line_number(n);
+        self.node(n, expr);
+    }
+
+    /// Everything of `expr` after its line number.
+    fn node(&mut self, n: usize, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Assign { name, expr } => {
+                self.put(n, "_assign");
+                self.put(n + 2, name);
+                self.expr(n + 2, expr);
+            }
+            ExprKind::Dispatch {
+                expr,
+                static_type,
+                method,
+                args,
+            } => {
+                match static_type {
+                    Some(typ) => {
+                        self.put(n, "_static_dispatch");
+                        self.expr(n + 2, expr);
+                        self.put(n + 2, typ);
+                    }
+                    None => {
+                        self.put(n, "_dispatch");
+                        self.expr(n + 2, expr);
+                    }
+                }
+                self.put(n + 2, method);
+                self.args(n + 2, args);
+            }
+            ExprKind::FuncCall { name, args } => {
+                self.put(n, "_dispatch");
+                self.expr(n + 2, &Expr::new(ExprKind::Id(sym::SELF), expr.span));
+                self.put(n + 2, name);
+                self.args(n + 2, args);
+            }
+            ExprKind::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => {
+                self.put(n, "_cond");
+                self.expr(n + 2, cond);
+                self.expr(n + 2, then_branch);
+                self.expr(n + 2, else_branch);
+            }
+            ExprKind::While { cond, body } => {
+                self.put(n, "_loop");
+                self.expr(n + 2, cond);
+                self.expr(n + 2, body);
+            }
+            ExprKind::Block(exprs) => {
+                self.put(n, "_block");
+                for expr in exprs {
+                    self.expr(n + 2, expr);
+                }
+            }
+            ExprKind::Let { bindings, body } => match bindings.split_first() {
+                Some((first, rest)) => {
+                    self.put(n, "_let");
+                    self.put(n + 2, first.name);
+                    self.put(n + 2, first.typ);
+                    self.optional_expr(n + 2, first.init.as_ref());
+                    if rest.is_empty() {
+                        self.expr(n + 2, body);
+                    } else {
+                        let kind = ExprKind::Let {
+                            bindings: rest.to_vec(),
+                            body: body.clone(),
+                        };
+                        let inner = Expr::new(kind, expr.span);
+                        self.expr(n + 2, &inner);
+                    }
+                }
+                // The grammar doesn't allow a `let` without bindings.
+                None => return self.node(n, body),
+            },
+            ExprKind::Case { expr, branches } => {
+                self.put(n, "_typcase");
+                self.expr(n + 2, expr);
+                for branch in branches {
+                    // A branch goes on its body's line.
+                    self.line = self.line_of(branch.expr.span);
+                    self.line_number(n + 2);
+                    self.put(n + 2, "_branch");
+                    self.put(n + 4, branch.name);
+                    self.put(n + 4, branch.typ);
+                    self.expr(n + 4, &branch.expr);
+                }
+            }
+            ExprKind::New(typ) => {
+                self.put(n, "_new");
+                self.put(n + 2, typ);
+            }
+            ExprKind::IsVoid(expr) => self.unary(n, "_isvoid", expr),
+            ExprKind::Plus(lhs, rhs) => self.binary(n, "_plus", lhs, rhs),
+            ExprKind::Minus(lhs, rhs) => self.binary(n, "_sub", lhs, rhs),
+            ExprKind::Times(lhs, rhs) => self.binary(n, "_mul", lhs, rhs),
+            ExprKind::Divide(lhs, rhs) => self.binary(n, "_divide", lhs, rhs),
+            ExprKind::Lt(lhs, rhs) => self.binary(n, "_lt", lhs, rhs),
+            ExprKind::Le(lhs, rhs) => self.binary(n, "_leq", lhs, rhs),
+            ExprKind::Eq(lhs, rhs) => self.binary(n, "_eq", lhs, rhs),
+            ExprKind::Not(expr) => self.unary(n, "_comp", expr),
+            ExprKind::Negate(expr) => self.unary(n, "_neg", expr),
+            ExprKind::Paren(expr) => return self.node(n, expr),
+            ExprKind::Id(name) => {
+                self.put(n, "_object");
+                self.put(n + 2, name);
+            }
+            ExprKind::Integer(value) => {
+                self.put(n, "_int");
+                self.put(n + 2, value);
+            }
+            ExprKind::String(value) => {
+                self.put(n, "_string");
+                self.put(n + 2, format_args!("\"{}\"", escape(value)));
+            }
+            ExprKind::True | ExprKind::False => {
+                self.put(n, "_bool");
+                self.put(n + 2, u8::from(matches!(expr.kind, ExprKind::True)));
+            }
+        }
+        self.put(n, format_args!(": {NO_TYPE}"));
+    }
+
+    fn args(&mut self, n: usize, args: &[Expr]) {
+        self.put(n, "(");
+        for arg in args {
+            self.expr(n, arg);
+        }
+        self.put(n, ")");
+    }
+
+    fn unary(&mut self, n: usize, kind: &str, expr: &Expr) {
+        self.put(n, kind);
+        self.expr(n + 2, expr);
+    }
+
+    fn binary(&mut self, n: usize, kind: &str, lhs: &Expr, rhs: &Expr) {
+        self.put(n, kind);
+        self.expr(n + 2, lhs);
+        self.expr(n + 2, rhs);
+    }
+}
+
+/// Why `read` couldn't make sense of its input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReadError {
+    /// The 1-based line of the input the problem is on.
+    pub line: usize,
+    pub message: String,
+}
+
+impl Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ReadError {}
+
+/// Reads a program in the reference format, such as the reference parser's output.
+/// Indentation is ignored. Each class's file is looked up by name in `sources` to give
+/// spans to it and its features. Expression types other than `_no_type` are accepted
+/// and dropped.
+pub fn read(text: &str, sources: &SourceMap) -> Result<Program, ReadError> {
+    let lines = text
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty())
+        .collect();
+    let mut reader = Reader {
+        lines,
+        pos: 0,
+        sources,
+        file: None,
+    };
+    reader.line_number()?;
+    reader.expect("_program")?;
+    let mut classes = Vec::new();
+    while reader.peek().is_some() {
+        classes.push(reader.class()?);
+    }
+    Ok(Program { classes })
+}
+
+struct Reader<'a> {
+    /// The non-blank lines of the input, trimmed, with their line numbers.
+    lines: Vec<(usize, &'a str)>,
+    pos: usize,
+    sources: &'a SourceMap,
+    /// The file of the class being read, if `sources` has it.
+    file: Option<FileId>,
+}
+
+impl<'a> Reader<'a> {
+    fn peek(&self) -> Option<&'a str> {
+        self.lines.get(self.pos).map(|&(_, line)| line)
+    }
+
+    fn next(&mut self) -> Result<&'a str, ReadError> {
+        let line = self
+            .peek()
+            .ok_or_else(|| self.error("unexpected end of input"))?;
+        self.pos += 1;
+        Ok(line)
+    }
+
+    /// An error on the line about to be read, or on the last line at the end of input.
+    fn error(&self, message: impl Into<String>) -> ReadError {
+        let line = self
+            .lines
+            .get(self.pos)
+            .or(self.lines.last())
+            .map_or(1, |&(line, _)| line);
+        ReadError {
+            line,
+            message: message.into(),
+        }
+    }
+
+    fn expect(&mut self, want: &str) -> Result<(), ReadError> {
+        match self.peek() {
+            Some(line) if line == want => {
+                self.pos += 1;
+                Ok(())
+            }
+            Some(line) => Err(self.error(format!("expected `{want}`, found `{line}`"))),
+            None => Err(self.error(format!("expected `{want}`, found end of input"))),
+        }
+    }
+
+    /// Reads a `#line` line.
+    fn line_number(&mut self) -> Result<usize, ReadError> {
+        let line = self.peek().unwrap_or_default();
+        let number = line
+            .strip_prefix('#')
+            .and_then(|number| number.parse().ok())
+            .ok_or_else(|| self.error(format!("expected a line number, found `{line}`")))?;
+        self.pos += 1;
+        Ok(number)
+    }
+
+    /// Reads a name or a type.
+    fn symbol(&mut self) -> Result<Symbol, ReadError> {
+        self.word().map(|word| Symbol::intern(&word))
+    }
+
+    /// Reads a name, a type or an integer, which take a line each.
+    fn word(&mut self) -> Result<String, ReadError> {
+        let line = self.next()?;
+        if line.starts_with(['#', '_', ':', '"', '(', ')']) {
+            self.pos -= 1;
+            return Err(self.error(format!("expected a name, found `{line}`")));
+        }
+        Ok(line.to_string())
+    }
+
+    fn string(&mut self) -> Result<Vec<u8>, ReadError> {
+        let line = self.next()?;
+        match line
+            .strip_prefix('"')
+            .and_then(|s| s.strip_suffix('"'))
+            .filter(|_| line.len() >= 2)
+        {
+            Some(inner) => Ok(unescape(inner)),
+            None => {
+                self.pos -= 1;
+                Err(self.error(format!("expected a string, found `{line}`")))
+            }
+        }
+    }
+
+    /// Reads the `: type` line that ends every expression.
+    fn type_annotation(&mut self) -> Result<(), ReadError> {
+        match self.peek() {
+            Some(line) if line.starts_with(':') => {
+                self.pos += 1;
+                Ok(())
+            }
+            Some(line) => Err(self.error(format!("expected `: {NO_TYPE}`, found `{line}`"))),
+            None => Err(self.error(format!("expected `: {NO_TYPE}`, found end of input"))),
+        }
+    }
+
+    /// An empty span where `line` of the current file starts, past its indentation.
+    fn span(&self, line: usize) -> Span {
+        let Some(id) = self.file else {
+            return Span::new(FileId::default(), 0..0);
+        };
+        let file = self.sources.file(id);
+        let offset = match file.line_start(line) {
+            Some(start) => {
+                let text = file.line_text(line);
+                start + text.len() - text.trim_start().len()
+            }
+            None => file.src().len(),
+        };
+        Span::new(id, offset..offset)
+    }
+
+    fn class(&mut self) -> Result<Class, ReadError> {
+        let line = self.line_number()?;
+        self.expect("_class")?;
+        let name = self.symbol()?;
+        let parent = Some(self.symbol()?).filter(|&parent| parent != sym::OBJECT);
+        let file = String::from_utf8_lossy(&self.string()?).into_owned();
+        self.file = self.sources.find(&file);
+        self.expect("(")?;
+        let mut features = Vec::new();
+        while self.peek().is_some_and(|line| line != ")") {
+            features.push(self.feature()?);
+        }
+        self.expect(")")?;
+        Ok(Class {
+            name,
+            parent,
+            features,
+            span: self.span(line),
+        })
+    }
+
+    fn feature(&mut self) -> Result<Feature, ReadError> {
+        let line = self.line_number()?;
+        match self.next()? {
+            "_method" => {
+                let name = self.symbol()?;
+                let mut formals = Vec::new();
+                while self.peek().is_some_and(|line| line.starts_with('#')) {
+                    let line = self.line_number()?;
+                    self.expect("_formal")?;
+                    formals.push(Formal {
+                        name: self.symbol()?,
+                        typ: self.symbol()?,
+                        span: self.span(line),
+                    });
+                }
+                Ok(Feature::Method(MethodFeature {
+                    name,
+                    formals,
+                    return_type: self.symbol()?,
+                    body: self.expr()?,
+                    span: self.span(line),
+                }))
+            }
+            "_attr" => Ok(Feature::Attribute(AttributeFeature {
+                name: self.symbol()?,
+                attr_type: self.symbol()?,
+                init: self.optional_expr()?,
+                span: self.span(line),
+            })),
+            other => {
+                self.pos -= 1;
+                Err(self.error(format!("expected `_method` or `_attr`, found `{other}`")))
+            }
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr, ReadError> {
+        let start = self.pos;
+        match self.optional_expr()? {
+            Some(expr) => Ok(expr),
+            None => {
+                self.pos = start + 1;
+                Err(self.error("expected an expression, found `_no_expr`"))
+            }
+        }
+    }
+
+    /// Reads an expression, or `None` for `_no_expr`.
+    fn optional_expr(&mut self) -> Result<Option<Expr>, ReadError> {
+        let line = self.line_number()?;
+        let kind = match self.next()? {
+            "_no_expr" => {
+                self.type_annotation()?;
+                return Ok(None);
+            }
+            "_assign" => ExprKind::Assign {
+                name: self.symbol()?,
+                expr: Box::new(self.expr()?),
+            },
+            "_static_dispatch" => {
+                let expr = Box::new(self.expr()?);
+                let static_type = Some(self.symbol()?);
+                ExprKind::Dispatch {
+                    expr,
+                    static_type,
+                    method: self.symbol()?,
+                    args: self.args()?,
+                }
+            }
+            "_dispatch" => {
+                let expr = self.expr()?;
+                let method = self.symbol()?;
+                let args = self.args()?;
+                match expr.kind {
+                    ExprKind::Id(name) if name == sym::SELF => {
+                        ExprKind::FuncCall { name: method, args }
+                    }
+                    _ => ExprKind::Dispatch {
+                        expr: Box::new(expr),
+                        static_type: None,
+                        method,
+                        args,
+                    },
+                }
+            }
+            "_cond" => ExprKind::If {
+                cond: Box::new(self.expr()?),
+                then_branch: Box::new(self.expr()?),
+                else_branch: Box::new(self.expr()?),
+            },
+            "_loop" => ExprKind::While {
+                cond: Box::new(self.expr()?),
+                body: Box::new(self.expr()?),
+            },
+            "_block" => {
+                let mut exprs = Vec::new();
+                while self.peek().is_some_and(|line| line.starts_with('#')) {
+                    exprs.push(self.expr()?);
+                }
+                ExprKind::Block(exprs)
+            }
+            "_let" => ExprKind::Let {
+                bindings: vec![LetBinding {
+                    name: self.symbol()?,
+                    typ: self.symbol()?,
+                    init: self.optional_expr()?,
+                    span: self.span(line),
+                }],
+                body: Box::new(self.expr()?),
+            },
+            "_typcase" => {
+                let expr = Box::new(self.expr()?);
+                let mut branches = Vec::new();
+                while self.peek().is_some_and(|line| line.starts_with('#')) {
+                    let line = self.line_number()?;
+                    self.expect("_branch")?;
+                    branches.push(CaseBranch {
+                        name: self.symbol()?,
+                        typ: self.symbol()?,
+                        expr: self.expr()?,
+                        span: self.span(line),
+                    });
+                }
+                ExprKind::Case { expr, branches }
+            }
+            "_new" => ExprKind::New(self.symbol()?),
+            "_isvoid" => ExprKind::IsVoid(Box::new(self.expr()?)),
+            "_plus" => ExprKind::Plus(Box::new(self.expr()?), Box::new(self.expr()?)),
+            "_sub" => ExprKind::Minus(Box::new(self.expr()?), Box::new(self.expr()?)),
+            "_mul" => ExprKind::Times(Box::new(self.expr()?), Box::new(self.expr()?)),
+            "_divide" => ExprKind::Divide(Box::new(self.expr()?), Box::new(self.expr()?)),
+            "_lt" => ExprKind::Lt(Box::new(self.expr()?), Box::new(self.expr()?)),
+            "_leq" => ExprKind::Le(Box::new(self.expr()?), Box::new(self.expr()?)),
+            "_eq" => ExprKind::Eq(Box::new(self.expr()?), Box::new(self.expr()?)),
+            "_comp" => ExprKind::Not(Box::new(self.expr()?)),
+            "_neg" => ExprKind::Negate(Box::new(self.expr()?)),
+            "_object" => ExprKind::Id(self.symbol()?),
+            "_int" => {
+                let value = self.word()?;
+                match value.parse() {
+                    Ok(value) => ExprKind::Integer(IntSymbol::intern(value)),
+                    Err(_) => {
+                        self.pos -= 1;
+                        return Err(self.error(format!("`{value}` isn't an Int")));
+                    }
+                }
+            }
+            "_string" => {
+                ExprKind::String(StrSymbol::intern(&String::from_utf8_lossy(&self.string()?)))
+            }
+            "_bool" => match self.next()? {
+                "1" => ExprKind::True,
+                "0" => ExprKind::False,
+                other => {
+                    self.pos -= 1;
+                    return Err(self.error(format!("expected `1` or `0`, found `{other}`")));
+                }
+            },
+            other => {
+                self.pos -= 1;
+                return Err(self.error(format!("expected an expression, found `{other}`")));
+            }
+        };
+        self.type_annotation()?;
+        Ok(Some(Expr::new(kind, self.span(line))))
+    }
+
+    fn args(&mut self) -> Result<Vec<Expr>, ReadError> {
+        self.expect("(")?;
+        let mut args = Vec::new();
+        while self.peek().is_some_and(|line| line != ")") {
+            args.push(self.expr()?);
+        }
+        self.expect(")")?;
+        Ok(args)
+    }
+}
+
+/// Escapes `s` the way the reference tools print strings: C-style escapes for the usual
+/// control characters and three octal digits for any other byte that isn't printable
+/// ASCII.
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for byte in s.bytes() {
+        match byte {
+            b'"' => escaped.push_str("\\\""),
+            b'\\' => escaped.push_str("\\\\"),
+            b'\n' => escaped.push_str("\\n"),
+            b'\t' => escaped.push_str("\\t"),
+            b'\x08' => escaped.push_str("\\b"),
+            b'\x0c' => escaped.push_str("\\f"),
+            b' '..=b'~' => escaped.push(byte as char),
+            _ => write!(escaped, "\\{byte:03o}").unwrap(),
+        }
+    }
+    escaped
+}
+
+/// Undoes [`escape`], giving bytes because an octal escape can be half of a UTF-8
+/// character.
+fn unescape(s: &str) -> Vec<u8> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'\\' || i + 1 == bytes.len() {
+            out.push(bytes[i]);
+            i += 1;
+            continue;
+        }
+        let octal = bytes[i + 1..]
+            .iter()
+            .take(3)
+            .take_while(|b| (b'0'..=b'7').contains(b))
+            .count();
+        if octal == 3 {
+            let digits = std::str::from_utf8(&bytes[i + 1..i + 4]).unwrap();
+            out.push(u8::from_str_radix(digits, 8).unwrap_or(u8::MAX));
+            i += 4;
+            continue;
+        }
+        out.push(match bytes[i + 1] {
+            b'n' => b'\n',
+            b't' => b'\t',
+            b'b' => b'\x08',
+            b'f' => b'\x0c',
+            other => other,
+        });
+        i += 2;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{Value, json};
+    use std::fs;
+
+    /// `program` as JSON in the form a round trip gives: without spans, and with one
+    /// binding per `let`.
+    fn normalized(program: &Program) -> Value {
+        fn normalize(value: &mut Value) {
+            match value {
+                Value::Object(map) => {
+                    map.remove("span");
+                    if map.get("kind") == Some(&json!("let"))
+                        && map["bindings"].as_array().unwrap().len() > 1
+                    {
+                        let rest = map["bindings"].as_array_mut().unwrap().split_off(1);
+                        let inner = json!({ "kind": "let", "bindings": rest, "body": map["body"] });
+                        map.insert("body".to_string(), inner);
+                    }
+                    map.values_mut().for_each(normalize);
+                }
+                Value::Array(items) => items.iter_mut().for_each(normalize),
+                _ => {}
+            }
+        }
+        let mut value = serde_json::to_value(program).unwrap();
+        normalize(&mut value);
+        value
+    }
+
+    #[test]
+    fn test_write() {
+        let mut sources = SourceMap::new();
+        let file = sources.add(
+            "a.cl",
+            "class A inherits IO {\n  x : Int;\n  f(y : Int) : Object {\n    out_int(~y)\n  };\n};",
+        );
+        let program = crate::parse_file(sources.file(file).src(), file).unwrap();
+        let expected = r#"#1
+_program
+  #1
+  _class
+    A
+    IO
+    "a.cl"
+    (
+    #2
+    _attr
+      x
+      Int
+      #2
+      _no_expr
+      : _no_type
+    #3
+    _method
+      f
+      #3
+      _formal
+        y
+        Int
+      Object
+      #4
+      _dispatch
+        #4
+        _object
+          self
+        : _no_type
+        out_int
+        (
+        #4
+        _neg
+          #4
+          _object
+            y
+          : _no_type
+        : _no_type
+        )
+      : _no_type
+    )
+"#;
+        assert_eq!(write(&program, &sources), expected);
+    }
+
+    #[test]
+    fn test_read() {
+        let mut sources = SourceMap::new();
+        let file = sources.add("b.cl", "class B {\n  g() : Object {\n    0 };\n};");
+        let text = "#1\n_program\n  #1\n  _class\n    B\n    Object\n    \"b.cl\"\n    (\n    #2\n    _method\n      g\n      Object\n      #3\n      _let\n        s\n        String\n        #3\n        _string\n          \"tab\\there\\303\\251\"\n        : String\n        #3\n        _let\n          b\n          Bool\n          #3\n          _no_expr\n          : _no_type\n        #3\n        _bool\n          1\n        : Bool\n        : Bool\n      : Bool\n    )\n";
+        let program = read(text, &sources).unwrap();
+        let class = &program.classes[0];
+        assert_eq!(class.parent, None);
+        assert_eq!(class.span, Span::new(file, 0..0));
+        let Feature::Method(method) = &class.features[0] else {
+            panic!("expected a method");
+        };
+        assert_eq!(method.span, Span::new(file, 12..12));
+        let ExprKind::Let { bindings, body } = &method.body.kind else {
+            panic!("expected a let, got {:?}", method.body);
+        };
+        assert_eq!(bindings.len(), 1);
+        let init = bindings[0].init.as_ref().unwrap();
+        assert!(matches!(init.kind, ExprKind::String(s) if s == "tab\there\u{e9}"));
+        assert_eq!(init.span, Span::new(file, 31..31));
+        let ExprKind::Let { bindings, body } = &body.kind else {
+            panic!("expected a nested let, got {body:?}");
+        };
+        assert!(bindings[0].init.is_none());
+        assert!(matches!(body.kind, ExprKind::True));
+    }
+
+    #[test]
+    fn test_read_errors() {
+        let sources = SourceMap::new();
+        let error = read("#1\n_program\n  #1\n  _clas\n", &sources).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "line 4: expected `_class`, found `_clas`"
+        );
+        let text =
+            "#1\n_program\n#1\n_class\nA\nObject\n\"a.cl\"\n(\n#1\n_attr\nx\nInt\n#1\n_int\n";
+        let error = read(text, &sources).unwrap_err();
+        assert_eq!(error.line, 14);
+        assert_eq!(error.message, "unexpected end of input");
+    }
+
+    #[test]
+    fn test_samples_round_trip() {
+        let mut paths: Vec<_> = fs::read_dir("../samples")
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "cl"))
+            .collect();
+        paths.sort();
+        assert!(!paths.is_empty());
+        for path in paths {
+            let mut sources = SourceMap::new();
+            let file = sources.load(&path).unwrap();
+            let program = crate::parse_file(sources.file(file).src(), file).unwrap();
+            let text = write(&program, &sources);
+            let read_back =
+                read(&text, &sources).unwrap_or_else(|error| panic!("{}: {error}", path.display()));
+            assert_eq!(
+                normalized(&read_back),
+                normalized(&program),
+                "{}",
+                path.display()
+            );
+            assert_eq!(write(&read_back, &sources), text, "{}", path.display());
+            for (class, original) in read_back.classes.iter().zip(&program.classes) {
+                assert_eq!(
+                    sources.location(file, class.span.start).line,
+                    sources.location(file, original.span.start).line
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,711 @@
+//! Traversals of the AST that only have to handle the nodes they care about.
+//!
+//! [`Visitor`] looks at a borrowed tree, [`VisitorMut`] changes one in place, and
+//! [`Fold`] rebuilds an owned one. Every method of each trait defaults to the matching
+//! free function, which goes on to the node's children; an implementation overrides the
+//! methods for the nodes it's interested in, and calls the free function from its
+//! override when it still wants the children visited.
+//!
+//! Children are visited in the order they appear in the source, so a `let`'s bindings
+//! come before its body and a dispatch's receiver before its arguments.
+
+use std::boxed::Box;
+
+use crate::ast::{
+    AttributeFeature, CaseBranch, Class, Expr, ExprKind, Feature, Formal, LetBinding,
+    MethodFeature, Program,
+};
+
+/// Visits a borrowed AST. The `'ast` lifetime lets a visitor keep references into the
+/// tree it's visiting.
+pub trait Visitor<'ast> {
+    fn visit_program(&mut self, program: &'ast Program) {
+        walk_program(self, program)
+    }
+
+    fn visit_class(&mut self, class: &'ast Class) {
+        walk_class(self, class)
+    }
+
+    fn visit_feature(&mut self, feature: &'ast Feature) {
+        walk_feature(self, feature)
+    }
+
+    fn visit_method(&mut self, method: &'ast MethodFeature) {
+        walk_method(self, method)
+    }
+
+    fn visit_attribute(&mut self, attr: &'ast AttributeFeature) {
+        walk_attribute(self, attr)
+    }
+
+    fn visit_formal(&mut self, _formal: &'ast Formal) {}
+
+    fn visit_expr(&mut self, expr: &'ast Expr) {
+        walk_expr(self, expr)
+    }
+
+    fn visit_let_binding(&mut self, binding: &'ast LetBinding) {
+        walk_let_binding(self, binding)
+    }
+
+    fn visit_case_branch(&mut self, branch: &'ast CaseBranch) {
+        walk_case_branch(self, branch)
+    }
+}
+
+pub fn walk_program<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, program: &'ast Program) {
+    for class in &program.classes {
+        visitor.visit_class(class);
+    }
+}
+
+pub fn walk_class<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, class: &'ast Class) {
+    for feature in &class.features {
+        visitor.visit_feature(feature);
+    }
+}
+
+pub fn walk_feature<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, feature: &'ast Feature) {
+    match feature {
+        Feature::Method(method) => visitor.visit_method(method),
+        Feature::Attribute(attr) => visitor.visit_attribute(attr),
+    }
+}
+
+pub fn walk_method<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, method: &'ast MethodFeature) {
+    for formal in &method.formals {
+        visitor.visit_formal(formal);
+    }
+    visitor.visit_expr(&method.body);
+}
+
+pub fn walk_attribute<'ast, V: Visitor<'ast> + ?Sized>(
+    visitor: &mut V,
+    attr: &'ast AttributeFeature,
+) {
+    if let Some(init) = &attr.init {
+        visitor.visit_expr(init);
+    }
+}
+
+pub fn walk_expr<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, expr: &'ast Expr) {
+    match &expr.kind {
+        ExprKind::Assign { expr, .. }
+        | ExprKind::IsVoid(expr)
+        | ExprKind::Not(expr)
+        | ExprKind::Negate(expr)
+        | ExprKind::Paren(expr) => visitor.visit_expr(expr),
+        ExprKind::Dispatch { expr, args, .. } => {
+            visitor.visit_expr(expr);
+            args.iter().for_each(|arg| visitor.visit_expr(arg));
+        }
+        ExprKind::FuncCall { args: exprs, .. } | ExprKind::Block(exprs) => {
+            exprs.iter().for_each(|expr| visitor.visit_expr(expr));
+        }
+        ExprKind::If {
+            cond,
+            then_branch,
+            else_branch,
+        } => {
+            visitor.visit_expr(cond);
+            visitor.visit_expr(then_branch);
+            visitor.visit_expr(else_branch);
+        }
+        ExprKind::While { cond, body } => {
+            visitor.visit_expr(cond);
+            visitor.visit_expr(body);
+        }
+        ExprKind::Let { bindings, body } => {
+            bindings.iter().for_each(|b| visitor.visit_let_binding(b));
+            visitor.visit_expr(body);
+        }
+        ExprKind::Case { expr, branches } => {
+            visitor.visit_expr(expr);
+            branches.iter().for_each(|b| visitor.visit_case_branch(b));
+        }
+        ExprKind::Plus(a, b)
+        | ExprKind::Minus(a, b)
+        | ExprKind::Times(a, b)
+        | ExprKind::Divide(a, b)
+        | ExprKind::Lt(a, b)
+        | ExprKind::Le(a, b)
+        | ExprKind::Eq(a, b) => {
+            visitor.visit_expr(a);
+            visitor.visit_expr(b);
+        }
+        ExprKind::New(_)
+        | ExprKind::Id(_)
+        | ExprKind::Integer(_)
+        | ExprKind::String(_)
+        | ExprKind::True
+        | ExprKind::False => {}
+    }
+}
+
+pub fn walk_let_binding<'ast, V: Visitor<'ast> + ?Sized>(
+    visitor: &mut V,
+    binding: &'ast LetBinding,
+) {
+    if let Some(init) = &binding.init {
+        visitor.visit_expr(init);
+    }
+}
+
+pub fn walk_case_branch<'ast, V: Visitor<'ast> + ?Sized>(
+    visitor: &mut V,
+    branch: &'ast CaseBranch,
+) {
+    visitor.visit_expr(&branch.expr);
+}
+
+/// Visits an AST that it may change in place.
+pub trait VisitorMut {
+    fn visit_program_mut(&mut self, program: &mut Program) {
+        walk_program_mut(self, program)
+    }
+
+    fn visit_class_mut(&mut self, class: &mut Class) {
+        walk_class_mut(self, class)
+    }
+
+    fn visit_feature_mut(&mut self, feature: &mut Feature) {
+        walk_feature_mut(self, feature)
+    }
+
+    fn visit_method_mut(&mut self, method: &mut MethodFeature) {
+        walk_method_mut(self, method)
+    }
+
+    fn visit_attribute_mut(&mut self, attr: &mut AttributeFeature) {
+        walk_attribute_mut(self, attr)
+    }
+
+    fn visit_formal_mut(&mut self, _formal: &mut Formal) {}
+
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        walk_expr_mut(self, expr)
+    }
+
+    fn visit_let_binding_mut(&mut self, binding: &mut LetBinding) {
+        walk_let_binding_mut(self, binding)
+    }
+
+    fn visit_case_branch_mut(&mut self, branch: &mut CaseBranch) {
+        walk_case_branch_mut(self, branch)
+    }
+}
+
+pub fn walk_program_mut<V: VisitorMut + ?Sized>(visitor: &mut V, program: &mut Program) {
+    for class in &mut program.classes {
+        visitor.visit_class_mut(class);
+    }
+}
+
+pub fn walk_class_mut<V: VisitorMut + ?Sized>(visitor: &mut V, class: &mut Class) {
+    for feature in &mut class.features {
+        visitor.visit_feature_mut(feature);
+    }
+}
+
+pub fn walk_feature_mut<V: VisitorMut + ?Sized>(visitor: &mut V, feature: &mut Feature) {
+    match feature {
+        Feature::Method(method) => visitor.visit_method_mut(method),
+        Feature::Attribute(attr) => visitor.visit_attribute_mut(attr),
+    }
+}
+
+pub fn walk_method_mut<V: VisitorMut + ?Sized>(visitor: &mut V, method: &mut MethodFeature) {
+    for formal in &mut method.formals {
+        visitor.visit_formal_mut(formal);
+    }
+    visitor.visit_expr_mut(&mut method.body);
+}
+
+pub fn walk_attribute_mut<V: VisitorMut + ?Sized>(visitor: &mut V, attr: &mut AttributeFeature) {
+    if let Some(init) = &mut attr.init {
+        visitor.visit_expr_mut(init);
+    }
+}
+
+pub fn walk_expr_mut<V: VisitorMut + ?Sized>(visitor: &mut V, expr: &mut Expr) {
+    match &mut expr.kind {
+        ExprKind::Assign { expr, .. }
+        | ExprKind::IsVoid(expr)
+        | ExprKind::Not(expr)
+        | ExprKind::Negate(expr)
+        | ExprKind::Paren(expr) => visitor.visit_expr_mut(expr),
+        ExprKind::Dispatch { expr, args, .. } => {
+            visitor.visit_expr_mut(expr);
+            args.iter_mut().for_each(|arg| visitor.visit_expr_mut(arg));
+        }
+        ExprKind::FuncCall { args: exprs, .. } | ExprKind::Block(exprs) => {
+            exprs
+                .iter_mut()
+                .for_each(|expr| visitor.visit_expr_mut(expr));
+        }
+        ExprKind::If {
+            cond,
+            then_branch,
+            else_branch,
+        } => {
+            visitor.visit_expr_mut(cond);
+            visitor.visit_expr_mut(then_branch);
+            visitor.visit_expr_mut(else_branch);
+        }
+        ExprKind::While { cond, body } => {
+            visitor.visit_expr_mut(cond);
+            visitor.visit_expr_mut(body);
+        }
+        ExprKind::Let { bindings, body } => {
+            bindings
+                .iter_mut()
+                .for_each(|b| visitor.visit_let_binding_mut(b));
+            visitor.visit_expr_mut(body);
+        }
+        ExprKind::Case { expr, branches } => {
+            visitor.visit_expr_mut(expr);
+            branches
+                .iter_mut()
+                .for_each(|b| visitor.visit_case_branch_mut(b));
+        }
+        ExprKind::Plus(a, b)
+        | ExprKind::Minus(a, b)
+        | ExprKind::Times(a, b)
+        | ExprKind::Divide(a, b)
+        | ExprKind::Lt(a, b)
+        | ExprKind::Le(a, b)
+        | ExprKind::Eq(a, b) => {
+            visitor.visit_expr_mut(a);
+            visitor.visit_expr_mut(b);
+        }
+        ExprKind::New(_)
+        | ExprKind::Id(_)
+        | ExprKind::Integer(_)
+        | ExprKind::String(_)
+        | ExprKind::True
+        | ExprKind::False => {}
+    }
+}
+
+pub fn walk_let_binding_mut<V: VisitorMut + ?Sized>(visitor: &mut V, binding: &mut LetBinding) {
+    if let Some(init) = &mut binding.init {
+        visitor.visit_expr_mut(init);
+    }
+}
+
+pub fn walk_case_branch_mut<V: VisitorMut + ?Sized>(visitor: &mut V, branch: &mut CaseBranch) {
+    visitor.visit_expr_mut(&mut branch.expr);
+}
+
+/// Rebuilds an AST, taking each node by value and returning its replacement, which may be
+/// a different kind of node.
+pub trait Fold {
+    fn fold_program(&mut self, program: Program) -> Program {
+        fold_program(self, program)
+    }
+
+    fn fold_class(&mut self, class: Class) -> Class {
+        fold_class(self, class)
+    }
+
+    fn fold_feature(&mut self, feature: Feature) -> Feature {
+        fold_feature(self, feature)
+    }
+
+    fn fold_method(&mut self, method: MethodFeature) -> MethodFeature {
+        fold_method(self, method)
+    }
+
+    fn fold_attribute(&mut self, attr: AttributeFeature) -> AttributeFeature {
+        fold_attribute(self, attr)
+    }
+
+    fn fold_formal(&mut self, formal: Formal) -> Formal {
+        formal
+    }
+
+    fn fold_expr(&mut self, expr: Expr) -> Expr {
+        fold_expr(self, expr)
+    }
+
+    fn fold_let_binding(&mut self, binding: LetBinding) -> LetBinding {
+        fold_let_binding(self, binding)
+    }
+
+    fn fold_case_branch(&mut self, branch: CaseBranch) -> CaseBranch {
+        fold_case_branch(self, branch)
+    }
+}
+
+pub fn fold_program<F: Fold + ?Sized>(folder: &mut F, program: Program) -> Program {
+    Program {
+        classes: fold_all(program.classes, |c| folder.fold_class(c)),
+    }
+}
+
+pub fn fold_class<F: Fold + ?Sized>(folder: &mut F, class: Class) -> Class {
+    Class {
+        features: fold_all(class.features, |f| folder.fold_feature(f)),
+        ..class
+    }
+}
+
+pub fn fold_feature<F: Fold + ?Sized>(folder: &mut F, feature: Feature) -> Feature {
+    match feature {
+        Feature::Method(method) => Feature::Method(folder.fold_method(method)),
+        Feature::Attribute(attr) => Feature::Attribute(folder.fold_attribute(attr)),
+    }
+}
+
+pub fn fold_method<F: Fold + ?Sized>(folder: &mut F, method: MethodFeature) -> MethodFeature {
+    MethodFeature {
+        formals: fold_all(method.formals, |f| folder.fold_formal(f)),
+        body: folder.fold_expr(method.body),
+        ..method
+    }
+}
+
+pub fn fold_attribute<F: Fold + ?Sized>(
+    folder: &mut F,
+    attr: AttributeFeature,
+) -> AttributeFeature {
+    AttributeFeature {
+        init: attr.init.map(|init| folder.fold_expr(init)),
+        ..attr
+    }
+}
+
+pub fn fold_expr<F: Fold + ?Sized>(folder: &mut F, expr: Expr) -> Expr {
+    let mut fold_box = |expr: Box<Expr>| Box::new(folder.fold_expr(*expr));
+    let Expr { kind, span } = expr;
+    let kind = match kind {
+        ExprKind::Assign { name, expr } => ExprKind::Assign {
+            name,
+            expr: fold_box(expr),
+        },
+        ExprKind::Dispatch {
+            expr,
+            static_type,
+            method,
+            args,
+        } => {
+            let expr = fold_box(expr);
+            ExprKind::Dispatch {
+                expr,
+                static_type,
+                method,
+                args: fold_all(args, |arg| folder.fold_expr(arg)),
+            }
+        }
+        ExprKind::FuncCall { name, args } => ExprKind::FuncCall {
+            name,
+            args: fold_all(args, |arg| folder.fold_expr(arg)),
+        },
+        ExprKind::If {
+            cond,
+            then_branch,
+            else_branch,
+        } => ExprKind::If {
+            cond: fold_box(cond),
+            then_branch: fold_box(then_branch),
+            else_branch: fold_box(else_branch),
+        },
+        ExprKind::While { cond, body } => ExprKind::While {
+            cond: fold_box(cond),
+            body: fold_box(body),
+        },
+        ExprKind::Block(exprs) => ExprKind::Block(fold_all(exprs, |e| folder.fold_expr(e))),
+        ExprKind::Let { bindings, body } => {
+            let bindings = fold_all(bindings, |b| folder.fold_let_binding(b));
+            ExprKind::Let {
+                bindings,
+                body: Box::new(folder.fold_expr(*body)),
+            }
+        }
+        ExprKind::Case { expr, branches } => {
+            let expr = fold_box(expr);
+            ExprKind::Case {
+                expr,
+                branches: fold_all(branches, |b| folder.fold_case_branch(b)),
+            }
+        }
+        ExprKind::IsVoid(expr) => ExprKind::IsVoid(fold_box(expr)),
+        ExprKind::Plus(a, b) => ExprKind::Plus(fold_box(a), fold_box(b)),
+        ExprKind::Minus(a, b) => ExprKind::Minus(fold_box(a), fold_box(b)),
+        ExprKind::Times(a, b) => ExprKind::Times(fold_box(a), fold_box(b)),
+        ExprKind::Divide(a, b) => ExprKind::Divide(fold_box(a), fold_box(b)),
+        ExprKind::Lt(a, b) => ExprKind::Lt(fold_box(a), fold_box(b)),
+        ExprKind::Le(a, b) => ExprKind::Le(fold_box(a), fold_box(b)),
+        ExprKind::Eq(a, b) => ExprKind::Eq(fold_box(a), fold_box(b)),
+        ExprKind::Not(expr) => ExprKind::Not(fold_box(expr)),
+        ExprKind::Negate(expr) => ExprKind::Negate(fold_box(expr)),
+        ExprKind::Paren(expr) => ExprKind::Paren(fold_box(expr)),
+        leaf @ (ExprKind::New(_)
+        | ExprKind::Id(_)
+        | ExprKind::Integer(_)
+        | ExprKind::String(_)
+        | ExprKind::True
+        | ExprKind::False) => leaf,
+    };
+    Expr::new(kind, span)
+}
+
+pub fn fold_let_binding<F: Fold + ?Sized>(folder: &mut F, binding: LetBinding) -> LetBinding {
+    LetBinding {
+        init: binding.init.map(|init| folder.fold_expr(init)),
+        ..binding
+    }
+}
+
+pub fn fold_case_branch<F: Fold + ?Sized>(folder: &mut F, branch: CaseBranch) -> CaseBranch {
+    CaseBranch {
+        expr: folder.fold_expr(branch.expr),
+        ..branch
+    }
+}
+
+fn fold_all<T>(items: Vec<T>, f: impl FnMut(T) -> T) -> Vec<T> {
+    items.into_iter().map(f).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use chumsky::span::Span as _;
+    use lexer::{IntSymbol, Symbol};
+
+    use super::*;
+    use crate::ast::{self, Span};
+
+    const PROGRAM: &str = r#"
+        class Main inherits IO {
+            x : Int <- (1 + 2);
+            main(a : Int, b : String) : Object { {
+                out_string(b.concat("!"));
+                let y : Int <- x, z : Int in case y of i : Int => i + z; esac;
+                while not isvoid self loop x <- ~(x * a) pool;
+                if x < a then new Main else self@IO.out_int(x) fi;
+            } };
+        };
+    "#;
+
+    fn parse(src: &str) -> Program {
+        crate::parse(src).unwrap()
+    }
+
+    /// Records every name the program mentions, in the order they're visited.
+    #[derive(Default)]
+    struct Names<'ast>(Vec<&'ast Symbol>);
+
+    impl<'ast> Visitor<'ast> for Names<'ast> {
+        fn visit_formal(&mut self, formal: &'ast Formal) {
+            self.0.push(&formal.name);
+        }
+
+        fn visit_expr(&mut self, expr: &'ast Expr) {
+            match &expr.kind {
+                ExprKind::Id(name)
+                | ExprKind::Assign { name, .. }
+                | ExprKind::FuncCall { name, .. } => self.0.push(name),
+                ExprKind::Dispatch { method, .. } => self.0.push(method),
+                _ => {}
+            }
+            walk_expr(self, expr);
+        }
+
+        fn visit_let_binding(&mut self, binding: &'ast LetBinding) {
+            walk_let_binding(self, binding);
+            self.0.push(&binding.name);
+        }
+
+        fn visit_case_branch(&mut self, branch: &'ast CaseBranch) {
+            self.0.push(&branch.name);
+            walk_case_branch(self, branch);
+        }
+    }
+
+    #[test]
+    fn test_visitor_order() {
+        let program = parse(PROGRAM);
+        let mut names = Names::default();
+        names.visit_program(&program);
+        let names: Vec<&str> = names.0.iter().map(|name| name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "a",
+                "b",
+                "out_string",
+                "concat",
+                "b",
+                "x",
+                "y",
+                "z",
+                "y",
+                "i",
+                "i",
+                "z",
+                "self",
+                "x",
+                "x",
+                "a",
+                "x",
+                "a",
+                "out_int",
+                "self",
+                "x",
+            ]
+        );
+    }
+
+    /// Counts the expressions of every kind it's not told to skip.
+    struct Count(usize);
+
+    impl Visitor<'_> for Count {
+        fn visit_expr(&mut self, expr: &Expr) {
+            self.0 += 1;
+            walk_expr(self, expr);
+        }
+
+        // Skipping a node's children is a matter of not calling the walk function.
+        fn visit_attribute(&mut self, _attr: &AttributeFeature) {}
+    }
+
+    #[test]
+    fn test_visitor_skips_children() {
+        let program = parse("class A { x : Int <- 1 + 2; f() : Int { 3 + 4 * 5 }; };");
+        let mut count = Count(0);
+        count.visit_program(&program);
+        assert_eq!(count.0, 5);
+    }
+
+    /// Renames a variable, formals and `let` bindings included.
+    struct Rename(Symbol, Symbol);
+
+    impl VisitorMut for Rename {
+        fn visit_formal_mut(&mut self, formal: &mut Formal) {
+            if formal.name == self.0 {
+                formal.name = self.1;
+            }
+        }
+
+        fn visit_expr_mut(&mut self, expr: &mut Expr) {
+            match &mut expr.kind {
+                ExprKind::Id(name) | ExprKind::Assign { name, .. } if *name == self.0 => {
+                    *name = self.1
+                }
+                _ => {}
+            }
+            walk_expr_mut(self, expr);
+        }
+
+        fn visit_let_binding_mut(&mut self, binding: &mut LetBinding) {
+            if binding.name == self.0 {
+                binding.name = self.1;
+            }
+            walk_let_binding_mut(self, binding);
+        }
+    }
+
+    #[test]
+    fn test_visitor_mut() {
+        let mut program = parse(PROGRAM);
+        Rename(Symbol::intern("a"), Symbol::intern("n")).visit_program_mut(&mut program);
+        Rename(Symbol::intern("y"), Symbol::intern("x")).visit_program_mut(&mut program);
+        let mut names = Names::default();
+        names.visit_program(&program);
+        let names: Vec<&str> = names.0.iter().map(|name| name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "n",
+                "b",
+                "out_string",
+                "concat",
+                "b",
+                "x",
+                "x",
+                "z",
+                "x",
+                "i",
+                "i",
+                "z",
+                "self",
+                "x",
+                "x",
+                "n",
+                "x",
+                "n",
+                "out_int",
+                "self",
+                "x",
+            ]
+        );
+    }
+
+    /// Removes parentheses and folds `~` of a constant.
+    struct Simplify;
+
+    impl Fold for Simplify {
+        fn fold_expr(&mut self, expr: Expr) -> Expr {
+            let expr = fold_expr(self, expr);
+            let kind = match expr.kind {
+                ExprKind::Paren(inner) => inner.kind,
+                ExprKind::Negate(inner) => match inner.kind {
+                    ExprKind::Integer(i) => ExprKind::Integer(IntSymbol::intern(-i.value())),
+                    _ => ExprKind::Negate(inner),
+                },
+                kind => kind,
+            };
+            Expr::new(kind, expr.span)
+        }
+    }
+
+    /// Gives every expression the same span, so trees can be compared by shape.
+    struct EraseSpans;
+
+    impl Fold for EraseSpans {
+        fn fold_expr(&mut self, expr: Expr) -> Expr {
+            let expr = fold_expr(self, expr);
+            Expr::new(expr.kind, Span::new(Default::default(), 0..0))
+        }
+    }
+
+    #[test]
+    fn test_fold() {
+        let src = "class A { f() : Int { ((~(2)) + (~((x)))) * ((3)) }; };";
+        let program = parse(src);
+        let folded = Simplify.fold_program(program);
+        let ast::Feature::Method(method) = &folded.classes[0].features[0] else {
+            panic!("expected a method");
+        };
+        let ExprKind::Times(lhs, _) = &method.body.kind else {
+            panic!("expected a product");
+        };
+        let ExprKind::Plus(two, _) = &lhs.kind else {
+            panic!("expected a sum");
+        };
+        // Folding keeps the span of the expression it replaces.
+        assert_eq!(&src[two.span.start..two.span.end], "(~(2))");
+
+        let expr = |kind| Box::new(Expr::new(kind, Span::new(Default::default(), 0..0)));
+        let expected = expr(ExprKind::Times(
+            expr(ExprKind::Plus(
+                expr(ExprKind::Integer(IntSymbol::intern(-2))),
+                expr(ExprKind::Negate(expr(ExprKind::Id(Symbol::intern("x"))))),
+            )),
+            expr(ExprKind::Integer(IntSymbol::intern(3))),
+        ));
+        assert_eq!(
+            format!("{:?}", EraseSpans.fold_expr(method.body.clone())),
+            format!("{expected:?}")
+        );
+
+        // A fold that overrides nothing gives back the same program.
+        struct Identity;
+        impl Fold for Identity {}
+        let program = parse(PROGRAM);
+        let folded = Identity.fold_program(program.clone());
+        assert_eq!(format!("{folded:?}"), format!("{program:?}"));
+    }
+}
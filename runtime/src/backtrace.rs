@@ -0,0 +1,132 @@
+//! Reporting a runtime error the same way whichever engine ran the program: the error
+//! with the file and line of the expression that failed, then the COOL methods that
+//! were running, innermost first, each at the line of the expression it was running.
+//!
+//! Builtin methods have no frames: an error in `substr` points at the call to it. A
+//! method inlined by the optimizer has no frame either, so its lines are reported in
+//! the frame of the method it was inlined into.
+
+use std::fmt::Write;
+
+use lexer::source::SourceMap;
+use parser::ast::Span;
+
+use crate::RuntimeError;
+
+/// Exit status of a program that stopped with a runtime error. Every engine uses it, so
+/// it can be told apart from a compile error (1), a bad command line (2) and an
+/// unreadable input (3).
+pub const EXIT_RUNTIME_ERROR: u8 = 4;
+
+/// Backtraces longer than this show only their innermost and outermost frames.
+const MAX_SHOWN: usize = 20;
+/// How many of the shown frames of a long backtrace are the outermost ones.
+const OUTERMOST_SHOWN: usize = 4;
+
+/// A COOL method that was running when the program failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StackFrame {
+    /// `Class.method`, or `Class._init` for an initializer.
+    pub function: String,
+    pub file: String,
+    pub line: usize,
+}
+
+impl StackFrame {
+    /// The frame of `class.method`, running the expression at `span`.
+    pub fn new(class: &str, method: &str, span: Span, sources: &SourceMap) -> Self {
+        let file = sources.file(span.context);
+        StackFrame {
+            function: format!("{class}.{method}"),
+            file: file.name().to_string(),
+            line: file.location(span.start).line,
+        }
+    }
+}
+
+/// Formats `error` as `file:line: runtime error: message`, followed by a
+/// `#i  Class.method at file:line` line per frame of `backtrace`, innermost first.
+/// Without frames, there's no location either.
+pub fn report(error: &RuntimeError, backtrace: &[StackFrame]) -> String {
+    let mut out = String::new();
+    if let Some(frame) = backtrace.first() {
+        write!(out, "{}:{}: ", frame.file, frame.line).unwrap();
+    }
+    writeln!(out, "runtime error: {error}").unwrap();
+    let omitted = backtrace.len().saturating_sub(MAX_SHOWN);
+    for (i, frame) in backtrace.iter().enumerate() {
+        if omitted > 0 && i == MAX_SHOWN - OUTERMOST_SHOWN {
+            writeln!(out, "... {omitted} frames omitted ...").unwrap();
+        }
+        if omitted > 0
+            && (MAX_SHOWN - OUTERMOST_SHOWN..backtrace.len() - OUTERMOST_SHOWN).contains(&i)
+        {
+            continue;
+        }
+        writeln!(
+            out,
+            "#{i}  {} at {}:{}",
+            frame.function, frame.file, frame.line
+        )
+        .unwrap();
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(function: &str, line: usize) -> StackFrame {
+        StackFrame {
+            function: function.to_string(),
+            file: "a.cl".to_string(),
+            line,
+        }
+    }
+
+    #[test]
+    fn test_report() {
+        let error = RuntimeError::DispatchOnVoid {
+            method: "f".to_string(),
+        };
+        assert_eq!(
+            report(&error, &[frame("A.g", 3), frame("Main.main", 9)]),
+            "a.cl:3: runtime error: dispatch to void (f)\n\
+             #0  A.g at a.cl:3\n\
+             #1  Main.main at a.cl:9\n"
+        );
+        assert_eq!(
+            report(
+                &RuntimeError::Undefined("method Main.main".to_string()),
+                &[]
+            ),
+            "runtime error: undefined method Main.main\n"
+        );
+
+        let mut sources = SourceMap::new();
+        let file = sources.add("b.cl", "class A {\n  -- f\n  f() : Int { 0 };\n};");
+        let span = Span {
+            start: 19,
+            end: 36,
+            context: file,
+        };
+        assert_eq!(StackFrame::new("A", "f", span, &sources), {
+            let mut f = frame("A.f", 3);
+            f.file = "b.cl".to_string();
+            f
+        });
+    }
+
+    #[test]
+    fn test_long_backtraces_are_shortened() {
+        let frames: Vec<StackFrame> = (0..100).map(|i| frame("A.f", i)).collect();
+        let report = report(&RuntimeError::StackOverflow, &frames);
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(lines.len(), 1 + MAX_SHOWN + 1);
+        assert_eq!(lines[16], "#15  A.f at a.cl:15");
+        assert_eq!(lines[17], "... 80 frames omitted ...");
+        assert_eq!(lines[18], "#96  A.f at a.cl:96");
+        assert_eq!(lines[21], "#99  A.f at a.cl:99");
+    }
+}
@@ -0,0 +1,476 @@
+//! The garbage-collected object heap.
+//!
+//! Collection is precise: the interpreter keeps every live value in its shadow stack
+//! and only lets the heap collect at safepoints between instructions, so the roots
+//! handed to [`Heap::collect`] are exactly the references the program can still reach.
+//!
+//! Three strategies are available:
+//!
+//! - [`GcKind::None`] never frees anything.
+//! - [`GcKind::MarkSweep`] allocates everything in one space and marks from the roots,
+//!   sweeping unmarked objects onto a free list.
+//! - [`GcKind::Generational`] bump-allocates into a nursery that is evacuated on every
+//!   minor collection, promoting survivors into a mark-swept old generation. A write
+//!   barrier remembers old objects that are made to point into the nursery.
+
+use std::fmt;
+use std::str::FromStr;
+
+/// Bytes allocated into the nursery before a minor collection is requested.
+pub const NURSERY_BYTES: usize = 256 * 1024;
+
+/// Smallest old-generation growth that triggers a full collection.
+pub const MIN_MAJOR_BYTES: usize = 1024 * 1024;
+
+/// Live bytes beyond which the heap is reported as exhausted.
+pub const DEFAULT_HEAP_LIMIT: usize = 512 * 1024 * 1024;
+
+/// Bytes charged per object on top of its payload.
+const HEADER_BYTES: usize = 16;
+
+/// Marks references into the nursery.
+const NURSERY_BIT: u32 = 1 << 31;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GcKind {
+    None,
+    MarkSweep,
+    #[default]
+    Generational,
+}
+
+impl FromStr for GcKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(GcKind::None),
+            "marksweep" => Ok(GcKind::MarkSweep),
+            "generational" => Ok(GcKind::Generational),
+            other => Err(format!(
+                "unknown collector '{other}' (expected none, marksweep or generational)"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for GcKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GcKind::None => write!(f, "none"),
+            GcKind::MarkSweep => write!(f, "marksweep"),
+            GcKind::Generational => write!(f, "generational"),
+        }
+    }
+}
+
+/// A reference to a heap object.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct GcRef(u32);
+
+impl GcRef {
+    fn in_nursery(self) -> bool {
+        self.0 & NURSERY_BIT != 0
+    }
+
+    fn index(self) -> usize {
+        (self.0 & !NURSERY_BIT) as usize
+    }
+}
+
+/// A COOL value. `Int` and `Bool` are unboxed; everything else lives on the heap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Value {
+    Void,
+    Int(i32),
+    Bool(bool),
+    Ref(GcRef),
+}
+
+#[derive(Debug, Clone)]
+pub enum ObjData {
+    Fields(Vec<Value>),
+    Str(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct Object {
+    /// Index of the object's class in the program's class table.
+    pub class: usize,
+    pub data: ObjData,
+}
+
+impl Object {
+    fn size(&self) -> usize {
+        HEADER_BYTES
+            + match &self.data {
+                ObjData::Fields(fields) => fields.len() * std::mem::size_of::<Value>(),
+                ObjData::Str(s) => s.len(),
+            }
+    }
+
+    fn refs_mut(&mut self) -> impl Iterator<Item = &mut GcRef> {
+        let fields: &mut [Value] = match &mut self.data {
+            ObjData::Fields(fields) => fields,
+            ObjData::Str(_) => &mut [],
+        };
+        fields.iter_mut().filter_map(|v| match v {
+            Value::Ref(r) => Some(r),
+            _ => None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GcStats {
+    pub minor_collections: usize,
+    pub major_collections: usize,
+    pub bytes_allocated: usize,
+    pub bytes_reclaimed: usize,
+    pub bytes_promoted: usize,
+}
+
+impl GcStats {
+    pub fn collections(&self) -> usize {
+        self.minor_collections + self.major_collections
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeapExhausted {
+    pub live_bytes: usize,
+    pub limit: usize,
+}
+
+pub struct Heap {
+    kind: GcKind,
+    nursery: Vec<Object>,
+    nursery_bytes: usize,
+    old: Vec<Option<Object>>,
+    free: Vec<usize>,
+    old_bytes: usize,
+    /// Old-generation size that triggers the next full collection.
+    major_threshold: usize,
+    /// Old objects written to point into the nursery since the last minor collection.
+    remembered: Vec<usize>,
+    limit: usize,
+    stats: GcStats,
+}
+
+impl Heap {
+    pub fn new(kind: GcKind) -> Self {
+        Heap::with_limit(kind, DEFAULT_HEAP_LIMIT)
+    }
+
+    pub fn with_limit(kind: GcKind, limit: usize) -> Self {
+        Heap {
+            kind,
+            nursery: Vec::new(),
+            nursery_bytes: 0,
+            old: Vec::new(),
+            free: Vec::new(),
+            old_bytes: 0,
+            major_threshold: MIN_MAJOR_BYTES,
+            remembered: Vec::new(),
+            limit,
+            stats: GcStats::default(),
+        }
+    }
+
+    pub fn kind(&self) -> GcKind {
+        self.kind
+    }
+
+    pub fn stats(&self) -> &GcStats {
+        &self.stats
+    }
+
+    pub fn live_bytes(&self) -> usize {
+        self.nursery_bytes + self.old_bytes
+    }
+
+    /// Allocates `object`. Never collects: collection only happens at safepoints.
+    pub fn alloc(&mut self, object: Object) -> GcRef {
+        let size = object.size();
+        self.stats.bytes_allocated += size;
+        if self.kind == GcKind::Generational {
+            self.nursery_bytes += size;
+            self.nursery.push(object);
+            GcRef((self.nursery.len() - 1) as u32 | NURSERY_BIT)
+        } else {
+            self.alloc_old(object, size)
+        }
+    }
+
+    fn alloc_old(&mut self, object: Object, size: usize) -> GcRef {
+        self.old_bytes += size;
+        match self.free.pop() {
+            Some(index) => {
+                self.old[index] = Some(object);
+                GcRef(index as u32)
+            }
+            None => {
+                self.old.push(Some(object));
+                GcRef((self.old.len() - 1) as u32)
+            }
+        }
+    }
+
+    pub fn get(&self, r: GcRef) -> &Object {
+        if r.in_nursery() {
+            &self.nursery[r.index()]
+        } else {
+            self.old[r.index()].as_ref().expect("dangling reference")
+        }
+    }
+
+    fn get_mut(&mut self, r: GcRef) -> &mut Object {
+        if r.in_nursery() {
+            &mut self.nursery[r.index()]
+        } else {
+            self.old[r.index()].as_mut().expect("dangling reference")
+        }
+    }
+
+    pub fn field(&self, r: GcRef, index: usize) -> Value {
+        match &self.get(r).data {
+            ObjData::Fields(fields) => fields[index],
+            ObjData::Str(_) => panic!("string objects have no fields"),
+        }
+    }
+
+    /// Stores into a field, recording old-to-young pointers for the next minor
+    /// collection.
+    pub fn set_field(&mut self, r: GcRef, index: usize, value: Value) {
+        if !r.in_nursery() && matches!(value, Value::Ref(v) if v.in_nursery()) {
+            self.remembered.push(r.index());
+        }
+        match &mut self.get_mut(r).data {
+            ObjData::Fields(fields) => fields[index] = value,
+            ObjData::Str(_) => panic!("string objects have no fields"),
+        }
+    }
+
+    pub fn string(&self, r: GcRef) -> &str {
+        match &self.get(r).data {
+            ObjData::Str(s) => s,
+            ObjData::Fields(_) => panic!("not a string object"),
+        }
+    }
+
+    /// Whether enough has been allocated that the next safepoint should collect.
+    pub fn should_collect(&self) -> bool {
+        match self.kind {
+            GcKind::None => self.live_bytes() > self.limit,
+            GcKind::MarkSweep => self.old_bytes >= self.major_threshold,
+            GcKind::Generational => {
+                self.nursery_bytes >= NURSERY_BYTES || self.old_bytes >= self.major_threshold
+            }
+        }
+    }
+
+    /// Collects garbage, treating `roots` as the only references into the heap and
+    /// updating them if their objects move.
+    pub fn collect(&mut self, roots: &mut [Value]) -> Result<(), HeapExhausted> {
+        match self.kind {
+            GcKind::None => {}
+            GcKind::MarkSweep => self.major(roots),
+            GcKind::Generational => {
+                self.minor(roots);
+                if self.old_bytes >= self.major_threshold {
+                    self.major(roots);
+                }
+            }
+        }
+        if self.live_bytes() > self.limit {
+            return Err(HeapExhausted {
+                live_bytes: self.live_bytes(),
+                limit: self.limit,
+            });
+        }
+        Ok(())
+    }
+
+    /// Evacuates every reachable nursery object into the old generation.
+    fn minor(&mut self, roots: &mut [Value]) {
+        self.stats.minor_collections += 1;
+        let mut forwarded: Vec<Option<GcRef>> = vec![None; self.nursery.len()];
+        let mut promoted: Vec<usize> = Vec::new();
+
+        for root in roots.iter_mut() {
+            if let Value::Ref(r) = root {
+                *r = self.evacuate(*r, &mut forwarded, &mut promoted);
+            }
+        }
+        // Old objects that point into the nursery, and then everything promoted
+        // along the way, Cheney-style.
+        let mut scan: Vec<usize> = std::mem::take(&mut self.remembered);
+        loop {
+            scan.append(&mut promoted);
+            let Some(index) = scan.pop() else {
+                break;
+            };
+            let mut object = self.old[index].take().unwrap();
+            for r in object.refs_mut() {
+                *r = self.evacuate(*r, &mut forwarded, &mut promoted);
+            }
+            self.old[index] = Some(object);
+        }
+
+        let survived: usize = forwarded
+            .iter()
+            .flatten()
+            .map(|r| self.get(*r).size())
+            .sum();
+        self.stats.bytes_reclaimed += self.nursery_bytes - survived;
+        self.stats.bytes_promoted += survived;
+        self.nursery.clear();
+        self.nursery_bytes = 0;
+    }
+
+    fn evacuate(
+        &mut self,
+        r: GcRef,
+        forwarded: &mut [Option<GcRef>],
+        promoted: &mut Vec<usize>,
+    ) -> GcRef {
+        if !r.in_nursery() {
+            return r;
+        }
+        if let Some(to) = forwarded[r.index()] {
+            return to;
+        }
+        let object = self.nursery[r.index()].clone();
+        let size = object.size();
+        let to = self.alloc_old(object, size);
+        forwarded[r.index()] = Some(to);
+        promoted.push(to.index());
+        to
+    }
+
+    /// Marks the old generation from `roots` and sweeps what wasn't reached. The
+    /// nursery must be empty.
+    fn major(&mut self, roots: &[Value]) {
+        debug_assert!(self.nursery.is_empty());
+        self.stats.major_collections += 1;
+        let mut marked = vec![false; self.old.len()];
+        let mut stack: Vec<GcRef> = roots
+            .iter()
+            .filter_map(|v| match v {
+                Value::Ref(r) => Some(*r),
+                _ => None,
+            })
+            .collect();
+        while let Some(r) = stack.pop() {
+            if std::mem::replace(&mut marked[r.index()], true) {
+                continue;
+            }
+            if let ObjData::Fields(fields) = &self.get(r).data {
+                stack.extend(fields.iter().filter_map(|v| match v {
+                    Value::Ref(r) => Some(*r),
+                    _ => None,
+                }));
+            }
+        }
+
+        for (index, slot) in self.old.iter_mut().enumerate() {
+            if marked[index] {
+                continue;
+            }
+            if let Some(object) = slot.take() {
+                let size = object.size();
+                self.old_bytes -= size;
+                self.stats.bytes_reclaimed += size;
+                self.free.push(index);
+            }
+        }
+        self.major_threshold = MIN_MAJOR_BYTES.max(self.old_bytes * 2);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(heap: &mut Heap, s: &str) -> Value {
+        Value::Ref(heap.alloc(Object {
+            class: 0,
+            data: ObjData::Str(s.to_string()),
+        }))
+    }
+
+    fn pair(heap: &mut Heap, a: Value, b: Value) -> Value {
+        Value::Ref(heap.alloc(Object {
+            class: 1,
+            data: ObjData::Fields(vec![a, b]),
+        }))
+    }
+
+    fn as_ref(v: Value) -> GcRef {
+        match v {
+            Value::Ref(r) => r,
+            _ => panic!("not a reference"),
+        }
+    }
+
+    #[test]
+    fn test_gc_kind_from_str() {
+        assert_eq!("none".parse(), Ok(GcKind::None));
+        assert_eq!("marksweep".parse(), Ok(GcKind::MarkSweep));
+        assert_eq!("generational".parse(), Ok(GcKind::Generational));
+        assert!("copying".parse::<GcKind>().is_err());
+    }
+
+    #[test]
+    fn test_collectors_keep_reachable_objects() {
+        for kind in [GcKind::MarkSweep, GcKind::Generational] {
+            let mut heap = Heap::new(kind);
+            let hello = string(&mut heap, "hello");
+            let inner = pair(&mut heap, hello, Value::Int(7));
+            let outer = pair(&mut heap, inner, Value::Void);
+            for i in 0..100 {
+                string(&mut heap, &format!("garbage {i}"));
+            }
+            let mut roots = [outer];
+            heap.collect(&mut roots).unwrap();
+
+            let inner = heap.field(as_ref(roots[0]), 0);
+            assert_eq!(heap.field(as_ref(inner), 1), Value::Int(7));
+            assert_eq!(heap.string(as_ref(heap.field(as_ref(inner), 0))), "hello");
+            assert!(heap.stats().bytes_reclaimed > 0, "{kind} reclaimed nothing");
+        }
+    }
+
+    #[test]
+    fn test_write_barrier_keeps_young_objects_alive() {
+        let mut heap = Heap::new(GcKind::Generational);
+        let old = pair(&mut heap, Value::Void, Value::Void);
+        let mut roots = [old];
+        heap.collect(&mut roots).unwrap();
+
+        // The only reference to the young string is from an old object.
+        let young = string(&mut heap, "young");
+        heap.set_field(as_ref(roots[0]), 0, young);
+        heap.collect(&mut roots).unwrap();
+
+        let field = heap.field(as_ref(roots[0]), 0);
+        assert_eq!(heap.string(as_ref(field)), "young");
+        assert_eq!(heap.stats().minor_collections, 2);
+    }
+
+    #[test]
+    fn test_none_never_reclaims() {
+        let mut heap = Heap::new(GcKind::None);
+        string(&mut heap, "garbage");
+        heap.collect(&mut []).unwrap();
+        assert_eq!(heap.stats().bytes_reclaimed, 0);
+        assert_eq!(heap.stats().collections(), 0);
+    }
+
+    #[test]
+    fn test_heap_exhaustion() {
+        let mut heap = Heap::with_limit(GcKind::MarkSweep, 100);
+        let mut roots: Vec<Value> = (0..10).map(|_| string(&mut heap, "live")).collect();
+        assert!(heap.collect(&mut roots).is_err());
+    }
+}
@@ -0,0 +1,6 @@
+pub mod backtrace;
+pub mod heap;
+pub mod vm;
+
+pub use heap::{GcKind, GcStats, Heap};
+pub use vm::{Options, RuntimeError, Vm};
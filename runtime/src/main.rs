@@ -0,0 +1,84 @@
+use clap::Parser;
+use ir::OptLevel;
+use lexer::source::SourceMap;
+use runtime::backtrace::{self, EXIT_RUNTIME_ERROR};
+use runtime::{GcKind, Options, Vm};
+use std::io::{self, BufWriter};
+use std::process::ExitCode;
+
+#[derive(Parser)]
+#[command(name = "runtime")]
+#[command(about = "Runs a Cool program on a garbage-collected heap")]
+#[command(version)]
+struct Args {
+    /// Input Cool (.cl) file
+    #[arg(value_name = "FILE")]
+    file: String,
+
+    /// Optimization level (0, 1 or 2)
+    #[arg(short = 'O', default_value = "0")]
+    opt_level: OptLevel,
+
+    /// Garbage collector: none, marksweep or generational
+    #[arg(long, default_value = "generational")]
+    gc: GcKind,
+
+    /// Print collection counts and bytes reclaimed when the program exits
+    #[arg(long)]
+    gc_stats: bool,
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    let mut sources = SourceMap::new();
+    let file = match sources.load(&args.file) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("Error: failed to read '{}': {e}", args.file);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let program = match parser::parse_file(sources.file(file).src(), file) {
+        Ok(program) => program,
+        Err(errors) => {
+            for error in errors {
+                eprintln!("{}", parser::format_error(&error, &sources));
+            }
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let mut module = ir::lower(&program);
+    ir::optimize(&mut module, args.opt_level);
+
+    let options = Options {
+        gc: args.gc,
+        ..Options::default()
+    };
+    let mut stdin = io::stdin().lock();
+    let mut stdout = BufWriter::new(io::stdout().lock());
+    let mut vm = Vm::new(&module, options, &mut stdin, &mut stdout);
+    let result = vm.run();
+
+    if args.gc_stats {
+        let stats = vm.gc_stats();
+        eprintln!(
+            "gc: {}, collections: {} ({} minor, {} major), allocated: {} bytes, reclaimed: {} bytes, promoted: {} bytes",
+            vm.gc_kind(),
+            stats.collections(),
+            stats.minor_collections,
+            stats.major_collections,
+            stats.bytes_allocated,
+            stats.bytes_reclaimed,
+            stats.bytes_promoted
+        );
+    }
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprint!("{}", backtrace::report(&e, &vm.backtrace(&sources)));
+            ExitCode::from(EXIT_RUNTIME_ERROR)
+        }
+    }
+}
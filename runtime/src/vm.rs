@@ -0,0 +1,788 @@
+//! An interpreter for the IR that runs on the garbage-collected [`Heap`].
+//!
+//! Every frame's registers live in one contiguous shadow stack, which is the root set
+//! handed to the collector. The interpreter only collects between instructions, when
+//! every live value is in a register.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{BufRead, Write};
+
+use ir::{BinOp, BlockId, Const, Function, INIT_METHOD, Inst, Module, Reg, Target, Terminator};
+use lexer::source::SourceMap;
+use lexer::{Symbol, sym};
+use parser::ast::Span;
+
+use crate::backtrace::StackFrame;
+use crate::heap::{GcKind, GcRef, GcStats, Heap, HeapExhausted, ObjData, Object, Value};
+
+/// Frames deeper than this are reported as a stack overflow.
+pub const MAX_FRAMES: usize = 100_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Options {
+    pub gc: GcKind,
+    pub heap_limit: usize,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            gc: GcKind::default(),
+            heap_limit: crate::heap::DEFAULT_HEAP_LIMIT,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RuntimeError {
+    DispatchOnVoid {
+        method: String,
+    },
+    CaseOnVoid,
+    CaseNoMatch {
+        class: String,
+    },
+    SubstrOutOfRange {
+        index: i32,
+        length: i32,
+    },
+    DivisionByZero,
+    HeapExhausted(HeapExhausted),
+    StackOverflow,
+    Abort {
+        class: String,
+    },
+    /// An operation saw a value of the wrong class; only possible for programs that
+    /// wouldn't pass type checking.
+    TypeMismatch {
+        expected: &'static str,
+    },
+    Undefined(String),
+    Io(String),
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuntimeError::DispatchOnVoid { method } => write!(f, "dispatch to void ({method})"),
+            RuntimeError::CaseOnVoid => write!(f, "match on void in case statement"),
+            RuntimeError::CaseNoMatch { class } => {
+                write!(f, "no match in case statement for class {class}")
+            }
+            RuntimeError::SubstrOutOfRange { index, length } => {
+                write!(f, "substring out of range (index {index}, length {length})")
+            }
+            RuntimeError::DivisionByZero => write!(f, "division by zero"),
+            RuntimeError::HeapExhausted(e) => write!(
+                f,
+                "heap exhausted ({} live bytes, limit {})",
+                e.live_bytes, e.limit
+            ),
+            RuntimeError::StackOverflow => write!(f, "stack overflow"),
+            RuntimeError::Abort { class } => write!(f, "abort called from class {class}"),
+            RuntimeError::TypeMismatch { expected } => {
+                write!(f, "expected a value of type {expected}")
+            }
+            RuntimeError::Undefined(what) => write!(f, "undefined {what}"),
+            RuntimeError::Io(e) => write!(f, "I/O error: {e}"),
+        }
+    }
+}
+
+impl From<HeapExhausted> for RuntimeError {
+    fn from(e: HeapExhausted) -> Self {
+        RuntimeError::HeapExhausted(e)
+    }
+}
+
+impl From<std::io::Error> for RuntimeError {
+    fn from(e: std::io::Error) -> Self {
+        RuntimeError::Io(e.to_string())
+    }
+}
+
+struct Frame<'m> {
+    func: &'m Function,
+    block: BlockId,
+    /// Index of the next instruction; equal to the instruction count at the terminator.
+    ip: usize,
+    /// Offset of the frame's registers in the shadow stack.
+    base: usize,
+    /// Register in the caller's frame that receives the return value.
+    ret: Option<Reg>,
+    /// The span of the instruction or terminator being run, which is where an error in
+    /// it is reported.
+    location: Span,
+}
+
+pub struct Vm<'m, 'io> {
+    module: &'m Module,
+    functions: HashMap<(Symbol, Symbol), &'m Function>,
+    class_index: HashMap<Symbol, usize>,
+    /// Field slot of each `(class, attribute)` pair, for every class that has it.
+    field_index: HashMap<(Symbol, Symbol), usize>,
+    heap: Heap,
+    stack: Vec<Value>,
+    frames: Vec<Frame<'m>>,
+    input: &'io mut dyn BufRead,
+    output: &'io mut dyn Write,
+}
+
+impl<'m, 'io> Vm<'m, 'io> {
+    pub fn new(
+        module: &'m Module,
+        options: Options,
+        input: &'io mut dyn BufRead,
+        output: &'io mut dyn Write,
+    ) -> Self {
+        let functions = module
+            .functions
+            .iter()
+            .map(|f| ((f.class, f.method), f))
+            .collect();
+        let class_index = module
+            .classes
+            .iter()
+            .enumerate()
+            .map(|(i, c)| (c.name, i))
+            .collect();
+        let mut field_index = HashMap::new();
+        for class in &module.classes {
+            for (slot, (_, attr)) in module.all_attributes(class.name).into_iter().enumerate() {
+                field_index.insert((class.name, attr.name), slot);
+            }
+        }
+        Vm {
+            module,
+            functions,
+            class_index,
+            field_index,
+            heap: Heap::with_limit(options.gc, options.heap_limit),
+            stack: Vec::new(),
+            frames: Vec::new(),
+            input,
+            output,
+        }
+    }
+
+    pub fn gc_stats(&self) -> &GcStats {
+        self.heap.stats()
+    }
+
+    pub fn gc_kind(&self) -> GcKind {
+        self.heap.kind()
+    }
+
+    /// The methods that were running when [`Vm::run`] stopped, innermost first. Empty
+    /// after a normal exit.
+    pub fn backtrace(&self, sources: &SourceMap) -> Vec<StackFrame> {
+        self.frames
+            .iter()
+            .rev()
+            .map(|frame| {
+                let func = frame.func;
+                StackFrame::new(&func.class, &func.method, frame.location, sources)
+            })
+            .collect()
+    }
+
+    /// Runs `(new Main).main()` to completion.
+    pub fn run(&mut self) -> Result<(), RuntimeError> {
+        let main = self
+            .functions
+            .get(&(sym::MAIN_CLASS, sym::MAIN_METHOD))
+            .copied()
+            .ok_or_else(|| RuntimeError::Undefined("method Main.main".to_string()))?;
+        let this = self.alloc_object(sym::MAIN_CLASS)?;
+        // `main` sits under `Main`'s initializer, so the initializer runs first.
+        self.push_frame(main, &[this], None)?;
+        if let Some(init) = self.functions.get(&(sym::MAIN_CLASS, INIT_METHOD)).copied() {
+            self.push_frame(init, &[this], None)?;
+        }
+        let result = self.execute();
+        self.output.flush()?;
+        result
+    }
+
+    fn execute(&mut self) -> Result<(), RuntimeError> {
+        while let Some(frame) = self.frames.last() {
+            if self.heap.should_collect() {
+                self.heap.collect(&mut self.stack)?;
+            }
+            let func = frame.func;
+            let block = func.block(frame.block);
+            let frame = self.frames.last_mut().unwrap();
+            if let Some(stmt) = block.stmts.get(frame.ip) {
+                frame.ip += 1;
+                frame.location = stmt.span;
+                self.exec(&stmt.inst)?;
+            } else {
+                frame.location = block.term_span;
+                self.terminate(&block.term)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn reg(&self, reg: Reg) -> Value {
+        self.stack[self.frames.last().unwrap().base + reg.0 as usize]
+    }
+
+    fn set_reg(&mut self, reg: Reg, value: Value) {
+        let base = self.frames.last().unwrap().base;
+        self.stack[base + reg.0 as usize] = value;
+    }
+
+    fn push_frame(
+        &mut self,
+        func: &'m Function,
+        args: &[Value],
+        ret: Option<Reg>,
+    ) -> Result<(), RuntimeError> {
+        if self.frames.len() >= MAX_FRAMES {
+            return Err(RuntimeError::StackOverflow);
+        }
+        let base = self.stack.len();
+        self.stack
+            .resize(base + func.next_reg as usize, Value::Void);
+        for (param, value) in func.params.iter().zip(args) {
+            self.stack[base + param.0 as usize] = *value;
+        }
+        self.frames.push(Frame {
+            func,
+            block: func.entry,
+            ip: 0,
+            base,
+            ret,
+            location: func.span,
+        });
+        self.enter_block(func.entry, None);
+        Ok(())
+    }
+
+    /// Moves the current frame to `block`, evaluating its phis in parallel.
+    fn enter_block(&mut self, block: BlockId, from: Option<BlockId>) {
+        let frame = self.frames.last_mut().unwrap();
+        frame.block = block;
+        frame.ip = 0;
+        let phis: Vec<(Reg, Value)> = frame
+            .func
+            .block(block)
+            .insts()
+            .map_while(|inst| match inst {
+                Inst::Phi { dst, args } => Some((*dst, args)),
+                _ => None,
+            })
+            .map(|(dst, args)| {
+                let value = args
+                    .iter()
+                    .find(|(pred, _)| Some(*pred) == from)
+                    .map_or(Value::Void, |(_, src)| self.reg(*src));
+                (dst, value)
+            })
+            .collect();
+        self.frames.last_mut().unwrap().ip = phis.len();
+        for (dst, value) in phis {
+            self.set_reg(dst, value);
+        }
+    }
+
+    fn terminate(&mut self, term: &Terminator) -> Result<(), RuntimeError> {
+        let current = self.frames.last().unwrap().block;
+        match term {
+            Terminator::Jump(target) => self.enter_block(*target, Some(current)),
+            Terminator::Branch {
+                cond,
+                then_block,
+                else_block,
+            } => {
+                let target = if self.bool(self.reg(*cond))? {
+                    *then_block
+                } else {
+                    *else_block
+                };
+                self.enter_block(target, Some(current));
+            }
+            Terminator::Case { scrutinee, arms } => {
+                let value = self.reg(*scrutinee);
+                if value == Value::Void {
+                    return Err(RuntimeError::CaseOnVoid);
+                }
+                let class = self.class_of(value);
+                let arm =
+                    self.module
+                        .case_arm(class, arms)
+                        .ok_or_else(|| RuntimeError::CaseNoMatch {
+                            class: class.to_string(),
+                        })?;
+                self.enter_block(arms[arm].block, Some(current));
+            }
+            Terminator::Return(reg) => {
+                let value = self.reg(*reg);
+                let frame = self.frames.pop().unwrap();
+                self.stack.truncate(frame.base);
+                if let (Some(ret), Some(_)) = (frame.ret, self.frames.last()) {
+                    self.set_reg(ret, value);
+                }
+            }
+            Terminator::Unreachable => unreachable!("executed an unreachable terminator"),
+        }
+        Ok(())
+    }
+
+    fn exec(&mut self, inst: &'m Inst) -> Result<(), RuntimeError> {
+        match inst {
+            Inst::Const { dst, value } => {
+                let value = match value {
+                    Const::Int(i) => Value::Int(*i),
+                    Const::Bool(b) => Value::Bool(*b),
+                    Const::Str(s) => self.alloc_string(s.to_string()),
+                    Const::Void => Value::Void,
+                };
+                self.set_reg(*dst, value);
+            }
+            Inst::Copy { dst, src } => self.set_reg(*dst, self.reg(*src)),
+            Inst::Binary { dst, op, lhs, rhs } => {
+                let (lhs, rhs) = (self.reg(*lhs), self.reg(*rhs));
+                let value = match op {
+                    BinOp::Eq => Value::Bool(self.equal(lhs, rhs)),
+                    BinOp::Lt => Value::Bool(self.int(lhs)? < self.int(rhs)?),
+                    BinOp::Le => Value::Bool(self.int(lhs)? <= self.int(rhs)?),
+                    BinOp::Add => Value::Int(self.int(lhs)?.wrapping_add(self.int(rhs)?)),
+                    BinOp::Sub => Value::Int(self.int(lhs)?.wrapping_sub(self.int(rhs)?)),
+                    BinOp::Mul => Value::Int(self.int(lhs)?.wrapping_mul(self.int(rhs)?)),
+                    BinOp::Div => match self.int(rhs)? {
+                        0 => return Err(RuntimeError::DivisionByZero),
+                        divisor => Value::Int(self.int(lhs)?.wrapping_div(divisor)),
+                    },
+                };
+                self.set_reg(*dst, value);
+            }
+            Inst::Unary { dst, op, src } => {
+                let src = self.reg(*src);
+                let value = match op {
+                    ir::UnOp::Neg => Value::Int(self.int(src)?.wrapping_neg()),
+                    ir::UnOp::Not => Value::Bool(!self.bool(src)?),
+                };
+                self.set_reg(*dst, value);
+            }
+            Inst::IsVoid { dst, src } => {
+                self.set_reg(*dst, Value::Bool(self.reg(*src) == Value::Void))
+            }
+            Inst::New { dst, class } => {
+                let class = match class {
+                    Some(class) => *class,
+                    None => self.class_of(self.reg(self.frames.last().unwrap().func.params[0])),
+                };
+                let object = self.alloc_object(class)?;
+                self.set_reg(*dst, object);
+                if let Some(init) = self.functions.get(&(class, INIT_METHOD)).copied() {
+                    self.push_frame(init, &[object], Some(*dst))?;
+                }
+            }
+            Inst::GetAttr {
+                dst,
+                obj,
+                class,
+                name,
+            } => {
+                let obj = self.object(self.reg(*obj))?;
+                let slot = self.field_slot(*class, *name)?;
+                self.set_reg(*dst, self.heap.field(obj, slot));
+            }
+            Inst::SetAttr {
+                obj,
+                class,
+                name,
+                src,
+            } => {
+                let obj = self.object(self.reg(*obj))?;
+                let slot = self.field_slot(*class, *name)?;
+                self.heap.set_field(obj, slot, self.reg(*src));
+            }
+            Inst::Call {
+                dst,
+                recv,
+                target,
+                method,
+                args,
+            } => {
+                let recv = self.reg(*recv);
+                if recv == Value::Void {
+                    return Err(RuntimeError::DispatchOnVoid {
+                        method: method.to_string(),
+                    });
+                }
+                let lookup = match target {
+                    Target::Virtual => self.class_of(recv),
+                    Target::Static(class) => *class,
+                };
+                let defining = self
+                    .module
+                    .resolve_method(lookup, *method)
+                    .ok_or_else(|| RuntimeError::Undefined(format!("method {lookup}.{method}")))?;
+                let mut values = Vec::with_capacity(args.len() + 1);
+                values.push(recv);
+                values.extend(args.iter().map(|arg| self.reg(*arg)));
+                match self.functions.get(&(defining, *method)).copied() {
+                    Some(func) => self.push_frame(func, &values, Some(*dst))?,
+                    None => {
+                        let result = self.builtin(defining, *method, &values)?;
+                        self.set_reg(*dst, result);
+                    }
+                }
+            }
+            // Phis are evaluated on entry to their block.
+            Inst::Phi { .. } => {}
+        }
+        Ok(())
+    }
+
+    fn builtin(
+        &mut self,
+        class: Symbol,
+        method: Symbol,
+        args: &[Value],
+    ) -> Result<Value, RuntimeError> {
+        let this = args[0];
+        Ok(match (class, method) {
+            (sym::OBJECT, sym::ABORT) => {
+                return Err(RuntimeError::Abort {
+                    class: self.class_of(this).to_string(),
+                });
+            }
+            (sym::OBJECT, sym::TYPE_NAME) => self.alloc_string(self.class_of(this).to_string()),
+            (sym::OBJECT, sym::COPY) => match this {
+                Value::Ref(r) => Value::Ref(self.heap.alloc(self.heap.get(r).clone())),
+                other => other,
+            },
+            (sym::IO, sym::OUT_STRING) => {
+                let s = self.string(args[1])?.to_string();
+                self.output.write_all(s.as_bytes())?;
+                this
+            }
+            (sym::IO, sym::OUT_INT) => {
+                let i = self.int(args[1])?;
+                write!(self.output, "{i}")?;
+                this
+            }
+            (sym::IO, sym::IN_STRING) => {
+                let line = self.read_line()?;
+                self.alloc_string(line)
+            }
+            (sym::IO, sym::IN_INT) => {
+                let line = self.read_line()?;
+                Value::Int(parse_int(&line))
+            }
+            (sym::STRING, sym::LENGTH) => Value::Int(self.string(this)?.len() as i32),
+            (sym::STRING, sym::CONCAT) => {
+                let s = format!("{}{}", self.string(this)?, self.string(args[1])?);
+                self.alloc_string(s)
+            }
+            (sym::STRING, sym::SUBSTR) => {
+                let (index, length) = (self.int(args[1])?, self.int(args[2])?);
+                let s = self.string(this)?;
+                let range = usize::try_from(index)
+                    .ok()
+                    .zip(usize::try_from(length).ok())
+                    .map(|(i, l)| i..i + l)
+                    .filter(|r| r.end <= s.len());
+                let Some(range) = range else {
+                    return Err(RuntimeError::SubstrOutOfRange { index, length });
+                };
+                let sub = String::from_utf8_lossy(&s.as_bytes()[range]).into_owned();
+                self.alloc_string(sub)
+            }
+            _ => return Err(RuntimeError::Undefined(format!("method {class}.{method}"))),
+        })
+    }
+
+    fn read_line(&mut self) -> Result<String, RuntimeError> {
+        self.output.flush()?;
+        let mut line = String::new();
+        self.input.read_line(&mut line)?;
+        Ok(line.trim_end_matches(['\n', '\r']).to_string())
+    }
+
+    fn alloc_string(&mut self, s: String) -> Value {
+        Value::Ref(self.heap.alloc(Object {
+            class: self.class_index[&sym::STRING],
+            data: ObjData::Str(s),
+        }))
+    }
+
+    /// Allocates an object of `class` with every attribute at its default value.
+    fn alloc_object(&mut self, class: Symbol) -> Result<Value, RuntimeError> {
+        Ok(match class {
+            sym::INT => Value::Int(0),
+            sym::BOOL => Value::Bool(false),
+            sym::STRING => self.alloc_string(String::new()),
+            _ => {
+                let index = *self
+                    .class_index
+                    .get(&class)
+                    .ok_or_else(|| RuntimeError::Undefined(format!("class {class}")))?;
+                let fields = self
+                    .module
+                    .all_attributes(class)
+                    .into_iter()
+                    .map(|(_, attr)| match attr.typ {
+                        sym::INT => Value::Int(0),
+                        sym::BOOL => Value::Bool(false),
+                        sym::STRING => self.alloc_string(String::new()),
+                        _ => Value::Void,
+                    })
+                    .collect();
+                Value::Ref(self.heap.alloc(Object {
+                    class: index,
+                    data: ObjData::Fields(fields),
+                }))
+            }
+        })
+    }
+
+    fn field_slot(&self, class: Symbol, name: Symbol) -> Result<usize, RuntimeError> {
+        self.field_index
+            .get(&(class, name))
+            .copied()
+            .ok_or_else(|| RuntimeError::Undefined(format!("attribute {class}.{name}")))
+    }
+
+    fn class_of(&self, value: Value) -> Symbol {
+        match value {
+            Value::Int(_) => sym::INT,
+            Value::Bool(_) => sym::BOOL,
+            Value::Ref(r) => self.module.classes[self.heap.get(r).class].name,
+            Value::Void => sym::VOID,
+        }
+    }
+
+    fn equal(&self, lhs: Value, rhs: Value) -> bool {
+        match (lhs, rhs) {
+            (Value::Ref(a), Value::Ref(b)) if a != b => {
+                match (&self.heap.get(a).data, &self.heap.get(b).data) {
+                    (ObjData::Str(a), ObjData::Str(b)) => a == b,
+                    _ => false,
+                }
+            }
+            (a, b) => a == b,
+        }
+    }
+
+    fn int(&self, value: Value) -> Result<i32, RuntimeError> {
+        match value {
+            Value::Int(i) => Ok(i),
+            _ => Err(RuntimeError::TypeMismatch { expected: "Int" }),
+        }
+    }
+
+    fn bool(&self, value: Value) -> Result<bool, RuntimeError> {
+        match value {
+            Value::Bool(b) => Ok(b),
+            _ => Err(RuntimeError::TypeMismatch { expected: "Bool" }),
+        }
+    }
+
+    fn object(&self, value: Value) -> Result<GcRef, RuntimeError> {
+        match value {
+            Value::Ref(r) => Ok(r),
+            _ => Err(RuntimeError::TypeMismatch { expected: "Object" }),
+        }
+    }
+
+    fn string(&self, value: Value) -> Result<&str, RuntimeError> {
+        match value {
+            Value::Ref(r) => match &self.heap.get(r).data {
+                ObjData::Str(s) => Ok(s),
+                ObjData::Fields(_) => Err(RuntimeError::TypeMismatch { expected: "String" }),
+            },
+            _ => Err(RuntimeError::TypeMismatch { expected: "String" }),
+        }
+    }
+}
+
+/// Parses the integer at the start of `line` the way the reference runtime's `in_int`
+/// does: anything unparsable, or out of range, reads as 0.
+pub fn parse_int(line: &str) -> i32 {
+    let line = line.trim_start();
+    let digits = line
+        .char_indices()
+        .take_while(|(i, c)| c.is_ascii_digit() || (*i == 0 && *c == '-'))
+        .count();
+    line[..digits].parse().unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_with(src: &str, input: &str, options: Options) -> (Result<(), RuntimeError>, String) {
+        let program = parser::parse(src).unwrap();
+        let module = ir::lower(&program);
+        let mut input = input.as_bytes();
+        let mut output = Vec::new();
+        let result = Vm::new(&module, options, &mut input, &mut output).run();
+        (result, String::from_utf8(output).unwrap())
+    }
+
+    fn run(src: &str) -> (Result<(), RuntimeError>, String) {
+        run_with(src, "", Options::default())
+    }
+
+    #[test]
+    fn test_hello_world() {
+        let src = std::fs::read_to_string("../samples/hello_world.cl").unwrap();
+        assert_eq!(run(&src), (Ok(()), "Hello, World.\n".to_string()));
+    }
+
+    #[test]
+    fn test_arithmetic_and_strings() {
+        let (result, output) = run(r#"
+            class Main inherits IO {
+                x : Int <- 6;
+                main() : Object { {
+                    out_int(x * 7 - 10 / 3);
+                    out_string("\n".concat("abcdef".substr(2, 3)));
+                    out_int("hello".length());
+                    if "a" = "a" then out_string(" eq") else out_string(" ne") fi;
+                } };
+            };
+        "#);
+        assert_eq!(result, Ok(()));
+        assert_eq!(output, "39\ncde5 eq");
+    }
+
+    #[test]
+    fn test_dispatch_case_and_let() {
+        let (result, output) = run(r#"
+            class Animal { speak() : String { "..." }; };
+            class Dog inherits Animal { speak() : String { "woof" }; };
+            class Main inherits IO {
+                describe(a : Object) : String {
+                    case a of d : Dog => "dog"; x : Animal => "animal"; o : Object => "other"; esac
+                };
+                main() : Object { let a : Animal <- new Dog, b : Animal <- new Animal in {
+                    out_string(a.speak().concat(b.speak()));
+                    out_string(a@Animal.speak());
+                    out_string(describe(a).concat(describe(b)).concat(describe(1)));
+                    out_string(a.type_name());
+                } };
+            };
+        "#);
+        assert_eq!(result, Ok(()));
+        assert_eq!(output, "woof......doganimalotherDog");
+    }
+
+    #[test]
+    fn test_runtime_errors() {
+        let (result, _) = run("class Main { a : Main; main() : Object { a.main() }; };");
+        assert!(matches!(result, Err(RuntimeError::DispatchOnVoid { .. })));
+
+        let (result, _) = run(r#"class Main { main() : Object { "abc".substr(2, 5) }; };"#);
+        assert!(matches!(result, Err(RuntimeError::SubstrOutOfRange { .. })));
+
+        let (result, _) =
+            run("class Main { main() : Object { case 1 of s : String => s; esac }; };");
+        assert!(matches!(result, Err(RuntimeError::CaseNoMatch { class }) if class == "Int"));
+
+        let (result, _) = run("class Main { main() : Object { main() }; };");
+        assert_eq!(result, Err(RuntimeError::StackOverflow));
+    }
+
+    #[test]
+    fn test_backtrace() {
+        let mut sources = SourceMap::new();
+        let file = sources.add(
+            "a.cl",
+            "class A {\n  f(n : Int) : Int {\n    1 / n\n  };\n};\n\
+             class Main {\n  a : A <- new A;\n  main() : Object {\n    a.f(0)\n  };\n};\n",
+        );
+        let program = parser::parse_file(sources.file(file).src(), file).unwrap();
+        // At -O2, `f` is inlined into `main`, which then fails at the division's line.
+        for (level, expected) in [
+            (ir::OptLevel::O0, &[("A.f", 3), ("Main.main", 9)][..]),
+            (ir::OptLevel::O2, &[("Main.main", 3)][..]),
+        ] {
+            let mut module = ir::lower(&program);
+            ir::optimize(&mut module, level);
+            let (mut input, mut output) = ("".as_bytes(), Vec::new());
+            let mut vm = Vm::new(&module, Options::default(), &mut input, &mut output);
+            assert_eq!(vm.run(), Err(RuntimeError::DivisionByZero));
+            let backtrace = vm.backtrace(&sources);
+            let backtrace: Vec<_> = backtrace
+                .iter()
+                .map(|frame| (frame.function.as_str(), frame.line))
+                .collect();
+            assert_eq!(backtrace, expected, "at {level}");
+        }
+    }
+
+    #[test]
+    fn test_input() {
+        let src = r#"
+            class Main inherits IO {
+                main() : Object { {
+                    out_int(in_int() + 1);
+                    out_string(in_string().concat("!"));
+                } };
+            };
+        "#;
+        let (result, output) = run_with(src, "41\nhi\n", Options::default());
+        assert_eq!(result, Ok(()));
+        assert_eq!(output, "42hi!");
+    }
+
+    #[test]
+    fn test_collectors_agree_on_allocation_heavy_program() {
+        // Builds a long linked list while allocating lots of garbage strings.
+        let src = r#"
+            class Node { next : Node; label : String;
+                init(n : Node, l : String) : Node { { next <- n; label <- l; self; } };
+                label() : String { label }; next() : Node { next };
+            };
+            class Main inherits IO {
+                main() : Object {
+                    let list : Node, i : Int <- 0, s : String in {
+                        while i < 20000 loop {
+                            s <- "garbage".concat("more garbage");
+                            list <- (new Node).init(list, "n".concat(s.substr(0, 1)));
+                            i <- i + 1;
+                        } pool;
+                        i <- 0;
+                        while not isvoid list loop { i <- i + list.label().length(); list <- list.next(); } pool;
+                        out_int(i);
+                    }
+                };
+            };
+        "#;
+        for gc in [GcKind::None, GcKind::MarkSweep, GcKind::Generational] {
+            let options = Options {
+                gc,
+                ..Options::default()
+            };
+            let program = parser::parse(src).unwrap();
+            let module = ir::lower(&program);
+            let mut input = "".as_bytes();
+            let mut output = Vec::new();
+            let mut vm = Vm::new(&module, options, &mut input, &mut output);
+            assert_eq!(vm.run(), Ok(()));
+            let stats = *vm.gc_stats();
+            assert_eq!(String::from_utf8(output).unwrap(), "40000");
+            if gc == GcKind::None {
+                assert_eq!(stats.collections(), 0);
+            } else {
+                assert!(stats.collections() > 0, "{gc} never collected");
+                assert!(stats.bytes_reclaimed > 0, "{gc} reclaimed nothing");
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_int() {
+        assert_eq!(parse_int("42"), 42);
+        assert_eq!(parse_int("  -7 apples"), -7);
+        assert_eq!(parse_int("nope"), 0);
+        assert_eq!(parse_int("99999999999"), 0);
+    }
+}
@@ -0,0 +1,5 @@
+(* Characters that can't start a token are reported, and lexing carries on. *)
+class Main {
+    main() : Int { 1 # 2 };
+    s : String <- "unterminated
+};
@@ -0,0 +1,8 @@
+-- Escapes, and keywords in any case.
+CLASS Main INHERITS IO {
+    main() : Object {
+        out_string("tab\tnewline\nquote\" backslash\\ \c\n")
+    };
+    b : Bool <- tRUE;
+    c : Bool <- fALSE;
+};
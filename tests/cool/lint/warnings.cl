@@ -0,0 +1,10 @@
+class Main inherits IO {
+    main() : Object {
+        let unused : Int <- 3 in
+            case 1 of
+                i : Int => out_int(i);
+                j : Int => out_int(j);
+            esac
+    };
+    f(x : Int) : Int { 0 };
+};
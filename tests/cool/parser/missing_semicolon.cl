@@ -0,0 +1,4 @@
+class Main {
+    main() : Int { 1 }
+    f() : Int { 2 };
+};
@@ -0,0 +1,7 @@
+-- Arithmetic binds as the manual's precedence table says, and let extends as far
+-- right as it can.
+class Main inherits IO {
+    main() : Object {
+        out_int(1 + 2 * 3 - ~4 / 2 + let a : Int <- 1, b : Int in a + b * 2)
+    };
+};
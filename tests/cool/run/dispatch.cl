@@ -0,0 +1,27 @@
+-- Dynamic, static and self dispatch, and case on the dynamic type.
+class Animal {
+    name() : String { "animal" };
+    speak() : String { name().concat(" says nothing") };
+};
+class Dog inherits Animal {
+    name() : String { "dog" };
+    speak() : String { name().concat(" says woof") };
+};
+class Main inherits IO {
+    describe(a : Object) : String {
+        case a of
+            d : Dog => "a Dog";
+            n : Animal => "an Animal";
+            o : Object => "something else";
+        esac
+    };
+    main() : Object {
+        let d : Animal <- new Dog in {
+            out_string(d.speak().concat("\n"));
+            out_string(d@Animal.speak().concat("\n"));
+            out_string(describe(d).concat("\n"));
+            out_string(describe(new Animal).concat("\n"));
+            out_string(describe(3).concat("\n"));
+        }
+    };
+};
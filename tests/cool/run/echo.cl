@@ -0,0 +1,11 @@
+-- Reads lines until an empty one, printing each with its length.
+class Main inherits IO {
+    main() : Object {
+        let line : String <- in_string() in
+            while not (line.length() = 0) loop {
+                out_int(line.length());
+                out_string(" ".concat(line).concat("\n"));
+                line <- in_string();
+            } pool
+    };
+};
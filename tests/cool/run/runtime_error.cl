@@ -0,0 +1,15 @@
+class List {
+    item : Int;
+    next : List;
+    cons(i : Int, n : List) : List { { item <- i; next <- n; self; } };
+    last() : Int { next.last() };
+};
+class Main inherits IO {
+    main() : Object {
+        {
+            out_string("before\n");
+            out_int((new List).cons(1, (new List).cons(2, (new List))).last());
+            out_string("after\n");
+        }
+    };
+};
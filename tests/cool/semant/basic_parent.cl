@@ -0,0 +1,4 @@
+class Int2 inherits Int {};
+class Main {
+    main() : Int { 0 };
+};
@@ -0,0 +1,5 @@
+class A inherits B {};
+class B inherits A {};
+class Main {
+    main() : Int { 0 };
+};
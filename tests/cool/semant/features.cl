@@ -0,0 +1,12 @@
+class Base {
+    f(x : Int) : Int { x };
+};
+class Derived inherits Base {
+    f(x : String) : Int { 0 };
+    a : Int;
+    a : Bool;
+    g(y : Missing) : Int { 0 };
+};
+class Main {
+    main(arg : Int) : Object { 0 };
+};
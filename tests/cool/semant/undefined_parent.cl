@@ -0,0 +1,4 @@
+class C inherits Undefined {};
+class Main {
+    main() : Int { 0 };
+};
@@ -0,0 +1,305 @@
+//! Runs WebAssembly modules produced by the COOL Wasm backend, providing the `cool`
+//! host functions they import.
+
+use std::fmt;
+use std::io::{BufRead, Write};
+
+use runtime::RuntimeError;
+use runtime::backtrace::StackFrame;
+use runtime::heap::HeapExhausted;
+use wasmi::core::TrapCode;
+use wasmi::{AsContext, Caller, Engine, Extern, Instance, Linker, Module, Store};
+
+/// Error codes the module passes to the `error` import, with what the other two
+/// arguments hold.
+pub const ERROR_DISPATCH_ON_VOID: i32 = 0; // method name (pointer, length)
+pub const ERROR_CASE_ON_VOID: i32 = 1;
+pub const ERROR_CASE_NO_MATCH: i32 = 2; // class name (pointer, length)
+pub const ERROR_SUBSTR_OUT_OF_RANGE: i32 = 3; // index, length
+pub const ERROR_DIVISION_BY_ZERO: i32 = 4;
+pub const ERROR_ABORT: i32 = 5; // class name (pointer, length)
+pub const ERROR_UNDEFINED: i32 = 6; // what (pointer, length)
+pub const ERROR_HEAP_EXHAUSTED: i32 = 7; // bytes in use, memory limit
+pub const ERROR_STACK_OVERFLOW: i32 = 8;
+
+/// Entries in the module's exported `frames` array.
+const MAX_RECORDED_FRAMES: u32 = 1024;
+
+#[derive(Debug)]
+pub enum RunError {
+    /// The text didn't parse or the module didn't validate or link.
+    Invalid(String),
+    /// The program failed the way it would have in the interpreter, in the methods of
+    /// the backtrace, innermost first.
+    Runtime(RuntimeError, Vec<StackFrame>),
+    /// The module trapped for some other reason.
+    Trap(String),
+}
+
+impl fmt::Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RunError::Invalid(e) => write!(f, "invalid module: {e}"),
+            RunError::Runtime(e, _) => write!(f, "{e}"),
+            RunError::Trap(e) => write!(f, "trap: {e}"),
+        }
+    }
+}
+
+struct Host<R, W> {
+    input: R,
+    output: W,
+    /// The line `in_string` read, waiting for `read_line` to copy it into memory.
+    line: Vec<u8>,
+    error: Option<RuntimeError>,
+}
+
+impl<R: BufRead, W: Write> Host<R, W> {
+    fn read_line(&mut self) -> Result<String, wasmi::Error> {
+        self.output.flush().map_err(io_error)?;
+        let mut line = String::new();
+        self.input.read_line(&mut line).map_err(io_error)?;
+        Ok(line.trim_end_matches(['\n', '\r']).to_string())
+    }
+
+    fn fail(&mut self, error: RuntimeError) -> wasmi::Error {
+        let trap = wasmi::Error::new(error.to_string());
+        self.error = Some(error);
+        trap
+    }
+}
+
+fn io_error(e: std::io::Error) -> wasmi::Error {
+    wasmi::Error::new(format!("I/O error: {e}"))
+}
+
+fn memory_bytes<R, W>(caller: &Caller<'_, Host<R, W>>, ptr: i32, len: i32) -> Vec<u8> {
+    let memory = caller
+        .get_export("memory")
+        .and_then(Extern::into_memory)
+        .expect("module exports its memory");
+    let start = ptr as u32 as usize;
+    memory.data(caller)[start..start + len as u32 as usize].to_vec()
+}
+
+/// The methods the module was running, innermost first, from its exported `depth` and
+/// `frames`. Empty for modules that don't export them.
+fn backtrace(store: impl AsContext, instance: &Instance) -> Vec<StackFrame> {
+    let global = |name| {
+        instance
+            .get_global(&store, name)
+            .and_then(|g| g.get(&store).i32())
+    };
+    let (Some(depth), Some(frames), Some(memory)) = (
+        global("depth"),
+        global("frames"),
+        instance.get_memory(&store, "memory"),
+    ) else {
+        return Vec::new();
+    };
+    let data = memory.data(&store);
+    let word = |addr: u32| {
+        let addr = addr as usize;
+        u32::from_le_bytes(data[addr..addr + 4].try_into().unwrap())
+    };
+    // A String object's bytes follow its descriptor and length.
+    let string = |addr: u32| {
+        let start = addr as usize + 8;
+        String::from_utf8_lossy(&data[start..start + word(addr + 4) as usize]).into_owned()
+    };
+    let recorded = (depth as u32).min(MAX_RECORDED_FRAMES);
+    (0..recorded)
+        .rev()
+        .map(|i| {
+            let site = word(frames as u32 + 4 * i);
+            StackFrame {
+                function: string(word(site)),
+                file: string(word(site + 4)),
+                line: word(site + 8) as usize,
+            }
+        })
+        .collect()
+}
+
+fn memory_string<R, W>(caller: &Caller<'_, Host<R, W>>, ptr: i32, len: i32) -> String {
+    String::from_utf8_lossy(&memory_bytes(caller, ptr, len)).into_owned()
+}
+
+/// Runs the exported `main` of `module`, given in either the text or the binary format,
+/// returning the output writer once the program has finished. The writer is flushed
+/// even when the program failed, so what it printed can come out before the error.
+pub fn run<R, W>(module: &[u8], input: R, output: W) -> (Result<(), RunError>, W)
+where
+    R: BufRead + 'static,
+    W: Write + 'static,
+{
+    let engine = Engine::default();
+    let mut store = Store::new(
+        &engine,
+        Host {
+            input,
+            output,
+            line: Vec::new(),
+            error: None,
+        },
+    );
+    let result = instantiate_and_run(&engine, &mut store, module);
+    let mut host = store.into_data();
+    let flushed = host.output.flush();
+    let result = result.and_then(|()| {
+        flushed.map_err(|e| RunError::Runtime(RuntimeError::Io(e.to_string()), Vec::new()))
+    });
+    (result, host.output)
+}
+
+fn instantiate_and_run<R, W>(
+    engine: &Engine,
+    store: &mut Store<Host<R, W>>,
+    module: &[u8],
+) -> Result<(), RunError>
+where
+    R: BufRead + 'static,
+    W: Write + 'static,
+{
+    let invalid = |e: &dyn fmt::Display| RunError::Invalid(e.to_string());
+    let wasm = wat::parse_bytes(module).map_err(|e| invalid(&e))?;
+    let module = Module::new(engine, &wasm[..]).map_err(|e| invalid(&e))?;
+
+    let mut linker = <Linker<Host<R, W>>>::new(engine);
+    linker
+        .func_wrap(
+            "cool",
+            "out_string",
+            |mut caller: Caller<'_, Host<R, W>>, ptr: i32, len: i32| {
+                let bytes = memory_bytes(&caller, ptr, len);
+                caller.data_mut().output.write_all(&bytes).map_err(io_error)
+            },
+        )
+        .and_then(|l| {
+            l.func_wrap(
+                "cool",
+                "out_int",
+                |mut caller: Caller<'_, Host<R, W>>, value: i32| {
+                    write!(caller.data_mut().output, "{value}").map_err(io_error)
+                },
+            )
+        })
+        .and_then(|l| {
+            l.func_wrap(
+                "cool",
+                "in_string",
+                |mut caller: Caller<'_, Host<R, W>>| -> Result<i32, wasmi::Error> {
+                    let host = caller.data_mut();
+                    host.line = host.read_line()?.into_bytes();
+                    Ok(host.line.len() as i32)
+                },
+            )
+        })
+        .and_then(|l| {
+            l.func_wrap(
+                "cool",
+                "read_line",
+                |mut caller: Caller<'_, Host<R, W>>, ptr: i32| {
+                    let line = std::mem::take(&mut caller.data_mut().line);
+                    let memory = caller
+                        .get_export("memory")
+                        .and_then(Extern::into_memory)
+                        .expect("module exports its memory");
+                    let start = ptr as u32 as usize;
+                    memory.data_mut(&mut caller)[start..start + line.len()].copy_from_slice(&line);
+                },
+            )
+        })
+        .and_then(|l| {
+            l.func_wrap(
+                "cool",
+                "in_int",
+                |mut caller: Caller<'_, Host<R, W>>| -> Result<i32, wasmi::Error> {
+                    let line = caller.data_mut().read_line()?;
+                    Ok(runtime::vm::parse_int(&line))
+                },
+            )
+        })
+        .and_then(|l| {
+            l.func_wrap(
+                "cool",
+                "error",
+                |mut caller: Caller<'_, Host<R, W>>,
+                 code: i32,
+                 a: i32,
+                 b: i32|
+                 -> Result<(), wasmi::Error> {
+                    let error = match code {
+                        ERROR_DISPATCH_ON_VOID => RuntimeError::DispatchOnVoid {
+                            method: memory_string(&caller, a, b),
+                        },
+                        ERROR_CASE_ON_VOID => RuntimeError::CaseOnVoid,
+                        ERROR_CASE_NO_MATCH => RuntimeError::CaseNoMatch {
+                            class: memory_string(&caller, a, b),
+                        },
+                        ERROR_SUBSTR_OUT_OF_RANGE => RuntimeError::SubstrOutOfRange {
+                            index: a,
+                            length: b,
+                        },
+                        ERROR_DIVISION_BY_ZERO => RuntimeError::DivisionByZero,
+                        ERROR_ABORT => RuntimeError::Abort {
+                            class: memory_string(&caller, a, b),
+                        },
+                        ERROR_UNDEFINED => RuntimeError::Undefined(memory_string(&caller, a, b)),
+                        ERROR_HEAP_EXHAUSTED => RuntimeError::HeapExhausted(HeapExhausted {
+                            live_bytes: a as u32 as usize,
+                            limit: b as u32 as usize,
+                        }),
+                        ERROR_STACK_OVERFLOW => RuntimeError::StackOverflow,
+                        _ => RuntimeError::Undefined(format!("error code {code}")),
+                    };
+                    Err(caller.data_mut().fail(error))
+                },
+            )
+        })
+        .map_err(|e| invalid(&e))?;
+
+    let instance = linker
+        .instantiate(&mut *store, &module)
+        .and_then(|pre| pre.start(&mut *store))
+        .map_err(|e| invalid(&e))?;
+    let main = instance
+        .get_typed_func::<(), ()>(&*store, "main")
+        .map_err(|e| invalid(&e))?;
+    main.call(&mut *store, ()).map_err(|e| {
+        let error = match (store.data_mut().error.take(), e.as_trap_code()) {
+            (Some(error), _) => error,
+            (None, Some(TrapCode::StackOverflow)) => RuntimeError::StackOverflow,
+            (None, _) => return RunError::Trap(e.to_string()),
+        };
+        RunError::Runtime(error, backtrace(&*store, &instance))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::BufWriter;
+
+    use super::*;
+
+    #[test]
+    fn test_output_is_flushed_when_the_program_fails() {
+        let module = r#"
+            (module
+              (import "cool" "out_string" (func $out_string (param i32 i32)))
+              (import "cool" "error" (func $error (param i32 i32 i32)))
+              (memory (export "memory") 1)
+              (data (i32.const 0) "before")
+              (func (export "main")
+                (call $out_string (i32.const 0) (i32.const 6))
+                (call $error (i32.const 4) (i32.const 0) (i32.const 0))))
+        "#;
+        let (result, output) = run(module.as_bytes(), "".as_bytes(), BufWriter::new(Vec::new()));
+        assert!(matches!(
+            result,
+            Err(RunError::Runtime(RuntimeError::DivisionByZero, _))
+        ));
+        assert!(output.buffer().is_empty());
+        assert_eq!(output.get_ref(), b"before");
+    }
+}
@@ -0,0 +1,43 @@
+use clap::Parser;
+use runtime::backtrace::{self, EXIT_RUNTIME_ERROR};
+use std::fs;
+use std::io::{self, BufWriter};
+use std::process::ExitCode;
+use wasm_host::RunError;
+
+#[derive(Parser)]
+#[command(name = "wasm-host")]
+#[command(about = "Runs a Cool program compiled to WebAssembly")]
+#[command(version)]
+struct Args {
+    /// Input WebAssembly module (.wat or .wasm)
+    #[arg(value_name = "FILE")]
+    file: String,
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    let module = match fs::read(&args.file) {
+        Ok(module) => module,
+        Err(e) => {
+            eprintln!("Error: failed to read '{}': {e}", args.file);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let stdin = io::BufReader::new(io::stdin());
+    let stdout = BufWriter::new(io::stdout());
+    // `run` flushes what the program printed before returning, so a runtime error's
+    // report comes after it.
+    match wasm_host::run(&module, stdin, stdout).0 {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(RunError::Runtime(e, frames)) => {
+            eprint!("{}", backtrace::report(&e, &frames));
+            ExitCode::from(EXIT_RUNTIME_ERROR)
+        }
+        Err(e) => {
+            eprintln!("Error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}